@@ -0,0 +1,110 @@
+// src/attributes.rs
+//! `key=value` attributes written after the chunk name on an opening line,
+//! e.g. `<<@file a.sh mode=755 eol=lf lang=bash>>=`. Values are kept as
+//! written and read back as the type each option needs.
+use std::collections::BTreeMap;
+
+/// The attributes of one definition, by key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Attributes(BTreeMap<String, String>);
+
+/// Whether `key` can name an attribute: a letter or `_`, then letters,
+/// digits, `_` or `-`.
+fn is_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+impl Attributes {
+    /// Parse whitespace-separated `key=value` pairs, where a value may be
+    /// double-quoted to hold spaces; None if some token is not such a pair.
+    /// A key given twice keeps its last value.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut attributes = Self::default();
+        let mut rest = text.trim_start();
+        while !rest.is_empty() {
+            let (key, after) = rest.split_once('=')?;
+            if !is_key(key) {
+                return None;
+            }
+            let (value, after) = match after.strip_prefix('"') {
+                Some(quoted) => {
+                    let (value, after) = quoted.split_once('"')?;
+                    if !after.is_empty() && !after.starts_with(char::is_whitespace) {
+                        return None;
+                    }
+                    (value, after)
+                }
+                None => after.split_at(after.find(char::is_whitespace).unwrap_or(after.len())),
+            };
+            attributes.insert(key, value);
+            rest = after.trim_start();
+        }
+        Some(attributes)
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.0.insert(key.to_string(), value.to_string());
+    }
+
+    /// The value of `key` as written.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// The value of `key` as an integer; None if it is absent or not one.
+    pub fn get_int(&self, key: &str) -> Option<i64> {
+        self.get(key)?.parse().ok()
+    }
+
+    /// The value of `key` as a flag: `true`/`yes`/`on` or `false`/`no`/`off`;
+    /// None if it is absent or neither.
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)?.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" => Some(true),
+            "false" | "no" | "off" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// The value of `key` as a comma-separated list, e.g. `tags=wasm,beta`,
+    /// without empty items; empty if it is absent.
+    pub fn get_list(&self, key: &str) -> Vec<&str> {
+        self.get(key).map_or_else(Vec::new, |value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+    }
+
+    /// Every (key, value) pair, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The pairs as an opening line reads them back, sorted by key and each
+    /// preceded by a space; values are quoted only when they must be. None if
+    /// some value holds a `"`, which has no escape in either form.
+    pub fn to_text(&self) -> Option<String> {
+        let mut text = String::new();
+        for (key, value) in self.iter() {
+            if value.contains('"') {
+                return None;
+            } else if value.is_empty() || value.contains(char::is_whitespace) {
+                text.push_str(&format!(" {}=\"{}\"", key, value));
+            } else {
+                text.push_str(&format!(" {}={}", key, value));
+            }
+        }
+        Some(text)
+    }
+}
@@ -0,0 +1,110 @@
+// src/build.rs
+//! Tangling from a Cargo build script, without shelling out to the binary.
+//!
+//! ```no_run
+//! // build.rs
+//! use azadi_noweb::build::{tangle, TangleOptions};
+//!
+//! let out_dir = std::env::var("OUT_DIR").unwrap();
+//! let options = TangleOptions {
+//!     gen: format!("{}/gen", out_dir).into(),
+//!     priv_dir: format!("{}/azadi", out_dir).into(),
+//!     ..TangleOptions::default()
+//! };
+//! let report = tangle(&["src/lib.nw"], &options).unwrap();
+//! report.emit_rerun_if_changed();
+//! ```
+use std::path::{Path, PathBuf};
+
+use crate::safe_writer::SafeWriterConfig;
+use crate::{AzadiError, Clip, Dialect, ExpandOptions, SafeFileWriter};
+
+/// What to read and where to write, mirroring the command-line options.
+#[derive(Debug, Clone)]
+pub struct TangleOptions {
+    pub gen: PathBuf,
+    pub priv_dir: PathBuf,
+    pub open_delim: String,
+    pub close_delim: String,
+    /// Further (open, close) pairs read alongside the delimiters above.
+    pub extra_delimiters: Vec<(String, String)>,
+    pub chunk_end: String,
+    pub comment_markers: Vec<String>,
+    pub dialect: Dialect,
+    pub expand: ExpandOptions,
+}
+
+impl Default for TangleOptions {
+    fn default() -> Self {
+        Self {
+            gen: PathBuf::from("gen"),
+            priv_dir: PathBuf::from("_azadi_work"),
+            open_delim: "<<".to_string(),
+            close_delim: ">>".to_string(),
+            extra_delimiters: Vec::new(),
+            chunk_end: "@".to_string(),
+            comment_markers: vec!["#".to_string(), "//".to_string()],
+            dialect: Dialect::default(),
+            expand: ExpandOptions::default(),
+        }
+    }
+}
+
+/// The files a tangle run read and wrote.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WriteReport {
+    /// The documents passed in, then the files their outputs insert.
+    pub inputs: Vec<PathBuf>,
+    /// Generated files, under the gen directory.
+    pub outputs: Vec<PathBuf>,
+}
+
+impl WriteReport {
+    /// Ask Cargo to re-run the build script when an input changes.
+    pub fn emit_rerun_if_changed(&self) {
+        for input in &self.inputs {
+            println!("cargo:rerun-if-changed={}", input.display());
+        }
+    }
+}
+
+/// Read `inputs` and write their file chunks, like running the binary on them.
+pub fn tangle<P: AsRef<Path>>(
+    inputs: &[P],
+    options: &TangleOptions,
+) -> Result<WriteReport, AzadiError> {
+    let mut builder = Clip::builder()
+        .safe_writer(SafeFileWriter::with_config(
+            &options.gen,
+            &options.priv_dir,
+            SafeWriterConfig::default(),
+        )?)
+        .delimiters(&options.open_delim, &options.close_delim)
+        .chunk_end(&options.chunk_end)
+        .comment_markers(&options.comment_markers)
+        .dialect(options.dialect);
+    for (open, close) in &options.extra_delimiters {
+        builder = builder.extra_delimiters(open, close);
+    }
+    let mut clip = builder.build()?;
+    clip.read_files(inputs)?;
+    clip.write_files_with_options(&options.expand)?;
+
+    let mut read: Vec<PathBuf> = inputs.iter().map(|p| p.as_ref().to_path_buf()).collect();
+    for (_, dependencies) in clip.output_dependencies()? {
+        for dependency in dependencies.into_iter().map(PathBuf::from) {
+            if !read.contains(&dependency) {
+                read.push(dependency);
+            }
+        }
+    }
+
+    Ok(WriteReport {
+        inputs: read,
+        outputs: clip
+            .output_paths()
+            .iter()
+            .map(|path| clip.output_path(path))
+            .collect(),
+    })
+}
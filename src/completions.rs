@@ -0,0 +1,38 @@
+// src/completions.rs
+//! Shell completion scripts, generated by clap_complete from the
+//! command-line definition so that they follow the options as they change.
+use clap::{Command, ValueEnum};
+
+/// A shell with a completion script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    #[value(name = "powershell")]
+    PowerShell,
+}
+
+impl From<Shell> for clap_complete::Shell {
+    fn from(shell: Shell) -> Self {
+        match shell {
+            Shell::Bash => clap_complete::Shell::Bash,
+            Shell::Zsh => clap_complete::Shell::Zsh,
+            Shell::Fish => clap_complete::Shell::Fish,
+            Shell::PowerShell => clap_complete::Shell::PowerShell,
+        }
+    }
+}
+
+/// The completion script for `shell`, completing command `bin_name`.
+pub fn generate(shell: Shell, command: &Command, bin_name: &str) -> String {
+    let mut command = command.clone();
+    let mut script = Vec::new();
+    clap_complete::generate(
+        clap_complete::Shell::from(shell),
+        &mut command,
+        bin_name,
+        &mut script,
+    );
+    String::from_utf8_lossy(&script).into_owned()
+}
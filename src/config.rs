@@ -0,0 +1,339 @@
+// src/config.rs
+//! Project configuration (azadi.toml), read by the command-line tool.
+//!
+//! ```toml
+//! strict_redefinitions = true
+//! backup_dir = "../backups"
+//! no_backup = ["*.log"]
+//! snapshots = 5
+//! extra_delimiters = ["⟨⟨ ⟩⟩"]
+//! auto_markers = true
+//! markers_for = ["docs/*.tex=%"]
+//! test_harness = "tests/doc_examples.rs"
+//!
+//! [names]
+//! chars = "a-z0-9_-"
+//! max_length = 40
+//! reserved_prefixes = ["gen_"]
+//!
+//! [lint]
+//! name_pattern = "^[a-z][a-z0-9-]*$"
+//! max_lines = 60
+//! extensionless_files = ["Makefile", "*/Dockerfile"]
+//!
+//! [profiles.release]
+//! gen = "dist"
+//! files = ["src/*", "Cargo.toml"]
+//! tags = ["wasm"]
+//!
+//! [profiles.release.defines]
+//! MODE = "release"
+//! ```
+//!
+//! The file is read with the `toml` crate, so any valid TOML is accepted,
+//! including multi-line arrays and inline tables such as
+//! `defines = { MODE = "release" }`. Only strings, integers, booleans,
+//! arrays of strings and tables are meaningful; unknown keys are rejected so
+//! that typos do not go unnoticed.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+use crate::harness::TestHarness;
+use crate::markers::marker_override;
+
+/// The configuration file looked up in the working directory by default.
+pub const DEFAULT_CONFIG: &str = "azadi.toml";
+
+/// A configuration file that could not be read or understood.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub file_name: String,
+    /// One-based line, when the problem is tied to one.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{} line {}: {}", self.file_name, line, self.message),
+            None => write!(f, "{}: {}", self.file_name, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// One output tree: where it goes, its constants and which file chunks it holds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// Base directory of generated files, unless --gen is given.
+    pub gen: Option<PathBuf>,
+    /// Constants for `@{NAME}`, overridden by -D.
+    pub defines: BTreeMap<String, String>,
+    /// Glob patterns of the file chunk paths to write; empty writes all.
+    pub files: Vec<String>,
+    /// Tags of the definitions to read, unless --tags is given; empty reads all.
+    pub tags: Vec<String>,
+}
+
+/// Naming conventions for chunks, from the `[names]` table.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NameRules {
+    /// Allowed characters, as a regex character class body.
+    pub chars: Option<String>,
+    pub max_length: Option<usize>,
+    pub reserved_prefixes: Vec<String>,
+}
+
+/// Settings of the style rules `lint` checks, from the `[lint]` table;
+/// unset rules keep their defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintSettings {
+    /// Regex every chunk name but file chunks' must match.
+    pub name_pattern: Option<String>,
+    /// Most body lines a definition may have; 0 turns the rule off.
+    pub max_lines: Option<usize>,
+    /// Report chunks without an end line; on by default.
+    pub missing_end: Option<bool>,
+    /// Report definitions repeating an earlier one of the same chunk; on by
+    /// default.
+    pub duplicates: Option<bool>,
+    /// Report file chunks whose paths have no extension; on by default.
+    pub file_extensions: Option<bool>,
+    /// Glob patterns of the file chunk paths allowed without an extension.
+    pub extensionless_files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    /// Make appending chunk redefinitions an error.
+    pub strict_redefinitions: Option<bool>,
+    /// Write the outputs of `dir/name.nw` under `gen/dir/name/`.
+    pub prefix_from_input: Option<bool>,
+    /// Where backups of the outputs are kept, unless --backup-dir is given.
+    pub backup_dir: Option<PathBuf>,
+    /// Glob patterns of the outputs not to back up, added to --no-backup.
+    pub no_backup: Vec<String>,
+    /// How many runs' snapshots of the written outputs to keep, unless
+    /// --snapshots is given.
+    pub snapshots: Option<usize>,
+    /// Further (open, close) delimiter pairs, added to --extra-delims.
+    pub extra_delimiters: Vec<(String, String)>,
+    /// Pick each input's comment markers from its extension.
+    pub auto_markers: Option<bool>,
+    /// (glob, markers) overrides of the inputs' comment markers, applied
+    /// before --markers-for.
+    pub markers_for: Vec<(String, Vec<String>)>,
+    /// The output collecting the @test chunks, unless --test-harness is given.
+    pub test_harness: Option<TestHarness>,
+    pub names: NameRules,
+    pub lint: LintSettings,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// Read and parse a configuration file.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let file_name = path.to_string_lossy().into_owned();
+        let text = fs::read_to_string(path).map_err(|e| ConfigError {
+            file_name: file_name.clone(),
+            line: None,
+            message: e.to_string(),
+        })?;
+        Self::parse(&text, &file_name)
+    }
+
+    /// Parse configuration text; `file_name` is used in error messages.
+    pub fn parse(text: &str, file_name: &str) -> Result<Self, ConfigError> {
+        let error = |message: String| ConfigError {
+            file_name: file_name.to_string(),
+            line: None,
+            message,
+        };
+        let root: Table = text.parse().map_err(|e: toml::de::Error| ConfigError {
+            file_name: file_name.to_string(),
+            line: e
+                .span()
+                .map(|span| text[..span.start].matches('\n').count() + 1),
+            message: e.message().to_string(),
+        })?;
+
+        let mut config = Config::default();
+        for (key, value) in &root {
+            match key.as_str() {
+                "strict_redefinitions" => {
+                    config.strict_redefinitions = Some(bool_value(key, value).map_err(error)?);
+                }
+                "prefix_from_input" => {
+                    config.prefix_from_input = Some(bool_value(key, value).map_err(error)?);
+                }
+                "backup_dir" => {
+                    config.backup_dir =
+                        Some(PathBuf::from(string_value(key, value).map_err(error)?));
+                }
+                "no_backup" => {
+                    config.no_backup = string_array(key, value).map_err(error)?;
+                }
+                "snapshots" => config.snapshots = Some(count_value(key, value).map_err(error)?),
+                "extra_delimiters" => {
+                    config.extra_delimiters = string_array(key, value)
+                        .and_then(|pairs| pairs.iter().map(|p| delimiter_pair(p)).collect())
+                        .map_err(error)?;
+                }
+                "auto_markers" => {
+                    config.auto_markers = Some(bool_value(key, value).map_err(error)?);
+                }
+                "markers_for" => {
+                    config.markers_for = string_array(key, value)
+                        .and_then(|rules| rules.iter().map(|r| marker_override(r)).collect())
+                        .map_err(error)?;
+                }
+                "test_harness" => {
+                    config.test_harness = Some(
+                        string_value(key, value)
+                            .and_then(|path| TestHarness::new(&path))
+                            .map_err(error)?,
+                    );
+                }
+                "names" => config.names = parse_names(value).map_err(error)?,
+                "lint" => config.lint = parse_lint(value).map_err(error)?,
+                "profiles" => {
+                    let profiles = value
+                        .as_table()
+                        .ok_or_else(|| error("'profiles' must be a table".to_string()))?;
+                    for (name, profile) in profiles {
+                        let profile = parse_profile(name, profile).map_err(error)?;
+                        config.profiles.insert(name.clone(), profile);
+                    }
+                }
+                _ => return Err(error(format!("unknown key '{}'", key))),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The named profile, or an error listing the defined ones.
+    pub fn profile(&self, name: &str) -> Result<&Profile, String> {
+        self.profiles.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            if known.is_empty() {
+                format!("unknown profile '{}': no profiles are defined", name)
+            } else {
+                format!("unknown profile '{}' (defined: {})", name, known.join(", "))
+            }
+        })
+    }
+}
+
+fn bool_value(key: &str, value: &Value) -> Result<bool, String> {
+    value
+        .as_bool()
+        .ok_or_else(|| format!("'{}' must be true or false", key))
+}
+
+fn string_value(key: &str, value: &Value) -> Result<String, String> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| format!("'{}' must be a string", key))
+}
+
+fn count_value(key: &str, value: &Value) -> Result<usize, String> {
+    value
+        .as_integer()
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or_else(|| format!("'{}' must be a non-negative integer", key))
+}
+
+fn parse_names(value: &Value) -> Result<NameRules, String> {
+    let table = value
+        .as_table()
+        .ok_or_else(|| "'names' must be a table".to_string())?;
+    let mut names = NameRules::default();
+    for (key, value) in table {
+        let qualified = format!("names.{}", key);
+        match key.as_str() {
+            "chars" => names.chars = Some(string_value(&qualified, value)?),
+            "max_length" => names.max_length = Some(count_value(&qualified, value)?),
+            "reserved_prefixes" => names.reserved_prefixes = string_array(&qualified, value)?,
+            _ => return Err(format!("unknown key '{}'", qualified)),
+        }
+    }
+    Ok(names)
+}
+
+fn parse_lint(value: &Value) -> Result<LintSettings, String> {
+    let table = value
+        .as_table()
+        .ok_or_else(|| "'lint' must be a table".to_string())?;
+    let mut lint = LintSettings::default();
+    for (key, value) in table {
+        let qualified = format!("lint.{}", key);
+        match key.as_str() {
+            "name_pattern" => lint.name_pattern = Some(string_value(&qualified, value)?),
+            "max_lines" => lint.max_lines = Some(count_value(&qualified, value)?),
+            "missing_end" => lint.missing_end = Some(bool_value(&qualified, value)?),
+            "duplicates" => lint.duplicates = Some(bool_value(&qualified, value)?),
+            "file_extensions" => lint.file_extensions = Some(bool_value(&qualified, value)?),
+            "extensionless_files" => lint.extensionless_files = string_array(&qualified, value)?,
+            _ => return Err(format!("unknown key '{}'", qualified)),
+        }
+    }
+    Ok(lint)
+}
+
+/// An (open, close) delimiter pair written as the two delimiters separated
+/// by whitespace, e.g. `⟨⟨ ⟩⟩`.
+pub fn delimiter_pair(text: &str) -> Result<(String, String), String> {
+    match text.split_whitespace().collect::<Vec<_>>()[..] {
+        [open, close] => Ok((open.to_string(), close.to_string())),
+        _ => Err(format!(
+            "'{}' is not an open and a close delimiter separated by a space",
+            text
+        )),
+    }
+}
+
+fn string_array(key: &str, value: &Value) -> Result<Vec<String>, String> {
+    value
+        .as_array()
+        .ok_or_else(|| format!("'{}' must be an array of strings", key))?
+        .iter()
+        .map(|item| string_value(key, item))
+        .collect()
+}
+
+fn parse_profile(name: &str, value: &Value) -> Result<Profile, String> {
+    let table = value
+        .as_table()
+        .ok_or_else(|| format!("profile '{}' must be a table", name))?;
+    let mut profile = Profile::default();
+    for (key, value) in table {
+        let qualified = format!("profiles.{}.{}", name, key);
+        match key.as_str() {
+            "gen" => profile.gen = Some(PathBuf::from(string_value(&qualified, value)?)),
+            "files" => profile.files = string_array(&qualified, value)?,
+            "tags" => profile.tags = string_array(&qualified, value)?,
+            "defines" => {
+                let defines = value
+                    .as_table()
+                    .ok_or_else(|| format!("'{}' must be a table", qualified))?;
+                for (define, value) in defines {
+                    let text = match value {
+                        Value::String(s) => s.clone(),
+                        Value::Integer(i) => i.to_string(),
+                        Value::Boolean(b) => b.to_string(),
+                        _ => return Err(format!("'{}.{}' must be a scalar", qualified, define)),
+                    };
+                    profile.defines.insert(define.clone(), text);
+                }
+            }
+            _ => return Err(format!("unknown key '{}'", qualified)),
+        }
+    }
+    Ok(profile)
+}
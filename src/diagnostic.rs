@@ -0,0 +1,155 @@
+// src/diagnostic.rs
+//! Stable codes of errors and warnings. Every message names its code, e.g.
+//! `Warning[AZ0101]: ...`, so it can be looked up in the README and warnings
+//! can be suppressed by code.
+use std::fmt;
+
+/// The code of an error (AZ00xx) or warning (AZ01xx).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    UndefinedChunk,
+    Recursion,
+    Redefinition,
+    FileChunkRedefinition,
+    ExecNotAllowed,
+    ExecFailed,
+    InsertFailed,
+    PrivateChunk,
+    Parse,
+    Io,
+    Config,
+    WriteFailed,
+    ModifiedExternally,
+    UnsafePath,
+    OutOfDate,
+    WarningsDenied,
+    InvalidDelimiters,
+    TypesetFailed,
+    OutputCollision,
+    InvalidChunkName,
+    InvalidAttribute,
+    Unformatted,
+    NamespaceCollision,
+    UnusedChunk,
+    OrphanedOutput,
+    UntangleSkipped,
+    InvalidPattern,
+    NoMatchingChunks,
+    StaleOutput,
+    Deprecated,
+    Unmigrated,
+    UnconventionalName,
+    MissingChunkEnd,
+    DuplicateDefinition,
+    LongChunk,
+    FileWithoutExtension,
+    Unexported,
+}
+
+impl Code {
+    pub const ALL: &'static [Code] = &[
+        Code::UndefinedChunk,
+        Code::Recursion,
+        Code::Redefinition,
+        Code::FileChunkRedefinition,
+        Code::ExecNotAllowed,
+        Code::ExecFailed,
+        Code::InsertFailed,
+        Code::PrivateChunk,
+        Code::Parse,
+        Code::Io,
+        Code::Config,
+        Code::WriteFailed,
+        Code::ModifiedExternally,
+        Code::UnsafePath,
+        Code::OutOfDate,
+        Code::WarningsDenied,
+        Code::InvalidDelimiters,
+        Code::TypesetFailed,
+        Code::OutputCollision,
+        Code::InvalidChunkName,
+        Code::InvalidAttribute,
+        Code::Unformatted,
+        Code::NamespaceCollision,
+        Code::UnusedChunk,
+        Code::OrphanedOutput,
+        Code::UntangleSkipped,
+        Code::InvalidPattern,
+        Code::NoMatchingChunks,
+        Code::StaleOutput,
+        Code::Deprecated,
+        Code::Unmigrated,
+        Code::UnconventionalName,
+        Code::MissingChunkEnd,
+        Code::DuplicateDefinition,
+        Code::LongChunk,
+        Code::FileWithoutExtension,
+        Code::Unexported,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::UndefinedChunk => "AZ0001",
+            Code::Recursion => "AZ0002",
+            Code::Redefinition => "AZ0003",
+            Code::FileChunkRedefinition => "AZ0004",
+            Code::ExecNotAllowed => "AZ0005",
+            Code::ExecFailed => "AZ0006",
+            Code::InsertFailed => "AZ0007",
+            Code::PrivateChunk => "AZ0008",
+            Code::Parse => "AZ0009",
+            Code::Io => "AZ0010",
+            Code::Config => "AZ0011",
+            Code::WriteFailed => "AZ0012",
+            Code::ModifiedExternally => "AZ0013",
+            Code::UnsafePath => "AZ0014",
+            Code::OutOfDate => "AZ0015",
+            Code::WarningsDenied => "AZ0016",
+            Code::InvalidDelimiters => "AZ0017",
+            Code::TypesetFailed => "AZ0018",
+            Code::OutputCollision => "AZ0019",
+            Code::InvalidChunkName => "AZ0020",
+            Code::InvalidAttribute => "AZ0021",
+            Code::Unformatted => "AZ0022",
+            Code::NamespaceCollision => "AZ0023",
+            Code::UnusedChunk => "AZ0101",
+            Code::OrphanedOutput => "AZ0102",
+            Code::UntangleSkipped => "AZ0103",
+            Code::InvalidPattern => "AZ0104",
+            Code::NoMatchingChunks => "AZ0105",
+            Code::StaleOutput => "AZ0106",
+            Code::Deprecated => "AZ0107",
+            Code::Unmigrated => "AZ0108",
+            Code::UnconventionalName => "AZ0109",
+            Code::MissingChunkEnd => "AZ0110",
+            Code::DuplicateDefinition => "AZ0111",
+            Code::LongChunk => "AZ0112",
+            Code::FileWithoutExtension => "AZ0113",
+            Code::Unexported => "AZ0114",
+        }
+    }
+
+    /// The code spelled `text`, e.g. `AZ0101`; case does not matter.
+    pub fn parse(text: &str) -> Option<Code> {
+        Code::ALL
+            .iter()
+            .copied()
+            .find(|code| code.as_str().eq_ignore_ascii_case(text))
+    }
+
+    /// `message` as a warning tagged with this code.
+    pub fn warning(self, message: impl fmt::Display) -> String {
+        format!("Warning[{}]: {}", self, message)
+    }
+
+    /// `message` as an error tagged with this code.
+    pub fn error(self, message: impl fmt::Display) -> String {
+        format!("Error[{}]: {}", self, message)
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,48 @@
+// src/dialect.rs
+//! Input syntaxes understood by the chunk reader.
+use std::fmt;
+use std::str::FromStr;
+
+/// Selects how input documents declare chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// Azadi's own `<<name>>=` ... `@` syntax.
+    #[default]
+    Noweb,
+    /// Entangled/lmt Markdown fenced code blocks.
+    Entangled,
+    /// reStructuredText `code-block` directives with `:name:`/`:tangle:`.
+    Rst,
+    /// nuweb `@o`/`@d` scraps with `@{ ... @}` bodies.
+    Nuweb,
+    /// FunnelWeb `@O`/`@$` macros with `@{ ... @}` bodies.
+    Funnelweb,
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "noweb" => Ok(Dialect::Noweb),
+            "entangled" | "lmt" | "markdown" => Ok(Dialect::Entangled),
+            "rst" | "restructuredtext" => Ok(Dialect::Rst),
+            "nuweb" => Ok(Dialect::Nuweb),
+            "funnelweb" | "fw" => Ok(Dialect::Funnelweb),
+            other => Err(format!("unknown dialect '{}'", other)),
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Dialect::Noweb => "noweb",
+            Dialect::Entangled => "entangled",
+            Dialect::Rst => "rst",
+            Dialect::Nuweb => "nuweb",
+            Dialect::Funnelweb => "funnelweb",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,113 @@
+// src/export.rs
+//! Export to classic noweb: the chunks read from azadi documents written
+//! back as one plain noweb document, for noweave, notangle and other tools
+//! that expect that syntax. Prose outside `@doc` sections is not kept by the
+//! reader, so only those sections survive as documentation.
+use crate::noweb::ChunkLocation;
+use crate::Clip;
+use std::collections::HashMap;
+
+/// A body line of an exported definition.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NowebLine {
+    /// Text emitted as written, without its line break.
+    Text(String),
+    /// A reference, with its indentation relative to the body and the text
+    /// following it on the line.
+    Reference {
+        indent: String,
+        name: String,
+        trailing: String,
+    },
+}
+
+/// One part of an exported document, in reading order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NowebSection {
+    /// A chunk definition; file chunks are named by their paths, as noweb
+    /// roots are.
+    Chunk { name: String, lines: Vec<NowebLine> },
+    /// The lines of a `@doc` section.
+    Doc(Vec<String>),
+}
+
+/// The sections read into a clip, with what could not be carried over.
+#[derive(Debug, Clone)]
+pub struct NowebSections {
+    pub sections: Vec<NowebSection>,
+    /// Constructs noweb has no equivalent for, each written out as text.
+    pub unexported: Vec<(ChunkLocation, String)>,
+}
+
+/// An exported document and the constructs it does not carry over.
+#[derive(Debug, Default)]
+pub struct Exported {
+    pub text: String,
+    /// Where each construct written out as text was read, with what it is.
+    pub notes: Vec<(ChunkLocation, String)>,
+}
+
+/// Escape the code text `line` for noweb: `<<` becomes `@<<` when a `>>`
+/// follows, so nothing reads as a reference, and a leading `@` is doubled so
+/// the line cannot end the chunk.
+fn escape_code(line: &str) -> String {
+    let line = match line.find("<<") {
+        Some(start) if line[start..].contains(">>") => line.replace("<<", "@<<"),
+        _ => line.to_string(),
+    };
+    if line.starts_with('@') && !line.starts_with("@<<") {
+        format!("@{}", line)
+    } else {
+        line
+    }
+}
+
+/// The sections as one classic noweb document.
+pub fn format_noweb(sections: &[NowebSection]) -> String {
+    let mut out = String::new();
+    for section in sections {
+        match section {
+            NowebSection::Chunk { name, lines } => {
+                out.push_str(&format!("<<{}>>=\n", name));
+                for line in lines {
+                    match line {
+                        NowebLine::Text(text) => out.push_str(&escape_code(text)),
+                        NowebLine::Reference {
+                            indent,
+                            name,
+                            trailing,
+                        } => out.push_str(&format!(
+                            "{}<<{}>>{}",
+                            indent,
+                            name,
+                            escape_code(trailing)
+                        )),
+                    }
+                    out.push('\n');
+                }
+                out.push_str("@\n");
+            }
+            NowebSection::Doc(lines) => {
+                out.push_str("@\n");
+                for line in lines {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The chunks and `@doc` sections read into `clip` as a classic noweb
+/// document, with `defines` substituted as on expansion.
+pub fn export_noweb(clip: &Clip, defines: &HashMap<String, String>) -> Exported {
+    let NowebSections {
+        sections,
+        unexported,
+    } = clip.noweb_sections(defines);
+    Exported {
+        text: format_noweb(&sections),
+        notes: unexported,
+    }
+}
@@ -0,0 +1,86 @@
+// src/filter.rs
+//! Output filters: transformations applied to expanded file chunks before they
+//! are written, registered on a `Clip` with `add_filter` or `add_line_filter`.
+//!
+//! Filters run in registration order, after expansion and managed-region
+//! filling, so provenance recorded for an output describes the unfiltered lines.
+
+/// Transforms all lines of one output at once, e.g. to inject a header.
+pub trait OutputFilter {
+    /// `path` is the output path relative to gen/; lines keep their newlines.
+    fn filter(&self, path: &str, lines: Vec<String>) -> Vec<String>;
+
+    /// Like `filter`, also given the language the output's chunk declares,
+    /// for filters that dispatch on it; the default ignores it.
+    fn filter_with_language(
+        &self,
+        path: &str,
+        language: Option<&str>,
+        lines: Vec<String>,
+    ) -> Vec<String> {
+        let _ = language;
+        self.filter(path, lines)
+    }
+}
+
+/// Transforms or drops single lines, e.g. to strip debug statements.
+pub trait LineFilter {
+    /// Returns the replacement line, or None to drop it.
+    fn filter_line(&self, path: &str, line: &str) -> Option<String>;
+}
+
+impl<F> OutputFilter for F
+where
+    F: Fn(&str, Vec<String>) -> Vec<String>,
+{
+    fn filter(&self, path: &str, lines: Vec<String>) -> Vec<String> {
+        self(path, lines)
+    }
+}
+
+impl<F> LineFilter for F
+where
+    F: Fn(&str, &str) -> Option<String>,
+{
+    fn filter_line(&self, path: &str, line: &str) -> Option<String> {
+        self(path, line)
+    }
+}
+
+/// Adapts a `LineFilter` to the `OutputFilter` pipeline.
+pub(crate) struct PerLine<L>(pub L);
+
+impl<L: LineFilter> OutputFilter for PerLine<L> {
+    fn filter(&self, path: &str, lines: Vec<String>) -> Vec<String> {
+        lines
+            .iter()
+            .filter_map(|line| self.0.filter_line(path, line))
+            .collect()
+    }
+}
+
+/// Applies a filter only to the outputs of one language.
+pub(crate) struct ForLanguage<F> {
+    pub language: String,
+    pub filter: F,
+}
+
+impl<F: OutputFilter> OutputFilter for ForLanguage<F> {
+    fn filter(&self, _path: &str, lines: Vec<String>) -> Vec<String> {
+        lines
+    }
+
+    fn filter_with_language(
+        &self,
+        path: &str,
+        language: Option<&str>,
+        lines: Vec<String>,
+    ) -> Vec<String> {
+        match language {
+            Some(language) if language.eq_ignore_ascii_case(&self.language) => self
+                .filter
+                .filter_with_language(path, Some(language), lines),
+            _ => lines,
+        }
+    }
+}
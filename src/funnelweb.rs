@@ -0,0 +1,42 @@
+// src/funnelweb.rs
+//! FunnelWeb-style front-end.
+//!
+//! `@O@<file@>` declares a file chunk and `@$@<Name@>` a named chunk, either
+//! optionally followed by `@Z`/`@M` call counts and `==` or `+=`; the body
+//! runs from `@{` to `@}`, and a `@-` right after the `@{` drops the line
+//! break it ends. References, `@@` and names are read as in nuweb.
+//! Macro parameters are not supported: a header's `@(...@)` list is ignored,
+//! and calls with arguments are left as text.
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::markdown::normalize_name;
+use crate::noweb::ChunkStore;
+use crate::nuweb::read_scraps;
+
+lazy_static! {
+    static ref HEADER_RE: Regex = Regex::new(
+        r"^\s*@([$Oo])\s*@<\s*(.+?)\s*@>\s*(?:@\(.*?@\)\s*)?(?:@[ZzMm]\s*)*(?:\+=|==)?\s*(?:@\{(.*))?$"
+    )
+    .unwrap();
+}
+
+/// A FunnelWeb `@O`/`@$` header.
+pub(crate) fn funnelweb_header(line: &str) -> Option<(String, Option<String>)> {
+    let caps = HEADER_RE.captures(line)?;
+    let name = if &caps[1] == "$" {
+        normalize_name(&caps[2])
+    } else {
+        format!("@file {}", &caps[2])
+    };
+    let rest = caps.get(3).map(|rest| {
+        let rest = rest.as_str();
+        rest.strip_prefix("@-").unwrap_or(rest).to_string()
+    });
+    Some((name, rest))
+}
+
+/// Parse FunnelWeb text and store the chunks declared by its macros.
+pub fn read_funnelweb(store: &mut ChunkStore, text: &str, file_idx: usize) {
+    read_scraps(store, text, file_idx, funnelweb_header);
+}
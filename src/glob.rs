@@ -0,0 +1,50 @@
+// src/glob.rs
+//! Shell-style wildcard patterns (`*`, `?`, `[...]`) compiled to regexes.
+use regex::Regex;
+
+/// True if `pattern` contains wildcard characters.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Compile a glob into an anchored regex. `*` also matches `/`, since chunk
+/// names and output paths are matched as plain strings.
+pub fn glob_to_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '[' => {
+                let mut class = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        closed = true;
+                        break;
+                    }
+                    class.push(c);
+                }
+                if closed {
+                    let class = class
+                        .strip_prefix('!')
+                        .map_or(class.clone(), |rest| format!("^{}", rest));
+                    re.push('[');
+                    re.push_str(&class.replace('\\', "\\\\"));
+                    re.push(']');
+                } else {
+                    re.push_str(&regex::escape(&format!("[{}", class)));
+                }
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re)
+}
+
+/// Match `text` against a glob; invalid patterns match nothing.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    glob_to_regex(pattern).is_ok_and(|re| re.is_match(text))
+}
@@ -0,0 +1,106 @@
+// src/harness.rs
+//! The generated test harness: every chunk opened with `@test` becomes one
+//! test of a single output file, wrapped in the test syntax of the file's
+//! language, so examples kept in the documents are compiled and run without
+//! wiring a `@file` chunk for each of them.
+use std::path::Path;
+
+/// How one test is written in a language. `{name}` is the chunk name made an
+/// identifier, `{chunk}` the name as written, and the line holding `{body}`
+/// is replaced by a reference to the chunk, indented like the placeholder.
+struct Template {
+    extensions: &'static [&'static str],
+    test: &'static str,
+}
+
+const TEMPLATES: &[Template] = &[
+    Template {
+        extensions: &["rs"],
+        test: "#[test]\nfn {name}() {\n    {body}\n}\n",
+    },
+    Template {
+        extensions: &["py"],
+        test: "def test_{name}():\n    {body}\n",
+    },
+    Template {
+        extensions: &["js", "mjs", "ts"],
+        test: "test(\"{chunk}\", () => {\n    {body}\n});\n",
+    },
+];
+
+/// `chunk` as an identifier: every character but ASCII letters, digits and
+/// `_` becomes `_`, and a leading digit gets a `_` before it.
+pub fn test_name(chunk: &str) -> String {
+    let name: String = chunk
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// The output collecting the `@test` chunks, and the template of its language.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestHarness {
+    path: String,
+    test: &'static str,
+}
+
+impl TestHarness {
+    /// A harness written to `path`, relative to the generated files'
+    /// directory; an error if its extension names no supported language.
+    pub fn new(path: &str) -> Result<Self, String> {
+        let ext = Path::new(path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let template = TEMPLATES
+            .iter()
+            .find(|t| t.extensions.contains(&ext.as_str()))
+            .ok_or_else(|| {
+                let known: Vec<String> = TEMPLATES
+                    .iter()
+                    .flat_map(|t| t.extensions.iter().map(|e| format!(".{}", e)))
+                    .collect();
+                format!(
+                    "no test harness template for '{}' (known: {})",
+                    path,
+                    known.join(", ")
+                )
+            })?;
+        Ok(Self {
+            path: path.replace('\\', "/"),
+            test: template.test,
+        })
+    }
+
+    /// Where the harness is written, relative to the generated files' directory.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The body of the harness: one test per chunk of `tests`, separated by
+    /// blank lines, each referencing its chunk with `open` and `close`.
+    pub fn lines(&self, tests: &[String], open: &str, close: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (i, chunk) in tests.iter().enumerate() {
+            if i > 0 {
+                lines.push("\n".to_string());
+            }
+            let escaped = chunk.replace('\\', "\\\\").replace('"', "\\\"");
+            for line in self.test.lines() {
+                let line = match line.split_once("{body}") {
+                    Some((indent, _)) => format!("{}{}{}{}", indent, open, chunk, close),
+                    None => line
+                        .replace("{name}", &test_name(chunk))
+                        .replace("{chunk}", &escaped),
+                };
+                lines.push(format!("{}\n", line));
+            }
+        }
+        lines
+    }
+}
@@ -0,0 +1,178 @@
+// src/highlight.rs
+//! Syntax highlighting of woven chunk bodies with syntect. Lines are marked
+//! up as HTML spans classed by their syntect scopes, which the CSS of an
+//! embedded theme colours.
+use lazy_static::lazy_static;
+use syntect::highlighting::{Color, ThemeSet};
+use syntect::html::{css_for_theme_with_class_style, line_tokens_to_classed_spans, ClassStyle};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+
+use crate::weave::escape_html;
+
+lazy_static! {
+    static ref SYNTAXES: SyntaxSet = SyntaxSet::load_defaults_nonewlines();
+    static ref THEMES: ThemeSet = ThemeSet::load_defaults();
+    /// The `Language::name` of each syntax, by index.
+    static ref NAMES: Vec<String> = SYNTAXES.syntaxes().iter().map(short_name).collect();
+}
+
+/// The syntax name in lowercase when it is one word, else its main extension.
+fn short_name(syntax: &SyntaxReference) -> String {
+    let one_word = syntax
+        .name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '#');
+    match syntax.file_extensions.first() {
+        Some(extension) if !one_word => extension.to_ascii_lowercase(),
+        _ => syntax.name.to_ascii_lowercase(),
+    }
+}
+
+/// Prefix of the classes of highlighted spans.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Names accepted by `@lang(NAME)` that are neither a syntax name nor one of
+/// its extensions, and the extension they stand for.
+const ALIASES: &[(&str, &str)] = &[
+    ("shell", "sh"),
+    ("zsh", "sh"),
+    ("python3", "py"),
+    ("golang", "go"),
+    ("c++", "cpp"),
+    ("cxx", "cpp"),
+];
+
+/// A language syntect can highlight.
+#[derive(Debug, Clone, Copy)]
+pub struct Language {
+    /// Short lowercase name, used in `language-NAME` classes: the syntax
+    /// name when it is one word, e.g. `python`, else its main extension,
+    /// e.g. `sh`.
+    pub name: &'static str,
+    syntax: &'static SyntaxReference,
+}
+
+impl Language {
+    fn new(syntax: &'static SyntaxReference) -> Self {
+        let index = SYNTAXES
+            .syntaxes()
+            .iter()
+            .position(|s| std::ptr::eq(s, syntax))
+            .unwrap_or_default();
+        Self {
+            name: &NAMES[index],
+            syntax,
+        }
+    }
+
+    /// The language with this name, alias or file extension, ignoring case.
+    pub fn by_name(name: &str) -> Option<Language> {
+        let name = name.to_ascii_lowercase();
+        let token = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map_or(name.as_str(), |(_, extension)| extension);
+        SYNTAXES.find_syntax_by_token(token).map(Language::new)
+    }
+
+    /// The language of a file, judged by the extension of its path.
+    pub fn by_path(path: &str) -> Option<Language> {
+        let (_, ext) = path.trim().rsplit_once('.')?;
+        SYNTAXES.find_syntax_by_extension(ext).map(Language::new)
+    }
+}
+
+/// Embedded colour schemes for highlighted code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+impl Theme {
+    /// Style rules for the spans written by `Highlighter`, and the colours
+    /// of the code blocks holding them.
+    pub fn css(&self) -> String {
+        let theme = &THEMES.themes[match self {
+            Theme::Light => "InspiredGitHub",
+            Theme::Dark => "base16-ocean.dark",
+        }];
+        let hex = |c: Color| format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b);
+        let mut css = String::from("pre {");
+        if let Some(background) = theme.settings.background {
+            css.push_str(&format!(" background: {};", hex(background)));
+        }
+        if let Some(foreground) = theme.settings.foreground {
+            css.push_str(&format!(" color: {};", hex(foreground)));
+        }
+        css.push_str(" }\n");
+        if *self == Theme::Dark {
+            css.push_str("pre a.ref { color: #9cdcfe; }\n");
+        }
+        // The embedded themes are known to convert
+        css.push_str(&css_for_theme_with_class_style(theme, CLASS_STYLE).unwrap_or_default());
+        css
+    }
+}
+
+impl std::str::FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "light" => Ok(Theme::Light),
+            "dark" => Ok(Theme::Dark),
+            other => Err(format!("unknown theme '{}'", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for Theme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        })
+    }
+}
+
+/// Highlights the lines of one chunk body in turn, carrying the parse state,
+/// e.g. an open block comment, over line ends.
+pub struct Highlighter {
+    parse_state: ParseState,
+    scopes: ScopeStack,
+}
+
+impl Highlighter {
+    pub fn new(language: Language) -> Self {
+        Self {
+            parse_state: ParseState::new(language.syntax),
+            scopes: ScopeStack::new(),
+        }
+    }
+
+    /// Render one line as escaped HTML with highlighting spans. The spans
+    /// still open at the end of a line are closed there and reopened at the
+    /// start of the next one, so each line stands on its own.
+    pub fn line(&mut self, line: &str) -> String {
+        let Ok(ops) = self.parse_state.parse_line(line, &SYNTAXES) else {
+            return escape_html(line);
+        };
+        let mut out = String::with_capacity(line.len() * 2);
+        for scope in self.scopes.as_slice() {
+            let classes: Vec<String> = scope
+                .build_string()
+                .split('.')
+                .map(|atom| format!("hl-{}", atom))
+                .collect();
+            out.push_str(&format!("<span class=\"{}\">", classes.join(" ")));
+        }
+        match line_tokens_to_classed_spans(line, &ops, CLASS_STYLE, &mut self.scopes) {
+            Ok((html, _)) => out.push_str(&html),
+            Err(_) => return escape_html(line),
+        }
+        out.push_str(&"</span>".repeat(self.scopes.len()));
+        out
+    }
+}
@@ -0,0 +1,103 @@
+// src/init.rs
+//! Project scaffolding for `azadi-noweb init`: a configuration file, a
+//! starter document showing the chunk syntax, and the directories a tangle
+//! writes to. Existing files are never overwritten.
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::DEFAULT_CONFIG;
+
+/// The starter document's name.
+pub const STARTER_DOCUMENT: &str = "hello.nw";
+
+const CONFIG: &str = r#"# azadi-noweb configuration; every key is optional.
+
+# Fail when a chunk is defined again without @append or @replace.
+# strict_redefinitions = true
+
+# Pick each input's comment markers from its extension.
+# auto_markers = true
+
+# Profiles select an output tree with --profile, e.g. --profile release.
+# [profiles.release]
+# gen = "dist"
+# files = ["*.py"]
+"#;
+
+const DOCUMENT: &str = r#"A literate program mixes prose with chunks of code. Everything outside
+a chunk, like this paragraph, is documentation.
+
+A chunk opens with its name between << and >>, followed by =, and ends
+with @. Chunk lines may start with a comment marker such as # or //, so
+the document stays valid in your language. A @file chunk is written to
+the generated directory when you run
+
+    azadi-noweb tangle hello.nw
+
+# <<@file hello.py>>=
+# <<imports>>
+
+def main():
+    # <<greet>>
+
+
+if __name__ == "__main__":
+    main()
+# @
+
+A reference, such as <<greet>> above, is replaced by the chunk it names,
+indented like the reference.
+
+# <<greet>>=
+name = sys.argv[1] if len(sys.argv) > 1 else "world"
+print(f"Hello, {name}!")
+# @
+
+Chunks may be defined in any order, so each can come where it suits the
+explanation best: the imports come last here.
+
+# <<imports>>=
+import sys
+# @
+"#;
+
+/// What `scaffold` did with one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scaffolded {
+    Created(PathBuf),
+    /// The path existed already and was left alone.
+    Kept(PathBuf),
+}
+
+/// Create `azadi.toml`, the starter document and the `gen` and `priv_dir`
+/// directories under `dir`, keeping whatever already exists.
+pub fn scaffold(dir: &Path, gen: &Path, priv_dir: &Path) -> io::Result<Vec<Scaffolded>> {
+    fs::create_dir_all(dir)?;
+    let mut done = Vec::new();
+    for (name, content) in [(DEFAULT_CONFIG, CONFIG), (STARTER_DOCUMENT, DOCUMENT)] {
+        let path = dir.join(name);
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                file.write_all(content.as_bytes())?;
+                done.push(Scaffolded::Created(path));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => done.push(Scaffolded::Kept(path)),
+            Err(e) => return Err(e),
+        }
+    }
+    for sub in [gen, priv_dir] {
+        let path = dir.join(sub);
+        if path.is_dir() {
+            done.push(Scaffolded::Kept(path));
+        } else {
+            fs::create_dir_all(&path)?;
+            done.push(Scaffolded::Created(path));
+        }
+    }
+    Ok(done)
+}
@@ -0,0 +1,9 @@
+// src/json.rs
+//! String quoting for the JSON azadi prints. Structured inputs such as
+//! notebooks are read with `serde_json`.
+
+/// Render `s` as a JSON string literal.
+pub fn quote(s: &str) -> String {
+    // Serializing a string cannot fail
+    serde_json::to_string(s).unwrap_or_default()
+}
@@ -0,0 +1,273 @@
+// src/latex.rs
+//! Weaving to LaTeX, and typesetting the result as PDF with an external
+//! engine such as pdflatex. The document follows the HTML page: numbered
+//! chunk sections listing where their chunk is defined and used, references
+//! linking to definitions, and a chunk index at the end.
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use crate::noweb::LineKind;
+use crate::weave::{anchor, section_anchor, CrossReferences};
+use crate::{AzadiError, Clip};
+
+/// The engine run when none is named.
+pub const DEFAULT_ENGINE: &str = "pdflatex";
+
+/// Base name of the files written in the work directory.
+const JOB: &str = "weave";
+
+/// A typesetting engine that could not be run or reported errors.
+#[derive(Debug)]
+pub struct TypesetError {
+    pub engine: String,
+    pub message: String,
+}
+
+impl fmt::Display for TypesetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.engine, self.message)
+    }
+}
+
+impl std::error::Error for TypesetError {}
+
+/// Escape text for LaTeX prose.
+pub fn escape_latex(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '{' | '}' | '&' | '%' | '$' | '#' | '_' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '<' => out.push_str("\\textless{}"),
+            '>' => out.push_str("\\textgreater{}"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Escape text for an `alltt` environment, where only `\`, `{` and `}` are
+/// special.
+fn escape_code(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// "section 1" with the number linking to the section.
+fn link(section: usize) -> String {
+    format!("\\hyperlink{{{}}}{{{}}}", section_anchor(section), section)
+}
+
+/// Emit the pending prose lines as one paragraph.
+fn flush(paragraph: &mut Vec<&str>, body: &mut String) {
+    if !paragraph.is_empty() {
+        body.push_str(&format!("{}\n\n", escape_latex(&paragraph.join("\n"))));
+        paragraph.clear();
+    }
+}
+
+/// Render `documents` (file name, text) read with `clip`'s delimiters as one
+/// LaTeX document titled `title`.
+pub fn weave_latex(clip: &Clip, title: &str, documents: &[(String, String)]) -> String {
+    let (open, close) = clip.delimiters();
+    let show = |name: &str| format!("{}{}{}", open, name, close);
+    let xref = CrossReferences::collect(clip, documents);
+    let close_section = |section: usize, name: &str, body: &mut String| {
+        body.push_str(&format!(
+            "\\end{{alltt}}\n\\noindent{{\\footnotesize Section {}: {}.}}\\par\n\n",
+            section,
+            xref.summary_with(name, link)
+        ));
+    };
+
+    let mut body = String::new();
+    let mut section = 0;
+    for (file_name, text) in documents {
+        body.push_str(&format!("\\section*{{{}}}\n\n", escape_latex(file_name)));
+        let mut paragraph: Vec<&str> = Vec::new();
+        let mut verbatim = false;
+        let mut current: Option<String> = None;
+        // Inside a @doc section, whose lines are prose
+        let mut in_doc = false;
+
+        for line in text.lines() {
+            let kind = clip.classify(file_name, line);
+            if in_doc {
+                match kind {
+                    LineKind::Close => {
+                        flush(&mut paragraph, &mut body);
+                        in_doc = false;
+                    }
+                    _ if line.trim().is_empty() => flush(&mut paragraph, &mut body),
+                    _ => paragraph.push(line),
+                }
+                continue;
+            }
+            if let Some(name) = &current {
+                match kind {
+                    LineKind::Close => {
+                        close_section(section, name, &mut body);
+                        current = None;
+                    }
+                    LineKind::Reference { name } if !verbatim => {
+                        // Keep what surrounds the reference, e.g. a comment leader
+                        let reference = show(&name);
+                        let (before, after) = line.split_once(&reference).unwrap_or((line, ""));
+                        body.push_str(&format!(
+                            "{}\\hyperlink{{{}}}{{{}}}{}\n",
+                            escape_code(before),
+                            anchor(&name),
+                            escape_code(&reference),
+                            escape_code(after)
+                        ));
+                    }
+                    _ => {
+                        let line = if verbatim {
+                            Cow::Borrowed(line)
+                        } else {
+                            clip.unescape(line)
+                        };
+                        body.push_str(&escape_code(&line));
+                        body.push('\n');
+                    }
+                }
+                continue;
+            }
+
+            match kind {
+                LineKind::Open {
+                    name,
+                    verbatim: is_verbatim,
+                    ..
+                } => {
+                    flush(&mut paragraph, &mut body);
+                    section += 1;
+                    body.push_str(&format!("\\hypertarget{{{}}}{{}}", section_anchor(section)));
+                    if xref.defined[&name].first() == Some(&section) {
+                        body.push_str(&format!(
+                            "\\hypertarget{{{}}}{{}}\\noindent\\texttt{{{}=}}\n",
+                            anchor(&name),
+                            escape_latex(&show(&name))
+                        ));
+                    } else {
+                        body.push_str(&format!(
+                            "\\noindent\\hyperlink{{{}}}{{\\texttt{{{}}}}}\\texttt{{+=}}\n",
+                            anchor(&name),
+                            escape_latex(&show(&name))
+                        ));
+                    }
+                    body.push_str("\\begin{alltt}\n");
+                    current = Some(name);
+                    verbatim = is_verbatim;
+                }
+                LineKind::Doc { .. } => {
+                    flush(&mut paragraph, &mut body);
+                    in_doc = true;
+                }
+                _ if line.trim().is_empty() => flush(&mut paragraph, &mut body),
+                _ => paragraph.push(line),
+            }
+        }
+        flush(&mut paragraph, &mut body);
+        if let Some(name) = &current {
+            close_section(section, name, &mut body);
+        }
+    }
+
+    let mut names: Vec<&String> = xref.defined.keys().collect();
+    names.sort();
+    if !names.is_empty() {
+        body.push_str("\\section*{Chunk index}\n\n\\begin{itemize}\n");
+        for name in names {
+            body.push_str(&format!(
+                "\\item \\hyperlink{{{}}}{{\\texttt{{{}}}}}: {}\n",
+                anchor(name),
+                escape_latex(&show(name)),
+                xref.summary_with(name, link)
+            ));
+        }
+        body.push_str("\\end{itemize}\n");
+    }
+
+    format!(
+        "\\documentclass{{article}}\n\\usepackage[T1]{{fontenc}}\n\\usepackage[utf8]{{inputenc}}\n\\usepackage{{alltt}}\n\\usepackage[hidelinks]{{hyperref}}\n\\title{{{}}}\n\\date{{}}\n\\begin{{document}}\n\\maketitle\n\n{}\\end{{document}}\n",
+        escape_latex(title),
+        body
+    )
+}
+
+/// The errors of a failed run: those in the engine's log, else the end of
+/// what it printed.
+fn failure_message(log: &str, output: &Output) -> String {
+    let mut errors: Vec<&str> = Vec::new();
+    let mut lines = log.lines();
+    while let Some(line) = lines.next() {
+        if line.starts_with('!') {
+            errors.push(line);
+            // The input line the error occurred on follows as "l.<n> ..."
+            if let Some(context) = lines.by_ref().find(|l| l.starts_with("l.")) {
+                errors.push(context);
+            }
+        }
+    }
+    if errors.is_empty() {
+        let printed = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let tail: Vec<&str> = printed.lines().filter(|l| !l.trim().is_empty()).collect();
+        let start = tail.len().saturating_sub(5);
+        return format!("failed ({}): {}", output.status, tail[start..].join("\n"));
+    }
+    format!("failed ({}): {}", output.status, errors.join("\n"))
+}
+
+/// Typeset `latex` with `engine` in `work_dir`, returning the PDF.
+pub fn typeset_pdf(latex: &str, engine: &str, work_dir: &Path) -> Result<Vec<u8>, AzadiError> {
+    let error = |message: String| {
+        AzadiError::Typeset(TypesetError {
+            engine: engine.to_string(),
+            message,
+        })
+    };
+    fs::create_dir_all(work_dir)?;
+    fs::write(work_dir.join(format!("{}.tex", JOB)), latex)?;
+    let pdf = work_dir.join(format!("{}.pdf", JOB));
+    if pdf.exists() {
+        fs::remove_file(&pdf)?;
+    }
+
+    let output = Command::new(engine)
+        .current_dir(work_dir)
+        .args([
+            "-interaction=nonstopmode",
+            "-halt-on-error",
+            &format!("{}.tex", JOB),
+        ])
+        .output()
+        .map_err(|e| error(format!("cannot run it: {}", e)))?;
+    if !output.status.success() {
+        let log = fs::read_to_string(work_dir.join(format!("{}.log", JOB))).unwrap_or_default();
+        return Err(error(failure_message(&log, &output)));
+    }
+    fs::read(&pdf).map_err(|_| error("finished without writing a PDF".to_string()))
+}
@@ -0,0 +1,147 @@
+// src/layout.rs
+//! Layout normalization for `azadi-noweb fmt`. Chunk openings get single
+//! spaces between their parts, every chunk ends with an end line carrying
+//! the comment marker of its opening, and exactly one blank line separates
+//! chunks from each other and from prose. Chunk bodies are never changed,
+//! and a document keeps its line ending and its final newline, or lack of
+//! one.
+use crate::noweb::LineKind;
+use crate::Clip;
+
+/// A run of prose, or a chunk with its opening and ending lines.
+enum Block {
+    Prose(Vec<String>),
+    Chunk {
+        /// The chunk's name, e.g. `@file a.py` or `@doc intro`, for sorting.
+        key: String,
+        lines: Vec<String>,
+    },
+}
+
+/// Split `text` into prose and chunks as the reader does: a `@verbatim`
+/// body ends only at its end line, and other bodies also at the next opening
+/// or the end of the text, where the missing end line is added.
+fn blocks(clip: &Clip, file_name: &str, text: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut prose = Vec::new();
+    // The open chunk's lines, the end line matching its opening, and whether
+    // it is verbatim
+    let mut chunk: Option<(String, Vec<String>, String, bool)> = None;
+    for line in text.lines() {
+        let kind = match clip.classify(file_name, line) {
+            LineKind::Close => LineKind::Close,
+            _ if matches!(chunk, Some((_, _, _, true))) => LineKind::Text,
+            kind => kind,
+        };
+        let opening = match &kind {
+            LineKind::Open { name, verbatim, .. } => Some((name.clone(), *verbatim)),
+            LineKind::Doc { name } => Some((format!("@doc {}", name), false)),
+            _ => None,
+        };
+        let formatted = opening
+            .as_ref()
+            .and_then(|_| clip.formatted_opening(file_name, line));
+        if let (Some((key, verbatim)), Some((opening, end))) = (opening, formatted) {
+            if let Some((key, mut lines, end, _)) = chunk.take() {
+                lines.push(end);
+                blocks.push(Block::Chunk { key, lines });
+            }
+            if !prose.is_empty() {
+                blocks.push(Block::Prose(std::mem::take(&mut prose)));
+            }
+            chunk = Some((key, vec![opening], end, verbatim));
+            continue;
+        }
+        match chunk.take() {
+            Some((key, mut lines, end, _)) if kind == LineKind::Close => {
+                lines.push(end);
+                blocks.push(Block::Chunk { key, lines });
+            }
+            Some((key, mut lines, end, verbatim)) => {
+                lines.push(line.to_string());
+                chunk = Some((key, lines, end, verbatim));
+            }
+            None => prose.push(line.to_string()),
+        }
+    }
+    if let Some((key, mut lines, end, _)) = chunk {
+        lines.push(end);
+        blocks.push(Block::Chunk { key, lines });
+    }
+    if !prose.is_empty() {
+        blocks.push(Block::Prose(prose));
+    }
+    blocks
+}
+
+/// `lines` without leading and trailing blank lines, and with every run of
+/// blank lines inside shortened to one.
+fn tidy_prose(lines: Vec<String>) -> Vec<String> {
+    let mut tidy: Vec<String> = Vec::new();
+    for line in lines {
+        let blank = line.trim().is_empty();
+        if blank && tidy.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        tidy.push(if blank { String::new() } else { line });
+    }
+    if tidy.last().is_some_and(|last| last.is_empty()) {
+        tidy.pop();
+    }
+    tidy
+}
+
+/// Order the chunks by name, each taking along the prose that follows it;
+/// the prose before the first chunk stays first. The sort is stable, so the
+/// definitions of one chunk keep their order and the output is unchanged.
+fn sort_blocks(blocks: Vec<Block>) -> Vec<Block> {
+    let mut preamble = Vec::new();
+    let mut groups: Vec<(String, Vec<Block>)> = Vec::new();
+    for block in blocks {
+        match (&block, groups.last_mut()) {
+            (Block::Chunk { key, .. }, _) => groups.push((key.clone(), vec![block])),
+            (Block::Prose(_), Some((_, group))) => group.push(block),
+            (Block::Prose(_), None) => preamble.push(block),
+        }
+    }
+    groups.sort_by(|a, b| a.0.cmp(&b.0));
+    preamble
+        .into_iter()
+        .chain(groups.into_iter().flat_map(|(_, group)| group))
+        .collect()
+}
+
+/// Document `file_name`, read with `clip`'s syntax, laid out consistently;
+/// with `sort`, its chunks are also ordered by name.
+pub fn format_document(clip: &Clip, file_name: &str, text: &str, sort: bool) -> String {
+    // The ending of the first line is taken for the whole document
+    let newline = match text.find('\n') {
+        Some(end) if text[..end].ends_with('\r') => "\r\n",
+        _ => "\n",
+    };
+    let mut blocks = blocks(clip, file_name, text);
+    if sort {
+        blocks = sort_blocks(blocks);
+    }
+    let mut out = String::new();
+    for block in blocks {
+        let lines = match block {
+            Block::Prose(lines) => tidy_prose(lines),
+            Block::Chunk { lines, .. } => lines,
+        };
+        if lines.is_empty() {
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(newline);
+        }
+        for line in lines {
+            out.push_str(&line);
+            out.push_str(newline);
+        }
+    }
+    if !text.ends_with('\n') && out.ends_with(newline) {
+        out.truncate(out.len() - newline.len());
+    }
+    out
+}
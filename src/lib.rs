@@ -1,11 +1,44 @@
+pub mod attributes;
+pub mod build;
+pub mod completions;
+pub mod config;
+pub mod diagnostic;
+pub mod dialect;
+pub mod export;
+pub mod filter;
+pub mod funnelweb;
+pub mod glob;
+pub mod harness;
+pub mod highlight;
+pub mod init;
+pub mod json;
+pub mod latex;
+pub mod layout;
+pub mod lint;
+pub mod log;
+pub mod managed;
+pub mod markdown;
+pub mod markers;
+pub mod migrate;
+pub mod names;
+pub mod notebook;
 pub mod noweb;
+pub mod nuweb;
+pub mod report;
+pub mod rst;
 pub mod safe_writer;
+pub mod serve;
+pub mod stats;
+pub mod untangle;
+pub mod weave;
 
 #[cfg(test)]
 mod tests;
 
 pub use noweb::ChunkError;
 
+use config::ConfigError;
+use latex::TypesetError;
 use safe_writer::SafeWriterError;
 use std::fmt;
 
@@ -13,6 +46,11 @@ use std::fmt;
 pub enum AzadiError {
     Chunk(ChunkError),
     SafeWriter(SafeWriterError),
+    Config(ConfigError),
+    Typeset(TypesetError),
+    /// The outputs that failed while tangling went on with the others, each
+    /// with its path relative to gen/.
+    Outputs(Vec<(String, AzadiError)>),
 }
 
 impl fmt::Display for AzadiError {
@@ -20,6 +58,31 @@ impl fmt::Display for AzadiError {
         match self {
             AzadiError::Chunk(e) => write!(f, "Chunk error: {}", e),
             AzadiError::SafeWriter(e) => write!(f, "Safe writer error: {}", e),
+            AzadiError::Config(e) => write!(f, "Config error: {}", e),
+            AzadiError::Typeset(e) => write!(f, "Typeset error: {}", e),
+            AzadiError::Outputs(failures) => {
+                write!(f, "{} output(s) could not be generated:", failures.len())?;
+                for (path, e) in failures {
+                    write!(f, "\n  {}: {}", path, e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl AzadiError {
+    /// The diagnostic code of the error.
+    pub fn code(&self) -> diagnostic::Code {
+        match self {
+            AzadiError::Chunk(e) => e.code(),
+            AzadiError::SafeWriter(e) => e.code(),
+            AzadiError::Config(_) => diagnostic::Code::Config,
+            AzadiError::Typeset(_) => diagnostic::Code::TypesetFailed,
+            // The code of the first failure stands for all of them
+            AzadiError::Outputs(failures) => failures
+                .first()
+                .map_or(diagnostic::Code::WriteFailed, |(_, e)| e.code()),
         }
     }
 }
@@ -38,11 +101,24 @@ impl From<SafeWriterError> for AzadiError {
     }
 }
 
+impl From<ConfigError> for AzadiError {
+    fn from(err: ConfigError) -> Self {
+        AzadiError::Config(err)
+    }
+}
+
+impl From<TypesetError> for AzadiError {
+    fn from(err: TypesetError) -> Self {
+        AzadiError::Typeset(err)
+    }
+}
+
 impl From<std::io::Error> for AzadiError {
     fn from(err: std::io::Error) -> Self {
         AzadiError::SafeWriter(SafeWriterError::IoError(err))
     }
 }
 
-pub use crate::noweb::Clip;
-pub use crate::safe_writer::SafeFileWriter;
+pub use crate::dialect::Dialect;
+pub use crate::noweb::{Clip, ClipBuilder, ExpandOptions};
+pub use crate::safe_writer::{SafeFileWriter, SymlinkPolicy, WriteOutcome, WriteResult};
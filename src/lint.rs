@@ -0,0 +1,238 @@
+// src/lint.rs
+//! Style rules for literate sources, checked by `azadi-noweb lint`. Each
+//! rule reports warnings with its own code, so single rules can be silenced
+//! with `--allow` and all of them made fatal with `--deny-warnings`.
+use std::fmt;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::diagnostic::Code;
+use crate::glob::glob_match;
+use crate::noweb::LineKind;
+use crate::Clip;
+
+/// The most body lines a definition may have by default.
+pub const DEFAULT_MAX_LINES: usize = 200;
+
+/// Which rules `lint` checks; by default all but the naming pattern.
+#[derive(Debug, Clone)]
+pub struct LintRules {
+    name_pattern: Option<Regex>,
+    max_lines: Option<usize>,
+    missing_end: bool,
+    duplicates: bool,
+    file_extensions: bool,
+    extensionless_files: Vec<String>,
+}
+
+impl Default for LintRules {
+    fn default() -> Self {
+        Self {
+            name_pattern: None,
+            max_lines: Some(DEFAULT_MAX_LINES),
+            missing_end: true,
+            duplicates: true,
+            file_extensions: true,
+            extensionless_files: Vec::new(),
+        }
+    }
+}
+
+impl LintRules {
+    /// Require every chunk name but file chunks' to match `pattern`.
+    pub fn name_pattern(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.name_pattern = Some(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Allow definitions of at most `max` body lines; 0 allows any length.
+    pub fn max_lines(mut self, max: usize) -> Self {
+        self.max_lines = Some(max).filter(|&max| max > 0);
+        self
+    }
+
+    /// Check that every chunk ends with an end line.
+    pub fn missing_end(mut self, check: bool) -> Self {
+        self.missing_end = check;
+        self
+    }
+
+    /// Check for definitions repeating an earlier one of the same chunk.
+    pub fn duplicates(mut self, check: bool) -> Self {
+        self.duplicates = check;
+        self
+    }
+
+    /// Check that file chunk paths have an extension.
+    pub fn file_extensions(mut self, check: bool) -> Self {
+        self.file_extensions = check;
+        self
+    }
+
+    /// Allow the file chunk paths matching the glob `pattern`, e.g.
+    /// `Makefile`, without an extension.
+    pub fn allow_extensionless(mut self, pattern: &str) -> Self {
+        self.extensionless_files.push(pattern.to_string());
+        self
+    }
+}
+
+/// A chunk breaking a rule, displayed as `file line N: message`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// The code of the rule broken.
+    pub code: Code,
+    pub file_name: String,
+    /// 0-based line of the chunk's opening.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} line {}: {}",
+            self.file_name,
+            self.line + 1,
+            self.message
+        )
+    }
+}
+
+/// The chunks of `documents`, given as (file name, text) pairs, that break
+/// `rules`, in document order. The chunk rules use what `clip` read; only
+/// the end-line rule reads the texts, which may be empty to skip it.
+pub fn lint(clip: &Clip, documents: &[(String, String)], rules: &LintRules) -> Vec<Finding> {
+    let order = |file: &str| {
+        documents
+            .iter()
+            .position(|(name, _)| name == file)
+            .unwrap_or(usize::MAX)
+    };
+    let mut findings: Vec<((usize, usize), Finding)> = Vec::new();
+    let mut warn = |code: Code, file: &str, line: usize, message: String| {
+        findings.push((
+            (order(file), line),
+            Finding {
+                code,
+                file_name: file.to_string(),
+                line,
+                message,
+            },
+        ));
+    };
+
+    for name in clip.chunk_names() {
+        let definitions = clip.definitions_of(&name);
+        let Some(first) = definitions.first() else {
+            continue;
+        };
+        match name.strip_prefix("@file ") {
+            Some(path) => {
+                let allowed = rules
+                    .extensionless_files
+                    .iter()
+                    .any(|pattern| glob_match(pattern, path));
+                if rules.file_extensions && Path::new(path).extension().is_none() && !allowed {
+                    warn(
+                        Code::FileWithoutExtension,
+                        &first.location.file_name,
+                        first.location.line,
+                        format!("file chunk '{}' has no extension", path),
+                    );
+                }
+            }
+            None => {
+                // The pattern is matched against the name as written
+                let written = clip.unqualified(&name, first.location.file_idx);
+                if let Some(re) = rules
+                    .name_pattern
+                    .as_ref()
+                    .filter(|re| !re.is_match(written))
+                {
+                    warn(
+                        Code::UnconventionalName,
+                        &first.location.file_name,
+                        first.location.line,
+                        format!("chunk name '{}' does not match '{}'", name, re.as_str()),
+                    );
+                }
+            }
+        }
+        for (i, def) in definitions.iter().enumerate() {
+            if let Some(max) = rules.max_lines.filter(|&max| def.content.len() > max) {
+                warn(
+                    Code::LongChunk,
+                    &def.location.file_name,
+                    def.location.line,
+                    format!(
+                        "definition of '{}' has {} lines, more than {}",
+                        name,
+                        def.content.len(),
+                        max
+                    ),
+                );
+            }
+            let earlier = definitions[..i]
+                .iter()
+                .find(|earlier| !def.content.is_empty() && earlier.content == def.content);
+            if let Some(earlier) = earlier.filter(|_| rules.duplicates) {
+                warn(
+                    Code::DuplicateDefinition,
+                    &def.location.file_name,
+                    def.location.line,
+                    format!(
+                        "definition of '{}' repeats the one at {} line {}",
+                        name,
+                        earlier.location.file_name,
+                        earlier.location.line + 1
+                    ),
+                );
+            }
+        }
+    }
+
+    if rules.missing_end {
+        for (file, text) in documents {
+            // The open chunk's opening line, name and whether it is verbatim
+            let mut open: Option<(usize, String, bool)> = None;
+            for (i, line) in text.lines().enumerate() {
+                let (name, verbatim) = match clip.classify(file, line) {
+                    LineKind::Close => {
+                        open = None;
+                        continue;
+                    }
+                    _ if matches!(open, Some((_, _, true))) => continue,
+                    LineKind::Open { name, verbatim, .. } => (name, verbatim),
+                    LineKind::Doc { name } => (format!("@doc {}", name), false),
+                    _ => continue,
+                };
+                if let Some((line, unclosed, _)) = open.take() {
+                    warn(
+                        Code::MissingChunkEnd,
+                        file,
+                        line,
+                        format!("chunk '{}' has no end line before the next chunk", unclosed),
+                    );
+                }
+                open = Some((i, name, verbatim));
+            }
+            if let Some((line, name, _)) = open {
+                warn(
+                    Code::MissingChunkEnd,
+                    file,
+                    line,
+                    format!(
+                        "chunk '{}' has no end line before the end of the file",
+                        name
+                    ),
+                );
+            }
+        }
+    }
+
+    findings.sort_by_key(|(position, _)| *position);
+    findings.into_iter().map(|(_, finding)| finding).collect()
+}
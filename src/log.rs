@@ -0,0 +1,94 @@
+// src/log.rs
+//! Leveled diagnostics through `tracing`. User-facing messages arrive through
+//! the console `Reporter`; library code traces its decisions with the
+//! `tracing::debug!` and `tracing::trace!` macros, whose arguments are only
+//! formatted when a subscriber wants them.
+//!
+//! The command-line tool installs a subscriber with `init` and maps `-q`,
+//! `-v` and `-vv` onto its filter. Messages go to stderr, so that stdout
+//! carries only requested output such as extracted chunks. Library users may
+//! install their own subscriber, optionally with `layer` for the same format;
+//! without one, the console `Reporter` prints to stderr itself.
+use std::fmt;
+use std::io;
+
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::{self, FormatEvent, FormatFields};
+use tracing_subscriber::fmt::{FmtContext, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Layer, Registry};
+
+/// The filter selected by `-q` and a count of `-v`: quiet shows only warnings
+/// and errors, each `-v` adds one level past informational messages.
+pub fn filter_for(quiet: bool, verbose: u8) -> LevelFilter {
+    if quiet {
+        return LevelFilter::WARN;
+    }
+    match verbose {
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Changes the filter of the subscriber installed by `init`.
+pub struct FilterHandle(reload::Handle<LevelFilter, Registry>);
+
+impl FilterHandle {
+    pub fn set(&self, filter: LevelFilter) {
+        // Only fails once the subscriber is gone, when nothing is logged anyway
+        let _ = self.0.reload(filter);
+    }
+}
+
+/// Install the global subscriber of the command-line tool: informational
+/// messages and above go to stderr until the filter is changed through the
+/// returned handle. Does nothing if a subscriber is already installed.
+pub fn init() -> FilterHandle {
+    let (filter, handle) = reload::Layer::new(LevelFilter::INFO);
+    let _ = tracing_subscriber::registry()
+        .with(filter)
+        .with(layer(io::stderr))
+        .try_init();
+    FilterHandle(handle)
+}
+
+/// A layer writing events to `writer` the way the command-line tool prints
+/// them: debug and trace messages prefixed with their level, others bare.
+pub fn layer<S, W>(writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt::layer()
+        .event_format(Plain)
+        .with_writer(writer)
+}
+
+/// One line per event: the level for debug and trace, then the message and
+/// any other fields.
+struct Plain;
+
+impl<S, N> FormatEvent<S, N> for Plain
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        match *event.metadata().level() {
+            Level::DEBUG => write!(writer, "debug: ")?,
+            Level::TRACE => write!(writer, "trace: ")?,
+            _ => {}
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
@@ -1,104 +1,1800 @@
-use azadi_noweb::{AzadiError, Clip, SafeFileWriter};
-use clap::Parser;
-use std::fs::File;
+use azadi_noweb::completions::{self, Shell};
+use azadi_noweb::config::{delimiter_pair, Config, ConfigError, Profile, DEFAULT_CONFIG};
+use azadi_noweb::diagnostic::Code;
+use azadi_noweb::export::export_noweb;
+use azadi_noweb::glob::{glob_to_regex, is_glob};
+use azadi_noweb::harness::TestHarness;
+use azadi_noweb::highlight::Theme;
+use azadi_noweb::init::{scaffold, Scaffolded, STARTER_DOCUMENT};
+use azadi_noweb::json;
+use azadi_noweb::latex;
+use azadi_noweb::layout::format_document;
+use azadi_noweb::lint::{lint, LintRules};
+use azadi_noweb::log;
+use azadi_noweb::markers::{extension_markers, marker_override, MarkerRules};
+use azadi_noweb::migrate::{migrate_funnelweb, migrate_noweb, migrate_nuweb};
+use azadi_noweb::names::NamePolicy;
+use azadi_noweb::notebook::is_notebook;
+use azadi_noweb::noweb::TrailingText;
+use azadi_noweb::report::{
+    ConsoleReporter, CountingReporter, JsonReporter, Reporter, SuppressingReporter,
+};
+use azadi_noweb::safe_writer::{
+    read_manifest_in, verify_outputs_in, OutputChange, SafeWriterConfig,
+};
+use azadi_noweb::serve::serve;
+use azadi_noweb::stats::Stats;
+use azadi_noweb::untangle::{apply_edits, format_patch, untangle};
+use azadi_noweb::weave::{weave_html_with_options, weave_site, WeaveOptions, WeaveTemplate};
+use azadi_noweb::{AzadiError, Clip, Dialect, ExpandOptions, SafeFileWriter};
+use clap::{CommandFactory, Parser, Subcommand};
+use regex::Regex;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-#[derive(Parser)]
-#[command(
-    name = "azadi",
-    about = "Expand chunks like noweb - A literate programming tool",
-    version
-)]
-struct Args {
-    /// Output file for --chunks [default: stdout]
-    #[arg(long)]
-    output: Option<PathBuf>,
+/// Exit status of a run that succeeded with warnings under --deny-warnings.
+const EXIT_WARNINGS: i32 = 3;
 
-    /// Names of chunks to extract (comma separated)
+fn parse_code(text: &str) -> Result<Code, String> {
+    Code::parse(text).ok_or_else(|| format!("unknown diagnostic code '{}'", text))
+}
+
+/// Format of the messages printed on stderr.
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum MessageFormat {
+    Text,
+    Json,
+}
+
+/// Literate programming tools whose documents `migrate` rewrites.
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum MigrateFrom {
+    Noweb,
+    Nuweb,
+    Funnelweb,
+}
+
+/// Options shared by every command that reads documents.
+#[derive(clap::Args)]
+struct Common {
+    /// Emit a line directive in generated files wherever the output stops
+    /// following the source, e.g. '#line {line} "{file}"'
     #[arg(long)]
-    chunks: Option<String>,
+    line_directive: Option<String>,
+
+    /// Join the next line onto a chunk body line that ends with this marker, e.g. '\'
+    #[arg(long, value_name = "MARKER")]
+    continuation: Option<String>,
+
+    /// Strip this comment prefix from chunk body lines, for chunks without @uncomment
+    #[arg(long, value_name = "PREFIX")]
+    uncomment: Option<String>,
+
+    /// Define a constant substituted for @{NAME}, overriding @define in the sources
+    #[arg(short = 'D', long = "define", value_name = "NAME=VALUE", value_parser = parse_define)]
+    defines: Vec<(String, String)>,
 
     /// Private work directory
-    #[arg(long, default_value = "_azadi_work")]
+    #[arg(long, env = "AZADI_PRIV_DIR", default_value = "_azadi_work")]
     priv_dir: PathBuf,
 
-    /// Base directory of generated files
-    #[arg(long, default_value = "gen")]
-    gen: PathBuf,
+    /// Keep backups of the generated files here [default: __old__ in the
+    /// private directory]
+    #[arg(long, env = "AZADI_BACKUP_DIR", value_name = "DIR")]
+    backup_dir: Option<PathBuf>,
+
+    /// Do not back up generated files matching this glob, relative to the
+    /// generated files' directory (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    no_backup: Vec<String>,
+
+    /// Keep a copy of the files each run writes under
+    /// <priv-dir>/__snapshots__/<time>/, retaining the last N runs [default: 0, none]
+    #[arg(long, value_name = "N")]
+    snapshots: Option<usize>,
+
+    /// Base directory of generated files [default: gen, or the profile's gen]
+    #[arg(long, env = "AZADI_GEN")]
+    gen: Option<PathBuf>,
+
+    /// Configuration file [default: azadi.toml when present]
+    #[arg(long, env = "AZADI_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Profile from the configuration file: its gen directory, defines and file selection
+    #[arg(long, env = "AZADI_PROFILE")]
+    profile: Option<String>,
 
     /// Delimiter used to open a chunk
-    #[arg(long, default_value = "<<")]
+    #[arg(long, env = "AZADI_OPEN_DELIM", default_value = "<<")]
     open_delim: String,
 
     /// Delimiter used to close a chunk definition
-    #[arg(long, default_value = ">>")]
+    #[arg(long, env = "AZADI_CLOSE_DELIM", default_value = ">>")]
     close_delim: String,
 
+    /// Another pair of delimiters read as well, the open and close delimiter
+    /// separated by a space, e.g. '⟨⟨ ⟩⟩'; may be repeated
+    #[arg(long, value_name = "OPEN CLOSE", value_parser = delimiter_pair)]
+    extra_delims: Vec<(String, String)>,
+
     /// Delimiter for chunk-end lines
-    #[arg(long, default_value = "@")]
+    #[arg(long, env = "AZADI_CHUNK_END", default_value = "@")]
     chunk_end: String,
 
     /// Comment markers (comma separated)
-    #[arg(long, default_value = "#,//")]
+    #[arg(long, env = "AZADI_COMMENT_MARKERS", default_value = "#,//")]
     comment_markers: String,
 
+    /// Pick each input's comment markers from its extension (# for .py, //
+    /// for .rs, % for .tex, ...); other inputs use --comment-markers
+    #[arg(long)]
+    auto_markers: bool,
+
+    /// Comment markers of the inputs matching a glob, e.g. 'docs/*.tex=%' or
+    /// 'src/*.nw=#,//'; may be repeated, and later ones win
+    #[arg(long, value_name = "GLOB=MARKERS", value_parser = marker_override)]
+    markers_for: Vec<(String, Vec<String>)>,
+
+    /// Input syntax: noweb, entangled (Entangled/lmt Markdown), rst, nuweb or
+    /// funnelweb
+    #[arg(long, env = "AZADI_DIALECT", default_value = "noweb")]
+    dialect: Dialect,
+
+    /// Fail when a regular chunk is defined again without @append or @replace,
+    /// instead of warning
+    #[arg(long)]
+    strict_redefinitions: bool,
+
+    /// Qualify chunk names with their input file's name (file.nw::chunk);
+    /// unqualified references resolve within the same file first
+    #[arg(long)]
+    namespaced: bool,
+
+    /// Write the outputs of modules/foo.nw under gen/modules/foo/
+    #[arg(long)]
+    prefix_from_input: bool,
+
+    /// What text after a reference on the same line means: plain text (the
+    /// line is not a reference), or text appended to the last expanded line
+    /// (append) or emitted after the expansion (after)
+    #[arg(long, default_value = "text")]
+    trailing_text: TrailingText,
+
+    /// Read only the chunk definitions tagged with one of these tags
+    /// (comma separated), e.g. wasm,experimental, and the untagged ones
+    #[arg(long, value_name = "TAGS", value_delimiter = ',')]
+    tags: Option<Vec<String>>,
+
+    /// Write the chunks opened with @test as the tests of this output, e.g.
+    /// tests/doc_examples.rs; its extension picks the test syntax (.rs, .py,
+    /// .js, .mjs or .ts)
+    #[arg(long, value_name = "PATH", value_parser = TestHarness::new)]
+    test_harness: Option<TestHarness>,
+
+    /// Allow @exec chunks to run their commands at tangle time
+    #[arg(long)]
+    allow_exec: bool,
+
+    /// Report more: -v traces chunk resolution and write decisions, -vv every expansion
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Report only warnings and errors
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// How warnings, notices and errors are printed on stderr: text, or one
+    /// JSON object per line
+    #[arg(long, env = "AZADI_MESSAGE_FORMAT", value_enum, default_value = "text")]
+    message_format: MessageFormat,
+
+    /// Exit with status 3 if any warning was reported
+    #[arg(long)]
+    deny_warnings: bool,
+
+    /// Do not report warnings with this code, e.g. AZ0101 for unused chunks
+    #[arg(long, value_name = "CODE", value_parser = parse_code)]
+    allow: Vec<Code>,
+
+    /// Also read the input files listed in this file, one path per line;
+    /// blank lines and lines starting with # are skipped
+    #[arg(long, value_name = "FILE")]
+    filelist: Option<PathBuf>,
+
     /// Input files
-    #[arg(required = true)]
+    #[arg(required_unless_present = "filelist")]
     files: Vec<PathBuf>,
 }
 
+/// How to render the woven page.
+#[derive(clap::Args)]
+struct WeaveArgs {
+    /// Page title [default: the first input's name]
+    #[arg(long)]
+    title: Option<String>,
+
+    /// Colour scheme of highlighted chunk bodies: light or dark
+    #[arg(long, default_value = "light")]
+    theme: Theme,
+
+    /// Leave chunk bodies without syntax highlighting
+    #[arg(long)]
+    no_highlight: bool,
+
+    /// Directory with header.html, footer.html and chunk.html templates
+    #[arg(long, value_name = "DIR")]
+    template: Option<PathBuf>,
+}
+
+/// Which chunks to extract and where to put them.
+#[derive(clap::Args)]
+struct ExtractArgs {
+    /// Output file for --chunks [default: stdout]
+    #[arg(long, conflicts_with = "output_dir")]
+    output: Option<PathBuf>,
+
+    /// Write each chunk named by --chunks to its own file in this directory
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// File name template for --output-dir; {name} is the sanitized chunk name
+    #[arg(long, default_value = "{name}")]
+    output_template: String,
+
+    /// Names of chunks to extract (comma separated); `*`, `?` and `[...]` wildcards select several
+    #[arg(long)]
+    chunks: Option<String>,
+
+    /// Also extract every chunk whose name matches this regular expression
+    #[arg(long, value_parser = parse_regex)]
+    chunks_re: Option<Regex>,
+
+    /// Concatenate the definitions of extracted chunks in reverse order
+    #[arg(long)]
+    reversed: bool,
+
+    /// Prefix every extracted line with this text, or with this many spaces
+    /// when it is a number, e.g. --indent 4
+    #[arg(long, value_name = "TEXT|N", value_parser = parse_indent)]
+    indent: Option<String>,
+
+    /// How to print extracted chunks: their text, or a JSON array of
+    /// {name, lines, sources} objects
+    #[arg(long, default_value = "text", value_parser = ["text", "json"], conflicts_with = "output_dir")]
+    output_format: String,
+
+    /// Follow each extracted chunk with a NUL byte instead of a blank line,
+    /// so the output splits safely whatever the chunks hold
+    #[arg(long, conflicts_with_all = ["output_dir", "output_format"])]
+    print0: bool,
+
+    /// Write every chunk but the file chunks to its own file in this
+    /// directory, named after the sanitized chunk name
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["output", "output_dir", "chunks", "chunks_re", "output_format", "print0"]
+    )]
+    explode: Option<PathBuf>,
+
+    /// Prefix each extracted line with the input file and line it comes
+    /// from, as in doc.nw:12:text
+    #[arg(
+        long,
+        conflicts_with_all = ["output_dir", "output_format", "print0", "explode"]
+    )]
+    with_locations: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Write the file chunks under the gen directory
+    Tangle {
+        /// Write Make rules mapping each generated file to the inputs it depends on
+        #[arg(long, value_name = "FILE")]
+        emit_depfile: Option<PathBuf>,
+
+        /// Delete generated files whose @file chunk no longer exists, instead
+        /// of warning about them
+        #[arg(long)]
+        prune: bool,
+
+        /// Go on generating the other files when one fails, then report all
+        /// the failures together
+        #[arg(long)]
+        keep_going: bool,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Print or save the expansion of chunks, without writing file chunks
+    Extract {
+        #[command(flatten)]
+        extract: ExtractArgs,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Render the documents as an HTML page, or a LaTeX or PDF document,
+    /// with cross-linked chunks
+    Weave {
+        /// File to write [default: stdout]
+        #[arg(short, long, required_if_eq("format", "pdf"))]
+        output: Option<PathBuf>,
+
+        /// Output format: an HTML page, a LaTeX document, or that document
+        /// typeset as PDF
+        #[arg(long, default_value = "html", value_parser = ["html", "latex", "pdf"])]
+        format: String,
+
+        /// LaTeX engine run for --format pdf
+        #[arg(long, default_value = latex::DEFAULT_ENGINE)]
+        latex_engine: String,
+
+        /// Write a site to this directory instead: index.html, listing the
+        /// documents and chunks, and a page for each document
+        #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "format"])]
+        site: Option<PathBuf>,
+
+        #[command(flatten)]
+        weave: WeaveArgs,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Serve the woven page on a local address, reloading open browsers
+    /// whenever an input changes
+    Serve {
+        /// IP address to listen on, e.g. 0.0.0.0 or ::1
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: IpAddr,
+
+        /// Port to listen on
+        #[arg(long, default_value_t = 8000)]
+        port: u16,
+
+        #[command(flatten)]
+        weave: WeaveArgs,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Write nothing; exit with an error listing the generated files that are
+    /// missing or differ from what would be written
+    Check {
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Print the paths of the files that would be generated, without writing them
+    List {
+        /// One path per line, or a JSON array
+        #[arg(long, default_value = "lines", value_parser = ["lines", "json"])]
+        format: String,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Print which chunks reference which, as a Graphviz DOT graph
+    Graph {
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Write the chunks and @doc sections as one classic noweb document,
+    /// for noweave, notangle and other noweb tools
+    Export {
+        /// Write the document here instead of to standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Report the chunks that break the style rules of the [lint] table
+    Lint {
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Lay the documents out consistently: single spaces in chunk openings,
+    /// end lines matching them and one blank line between chunks
+    Fmt {
+        /// Change nothing; exit non-zero listing the documents fmt would change
+        #[arg(long)]
+        check: bool,
+
+        /// Also order the chunks by name, each with the prose that follows it
+        #[arg(long)]
+        sort: bool,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Report chunk, reference and line counts and the largest chunks
+    Stats {
+        /// Plain text, or a JSON object
+        #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+
+        /// How many of the largest chunks to list
+        #[arg(long, default_value_t = 5)]
+        top: usize,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Report which chunks each generated file includes and which files include each chunk
+    Coverage {
+        /// Plain text, or a JSON object
+        #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Carry edits made under the gen directory back into the input files
+    Untangle {
+        /// Print the source changes as a patch instead of applying them
+        #[arg(long)]
+        patch: bool,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Report the files the input files generated that were modified or
+    /// deleted by hand since the last tangle, and the files added under the
+    /// gen directory, exiting with an error if there are any; without input
+    /// files, every file in the manifest is checked
+    #[command(mut_arg("files", |arg| arg.required_unless_present(clap::builder::Resettable::Reset)))]
+    Verify {
+        /// One "change: path" line per file, or a JSON array
+        #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+
+        #[command(flatten)]
+        common: Common,
+    },
+    /// Start a project: write azadi.toml and a starter document, and create
+    /// the gen and private directories; existing files are kept
+    Init {
+        /// Directory of the project
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Private work directory, under the project directory
+        #[arg(long, env = "AZADI_PRIV_DIR", default_value = "_azadi_work")]
+        priv_dir: PathBuf,
+
+        /// Base directory of generated files, under the project directory
+        #[arg(long, env = "AZADI_GEN", default_value = "gen")]
+        gen: PathBuf,
+    },
+    /// Rewrite a document written for another literate programming tool in
+    /// azadi's syntax
+    Migrate {
+        /// Tool the document was written for
+        #[arg(long, value_enum)]
+        from: MigrateFrom,
+
+        /// Language of the code, as a file extension such as py or rs, whose
+        /// comment marker prefixes the chunk lines
+        #[arg(long, value_parser = language_marker, conflicts_with = "marker")]
+        language: Option<String>,
+
+        /// Comment marker prefixing the chunk lines
+        #[arg(long)]
+        marker: Option<String>,
+
+        /// Make the noweb root chunk NAME a @file chunk written to NAME
+        /// (repeatable)
+        #[arg(long = "file", value_name = "NAME")]
+        files: Vec<String>,
+
+        /// Write the result here instead of to standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Document to migrate
+        input: PathBuf,
+    },
+    /// Print a shell completion script
+    Completions {
+        /// Shell to complete for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Parser)]
+#[command(
+    name = "azadi",
+    about = "Expand chunks like noweb - A literate programming tool",
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    // Without a subcommand, the deprecated single mode: tangle, then extract
+    // any --chunks, or do what one of the flags below asks instead
+    #[command(flatten)]
+    extract: ExtractArgs,
+
+    /// Print the paths of the files that would be generated, without writing
+    /// them: one per line, or as a JSON array with --list-outputs=json
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "lines",
+        value_parser = ["lines", "json"]
+    )]
+    list_outputs: Option<String>,
+
+    /// Write nothing; exit with an error listing the generated files that are
+    /// missing or differ from what would be written
+    #[arg(long)]
+    check: bool,
+
+    /// Write Make rules mapping each generated file to the inputs it depends on
+    #[arg(long, value_name = "FILE")]
+    emit_depfile: Option<PathBuf>,
+
+    /// Carry edits made under the gen directory back into the input files
+    #[arg(long)]
+    untangle: bool,
+
+    /// Like --untangle, but print the source changes as a patch instead of applying them
+    #[arg(long)]
+    untangle_patch: bool,
+
+    #[command(flatten)]
+    common: Common,
+}
+
+/// What to do once the documents are read.
+enum Action {
+    /// Write the file chunks, then extract the chunks asked for, if any.
+    Tangle {
+        emit_depfile: Option<PathBuf>,
+        prune: bool,
+        keep_going: bool,
+        extract: Option<ExtractArgs>,
+    },
+    Extract(ExtractArgs),
+    Weave {
+        output: Option<PathBuf>,
+        format: String,
+        latex_engine: String,
+        site: Option<PathBuf>,
+        weave: WeaveArgs,
+    },
+    Serve {
+        addr: SocketAddr,
+        weave: WeaveArgs,
+    },
+    Check,
+    List {
+        json: bool,
+    },
+    Verify {
+        json: bool,
+    },
+    Graph,
+    Export {
+        output: Option<PathBuf>,
+    },
+    Fmt {
+        check: bool,
+        sort: bool,
+    },
+    Lint,
+    Stats {
+        json: bool,
+        top: usize,
+    },
+    Coverage {
+        json: bool,
+    },
+    Untangle {
+        patch: bool,
+    },
+}
+
 fn write_chunks<W: Write>(
     clipper: &mut Clip,
     chunks: &[&str],
+    options: &ExpandOptions,
+    writer: &mut W,
+) -> Result<(), AzadiError> {
+    for chunk in chunks {
+        clipper.get_chunk_with_options(chunk, options, writer)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Write the chunks exactly as expanded, each followed by a NUL byte.
+fn write_chunks_nul<W: Write>(
+    clipper: &Clip,
+    chunks: &[&str],
+    options: &ExpandOptions,
+    writer: &mut W,
+) -> Result<(), AzadiError> {
+    for chunk in chunks {
+        clipper.expand_to(chunk, options, writer)?;
+        writer.write_all(b"\0")?;
+    }
+    Ok(())
+}
+
+/// Write the chunks like `write_chunks`, with each line prefixed by the
+/// input file and one-based line it comes from, as in `doc.nw:12:text`.
+fn write_chunks_located<W: Write>(
+    clipper: &Clip,
+    chunks: &[&str],
+    options: &ExpandOptions,
     writer: &mut W,
 ) -> Result<(), AzadiError> {
     for chunk in chunks {
-        clipper.get_chunk(chunk, writer)?;
+        for (line, source) in clipper.expand_traced(chunk, options)? {
+            write!(
+                writer,
+                "{}:{}:{}",
+                source.location.file_name,
+                source.location.line + 1,
+                line
+            )?;
+            if !line.ends_with('\n') {
+                writeln!(writer)?;
+            }
+        }
         writeln!(writer)?;
     }
     Ok(())
 }
 
-fn run(args: Args) -> Result<(), AzadiError> {
-    let comment_markers: Vec<String> = args
+/// Write the chunks as a JSON array of `{"name", "lines", "sources"}`
+/// objects, with the source location of each line.
+fn write_chunks_json<W: Write>(
+    clipper: &Clip,
+    chunks: &[&str],
+    options: &ExpandOptions,
+    writer: &mut W,
+) -> Result<(), AzadiError> {
+    let mut items = Vec::new();
+    for chunk in chunks {
+        let traced = clipper.expand_traced(chunk, options)?;
+        let lines: Vec<String> = traced
+            .iter()
+            .map(|(line, _)| json::quote(line.strip_suffix('\n').unwrap_or(line)))
+            .collect();
+        let sources: Vec<String> = traced
+            .iter()
+            .map(|(_, source)| {
+                format!(
+                    "{{\"file\": {}, \"line\": {}, \"chunk\": {}}}",
+                    json::quote(&source.location.file_name),
+                    source.location.line + 1,
+                    json::quote(&source.chunk)
+                )
+            })
+            .collect();
+        items.push(format!(
+            "{{\"name\": {}, \"lines\": [{}], \"sources\": [{}]}}",
+            json::quote(chunk),
+            lines.join(", "),
+            sources.join(", ")
+        ));
+    }
+    writeln!(writer, "[{}]", items.join(", "))?;
+    Ok(())
+}
+
+/// The paths listed in a file list, one per line, trimmed; blank lines and
+/// lines starting with `#` are skipped.
+fn read_filelist(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect())
+}
+
+fn parse_regex(s: &str) -> Result<Regex, regex::Error> {
+    Regex::new(s)
+}
+
+/// An indentation given as text, or as a number of spaces.
+fn parse_indent(s: &str) -> Result<String, String> {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+        let count: usize = s
+            .parse()
+            .map_err(|_| format!("'{}' is too many spaces", s))?;
+        return Ok(" ".repeat(count));
+    }
+    Ok(s.to_string())
+}
+
+/// The comment marker of the language named by a file extension.
+fn language_marker(s: &str) -> Result<String, String> {
+    extension_markers(&format!("x.{}", s.trim_start_matches('.')))
+        .and_then(|markers| markers.into_iter().next())
+        .ok_or_else(|| format!("unknown language '{}'; give its file extension, e.g. py", s))
+}
+
+fn parse_define(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected NAME=VALUE, got '{}'", s)),
+    }
+}
+
+/// Resolve the --chunks list and --chunks-re pattern into chunk names.
+fn select_chunks(clipper: &Clip, chunks: Option<&str>, chunks_re: Option<&Regex>) -> Vec<String> {
+    let names = clipper.chunk_names();
+    let mut selected: Vec<String> = Vec::new();
+    let mut add = |name: &str| {
+        if !selected.iter().any(|s| s == name) {
+            selected.push(name.to_string());
+        }
+    };
+
+    for pattern in chunks.into_iter().flat_map(|c| c.split(',')) {
+        if is_glob(pattern) && !clipper.has_chunk(pattern) {
+            let re = match glob_to_regex(pattern) {
+                Ok(re) => re,
+                Err(_) => {
+                    clipper.reporter().coded_warning(
+                        Code::InvalidPattern,
+                        &format!("invalid chunk pattern '{}'", pattern),
+                    );
+                    continue;
+                }
+            };
+            let matched: Vec<&String> = names.iter().filter(|n| re.is_match(n)).collect();
+            if matched.is_empty() {
+                clipper.reporter().coded_warning(
+                    Code::NoMatchingChunks,
+                    &format!("no chunks match '{}'", pattern),
+                );
+            }
+            matched.into_iter().for_each(|n| add(n));
+        } else {
+            add(pattern);
+        }
+    }
+    if let Some(re) = chunks_re {
+        names.iter().filter(|n| re.is_match(n)).for_each(|n| add(n));
+    }
+    selected
+}
+
+/// Replace characters that are unsafe in file names.
+fn sanitize_file_name(chunk: &str) -> String {
+    chunk
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Write every chunk but the file chunks to `dir`, one file per chunk.
+fn explode_chunks(clipper: &Clip, dir: &Path, options: &ExpandOptions) -> Result<(), AzadiError> {
+    let chunks: Vec<String> = clipper
+        .chunk_names()
+        .into_iter()
+        .filter(|name| !name.starts_with("@file "))
+        .collect();
+    let chunks: Vec<&str> = chunks.iter().map(String::as_str).collect();
+    fs::create_dir_all(dir)?;
+    write_chunk_files(clipper, &chunks, dir, "{name}", options)
+}
+
+/// Write each chunk to the file `template` names under `dir`. Chunks whose
+/// sanitized names coincide are reported, and only the first of them is
+/// written.
+fn write_chunk_files(
+    clipper: &Clip,
+    chunks: &[&str],
+    dir: &Path,
+    template: &str,
+    options: &ExpandOptions,
+) -> Result<(), AzadiError> {
+    let mut written: HashMap<PathBuf, &str> = HashMap::new();
+    for chunk in chunks {
+        let path = dir.join(template.replace("{name}", &sanitize_file_name(chunk)));
+        if let Some(first) = written.get(&path) {
+            clipper.reporter().coded_warning(
+                Code::OutputCollision,
+                &format!(
+                    "chunks '{}' and '{}' both write to {}; only the first is written",
+                    first,
+                    chunk,
+                    path.display()
+                ),
+            );
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let lines = clipper.expand_with_options(chunk, options)?;
+        fs::write(&path, lines.concat())?;
+        written.insert(path, chunk);
+    }
+    Ok(())
+}
+
+/// Escape a path for use in a Make rule.
+fn make_escape(path: &str) -> String {
+    path.replace('$', "$$")
+        .replace(' ', "\\ ")
+        .replace('#', "\\#")
+}
+
+/// Make rules `gen/out: input.nw ...`, one per generated file.
+fn format_depfile(gen: &Path, deps: &[(String, Vec<String>)]) -> String {
+    let mut out = String::new();
+    for (path, inputs) in deps {
+        let target = gen.join(path).to_string_lossy().into_owned();
+        out.push_str(&make_escape(&target));
+        out.push(':');
+        for input in inputs {
+            out.push(' ');
+            out.push_str(&make_escape(input));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Load the configuration named by --config, else azadi.toml if present.
+fn load_config(path: Option<&Path>) -> Result<Config, ConfigError> {
+    match path {
+        Some(path) => Config::load(path),
+        None if Path::new(DEFAULT_CONFIG).is_file() => Config::load(Path::new(DEFAULT_CONFIG)),
+        None => Ok(Config::default()),
+    }
+}
+
+/// An error in the configuration file `common` names.
+fn config_error(common: &Common, message: String) -> ConfigError {
+    ConfigError {
+        file_name: common
+            .config
+            .as_ref()
+            .map_or(DEFAULT_CONFIG.to_string(), |p| {
+                p.to_string_lossy().into_owned()
+            }),
+        line: None,
+        message,
+    }
+}
+
+/// The configuration and the profile `--profile` selects from it.
+fn load_profile(common: &Common) -> Result<(Config, Profile), ConfigError> {
+    let config = load_config(common.config.as_deref())?;
+    let profile = match &common.profile {
+        Some(name) => config
+            .profile(name)
+            .cloned()
+            .map_err(|message| config_error(common, message))?,
+        None => Profile::default(),
+    };
+    Ok((config, profile))
+}
+
+/// The gen directory: `--gen`, else the profile's, else `gen`.
+fn gen_dir(common: &Common, profile: &Profile) -> PathBuf {
+    common
+        .gen
+        .clone()
+        .or_else(|| profile.gen.clone())
+        .unwrap_or_else(|| PathBuf::from("gen"))
+}
+
+/// The style rules of the configuration's `[lint]` table.
+fn lint_rules(common: &Common, config: &Config) -> Result<LintRules, ConfigError> {
+    let settings = &config.lint;
+    let mut rules = LintRules::default()
+        .missing_end(settings.missing_end.unwrap_or(true))
+        .duplicates(settings.duplicates.unwrap_or(true))
+        .file_extensions(settings.file_extensions.unwrap_or(true));
+    if let Some(pattern) = &settings.name_pattern {
+        rules = rules.name_pattern(pattern).map_err(|e| {
+            config_error(
+                common,
+                format!("'lint.name_pattern' is not a regular expression: {}", e),
+            )
+        })?;
+    }
+    if let Some(max) = settings.max_lines {
+        rules = rules.max_lines(max);
+    }
+    for pattern in &settings.extensionless_files {
+        rules = rules.allow_extensionless(pattern);
+    }
+    Ok(rules)
+}
+
+/// Graphviz DOT source of the reference graph; file chunks are boxes.
+fn format_dot(edges: &[(String, String)]) -> String {
+    let mut out = String::from("digraph chunks {\n");
+    let mut files: Vec<&str> = edges
+        .iter()
+        .flat_map(|(from, to)| [from.as_str(), to.as_str()])
+        .filter(|name| name.starts_with("@file "))
+        .collect();
+    files.sort();
+    files.dedup();
+    for name in files {
+        out.push_str(&format!("    {} [shape=box];\n", json::quote(name)));
+    }
+    for (from, to) in edges {
+        out.push_str(&format!(
+            "    {} -> {};\n",
+            json::quote(from),
+            json::quote(to)
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Read the documents as configured by the shared options; the loaded
+/// configuration is returned along with them.
+fn open(
+    common: &Common,
+    reporter: Arc<dyn Reporter>,
+) -> Result<(Clip, PathBuf, ExpandOptions, Config), AzadiError> {
+    let comment_markers: Vec<String> = common
         .comment_markers
         .split(',')
         .map(|s| s.trim().to_string())
         .collect();
 
-    let safe_writer = SafeFileWriter::new(&args.gen, &args.priv_dir);
-    let mut clipper = Clip::new(
-        safe_writer,
-        &args.open_delim,
-        &args.close_delim,
-        &args.chunk_end,
-        &comment_markers,
-    );
+    let (config, profile) = load_profile(common)?;
+    let mut names = NamePolicy::default();
+    if let Some(chars) = &config.names.chars {
+        names = names.allowed_chars(chars).map_err(|e| {
+            config_error(
+                common,
+                format!("'names.chars' is not a character class: {}", e),
+            )
+        })?;
+    }
+    if let Some(max) = config.names.max_length {
+        names = names.max_length(max);
+    }
+    for prefix in &config.names.reserved_prefixes {
+        names = names.reserved_prefix(prefix);
+    }
+    let mut marker_rules = MarkerRules::default()
+        .auto_detect(common.auto_markers || config.auto_markers.unwrap_or(false));
+    for (pattern, markers) in config.markers_for.iter().chain(&common.markers_for) {
+        marker_rules = marker_rules.override_for(pattern, markers);
+    }
+    let gen = gen_dir(common, &profile);
+
+    let mut builder = Clip::builder()
+        .safe_writer(SafeFileWriter::with_config(
+            &gen,
+            &common.priv_dir,
+            SafeWriterConfig {
+                backup_dir: common.backup_dir.clone().or(config.backup_dir.clone()),
+                no_backup: config
+                    .no_backup
+                    .iter()
+                    .chain(&common.no_backup)
+                    .cloned()
+                    .collect(),
+                snapshots: common.snapshots.or(config.snapshots).unwrap_or(0),
+                ..SafeWriterConfig::default()
+            },
+        )?)
+        .delimiters(&common.open_delim, &common.close_delim)
+        .chunk_end(&common.chunk_end)
+        .comment_markers(&comment_markers)
+        .marker_rules(marker_rules)
+        .reporter(reporter)
+        .dialect(common.dialect)
+        .allow_exec(common.allow_exec)
+        .namespaced(common.namespaced)
+        .prefix_from_input(common.prefix_from_input || config.prefix_from_input.unwrap_or(false))
+        .trailing_text(common.trailing_text)
+        .strict_redefinitions(
+            common.strict_redefinitions || config.strict_redefinitions.unwrap_or(false),
+        )
+        .name_policy(names)
+        .tags(common.tags.as_ref().unwrap_or(&profile.tags));
+    for (open, close) in config.extra_delimiters.iter().chain(&common.extra_delims) {
+        builder = builder.extra_delimiters(open, close);
+    }
+    if let Some(harness) = common.test_harness.clone().or(config.test_harness.clone()) {
+        builder = builder.test_harness(harness);
+    }
+    let mut clipper = builder.build()?;
+    clipper.set_file_selection(profile.files.clone());
+
+    clipper.read_files(&common.files)?;
+    clipper.check_chunk_names()?;
+
+    let file_options = ExpandOptions {
+        line_directive: common.line_directive.clone(),
+        continuation: common.continuation.clone(),
+        uncomment: common.uncomment.clone(),
+        defines: profile
+            .defines
+            .into_iter()
+            .chain(common.defines.iter().cloned())
+            .collect(),
+        ..ExpandOptions::default()
+    };
+    Ok((clipper, gen, file_options, config))
+}
+
+/// The inputs as (file name, text) pairs, and the title of the woven page.
+fn weave_documents(
+    common: &Common,
+    args: &WeaveArgs,
+) -> Result<(Vec<(String, String)>, String), AzadiError> {
+    let mut documents = Vec::new();
+    for file in &common.files {
+        documents.push((
+            file.to_string_lossy().into_owned(),
+            fs::read_to_string(file)?,
+        ));
+    }
+    let title = args.title.clone().unwrap_or_else(|| documents[0].0.clone());
+    Ok((documents, title))
+}
+
+/// The weave options the arguments select.
+fn weave_options(common: &Common, args: &WeaveArgs) -> Result<WeaveOptions, AzadiError> {
+    Ok(WeaveOptions {
+        theme: (!args.no_highlight).then_some(args.theme),
+        cache_dir: Some(common.priv_dir.join("__weave__")),
+        template: match &args.template {
+            Some(dir) => WeaveTemplate::load(dir)?,
+            None => WeaveTemplate::default(),
+        },
+    })
+}
+
+/// Weave the documents into one HTML page.
+fn weave_page(common: &Common, clipper: &Clip, args: &WeaveArgs) -> Result<String, AzadiError> {
+    let (documents, title) = weave_documents(common, args)?;
+    let options = weave_options(common, args)?;
+    Ok(weave_html_with_options(
+        clipper, &title, &documents, &options,
+    ))
+}
 
-    clipper.read_files(&args.files)?;
-    clipper.write_files()?;
+/// Write `content` to `path`, unless the file already holds it.
+fn write_if_changed(path: &Path, content: &str) -> io::Result<()> {
+    if fs::read_to_string(path).ok().as_deref() == Some(content) {
+        return Ok(());
+    }
+    fs::write(path, content)
+}
 
-    if let Some(chunks) = args.chunks {
-        let chunks: Vec<&str> = chunks.split(',').collect();
-        if let Some(output_path) = args.output {
-            let mut file = File::create(output_path)?;
-            write_chunks(&mut clipper, &chunks, &mut file)?;
+fn extract(
+    clipper: &mut Clip,
+    extract: ExtractArgs,
+    file_options: ExpandOptions,
+) -> Result<(), AzadiError> {
+    let selected = select_chunks(
+        clipper,
+        extract.chunks.as_deref(),
+        extract.chunks_re.as_ref(),
+    );
+    let chunks: Vec<&str> = selected.iter().map(String::as_str).collect();
+    let options = ExpandOptions {
+        reversed: extract.reversed,
+        indent: extract.indent.unwrap_or_default(),
+        ..file_options
+    };
+    if let Some(dir) = extract.explode {
+        return explode_chunks(clipper, &dir, &options);
+    }
+    if let Some(dir) = extract.output_dir {
+        return write_chunk_files(clipper, &chunks, &dir, &extract.output_template, &options);
+    }
+    let mut write = |mut writer: &mut dyn Write| -> Result<(), AzadiError> {
+        if extract.print0 {
+            write_chunks_nul(clipper, &chunks, &options, &mut writer)?;
+        } else if extract.with_locations {
+            write_chunks_located(clipper, &chunks, &options, &mut writer)?;
+        } else if extract.output_format == "json" {
+            write_chunks_json(clipper, &chunks, &options, &mut writer)?;
         } else {
-            let stdout = io::stdout();
-            let mut handle = stdout.lock();
-            write_chunks(&mut clipper, &chunks, &mut handle)?;
+            write_chunks(clipper, &chunks, &options, &mut writer)?;
         }
+        Ok(writer.flush()?)
+    };
+    // A failing chunk leaves an existing output file untouched
+    match &extract.output {
+        Some(output_path) => write_replacing(output_path, write),
+        None => write(&mut io::stdout().lock()),
     }
+}
 
+/// Stream `write` into a temporary file next to `path` and rename it into
+/// place once it succeeded, so a failure leaves `path` as it was.
+fn write_replacing(
+    path: &Path,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), AzadiError>,
+) -> Result<(), AzadiError> {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    let result = fs::File::create(&temp_path)
+        .map_err(AzadiError::from)
+        .and_then(|file| write(&mut io::BufWriter::new(file)))
+        .and_then(|()| Ok(fs::rename(&temp_path, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Rewrite `input` in azadi's syntax, reporting the lines it could not.
+fn migrate(
+    from: MigrateFrom,
+    input: &Path,
+    marker: &str,
+    files: &[String],
+    output: Option<&Path>,
+) -> Result<(), AzadiError> {
+    let source = fs::read_to_string(input)?;
+    let migrated = match from {
+        MigrateFrom::Noweb => migrate_noweb(&source, marker, files),
+        MigrateFrom::Nuweb => migrate_nuweb(&source, marker),
+        MigrateFrom::Funnelweb => migrate_funnelweb(&source, marker),
+    };
+    for (line, note) in &migrated.notes {
+        ConsoleReporter.coded_warning(
+            Code::Unmigrated,
+            &format!("{} line {}: {}", input.display(), line, note),
+        );
+    }
+    match output {
+        Some(path) => fs::write(path, &migrated.text)?,
+        None => io::stdout().write_all(migrated.text.as_bytes())?,
+    }
     Ok(())
 }
 
+/// Print how the outputs of the input files differ from what the last tangle
+/// wrote, and the files added under gen/; returns how many there are.
+/// Print the changes to the outputs of the input files, or of every output
+/// in the manifest without input files, and report the input files the
+/// manifest knows nothing about; returns how many outputs changed and how
+/// many input files are unknown.
+fn verify(
+    common: &Common,
+    json: bool,
+    reporter: &dyn Reporter,
+) -> Result<(usize, usize), AzadiError> {
+    let (_, profile) = load_profile(common)?;
+    let gen = gen_dir(common, &profile);
+    let documents: Vec<String> = common
+        .files
+        .iter()
+        .map(|file| file.to_string_lossy().into_owned())
+        .collect();
+    let producers: HashMap<String, Vec<String>> = read_manifest_in(&common.priv_dir)?
+        .into_iter()
+        .map(|entry| (entry.path, entry.inputs))
+        .collect();
+    let unknown: Vec<&String> = documents
+        .iter()
+        .filter(|document| !producers.values().flatten().any(|input| input == *document))
+        .collect();
+    for document in &unknown {
+        reporter.error(
+            None,
+            &format!(
+                "{} generated no files in the manifest; tangle it first",
+                document
+            ),
+        );
+    }
+    // Edits to other documents' outputs are for their own verify
+    let changes: Vec<(String, OutputChange)> = verify_outputs_in(&gen, &common.priv_dir)?
+        .into_iter()
+        .filter(|(path, change)| {
+            documents.is_empty()
+                || *change == OutputChange::Added
+                || producers.get(path).is_none_or(|inputs| {
+                    inputs.is_empty() || inputs.iter().any(|input| documents.contains(input))
+                })
+        })
+        .collect();
+    if json {
+        let items: Vec<String> = changes
+            .iter()
+            .map(|(path, change)| {
+                format!(
+                    "{{\"path\": {}, \"change\": {}}}",
+                    json::quote(&gen.join(path).to_string_lossy()),
+                    json::quote(&change.to_string())
+                )
+            })
+            .collect();
+        println!("[{}]", items.join(", "));
+    } else {
+        for (path, change) in &changes {
+            println!("{}: {}", change, gen.join(path).display());
+        }
+    }
+    Ok((changes.len(), unknown.len()))
+}
+
+fn run(common: &Common, action: Action, reporter: Arc<dyn Reporter>) -> Result<(), AzadiError> {
+    // The preview server reads the documents again whenever they change
+    if let Action::Serve { addr, weave } = &action {
+        let render = || {
+            let (clipper, _, _, _) = open(common, Arc::clone(&reporter))?;
+            weave_page(common, &clipper, weave)
+        };
+        return serve(*addr, &common.files, render, Arc::clone(&reporter));
+    }
+    // Verifying compares gen/ with the manifest, without reading the documents
+    if let Action::Verify { json } = action {
+        let (changes, unknown) = verify(common, json, reporter.as_ref())?;
+        if changes > 0 {
+            reporter.coded_error(
+                Code::ModifiedExternally,
+                &format!(
+                    "{} generated file(s) changed since the last tangle",
+                    changes
+                ),
+            );
+        }
+        if changes > 0 || unknown > 0 {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let (mut clipper, gen, file_options, config) = open(common, reporter)?;
+
+    match action {
+        Action::List { json } => {
+            let paths: Vec<String> = clipper
+                .output_paths()
+                .iter()
+                .map(|path| gen.join(path).to_string_lossy().into_owned())
+                .collect();
+            if json {
+                let items: Vec<String> = paths.iter().map(|p| json::quote(p)).collect();
+                println!("[{}]", items.join(", "));
+            } else {
+                for path in &paths {
+                    println!("{}", path);
+                }
+            }
+        }
+        Action::Untangle { patch } => {
+            let report = untangle(&clipper)?;
+            for skipped in &report.skipped {
+                clipper
+                    .reporter()
+                    .coded_warning(Code::UntangleSkipped, skipped);
+            }
+            if patch {
+                print!("{}", format_patch(&report.edits)?);
+            } else {
+                apply_edits(&report.edits)?;
+                if report.skipped.is_empty() {
+                    clipper.accept_outputs(&report.changed_outputs)?;
+                }
+            }
+        }
+        Action::Check => {
+            let stale = clipper.stale_outputs(&file_options)?;
+            if stale.is_empty() {
+                return Ok(());
+            }
+            for path in &stale {
+                clipper.reporter().coded_warning(
+                    Code::StaleOutput,
+                    &format!("stale: {}", gen.join(path).display()),
+                );
+            }
+            clipper.reporter().coded_error(
+                Code::OutOfDate,
+                &format!("{} generated file(s) out of date", stale.len()),
+            );
+            std::process::exit(1);
+        }
+        Action::Tangle {
+            emit_depfile,
+            prune,
+            keep_going,
+            extract: then_extract,
+        } => {
+            clipper.set_prune(prune);
+            clipper.set_keep_going(keep_going);
+            clipper.write_files_with_options(&file_options)?;
+            if let Some(depfile) = &emit_depfile {
+                let deps = clipper.output_dependencies()?;
+                fs::write(depfile, format_depfile(&gen, &deps))?;
+            }
+            if let Some(args) = then_extract {
+                if args.chunks.is_some() || args.chunks_re.is_some() || args.explode.is_some() {
+                    extract(&mut clipper, args, file_options)?;
+                }
+            }
+        }
+        Action::Extract(args) => extract(&mut clipper, args, file_options)?,
+        Action::Weave {
+            site: Some(dir),
+            weave,
+            ..
+        } => {
+            let (documents, title) = weave_documents(common, &weave)?;
+            let options = weave_options(common, &weave)?;
+            fs::create_dir_all(&dir)?;
+            for (name, html) in weave_site(&clipper, &title, &documents, &options) {
+                write_if_changed(&dir.join(name), &html)?;
+            }
+        }
+        Action::Weave {
+            output,
+            format,
+            latex_engine,
+            weave,
+            ..
+        } if format != "html" => {
+            let (documents, title) = weave_documents(common, &weave)?;
+            let tex = latex::weave_latex(&clipper, &title, &documents);
+            match output {
+                Some(path) if format == "pdf" => {
+                    let work_dir = common.priv_dir.join("__pdf__");
+                    fs::write(path, latex::typeset_pdf(&tex, &latex_engine, &work_dir)?)?
+                }
+                Some(path) => fs::write(path, tex)?,
+                None => print!("{}", tex),
+            }
+        }
+        Action::Weave { output, weave, .. } => {
+            let html = weave_page(common, &clipper, &weave)?;
+            match output {
+                // Leave an unchanged page alone, so documentation builds see it as current
+                Some(path) => write_if_changed(&path, &html)?,
+                None => print!("{}", html),
+            }
+        }
+        Action::Serve { .. } => unreachable!("served before the documents are read"),
+        Action::Verify { .. } => unreachable!("verified before the documents are read"),
+        Action::Graph => print!("{}", format_dot(&clipper.references())),
+        Action::Lint => {
+            let rules = lint_rules(common, &config)?;
+            // Only noweb documents are read for their end lines
+            let mut documents = Vec::new();
+            if common.dialect == Dialect::Noweb {
+                for file in &common.files {
+                    let name = file.to_string_lossy().into_owned();
+                    if !is_notebook(&name) {
+                        documents.push((name, fs::read_to_string(file)?));
+                    }
+                }
+            }
+            for finding in lint(&clipper, &documents, &rules) {
+                clipper
+                    .reporter()
+                    .coded_warning(finding.code, &finding.to_string());
+            }
+        }
+        Action::Fmt { check, sort } => {
+            if common.dialect != Dialect::Noweb {
+                clipper.reporter().error(
+                    None,
+                    &format!("fmt lays out noweb documents only, not {}", common.dialect),
+                );
+                std::process::exit(1);
+            }
+            let mut unformatted = 0;
+            for file in &common.files {
+                let name = file.to_string_lossy();
+                if is_notebook(&name) {
+                    continue;
+                }
+                let text = fs::read_to_string(file)?;
+                let formatted = format_document(&clipper, &name, &text, sort);
+                if formatted == text {
+                    continue;
+                }
+                if check {
+                    clipper
+                        .reporter()
+                        .info(&format!("would reformat: {}", name));
+                    unformatted += 1;
+                } else {
+                    fs::write(file, formatted)?;
+                    clipper.reporter().info(&format!("formatted: {}", name));
+                }
+            }
+            if unformatted > 0 {
+                clipper.reporter().coded_error(
+                    Code::Unformatted,
+                    &format!("{} document(s) not formatted", unformatted),
+                );
+                std::process::exit(1);
+            }
+        }
+        Action::Export { output } => {
+            let exported = export_noweb(&clipper, &file_options.defines);
+            for (location, note) in &exported.notes {
+                clipper.reporter().coded_warning(
+                    Code::Unexported,
+                    &format!(
+                        "{} line {}: {}",
+                        location.file_name,
+                        location.line + 1,
+                        note
+                    ),
+                );
+            }
+            match output {
+                Some(path) => fs::write(path, exported.text)?,
+                None => print!("{}", exported.text),
+            }
+        }
+        Action::Stats { json, top } => {
+            let mut total_lines = 0;
+            for file in &common.files {
+                total_lines += fs::read_to_string(file)?.lines().count();
+            }
+            let stats = Stats::collect(&clipper, total_lines, top);
+            if json {
+                print!("{}", stats.to_json());
+            } else {
+                print!("{}", stats.to_text());
+            }
+        }
+        Action::Coverage { json } => {
+            let coverage = clipper.coverage()?;
+            if json {
+                print!("{}", coverage.to_json());
+            } else {
+                print!("{}", coverage.to_text());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Split the text of a response file into arguments: whitespace separates
+/// them, and single or double quotes keep whitespace inside one. Backslashes
+/// are kept as written, so Windows paths need no escaping.
+fn split_response_file(text: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quote: Option<char> = None;
+    for c in text.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.get_or_insert_with(String::new).push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                current.get_or_insert_with(String::new);
+            }
+            None if c.is_whitespace() => args.extend(current.take()),
+            None => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(format!("unterminated {} quote", q));
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+/// The command line with every `@FILE` argument replaced by the arguments
+/// written in `FILE`, which may name response files in turn. As with GCC, an
+/// `@FILE` that cannot be read is kept as written, so chunk names such as
+/// `@file out.rs` and delimiters such as `@@` still pass through, as do the
+/// arguments after `--`.
+fn expand_response_files(
+    args: impl IntoIterator<Item = OsString>,
+    depth: usize,
+) -> Result<Vec<OsString>, String> {
+    let mut expanded = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            expanded.push(arg);
+            expanded.extend(args);
+            break;
+        }
+        let text = arg
+            .to_str()
+            .and_then(|a| a.strip_prefix('@'))
+            .and_then(|path| Some((path, fs::read_to_string(path).ok()?)));
+        let Some((path, text)) = text else {
+            expanded.push(arg);
+            continue;
+        };
+        if depth >= MAX_RESPONSE_FILE_DEPTH {
+            return Err(format!(
+                "{}: response files nested more than {} deep",
+                path, MAX_RESPONSE_FILE_DEPTH
+            ));
+        }
+        let inner = split_response_file(&text).map_err(|e| format!("{}: {}", path, e))?;
+        expanded.extend(expand_response_files(
+            inner.into_iter().map(OsString::from),
+            depth + 1,
+        )?);
+    }
+    Ok(expanded)
+}
+
+/// How deeply response files may name further response files.
+const MAX_RESPONSE_FILE_DEPTH: usize = 8;
+
 fn main() {
-    let args = Args::parse();
+    let mut argv = std::env::args_os();
+    let program = argv.next().unwrap_or_else(|| OsString::from("azadi-noweb"));
+    let argv = match expand_response_files(argv, 0) {
+        Ok(argv) => argv,
+        Err(e) => Args::command().error(clap::error::ErrorKind::Io, e).exit(),
+    };
+    let args = Args::parse_from(std::iter::once(program).chain(argv));
+    // Informational until the common options, if any, say otherwise
+    let filter = log::init();
+    let mut legacy = false;
+    let (mut common, action) = match args.command {
+        Some(Commands::Completions { shell }) => {
+            print!(
+                "{}",
+                completions::generate(shell, &Args::command(), "azadi-noweb")
+            );
+            return;
+        }
+        Some(Commands::Init { dir, priv_dir, gen }) => {
+            match scaffold(&dir, &gen, &priv_dir) {
+                Ok(done) => {
+                    for step in done {
+                        match step {
+                            Scaffolded::Created(path) => println!("created: {}", path.display()),
+                            Scaffolded::Kept(path) => {
+                                println!("kept: {} (already exists)", path.display())
+                            }
+                        }
+                    }
+                    if dir == Path::new(".") {
+                        println!("next: azadi-noweb tangle {}", STARTER_DOCUMENT);
+                    } else {
+                        println!(
+                            "next: cd {} && azadi-noweb tangle {}",
+                            dir.display(),
+                            STARTER_DOCUMENT
+                        );
+                    }
+                }
+                Err(e) => {
+                    ConsoleReporter.error(None, &e.to_string());
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Commands::Migrate {
+            from,
+            language,
+            marker,
+            files,
+            output,
+            input,
+        }) => {
+            // Without a choice, the first file chunk's extension names the language
+            let marker = marker.or(language).or_else(|| {
+                files
+                    .iter()
+                    .find_map(|f| extension_markers(f))
+                    .and_then(|markers| markers.into_iter().next())
+            });
+            let Some(marker) = marker else {
+                ConsoleReporter.error(
+                    None,
+                    "no --file names a language; pass --language or --marker \
+                     (--marker '' for none)",
+                );
+                std::process::exit(1);
+            };
+            if let Err(e) = migrate(from, &input, &marker, &files, output.as_deref()) {
+                ConsoleReporter.error(None, &e.to_string());
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Commands::Tangle {
+            emit_depfile,
+            prune,
+            keep_going,
+            common,
+        }) => (
+            common,
+            Action::Tangle {
+                emit_depfile,
+                prune,
+                keep_going,
+                extract: None,
+            },
+        ),
+        Some(Commands::Extract { extract, common }) => {
+            if extract.chunks.is_none() && extract.chunks_re.is_none() && extract.explode.is_none()
+            {
+                Args::command()
+                    .error(
+                        clap::error::ErrorKind::MissingRequiredArgument,
+                        "extract needs --chunks, --chunks-re or --explode",
+                    )
+                    .exit();
+            }
+            (common, Action::Extract(extract))
+        }
+        Some(Commands::Weave {
+            output,
+            format,
+            latex_engine,
+            site,
+            weave,
+            common,
+        }) => (
+            common,
+            Action::Weave {
+                output,
+                format,
+                latex_engine,
+                site,
+                weave,
+            },
+        ),
+        Some(Commands::Serve {
+            bind,
+            port,
+            weave,
+            common,
+        }) => (
+            common,
+            Action::Serve {
+                addr: SocketAddr::new(bind, port),
+                weave,
+            },
+        ),
+        Some(Commands::Check { common }) => (common, Action::Check),
+        Some(Commands::List { format, common }) => (
+            common,
+            Action::List {
+                json: format == "json",
+            },
+        ),
+        Some(Commands::Graph { common }) => (common, Action::Graph),
+        Some(Commands::Verify { format, common }) => (
+            common,
+            Action::Verify {
+                json: format == "json",
+            },
+        ),
+        Some(Commands::Export { output, common }) => (common, Action::Export { output }),
+        Some(Commands::Lint { common }) => (common, Action::Lint),
+        Some(Commands::Fmt {
+            check,
+            sort,
+            common,
+        }) => (common, Action::Fmt { check, sort }),
+        Some(Commands::Stats {
+            format,
+            top,
+            common,
+        }) => (
+            common,
+            Action::Stats {
+                json: format == "json",
+                top,
+            },
+        ),
+        Some(Commands::Coverage { format, common }) => (
+            common,
+            Action::Coverage {
+                json: format == "json",
+            },
+        ),
+        Some(Commands::Untangle { patch, common }) => (common, Action::Untangle { patch }),
+        None => {
+            let action = if let Some(format) = &args.list_outputs {
+                Action::List {
+                    json: format == "json",
+                }
+            } else if args.untangle || args.untangle_patch {
+                Action::Untangle {
+                    patch: args.untangle_patch,
+                }
+            } else if args.check {
+                Action::Check
+            } else {
+                Action::Tangle {
+                    emit_depfile: args.emit_depfile,
+                    prune: false,
+                    keep_going: false,
+                    extract: Some(args.extract),
+                }
+            };
+            legacy = true;
+            (args.common, action)
+        }
+    };
+    filter.set(log::filter_for(common.quiet, common.verbose));
+    let output: Arc<dyn Reporter> = match common.message_format {
+        MessageFormat::Text => Arc::new(ConsoleReporter),
+        MessageFormat::Json => Arc::new(
+            JsonReporter::new(io::stderr())
+                .with_filter(log::filter_for(common.quiet, common.verbose)),
+        ),
+    };
+    // Suppressed warnings are neither printed nor counted
+    let counter = Arc::new(CountingReporter::new(Arc::clone(&output)));
+    let reporter: Arc<dyn Reporter> = Arc::new(SuppressingReporter::new(
+        Arc::clone(&counter) as Arc<dyn Reporter>,
+        common.allow.clone(),
+    ));
 
-    if let Err(e) = run(args) {
-        eprintln!("Error: {}", e);
+    if let Some(list) = &common.filelist {
+        match read_filelist(list) {
+            Ok(files) if files.is_empty() && common.files.is_empty() => {
+                reporter.error(None, &format!("{} lists no input files", list.display()));
+                std::process::exit(1);
+            }
+            Ok(files) => common.files.extend(files),
+            Err(e) => {
+                reporter.error(None, &e.to_string());
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if legacy {
+        // Not counted: --deny-warnings judges the run, not how it was invoked
+        SuppressingReporter::new(Arc::clone(&output), common.allow.clone()).coded_warning(
+            Code::Deprecated,
+            "running without a subcommand is deprecated; \
+             use 'tangle', 'extract', 'check', 'list' or 'untangle'",
+        );
+    }
+
+    if let Err(e) = run(&common, action, Arc::clone(&reporter)) {
+        reporter.error(Some(e.code()), &e.to_string());
         std::process::exit(1);
     }
+    if common.deny_warnings && counter.warnings() > 0 {
+        reporter.coded_error(
+            Code::WarningsDenied,
+            &format!(
+                "{} warning(s) reported and --deny-warnings is set",
+                counter.warnings()
+            ),
+        );
+        std::process::exit(EXIT_WARNINGS);
+    }
 }
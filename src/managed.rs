@@ -0,0 +1,77 @@
+// src/managed.rs
+//! Managed regions: partial generation into files that are also edited by hand.
+//!
+//! A `@managed` file chunk is not written wholesale. Instead, every region
+//!
+//! ```text
+//! // AZADI BEGIN name
+//! ...
+//! // AZADI END
+//! ```
+//!
+//! of the existing output is replaced with the expansion of chunk `name`,
+//! indented like its BEGIN marker. Everything outside the markers, and the
+//! markers themselves, are kept. Any comment leader may precede the keywords.
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::noweb::ChunkError;
+
+lazy_static! {
+    static ref BEGIN_RE: Regex = Regex::new(r"^(\s*)\S*\s*AZADI BEGIN\s+(\S+)").unwrap();
+    static ref END_RE: Regex = Regex::new(r"^\s*\S*\s*AZADI END\b").unwrap();
+}
+
+/// Replace the body of every managed region in `template` with the lines
+/// returned by `expand` for the region's chunk name. `file_name` is only used
+/// to report an unterminated region.
+pub fn fill_regions<F>(template: &str, file_name: &str, mut expand: F) -> Result<String, ChunkError>
+where
+    F: FnMut(&str, &str) -> Result<Vec<String>, ChunkError>,
+{
+    let mut out = String::with_capacity(template.len());
+    // The region being replaced and the line it started on
+    let mut open: Option<(String, usize)> = None;
+
+    for (line_no, line) in template.split_inclusive('\n').enumerate() {
+        if let Some((name, _)) = &open {
+            if END_RE.is_match(line) {
+                out.push_str(line);
+                open = None;
+            } else if BEGIN_RE.is_match(line) {
+                return Err(ChunkError::ParseError {
+                    file_name: file_name.to_string(),
+                    message: format!(
+                        "line {}: region '{}' starts before the previous one ends",
+                        line_no + 1,
+                        name
+                    ),
+                });
+            }
+            continue;
+        }
+
+        out.push_str(line);
+        if let Some(caps) = BEGIN_RE.captures(line) {
+            if !line.ends_with('\n') {
+                out.push('\n');
+            }
+            for expanded in expand(&caps[2], &caps[1])? {
+                out.push_str(&expanded);
+            }
+            open = Some((caps[2].to_string(), line_no));
+        }
+    }
+
+    match open {
+        Some((name, line_no)) => Err(ChunkError::ParseError {
+            file_name: file_name.to_string(),
+            message: format!(
+                "line {}: region '{}' has no AZADI END marker",
+                line_no + 1,
+                name
+            ),
+        }),
+        None => Ok(out),
+    }
+}
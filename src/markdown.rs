@@ -0,0 +1,192 @@
+// src/markdown.rs
+//! Entangled/lmt-style Markdown front-end.
+//!
+//! Fenced code blocks carry their chunk name in the info string:
+//!
+//! - Entangled: ```` ```{.python #name} ````, ```` ```{.python file=src/x.py} ````
+//!   or both at once, in which case the file chunk includes the named one.
+//! - lmt: ```` ```go "Chunk name" ````, ```` ```go "Chunk name" += ```` or
+//!   ```` ```go path/to/file.go ````; lmt's `<<<Chunk name>>>` references are
+//!   translated to the configured delimiters.
+//!
+//! Names containing whitespace are normalized by joining the words with `-`.
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::attributes::Attributes;
+use crate::noweb::{ChunkModifiers, ChunkStore};
+
+lazy_static! {
+    static ref FENCE_RE: Regex = Regex::new(r"^(\s*)(`{3,}|~{3,})\s*(.*?)\s*$").unwrap();
+    static ref LMT_REF_RE: Regex = Regex::new(r"^(\s*)<<<(.+?)>>>\s*$").unwrap();
+}
+
+/// Joins whitespace-separated words so the name is a valid chunk name.
+pub fn normalize_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join("-")
+}
+
+/// What a fenced block's info string declares.
+#[derive(Debug, Default, PartialEq)]
+struct BlockHeader {
+    name: Option<String>,
+    file: Option<String>,
+    /// lmt's `+=`, or any Entangled block: the block continues an existing
+    /// chunk.
+    append: bool,
+    /// Language of the block, e.g. `python` in `{.python #name}`.
+    lang: Option<String>,
+    /// Entangled's other `key=value` pairs, e.g. `mode=755`.
+    attributes: Attributes,
+}
+
+/// Split an attribute list on whitespace, keeping double-quoted values together.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    for c in text.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn parse_header(info: &str) -> BlockHeader {
+    let mut header = BlockHeader::default();
+
+    // Entangled: {.lang #name file=path}
+    if let Some(inner) = info.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+        // Repeated `#name` blocks are concatenated, as in Entangled
+        header.append = true;
+        for token in tokenize(inner) {
+            if let Some(id) = token.strip_prefix('#') {
+                header.name = Some(normalize_name(id));
+            } else if let Some(lang) = token.strip_prefix('.') {
+                header.lang.get_or_insert_with(|| lang.to_string());
+            } else if let Some(path) = token.strip_prefix("file=") {
+                header.file = Some(path.to_string());
+            } else if let Some((key, value)) = token.split_once('=') {
+                header.attributes.insert(key, value);
+            }
+        }
+        return header;
+    }
+
+    // lmt: lang "Name" [+=]  or  lang path/to/file
+    let mut rest = info.splitn(2, char::is_whitespace);
+    header.lang = rest
+        .next()
+        .filter(|lang| !lang.is_empty())
+        .map(str::to_string);
+    let rest = rest.next().unwrap_or("").trim();
+    header.append = rest.ends_with("+=");
+    let rest = rest.strip_suffix("+=").unwrap_or(rest).trim();
+    if let Some(quoted) = rest.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        header.name = Some(normalize_name(quoted));
+    } else if !rest.is_empty() && !rest.contains(char::is_whitespace) {
+        header.file = Some(rest.to_string());
+    }
+    header
+}
+
+/// Parse Markdown text and store the chunks declared by its fenced blocks.
+pub fn read_entangled(store: &mut ChunkStore, text: &str, file_idx: usize) {
+    let (open_delim, close_delim) = store.delimiters();
+    // The open fence plus the (chunk name, definition index) of each target
+    let mut current: Option<(String, Vec<(String, usize)>)> = None;
+
+    for (line_no, line) in text.lines().enumerate() {
+        if let Some((fence, targets)) = &current {
+            let is_close = FENCE_RE.captures(line).is_some_and(|caps| {
+                let marker = &caps[2];
+                caps[3].is_empty() && marker.starts_with(&fence[..1]) && marker.len() >= fence.len()
+            });
+            if is_close {
+                current = None;
+                continue;
+            }
+            let stored = match LMT_REF_RE.captures(line) {
+                Some(caps) => format!(
+                    "{}{}{}{}",
+                    &caps[1],
+                    open_delim,
+                    normalize_name(&caps[2]),
+                    close_delim
+                ),
+                None => line.to_string(),
+            };
+            for (name, def_idx) in targets {
+                store.push_line(name, *def_idx, &stored, line_no);
+            }
+            continue;
+        }
+
+        let Some(caps) = FENCE_RE.captures(line) else {
+            continue;
+        };
+        let indent = caps[1].len();
+        let fence = caps[2].to_string();
+        let header = parse_header(&caps[3]);
+        let modifiers = ChunkModifiers {
+            append: header.append,
+            lang: header.lang.clone(),
+            attributes: header.attributes.clone(),
+            ..ChunkModifiers::default()
+        };
+
+        let mut targets = Vec::new();
+        match (header.name, header.file) {
+            (Some(name), Some(path)) => {
+                // The file includes the named chunk, which keeps its own identity.
+                let file_chunk = format!("@file {}", path);
+                if store.validate_chunk_name(&file_chunk, &file_chunk) {
+                    if let Some((stored, idx)) = store.begin_definition(
+                        &file_chunk,
+                        &ChunkModifiers {
+                            lang: header.lang.clone(),
+                            attributes: header.attributes.clone(),
+                            ..ChunkModifiers::default()
+                        },
+                        indent,
+                        file_idx,
+                        line_no,
+                    ) {
+                        let reference =
+                            format!("{}{}{}{}", &caps[1], open_delim, name, close_delim);
+                        store.push_line(&stored, idx, &reference, line_no);
+                    }
+                }
+                targets.push(name);
+            }
+            (Some(name), None) => targets.push(name),
+            (None, Some(path)) => targets.push(format!("@file {}", path)),
+            (None, None) => {}
+        }
+
+        let mut defs = Vec::new();
+        for name in targets {
+            if !store.validate_chunk_name(&name, &name) {
+                continue;
+            }
+            if let Some(target) =
+                store.begin_definition(&name, &modifiers, indent, file_idx, line_no)
+            {
+                defs.push(target);
+            }
+        }
+        current = Some((fence, defs));
+    }
+
+    store.finish_read();
+}
@@ -0,0 +1,131 @@
+// src/markers.rs
+//! Which comment markers may precede the chunk lines of each input. By
+//! default every input uses the configured markers; with auto-detection an
+//! input's extension picks the comment syntax of its language instead, and
+//! overrides give the markers of the inputs matching a glob.
+use std::path::Path;
+
+use crate::glob::glob_match;
+
+/// The comment markers of the languages recognised by file extension.
+const EXTENSION_MARKERS: &[(&[&str], &[&str])] = &[
+    (
+        &[
+            "py", "pyw", "sh", "bash", "zsh", "fish", "rb", "pl", "pm", "r", "jl", "nim", "yaml",
+            "yml", "toml", "cmake", "mk", "ps1", "tcl", "awk",
+        ],
+        &["#"],
+    ),
+    (
+        &[
+            "rs", "c", "h", "cc", "cpp", "cxx", "hpp", "hh", "java", "js", "mjs", "ts", "jsx",
+            "tsx", "go", "swift", "kt", "kts", "cs", "scala", "zig", "dart", "sv", "proto",
+        ],
+        &["//"],
+    ),
+    (&["tex", "sty", "cls", "bib", "erl", "hrl"], &["%"]),
+    (
+        &[
+            "hs", "lhs", "lua", "sql", "elm", "ada", "adb", "ads", "vhd", "vhdl",
+        ],
+        &["--"],
+    ),
+    (
+        &[
+            "lisp", "lsp", "el", "clj", "cljs", "scm", "ss", "rkt", "ini",
+        ],
+        &[";"],
+    ),
+    (&["vim"], &["\""]),
+    (&["f90", "f95", "f03", "f08"], &["!"]),
+];
+
+/// The comment markers of the language `file_name`'s extension names, if it
+/// is one of the recognised ones.
+pub fn extension_markers(file_name: &str) -> Option<Vec<String>> {
+    let ext = Path::new(file_name)
+        .extension()?
+        .to_string_lossy()
+        .to_ascii_lowercase();
+    EXTENSION_MARKERS
+        .iter()
+        .find(|(exts, _)| exts.contains(&ext.as_str()))
+        .map(|(_, markers)| markers.iter().map(|m| m.to_string()).collect())
+}
+
+/// A marker override written as a glob and comma-separated markers, e.g.
+/// `docs/*.tex=%`; nothing after the `=` means no markers.
+pub fn marker_override(text: &str) -> Result<(String, Vec<String>), String> {
+    let (pattern, markers) = text
+        .split_once('=')
+        .filter(|(pattern, _)| !pattern.trim().is_empty())
+        .ok_or_else(|| format!("'{}' is not GLOB=MARKERS", text))?;
+    let markers = markers
+        .split(',')
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(str::to_string)
+        .collect();
+    Ok((pattern.trim().to_string(), markers))
+}
+
+/// How the comment markers of an input are chosen.
+#[derive(Debug, Clone, Default)]
+pub struct MarkerRules {
+    auto_detect: bool,
+    /// (glob, markers) pairs; the last one matching an input wins.
+    overrides: Vec<(String, Vec<String>)>,
+}
+
+impl MarkerRules {
+    /// Pick each input's markers from its extension, keeping the configured
+    /// ones for extensions not recognised.
+    pub fn auto_detect(mut self, auto: bool) -> Self {
+        self.auto_detect = auto;
+        self
+    }
+
+    /// Use `markers` for the inputs whose names match `pattern`, whatever
+    /// their extension; a later override wins over an earlier one.
+    pub fn override_for<S: AsRef<str>>(mut self, pattern: &str, markers: &[S]) -> Self {
+        let markers = markers.iter().map(|m| m.as_ref().to_string()).collect();
+        self.overrides.push((pattern.to_string(), markers));
+        self
+    }
+
+    /// The markers of `file_name`, or None for the configured ones.
+    pub fn markers_for(&self, file_name: &str) -> Option<Vec<String>> {
+        let name = file_name.replace('\\', "/");
+        let name = name.trim_start_matches("./");
+        if let Some((_, markers)) = self
+            .overrides
+            .iter()
+            .rev()
+            .find(|(pattern, _)| glob_match(pattern, name))
+        {
+            return Some(markers.clone());
+        }
+        if self.auto_detect {
+            extension_markers(file_name)
+        } else {
+            None
+        }
+    }
+
+    /// Every marker set `markers_for` may return.
+    pub fn marker_sets(&self) -> Vec<Vec<String>> {
+        let mut sets: Vec<Vec<String>> = self
+            .overrides
+            .iter()
+            .map(|(_, markers)| markers.clone())
+            .collect();
+        if self.auto_detect {
+            sets.extend(
+                EXTENSION_MARKERS
+                    .iter()
+                    .map(|(_, markers)| markers.iter().map(|m| m.to_string()).collect()),
+            );
+        }
+        sets
+    }
+}
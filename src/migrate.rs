@@ -0,0 +1,267 @@
+// src/migrate.rs
+//! Rewriting documents of classic noweb, nuweb and FunnelWeb in azadi's own
+//! syntax, so a project can move over one document at a time. Chunk lines
+//! get the comment marker of the code's language, names containing
+//! whitespace are joined with `-`, and what has no azadi equivalent is
+//! reported for a hand edit.
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::funnelweb::funnelweb_header;
+use crate::markdown::normalize_name;
+use crate::nuweb::{nuweb_header, Header, BODY_OPEN_RE, REF_RE};
+
+lazy_static! {
+    static ref OPEN_RE: Regex = Regex::new(r"^<<(.+?)>>=\s*$").unwrap();
+    static ref END_RE: Regex = Regex::new(r"^@(?:[ \t]+(.*?))?\s*$").unwrap();
+    static ref LONE_REF_RE: Regex = Regex::new(r"^(\s*)<<(.+?)>>\s*$").unwrap();
+    static ref INLINE_REF_RE: Regex = Regex::new(r"(^|[^@])<<(.+?)>>").unwrap();
+    static ref SCRAP_REF_RE: Regex = Regex::new(r"@<\s*(.+?)\s*@>").unwrap();
+    static ref LEADING_DELIM_RE: Regex = Regex::new(r"^(\s*)<<").unwrap();
+}
+
+/// A migrated document and the lines left for a hand edit.
+#[derive(Debug, Default, PartialEq)]
+pub struct Migrated {
+    pub text: String,
+    /// 1-based line numbers of the input, each with what needs doing.
+    pub notes: Vec<(usize, String)>,
+}
+
+/// `marker` followed by a space, or nothing for an empty marker.
+fn marker_prefix(marker: &str) -> String {
+    if marker.is_empty() {
+        String::new()
+    } else {
+        format!("{} ", marker)
+    }
+}
+
+/// Rewrite the classic noweb document `source`. Chunk lines are prefixed with
+/// `marker` and a space, or left bare for an empty marker. The root chunks
+/// named in `files` become `@file` chunks written to their names; with none
+/// named, every root chunk is noted, as the result would write no file.
+pub fn migrate_noweb(source: &str, marker: &str, files: &[String]) -> Migrated {
+    let prefix = marker_prefix(marker);
+    let mut migrated = Migrated::default();
+    let referenced: HashSet<&str> = source
+        .lines()
+        .filter(|line| !OPEN_RE.is_match(line))
+        .flat_map(|line| INLINE_REF_RE.captures_iter(line))
+        .filter_map(|caps| caps.get(2))
+        .map(|name| name.as_str().trim())
+        .collect();
+    let mut roots_noted = HashSet::new();
+    let mut in_code = false;
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        if let Some(caps) = OPEN_RE.captures(line) {
+            if in_code {
+                // noweb ends a chunk at the next one's opening; azadi needs the `@`
+                migrated.text.push_str(&format!("{}@\n", prefix));
+            }
+            let raw = caps.get(1).map_or("", |m| m.as_str()).trim();
+            if files.is_empty() && !referenced.contains(raw) && roots_noted.insert(raw) {
+                migrated.notes.push((
+                    line_no,
+                    format!(
+                        "root chunk '{}' is written to no file; pass --file {} to make it \
+                         a @file chunk",
+                        raw, raw
+                    ),
+                ));
+            }
+            let name = if files.iter().any(|f| f == raw) {
+                format!("@file {}", raw)
+            } else {
+                normalize_name(raw)
+            };
+            migrated
+                .text
+                .push_str(&format!("{}<<{}>>=\n", prefix, name));
+            in_code = true;
+            continue;
+        }
+        if let Some(caps) = END_RE.captures(line) {
+            let rest = caps.get(1).map_or("", |m| m.as_str());
+            if in_code {
+                migrated.text.push_str(&format!("{}@\n", prefix));
+                in_code = false;
+                // `%def` lines feed noweb's identifier index, which azadi lacks
+                if rest.starts_with("%def") {
+                    continue;
+                }
+                if !rest.is_empty() {
+                    migrated.text.push_str(&format!("{}\n", rest));
+                }
+            } else {
+                migrated.text.push_str(&format!("{}\n", rest));
+            }
+            continue;
+        }
+        if !in_code {
+            migrated.text.push_str(&format!("{}\n", line));
+        } else if let Some(caps) = LONE_REF_RE.captures(line) {
+            migrated.text.push_str(&format!(
+                "{}{}<<{}>>\n",
+                &caps[1],
+                prefix,
+                normalize_name(&caps[2])
+            ));
+        } else {
+            if let Some(caps) = INLINE_REF_RE.captures(line) {
+                migrated.notes.push((
+                    line_no,
+                    format!(
+                        "the reference <<{}>> shares its line with other code; \
+                         move it to a line of its own",
+                        &caps[2]
+                    ),
+                ));
+            }
+            // noweb doubles an `@` starting a code line
+            let line = line
+                .strip_prefix('@')
+                .filter(|l| l.starts_with('@'))
+                .unwrap_or(line);
+            if line.trim() == "@" {
+                migrated
+                    .notes
+                    .push((line_no, "this body line reads as a chunk end".to_string()));
+            }
+            migrated.text.push_str(&format!("{}\n", line));
+        }
+    }
+    if in_code {
+        migrated.text.push_str(&format!("{}@\n", prefix));
+    }
+    migrated
+}
+
+/// Rewrite the nuweb document `source`, prefixing chunk lines with `marker`
+/// as `migrate_noweb` does; `@o` scraps become `@file` chunks.
+pub fn migrate_nuweb(source: &str, marker: &str) -> Migrated {
+    migrate_scraps(source, marker, nuweb_header)
+}
+
+/// Rewrite the FunnelWeb document `source`, prefixing chunk lines with
+/// `marker` as `migrate_noweb` does; `@O` macros become `@file` chunks.
+pub fn migrate_funnelweb(source: &str, marker: &str) -> Migrated {
+    migrate_scraps(source, marker, funnelweb_header)
+}
+
+/// The rewriting of a document whose chunks have `@{ ... @}` bodies.
+struct ScrapMigration {
+    prefix: String,
+    migrated: Migrated,
+}
+
+impl ScrapMigration {
+    fn line(&mut self, text: &str) {
+        self.migrated.text.push_str(text);
+        self.migrated.text.push('\n');
+    }
+
+    fn open(&mut self, name: &str) {
+        let line = format!("{}<<{}>>=", self.prefix, name);
+        self.line(&line);
+    }
+
+    /// Feed body text, including the rest of a line opening the body;
+    /// returns true while the body is still open.
+    fn body(&mut self, text: &str, line_no: usize) -> bool {
+        match text.find("@}") {
+            Some(end) => {
+                if !text[..end].trim().is_empty() {
+                    self.body_line(&text[..end], line_no);
+                }
+                let end_line = format!("{}@", self.prefix);
+                self.line(&end_line);
+                let after = text[end + 2..].trim();
+                if !after.is_empty() {
+                    self.line(after);
+                }
+                false
+            }
+            None => {
+                self.body_line(text, line_no);
+                true
+            }
+        }
+    }
+
+    fn body_line(&mut self, text: &str, line_no: usize) {
+        if let Some(caps) = REF_RE.captures(text) {
+            let line = format!(
+                "{}{}<<{}>>",
+                &caps[1],
+                self.prefix,
+                normalize_name(&caps[2])
+            );
+            self.line(&line);
+            return;
+        }
+        if let Some(caps) = SCRAP_REF_RE.captures(text) {
+            self.migrated.notes.push((
+                line_no,
+                format!(
+                    "the reference @<{}@> shares its line with other code; \
+                     move it to a line of its own",
+                    &caps[1]
+                ),
+            ));
+        }
+        let text = text.replace("@@", "@");
+        if text.trim() == "@" {
+            self.migrated
+                .notes
+                .push((line_no, "this body line reads as a chunk end".to_string()));
+        }
+        // Literal text that azadi would read as a chunk opening or reference
+        let text = LEADING_DELIM_RE.replace(&text, "${1}@<<");
+        self.line(&text);
+    }
+}
+
+fn migrate_scraps(source: &str, marker: &str, header: Header) -> Migrated {
+    let mut migration = ScrapMigration {
+        prefix: marker_prefix(marker),
+        migrated: Migrated::default(),
+    };
+    // A header line waiting for its `@{`, and whether a body is open
+    let mut pending: Option<(String, &str)> = None;
+    let mut in_body = false;
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        if in_body {
+            in_body = migration.body(line, line_no);
+            continue;
+        }
+        if let Some((name, header_line)) = pending.take() {
+            if let Some(caps) = BODY_OPEN_RE.captures(line) {
+                migration.open(&name);
+                in_body = caps[1].trim().is_empty() || migration.body(&caps[1], line_no);
+                continue;
+            }
+            migration.line(header_line);
+        }
+        match header(line) {
+            Some((name, Some(rest))) => {
+                migration.open(&name);
+                in_body = rest.trim().is_empty() || migration.body(&rest, line_no);
+            }
+            Some((name, None)) => pending = Some((name, line)),
+            None => migration.line(line),
+        }
+    }
+    if let Some((_, header_line)) = pending {
+        migration.line(header_line);
+    }
+    if in_body {
+        let end_line = format!("{}@", migration.prefix);
+        migration.line(&end_line);
+    }
+    migration.migrated
+}
@@ -0,0 +1,62 @@
+// src/names.rs
+//! Naming conventions for chunks. Whatever the policy, a chunk name must be
+//! non-empty and free of whitespace; a policy can further restrict the
+//! characters used, the length and the prefixes. File chunks are named by
+//! their paths and are not subject to it.
+use regex::Regex;
+
+/// Rules chunk names must follow; the default allows any name.
+#[derive(Debug, Clone, Default)]
+pub struct NamePolicy {
+    /// The allowed characters as a regex class body, e.g. `a-z0-9_-`, and
+    /// the anchored regex matching one of them.
+    allowed: Option<(String, Regex)>,
+    max_length: Option<usize>,
+    reserved_prefixes: Vec<String>,
+}
+
+impl NamePolicy {
+    /// Allow only the characters of `class`, the body of a regex character
+    /// class such as `a-z0-9_:-`.
+    pub fn allowed_chars(mut self, class: &str) -> Result<Self, regex::Error> {
+        let re = Regex::new(&format!("^[{}]$", class))?;
+        self.allowed = Some((class.to_string(), re));
+        Ok(self)
+    }
+
+    /// Allow names of at most `max` characters.
+    pub fn max_length(mut self, max: usize) -> Self {
+        self.max_length = Some(max);
+        self
+    }
+
+    /// Reject names starting with `prefix`, e.g. one kept for generated chunks.
+    pub fn reserved_prefix(mut self, prefix: &str) -> Self {
+        self.reserved_prefixes.push(prefix.to_string());
+        self
+    }
+
+    /// Why `name` breaks the policy, if it does, e.g. "is longer than 20
+    /// characters".
+    pub fn check(&self, name: &str) -> Result<(), String> {
+        if let Some((class, re)) = &self.allowed {
+            let mut buf = [0; 4];
+            if let Some(c) = name.chars().find(|c| !re.is_match(c.encode_utf8(&mut buf))) {
+                return Err(format!("contains {:?}, which is not in [{}]", c, class));
+            }
+        }
+        if let Some(max) = self.max_length {
+            if name.chars().count() > max {
+                return Err(format!("is longer than {} characters", max));
+            }
+        }
+        if let Some(prefix) = self
+            .reserved_prefixes
+            .iter()
+            .find(|p| name.starts_with(p.as_str()))
+        {
+            return Err(format!("starts with the reserved prefix '{}'", prefix));
+        }
+        Ok(())
+    }
+}
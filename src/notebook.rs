@@ -0,0 +1,96 @@
+// src/notebook.rs
+//! Jupyter notebook (.ipynb) front-end.
+//!
+//! Code cells tagged `chunk:NAME` define the chunk `NAME`, and cells tagged
+//! `file:PATH` define the file chunk `@file PATH`. Untagged cells are ignored.
+//! Since notebooks have no meaningful line numbers, locations refer to the
+//! zero-based cell index.
+use serde_json::Value;
+
+use crate::noweb::{ChunkError, ChunkModifiers, ChunkStore};
+
+/// Returns true when `path` names a notebook by extension.
+pub fn is_notebook(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".ipynb")
+}
+
+/// Parse a notebook document and store the chunks found in its tagged code cells.
+pub fn read_notebook(
+    store: &mut ChunkStore,
+    text: &str,
+    file_idx: usize,
+) -> Result<(), ChunkError> {
+    let parse_error = |message: String| ChunkError::ParseError {
+        file_name: store.file_name(file_idx),
+        message,
+    };
+
+    let doc: Value = serde_json::from_str(text).map_err(|e| parse_error(e.to_string()))?;
+    let cells = doc
+        .get("cells")
+        .and_then(Value::as_array)
+        .ok_or_else(|| parse_error("notebook has no 'cells' array".to_string()))?;
+
+    for (cell_idx, cell) in cells.iter().enumerate() {
+        if cell.get("cell_type").and_then(Value::as_str) != Some("code") {
+            continue;
+        }
+        let names = cell_chunk_names(cell);
+        if names.is_empty() {
+            continue;
+        }
+        let lines = cell_source(cell);
+        // Cells sharing a `chunk:` tag are concatenated
+        let modifiers = ChunkModifiers {
+            append: true,
+            ..ChunkModifiers::default()
+        };
+        for name in names {
+            if !store.validate_chunk_name(&name, &name) {
+                continue;
+            }
+            if let Some((stored, def_idx)) =
+                store.begin_definition(&name, &modifiers, 0, file_idx, cell_idx)
+            {
+                for line in &lines {
+                    store.push_line(&stored, def_idx, line, cell_idx);
+                }
+            }
+        }
+    }
+
+    store.finish_read();
+    Ok(())
+}
+
+/// Chunk names declared by a cell's `chunk:` and `file:` tags.
+fn cell_chunk_names(cell: &Value) -> Vec<String> {
+    let tags = cell
+        .get("metadata")
+        .and_then(|m| m.get("tags"))
+        .and_then(Value::as_array)
+        .map_or(&[][..], Vec::as_slice);
+
+    tags.iter()
+        .filter_map(Value::as_str)
+        .filter_map(|tag| {
+            if let Some(name) = tag.strip_prefix("chunk:") {
+                Some(name.trim().to_string())
+            } else {
+                tag.strip_prefix("file:")
+                    .map(|path| format!("@file {}", path.trim()))
+            }
+        })
+        .filter(|name| !name.is_empty() && name != "@file ")
+        .collect()
+}
+
+/// A cell's source, which nbformat stores either as one string or a list of lines.
+fn cell_source(cell: &Value) -> Vec<String> {
+    let text = match cell.get("source") {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(parts)) => parts.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    };
+    text.lines().map(str::to_string).collect()
+}
@@ -1,123 +1,831 @@
 // <[@file src/noweb.rs]>=
 // src/noweb.rs
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::io::{self, Write};
-use std::path::{Component, Path};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
 use std::rc::Rc;
+use std::sync::Arc;
+use tracing::{debug, trace};
 
+use crate::attributes::Attributes;
+use crate::diagnostic::Code;
+use crate::dialect::Dialect;
+use crate::export::{NowebLine, NowebSection, NowebSections};
+use crate::filter::{ForLanguage, LineFilter, OutputFilter, PerLine};
+use crate::funnelweb::read_funnelweb;
+use crate::glob::glob_match;
+use crate::harness::TestHarness;
+use crate::managed::fill_regions;
+use crate::markdown::read_entangled;
+use crate::markers::MarkerRules;
+use crate::names::NamePolicy;
+use crate::notebook::{is_notebook, read_notebook};
+use crate::nuweb::read_nuweb;
+use crate::report::{self, Reporter};
+use crate::rst::read_rst;
+use crate::safe_writer::ManifestEntry;
+use crate::stats::{ChunkStats, Coverage};
 use crate::AzadiError;
 use crate::SafeFileWriter;
 use crate::SafeWriterError;
+use crate::WriteOutcome;
 
 /// Represents a single definition of a named chunk.
 #[derive(Debug, Clone)]
 struct ChunkDef {
     content: Vec<String>,
+    /// Source line of each entry in `content`.
+    source_lines: Vec<usize>,
     base_indent: usize,
     file_idx: usize,
     line: usize,
+    /// The body is a shell command whose output replaces it (@exec).
+    exec: bool,
+    /// Only chunks defined in the same file may reference it (@private).
+    private: bool,
+    /// The body is emitted as written, without expanding references (@verbatim).
+    verbatim: bool,
+    /// Only the marked regions of the existing output are regenerated (@managed).
+    managed: bool,
+    /// The chunk is a test of the generated test harness (@test).
+    test: bool,
+    /// Definitions are concatenated by ascending weight, then in reading order.
+    order: i64,
+    /// Comment prefix stripped from body lines during expansion (`@uncomment(PREFIX)`).
+    uncomment: Option<String>,
+    /// Text after the `=` of the opening line, e.g. `rust` in `<<parser>>= rust`.
+    annotation: Option<String>,
+    /// Language of the body; see `ChunkModifiers::language`.
+    lang: Option<String>,
+    /// `key=value` pairs written after the chunk name.
+    attributes: Attributes,
+}
+
+/// State of Tarjan's algorithm for the strongly connected components of a
+/// graph given as adjacency sets; iterative, so deep chains cannot overflow
+/// the stack.
+struct Tarjan<'a, 'g> {
+    edges: &'g BTreeMap<&'a str, BTreeSet<&'a str>>,
+    index: HashMap<&'a str, usize>,
+    low: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    on_stack: BTreeSet<&'a str>,
+    /// Nodes being visited, each with the successors still to visit.
+    frames: Vec<(&'a str, Vec<&'a str>)>,
+    components: Vec<Vec<&'a str>>,
+}
+
+impl<'a, 'g> Tarjan<'a, 'g> {
+    fn run(edges: &'g BTreeMap<&'a str, BTreeSet<&'a str>>) -> Vec<Vec<&'a str>> {
+        let mut tarjan = Tarjan {
+            edges,
+            index: HashMap::new(),
+            low: HashMap::new(),
+            stack: Vec::new(),
+            on_stack: BTreeSet::new(),
+            frames: Vec::new(),
+            components: Vec::new(),
+        };
+        for &root in edges.keys() {
+            if !tarjan.index.contains_key(root) {
+                tarjan.visit(root);
+                tarjan.walk();
+            }
+        }
+        tarjan.components
+    }
+
+    fn visit(&mut self, node: &'a str) {
+        let n = self.index.len();
+        self.index.insert(node, n);
+        self.low.insert(node, n);
+        self.stack.push(node);
+        self.on_stack.insert(node);
+        let next = self
+            .edges
+            .get(node)
+            .map_or(Vec::new(), |to| to.iter().rev().copied().collect());
+        self.frames.push((node, next));
+    }
+
+    fn walk(&mut self) {
+        while let Some((node, next)) = self.frames.last_mut() {
+            let node = *node;
+            if let Some(to) = next.pop() {
+                if !self.index.contains_key(to) {
+                    self.visit(to);
+                } else if self.on_stack.contains(to) {
+                    let low = self.low[node].min(self.index[to]);
+                    self.low.insert(node, low);
+                }
+                continue;
+            }
+            self.frames.pop();
+            if let Some(&(parent, _)) = self.frames.last() {
+                let low = self.low[parent].min(self.low[node]);
+                self.low.insert(parent, low);
+            }
+            if self.low[node] == self.index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = self.stack.pop() {
+                    self.on_stack.remove(member);
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                self.components.push(component);
+            }
+        }
+    }
 }
 
 impl ChunkDef {
     fn new(base_indent: usize, file_idx: usize, line: usize) -> Self {
         Self {
             content: Vec::new(),
+            source_lines: Vec::new(),
             base_indent,
             file_idx,
             line,
+            exec: false,
+            private: false,
+            verbatim: false,
+            managed: false,
+            test: false,
+            order: 0,
+            uncomment: None,
+            annotation: None,
+            lang: None,
+            attributes: Attributes::default(),
         }
     }
 }
 
-/// Indicates file + line for error reporting.
+/// Modifiers written before the chunk name on an opening line,
+/// e.g. `<<@replace @file out.txt>>=`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChunkModifiers {
+    pub replace: bool,
+    pub file: bool,
+    pub exec: bool,
+    pub private: bool,
+    pub verbatim: bool,
+    pub managed: bool,
+    /// The body is documentation for the weaver, never tangled (@doc).
+    pub doc: bool,
+    /// The chunk is a test of the generated test harness (@test).
+    pub test: bool,
+    /// The definition intentionally continues an existing chunk (@append).
+    pub append: bool,
+    /// Replace only the definitions read from this file (`@replace(from=FILE)`).
+    pub replace_from: Option<String>,
+    /// Weight of the definition among those of the same chunk (`@order N`).
+    pub order: Option<i64>,
+    /// Comment prefix stripped from body lines (`@uncomment(PREFIX)`).
+    pub uncomment: Option<String>,
+    /// Language of the body, used to highlight it when weaving (`@lang(NAME)`).
+    pub lang: Option<String>,
+    /// Text after the `=` of the opening line, e.g. `rust` in `<<parser>>= rust`.
+    pub annotation: Option<String>,
+    /// `key=value` pairs after the chunk name, e.g. `<<@file a.sh mode=755>>=`.
+    pub attributes: Attributes,
+}
+
+impl ChunkModifiers {
+    /// Parse a run of `@word` modifiers; None if any of them is unknown.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut modifiers = Self::default();
+        let mut words = text.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "@order" => modifiers.order = Some(words.next()?.parse().ok()?),
+                "@replace" => modifiers.replace = true,
+                "@file" => modifiers.file = true,
+                "@exec" => modifiers.exec = true,
+                "@private" => modifiers.private = true,
+                "@verbatim" => modifiers.verbatim = true,
+                "@managed" => modifiers.managed = true,
+                "@append" => modifiers.append = true,
+                "@doc" => modifiers.doc = true,
+                "@test" => modifiers.test = true,
+                _ if word.starts_with("@lang(") => {
+                    let lang = word
+                        .strip_prefix("@lang(")
+                        .and_then(|rest| rest.strip_suffix(')'))
+                        .filter(|lang| !lang.is_empty())?;
+                    modifiers.lang = Some(lang.to_string());
+                }
+                _ if word.starts_with("@uncomment(") => {
+                    let prefix = word
+                        .strip_prefix("@uncomment(")
+                        .and_then(|rest| rest.strip_suffix(')'))
+                        .filter(|prefix| !prefix.is_empty())?;
+                    modifiers.uncomment = Some(prefix.to_string());
+                }
+                _ => {
+                    let from = word
+                        .strip_prefix("@replace(from=")
+                        .and_then(|rest| rest.strip_suffix(')'))
+                        .filter(|from| !from.is_empty())?;
+                    modifiers.replace_from = Some(from.to_string());
+                }
+            }
+        }
+        Some(modifiers)
+    }
+
+    /// The modifiers of an opening line matched by a chunk opening pattern,
+    /// with its annotation; None if a modifier is unknown.
+    fn of_opening(caps: &regex::Captures) -> Option<Self> {
+        let mut modifiers = Self::parse(caps.get(2).map_or("", |m| m.as_str()))?;
+        modifiers.attributes = Attributes::parse(caps.get(4).map_or("", |m| m.as_str()))?;
+        modifiers.annotation = caps
+            .get(5)
+            .map(|m| m.as_str().to_string())
+            .filter(|a| !a.is_empty());
+        Some(modifiers)
+    }
+
+    /// Language of the body: the one given with `@lang(NAME)`, else the
+    /// `lang` attribute, else an annotation of a single word, as in
+    /// `<<parser>>= rust`.
+    pub fn language(&self) -> Option<&str> {
+        self.lang
+            .as_deref()
+            .or_else(|| self.attributes.get("lang"))
+            .or_else(|| {
+                self.annotation
+                    .as_deref()
+                    .filter(|a| !a.contains(char::is_whitespace))
+            })
+    }
+}
+
+/// Options for a top-level expansion.
 #[derive(Debug, Clone)]
+pub struct ExpandOptions {
+    /// Indentation prepended to every output line.
+    pub indent: String,
+    /// Concatenate the top-level chunk's definitions in reverse order,
+    /// as `@reversed` does at a reference.
+    pub reversed: bool,
+    /// Nesting depth beyond which expansion fails with a recursion error.
+    pub max_depth: usize,
+    /// Template of a line directive emitted wherever the output stops following
+    /// the source consecutively, e.g. `#line {line} "{file}"`.
+    pub line_directive: Option<String>,
+    /// Keep the provenance of every line written by `Clip::write_files_with_options`.
+    pub track_provenance: bool,
+    /// Values substituted for `@{NAME}` in chunk bodies.
+    pub defines: HashMap<String, String>,
+    /// Marker (e.g. `\`) that, ending a body line, joins the next line onto it.
+    pub continuation: Option<String>,
+    /// Comment prefix stripped from body lines, for chunks without `@uncomment`.
+    pub uncomment: Option<String>,
+}
+
+impl Default for ExpandOptions {
+    fn default() -> Self {
+        Self {
+            indent: String::new(),
+            reversed: false,
+            max_depth: 100,
+            line_directive: None,
+            track_provenance: false,
+            defines: HashMap::new(),
+            continuation: None,
+            uncomment: None,
+        }
+    }
+}
+
+impl ExpandOptions {
+    /// Replace `@{NAME}` with the defined values; unknown names are kept.
+    fn substitute<'a>(&self, line: Cow<'a, str>) -> Cow<'a, str> {
+        if self.defines.is_empty() || !line.contains("@{") {
+            return line;
+        }
+        let mut out = String::with_capacity(line.len());
+        let mut rest = line.as_ref();
+        while let Some(start) = rest.find("@{") {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            match after
+                .find('}')
+                .and_then(|end| self.defines.get(&after[..end]).map(|value| (end, value)))
+            {
+                Some((end, value)) => {
+                    out.push_str(value);
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    out.push_str("@{");
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        Cow::Owned(out)
+    }
+
+    /// The line without its trailing continuation marker, if it ends with one.
+    fn continued(&self, line: &str) -> Option<String> {
+        let marker = self.continuation.as_deref().filter(|m| !m.is_empty())?;
+        line.trim_end_matches(['\n', '\r'])
+            .strip_suffix(marker)
+            .map(str::to_string)
+    }
+
+    /// Render the line directive for a source location.
+    fn directive(&self, file_name: &str, line: usize) -> Option<String> {
+        self.line_directive.as_ref().map(|template| {
+            let text = template
+                .replace("{line}", &(line + 1).to_string())
+                .replace("{file}", file_name);
+            format!("{}\n", text)
+        })
+    }
+}
+
+/// Indicates file + line for error reporting.
+#[derive(Debug, Clone, Default)]
 pub struct ChunkLocation {
     pub file_idx: usize,
+    /// Name of the input file `file_idx` refers to, as it was read.
+    pub file_name: String,
     pub line: usize,
 }
 
-/// Possible errors during expansion/definition.
+/// Where one line of an expansion came from.
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    /// The chunk whose definition holds the line.
+    pub chunk: String,
+    /// Source location of the line itself.
+    pub location: ChunkLocation,
+    /// Indentation prepended to the stored line during expansion.
+    pub indent: String,
+    /// Number of leading characters stripped from the stored line.
+    pub base_indent: usize,
+}
+
+/// One definition of a chunk, as read.
+#[derive(Debug, Clone)]
+pub struct ChunkDefView {
+    /// Body lines as stored, references unexpanded.
+    pub content: Vec<String>,
+    /// Number of leading characters stripped from each line on expansion.
+    pub base_indent: usize,
+    /// Location of the line opening the definition.
+    pub location: ChunkLocation,
+    /// Text after the `=` of the opening line, if any.
+    pub annotation: Option<String>,
+    /// Language of the body, from `@lang(NAME)`, the `lang` attribute or a
+    /// one-word annotation.
+    pub lang: Option<String>,
+    /// `key=value` pairs written after the chunk name.
+    pub attributes: Attributes,
+}
+
+/// A `@doc` section: documentation read for the weaver, never tangled.
+#[derive(Debug, Clone)]
+pub struct DocSection {
+    /// The name written on the opening line, e.g. `intro` in `<<@doc intro>>=`.
+    pub name: String,
+    /// Lines between the opening and closing lines, as written.
+    pub content: Vec<String>,
+    /// Location of the line opening the section.
+    pub location: ChunkLocation,
+}
+
+/// A second definition that was appended to an existing regular chunk.
+#[derive(Debug, Clone)]
+pub struct Redefinition {
+    pub chunk: String,
+    pub file_name: String,
+    pub location: ChunkLocation,
+    pub previous_file_name: String,
+    pub previous_location: ChunkLocation,
+}
+
+impl Redefinition {
+    /// The same finding, reported as an error.
+    pub fn to_error(&self) -> ChunkError {
+        ChunkError::ChunkRedefinition {
+            chunk: self.chunk.clone(),
+            location: self.location.clone(),
+            previous_location: Box::new(self.previous_location.clone()),
+        }
+    }
+}
+
+/// A file chunk defined again without @replace; the later definition is
+/// dropped.
+#[derive(Debug, Clone)]
+pub struct FileRedefinition {
+    pub chunk: String,
+    pub location: ChunkLocation,
+    pub previous_location: ChunkLocation,
+}
+
+impl FileRedefinition {
+    /// The redefinition as the error it is reported as.
+    pub fn to_error(&self) -> ChunkError {
+        ChunkError::FileChunkRedefinition {
+            file_chunk: self.chunk.clone(),
+            location: self.location.clone(),
+            previous_location: Box::new(self.previous_location.clone()),
+        }
+    }
+}
+
+/// A chunk definition whose name breaks the `NamePolicy`; the chunk is not
+/// defined.
+#[derive(Debug, Clone)]
+pub struct NameViolation {
+    pub chunk: String,
+    pub location: ChunkLocation,
+    /// What is wrong with the name, e.g. "is longer than 20 characters".
+    pub reason: String,
+}
+
+impl NameViolation {
+    /// The violation as the error it is reported as.
+    pub fn to_error(&self) -> ChunkError {
+        ChunkError::InvalidChunkName {
+            chunk: self.chunk.clone(),
+            location: self.location.clone(),
+            reason: self.reason.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for Redefinition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} line {}: chunk '{}' is redefined; appending to the definition at {} line {}",
+            self.file_name,
+            self.location.line + 1,
+            self.chunk,
+            self.previous_file_name,
+            self.previous_location.line + 1
+        )
+    }
+}
+
+/// A reference from one chunk to another, at the line making it.
+#[derive(Debug, Clone)]
+pub struct ReferenceSite {
+    pub from: String,
+    pub to: String,
+    pub file_name: String,
+    pub location: ChunkLocation,
+}
+
+/// Chunks that reference each other in a cycle, with every reference
+/// among them.
+#[derive(Debug, Clone)]
+pub struct ReferenceCycle {
+    /// The participating chunks, sorted.
+    pub chunks: Vec<String>,
+    /// The references between them, in source order.
+    pub references: Vec<ReferenceSite>,
+}
+
+impl std::fmt::Display for ReferenceCycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let chunks: Vec<String> = self.chunks.iter().map(|c| format!("'{}'", c)).collect();
+        write!(f, "{}", chunks.join(", "))?;
+        for site in &self.references {
+            write!(
+                f,
+                "\n    {} line {}: '{}' references '{}'",
+                site.file_name,
+                site.location.line + 1,
+                site.from,
+                site.to
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Possible errors during expansion/definition. Locations carry the name of
+/// the input file they point into.
 #[derive(Debug)]
 pub enum ChunkError {
     RecursionLimit {
         chunk: String,
-        file_name: String,
         location: ChunkLocation,
     },
     RecursiveReference {
         chunk: String,
-        file_name: String,
         location: ChunkLocation,
     },
     UndefinedChunk {
         chunk: String,
-        file_name: String,
         location: ChunkLocation,
     },
     IoError(io::Error),
-    /// We add a custom error for multiple @file definitions without @replace.
+    /// A file chunk defined again without @replace.
     FileChunkRedefinition {
         file_chunk: String,
+        location: ChunkLocation,
+        /// Boxed to keep `ChunkError` small.
+        previous_location: Box<ChunkLocation>,
+    },
+    /// An @exec chunk was expanded without exec being allowed.
+    ExecNotAllowed {
+        chunk: String,
+        location: ChunkLocation,
+    },
+    /// The command of an @exec chunk could not be run or failed.
+    ExecFailed {
+        chunk: String,
+        location: ChunkLocation,
+        message: String,
+    },
+    /// The file named by an @insert reference could not be read.
+    InsertFailed {
+        path: String,
+        location: ChunkLocation,
+        message: String,
+    },
+    /// A regular chunk was defined again without @replace or @append.
+    ChunkRedefinition {
+        chunk: String,
+        location: ChunkLocation,
+        /// Boxed to keep `ChunkError` small.
+        previous_location: Box<ChunkLocation>,
+    },
+    /// A @private chunk was referenced from another source file.
+    PrivateChunkReference {
+        chunk: String,
+        location: ChunkLocation,
+        defined_in: String,
+    },
+    /// An input document that could not be parsed at all.
+    ParseError {
         file_name: String,
+        message: String,
+    },
+    /// Chunks that reference themselves, directly or through others; found
+    /// all at once after reading.
+    ReferenceCycles {
+        cycles: Vec<ReferenceCycle>,
+    },
+    /// The delimiters or comment markers cannot form a usable syntax.
+    InvalidDelimiters {
+        message: String,
+    },
+    /// A chunk name that breaks the configured naming conventions.
+    InvalidChunkName {
+        chunk: String,
+        location: ChunkLocation,
+        reason: String,
+    },
+    /// An attribute of a file chunk that the writer cannot apply, e.g.
+    /// `mode=9`.
+    InvalidAttribute {
+        chunk: String,
+        location: ChunkLocation,
+        message: String,
+    },
+    /// Two file chunks whose output paths name the same file, e.g. `./a.txt`
+    /// and `a.txt`.
+    DuplicateOutput {
+        path: String,
+        location: ChunkLocation,
+        previous_path: String,
+        /// Boxed to keep `ChunkError` small.
+        previous_location: Box<ChunkLocation>,
+    },
+    /// Two file chunks whose output paths differ only in case, so one would
+    /// overwrite the other on a case-insensitive file system.
+    OutputCollision {
+        path: String,
         location: ChunkLocation,
+        previous_path: String,
+        /// Boxed to keep `ChunkError` small.
+        previous_location: Box<ChunkLocation>,
+    },
+    /// Two inputs with the same base name, whose chunks would share one
+    /// namespace under `--namespaced`.
+    NamespaceCollision {
+        namespace: String,
+        file_name: String,
+        previous_file_name: String,
     },
 }
 
 impl std::fmt::Display for ChunkError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ChunkError::RecursionLimit {
+            ChunkError::RecursionLimit { chunk, location } => write!(
+                f,
+                "{} line {}: maximum recursion depth exceeded while expanding chunk '{}'",
+                location.file_name,
+                location.line + 1,
+                chunk
+            ),
+            ChunkError::RecursiveReference { chunk, location } => write!(
+                f,
+                "{} line {}: recursive reference detected in chunk '{}'",
+                location.file_name,
+                location.line + 1,
+                chunk
+            ),
+            ChunkError::UndefinedChunk { chunk, location } => write!(
+                f,
+                "{} line {}: referenced chunk '{}' is undefined",
+                location.file_name,
+                location.line + 1,
+                chunk
+            ),
+            ChunkError::IoError(e) => write!(f, "I/O error: {}", e),
+            ChunkError::FileChunkRedefinition {
+                file_chunk,
+                location,
+                previous_location,
+            } => write!(
+                f,
+                "{} line {}: file chunk '{}' is already defined at {} line {} (use @replace to redefine)",
+                location.file_name,
+                location.line + 1,
+                file_chunk,
+                previous_location.file_name,
+                previous_location.line + 1
+            ),
+            ChunkError::ExecNotAllowed { chunk, location } => write!(
+                f,
+                "{} line {}: chunk '{}' runs a command; pass --allow-exec to permit it",
+                location.file_name,
+                location.line + 1,
+                chunk
+            ),
+            ChunkError::ExecFailed {
                 chunk,
-                file_name,
                 location,
-            } => {
-                write!(
+                message,
+            } => write!(
                 f,
-                "Error: {} line {}: maximum recursion depth exceeded while expanding chunk '{}'",
-                file_name, location.line + 1, chunk
-            )
-            }
-            ChunkError::RecursiveReference {
+                "{} line {}: command of chunk '{}' failed: {}",
+                location.file_name,
+                location.line + 1,
                 chunk,
-                file_name,
+                message
+            ),
+            ChunkError::InsertFailed {
+                path,
                 location,
+                message,
             } => write!(
                 f,
-                "Error: {} line {}: recursive reference detected in chunk '{}'",
-                file_name,
+                "{} line {}: cannot insert '{}': {}",
+                location.file_name,
                 location.line + 1,
-                chunk
+                path,
+                message
             ),
-            ChunkError::UndefinedChunk {
+            ChunkError::ChunkRedefinition {
                 chunk,
-                file_name,
                 location,
+                previous_location,
             } => write!(
                 f,
-                "Error: {} line {}: referenced chunk '{}' is undefined",
-                file_name,
+                "{} line {}: chunk '{}' is already defined at {} line {} (use @append to continue it)",
+                location.file_name,
                 location.line + 1,
-                chunk
+                chunk,
+                previous_location.file_name,
+                previous_location.line + 1
             ),
-            ChunkError::IoError(e) => write!(f, "Error: I/O error: {}", e),
-            ChunkError::FileChunkRedefinition {
-                file_chunk,
-                file_name,
+            ChunkError::InvalidChunkName {
+                chunk,
                 location,
+                reason,
             } => write!(
                 f,
-                "Error: {} line {}: file chunk '{}' is already defined (use @replace to redefine)",
-                file_name,
+                "{} line {}: chunk name '{}' {}",
+                location.file_name,
+                location.line + 1,
+                chunk,
+                reason
+            ),
+            ChunkError::InvalidAttribute {
+                chunk,
+                location,
+                message,
+            } => write!(
+                f,
+                "{} line {}: chunk '{}': {}",
+                location.file_name,
+                location.line + 1,
+                chunk,
+                message
+            ),
+            ChunkError::DuplicateOutput {
+                path,
+                location,
+                previous_path,
+                previous_location,
+            } => write!(
+                f,
+                "{} line {}: output '{}' is the same file as '{}' defined at {} line {}",
+                location.file_name,
+                location.line + 1,
+                path,
+                previous_path,
+                previous_location.file_name,
+                previous_location.line + 1
+            ),
+            ChunkError::OutputCollision {
+                path,
+                location,
+                previous_path,
+                previous_location,
+            } => write!(
+                f,
+                "{} line {}: output '{}' differs only in case from '{}' defined at {} line {}",
+                location.file_name,
+                location.line + 1,
+                path,
+                previous_path,
+                previous_location.file_name,
+                previous_location.line + 1
+            ),
+            ChunkError::PrivateChunkReference {
+                chunk,
+                location,
+                defined_in,
+            } => write!(
+                f,
+                "{} line {}: chunk '{}' is private to {}",
+                location.file_name,
                 location.line + 1,
-                file_chunk
+                chunk,
+                defined_in
+            ),
+            ChunkError::ParseError { file_name, message } => {
+                write!(f, "{}: {}", file_name, message)
+            }
+            ChunkError::InvalidDelimiters { message } => {
+                write!(f, "invalid delimiters: {}", message)
+            }
+            ChunkError::ReferenceCycles { cycles } => {
+                write!(f, "{} reference cycle(s) between chunks:", cycles.len())?;
+                for cycle in cycles {
+                    write!(f, "\n  {}", cycle)?;
+                }
+                Ok(())
+            }
+            ChunkError::NamespaceCollision {
+                namespace,
+                file_name,
+                previous_file_name,
+            } => write!(
+                f,
+                "{} and {} would share the namespace '{}'; rename one of them",
+                previous_file_name, file_name, namespace
             ),
         }
     }
 }
 
+impl ChunkError {
+    /// The diagnostic code of the error.
+    pub fn code(&self) -> Code {
+        match self {
+            ChunkError::RecursionLimit { .. }
+            | ChunkError::RecursiveReference { .. }
+            | ChunkError::ReferenceCycles { .. } => Code::Recursion,
+            ChunkError::UndefinedChunk { .. } => Code::UndefinedChunk,
+            ChunkError::IoError(_) => Code::Io,
+            ChunkError::FileChunkRedefinition { .. } | ChunkError::DuplicateOutput { .. } => {
+                Code::FileChunkRedefinition
+            }
+            ChunkError::ExecNotAllowed { .. } => Code::ExecNotAllowed,
+            ChunkError::ExecFailed { .. } => Code::ExecFailed,
+            ChunkError::InsertFailed { .. } => Code::InsertFailed,
+            ChunkError::ChunkRedefinition { .. } => Code::Redefinition,
+            ChunkError::PrivateChunkReference { .. } => Code::PrivateChunk,
+            ChunkError::ParseError { .. } => Code::Parse,
+            ChunkError::InvalidDelimiters { .. } => Code::InvalidDelimiters,
+            ChunkError::OutputCollision { .. } => Code::OutputCollision,
+            ChunkError::InvalidChunkName { .. } => Code::InvalidChunkName,
+            ChunkError::InvalidAttribute { .. } => Code::InvalidAttribute,
+            ChunkError::NamespaceCollision { .. } => Code::NamespaceCollision,
+        }
+    }
+}
+
 impl std::error::Error for ChunkError {}
 
 impl From<io::Error> for ChunkError {
@@ -128,15 +836,12 @@ impl From<io::Error> for ChunkError {
 
 impl From<AzadiError> for ChunkError {
     fn from(e: AzadiError) -> Self {
-        ChunkError::IoError(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            e.to_string(),
-        ))
+        ChunkError::IoError(std::io::Error::other(e.to_string()))
     }
 }
 
 /// Each named chunk can have multiple definitions plus a reference counter.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct NamedChunk {
     definitions: Vec<ChunkDef>,
     references: usize,
@@ -151,99 +856,783 @@ impl NamedChunk {
     }
 }
 
-/// Main store: chunk name -> Rc<RefCell<NamedChunk>>,
-/// plus a list of which chunk names start with @file .
-pub struct ChunkStore {
-    chunks: HashMap<String, Rc<RefCell<NamedChunk>>>,
-    file_chunks: Vec<String>,
-
-    open_re: Regex,
-    slot_re: Regex,
-    close_re: Regex,
+/// The receiver of expanded lines and their provenance.
+type LineSink<'s> = dyn FnMut(String, Provenance) -> Result<(), ChunkError> + 's;
 
-    /// All file names for error reporting, indexed by file_idx.
-    file_names: Vec<String>,
+/// Passes expanded lines on to a sink as they are produced. The latest line
+/// is held back until the next one arrives, since text trailing a reference
+/// may still be appended to it.
+struct Emitter<'a, 's> {
+    sink: &'a mut LineSink<'s>,
+    held: Option<(String, Provenance)>,
+    count: usize,
+    /// Every file the expansion read: the inputs of the definitions visited,
+    /// including those that emitted nothing, and the files spliced in.
+    inputs: BTreeSet<String>,
 }
 
-/// Check if the given path is safe (not absolute, no .., no colon).
-fn path_is_safe(path: &str) -> Result<(), SafeWriterError> {
-    let p = Path::new(path);
-    if p.is_absolute() {
-        return Err(SafeWriterError::SecurityViolation(
-            "Absolute paths are not allowed".to_string(),
-        ));
+impl<'a, 's> Emitter<'a, 's> {
+    fn new(sink: &'a mut LineSink<'s>) -> Self {
+        Self {
+            sink,
+            held: None,
+            count: 0,
+            inputs: BTreeSet::new(),
+        }
     }
-    if p.to_string_lossy().contains(':') {
-        return Err(SafeWriterError::SecurityViolation(
-            "Windows-style paths are not allowed".to_string(),
-        ));
+
+    fn push(&mut self, line: (String, Provenance)) -> Result<(), ChunkError> {
+        self.count += 1;
+        match self.held.replace(line) {
+            Some((line, provenance)) => (self.sink)(line, provenance),
+            None => Ok(()),
+        }
     }
-    if p.components().any(|c| matches!(c, Component::ParentDir)) {
-        return Err(SafeWriterError::SecurityViolation(
-            "Path traversal is not allowed".to_string(),
-        ));
+
+    /// Number of lines produced so far.
+    fn len(&self) -> usize {
+        self.count
+    }
+
+    /// The latest line, while it can still be changed.
+    fn last_mut(&mut self) -> Option<&mut (String, Provenance)> {
+        self.held.as_mut()
+    }
+
+    /// Pass on the line held back, returning the files the expansion read.
+    fn finish(mut self) -> Result<BTreeSet<String>, ChunkError> {
+        if let Some((line, provenance)) = self.held.take() {
+            (self.sink)(line, provenance)?;
+        }
+        Ok(self.inputs)
     }
-    Ok(())
 }
 
-impl ChunkStore {
-    pub fn new(
-        open_delim: &str,           // e.g. "<<"
-        close_delim: &str,          // e.g. ">>"
-        chunk_end: &str,            // e.g. "@"
-        comment_markers: &[String], // e.g. ["#", "//"]
-    ) -> Self {
-        let od = regex::escape(open_delim);
-        let cd = regex::escape(close_delim);
+/// A document line as the reader sees it.
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub(crate) enum LineKind {
+    Open {
+        name: String,
+        verbatim: bool,
+        lang: Option<String>,
+    },
+    Close,
+    Reference {
+        name: String,
+    },
+    /// The opening line of a @doc section.
+    Doc {
+        name: String,
+    },
+    Text,
+}
+
+/// What a reference line with text after the closing delimiter, such as
+/// `<<chunk>> // note`, stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingText {
+    /// Not a reference: the whole line is emitted as text.
+    #[default]
+    Text,
+    /// A reference, with the text appended to the last line of its expansion.
+    Append,
+    /// A reference, with the text emitted on a line of its own after the expansion.
+    After,
+}
+
+impl std::str::FromStr for TrailingText {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(TrailingText::Text),
+            "append" => Ok(TrailingText::Append),
+            "after" => Ok(TrailingText::After),
+            other => Err(format!("unknown trailing text mode '{}'", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for TrailingText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TrailingText::Text => "text",
+            TrailingText::Append => "append",
+            TrailingText::After => "after",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One pair of delimiters around chunk names, with the patterns of the
+/// chunk openings and references written with it.
+#[derive(Clone)]
+struct DelimiterPair {
+    open: String,
+    close: String,
+    open_re: Regex,
+    slot_re: Regex,
+}
+
+impl DelimiterPair {
+    fn new(open: &str, close: &str, escaped_comments: &str) -> Result<Self, ChunkError> {
+        let od = regex::escape(open);
+        let cd = regex::escape(close);
 
         // Build patterns that match lines like:
         //   # <<@replace @file chunk>>=
-        //   # <<chunk>>=
+        //   # <<chunk>>= annotation
+        //   # <<@file chunk key=value>>=
         // for references:
         //   # <<chunk>>
         //   # <<@reversed chunk>>
-        // for closings:
-        //   # @
-        let escaped_comments = comment_markers
-            .iter()
-            .map(|m| regex::escape(m))
-            .collect::<Vec<_>>()
-            .join("|");
 
         // Opening lines
         let open_pattern = format!(
-            r"^(\s*)(?:{})?[ \t]*{}(?:@replace[ \t]+)?(?:@file[ \t]+)?([^\s]+){}=",
+            r#"^(\s*)(?:{})?[ \t]*{}((?:@[A-Za-z]+(?:\([^)\s]*\)|[ \t]+-?\d+)?[ \t]+)*)([^\s]+)((?:[ \t]+[A-Za-z_][A-Za-z0-9_-]*=(?:"[^"]*"|[^\s"]*))*)[ \t]*{}=[ \t]*(.*?)\s*$"#,
             escaped_comments, od, cd
         );
         // Reference lines
         let slot_pattern = format!(
-            r"^(\s*)(?:{})?\s*{}(?:@file\s+|@reversed\s+)?([^\s>]+){}\s*$",
+            r"^(\s*)(?:{})?\s*{}(@file\s+|@reversed\s+|@insert\s+)?([^\s>]+){}(.*?)\s*$",
             escaped_comments, od, cd
         );
-        // Closing lines
+        Ok(Self {
+            open: open.to_string(),
+            close: close.to_string(),
+            open_re: compile_syntax(&open_pattern, "chunk opening")?,
+            slot_re: compile_syntax(&slot_pattern, "reference")?,
+        })
+    }
+}
+
+/// Check one delimiter pair against the chunk end and the pairs already in use.
+fn check_delimiters(
+    open: &str,
+    close: &str,
+    chunk_end: &str,
+    in_use: &[DelimiterPair],
+) -> Result<(), ChunkError> {
+    let invalid = |message: String| Err(ChunkError::InvalidDelimiters { message });
+    for (what, text) in [("open delimiter", open), ("close delimiter", close)] {
+        if text.trim().is_empty() {
+            return invalid(format!("the {} is empty or blank", what));
+        }
+        if text.contains(['\n', '\r']) {
+            return invalid(format!("the {} {:?} spans more than one line", what, text));
+        }
+    }
+    if open == close {
+        return invalid(format!(
+            "the open and close delimiters are both {:?}; use different ones",
+            open
+        ));
+    }
+    if open.starts_with(chunk_end) {
+        return invalid(format!(
+            "the chunk end {:?} is a prefix of the open delimiter {:?}, so chunk openings \
+             could be read as chunk ends; choose another chunk end",
+            chunk_end, open
+        ));
+    }
+    if in_use.iter().any(|pair| pair.open == open) {
+        return invalid(format!(
+            "the open delimiter {:?} is already in use by another pair",
+            open
+        ));
+    }
+    Ok(())
+}
+
+/// The patterns of chunk lines in inputs using one set of comment markers.
+#[derive(Clone)]
+struct Syntax {
+    markers: Vec<String>,
+    /// The marker alternation the patterns are built from.
+    escaped_comments: String,
+    /// The delimiter pairs around chunk names, the configured one first.
+    delimiter_pairs: Vec<DelimiterPair>,
+    close_re: Regex,
+    define_re: Regex,
+}
+
+impl Syntax {
+    fn new(
+        markers: &[String],
+        chunk_end: &str,
+        delimiters: &[(String, String)],
+    ) -> Result<Self, ChunkError> {
+        if let Some(marker) = markers.iter().find(|m| m.contains(['\n', '\r'])) {
+            return Err(ChunkError::InvalidDelimiters {
+                message: format!("the comment marker {:?} spans more than one line", marker),
+            });
+        }
+        let escaped_comments = markers
+            .iter()
+            .map(|m| regex::escape(m))
+            .collect::<Vec<_>>()
+            .join("|");
+        let delimiter_pairs = delimiters
+            .iter()
+            .map(|(open, close)| DelimiterPair::new(open, close, &escaped_comments))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Closing lines, like:
+        //   # @
         let close_pattern = format!(
             r"^(?:{})?[ \t]*{}\s*$",
             escaped_comments,
             regex::escape(chunk_end)
         );
 
+        // Document-level constants, outside chunks: @define NAME value
+        let define_pattern = format!(
+            r"^(?:{})?[ \t]*@define[ \t]+([A-Za-z_][A-Za-z0-9_]*)(?:[ \t]+(.*?))?\s*$",
+            escaped_comments
+        );
+
+        Ok(Self {
+            markers: markers.to_vec(),
+            escaped_comments,
+            delimiter_pairs,
+            close_re: compile_syntax(&close_pattern, "chunk end")?,
+            define_re: compile_syntax(&define_pattern, "@define")?,
+        })
+    }
+}
+
+/// How the attributes of a file chunk adjust the writing of its output:
+/// `mode=755`, `eol=lf` or `eol=crlf`, and `line-directives=off`.
+#[derive(Debug, Clone, PartialEq)]
+struct OutputAttributes {
+    /// Unix permission bits, written in octal.
+    mode: Option<u32>,
+    /// Line ending every output line is given.
+    eol: Option<&'static str>,
+    /// Whether `ExpandOptions::line_directive` applies to the output.
+    line_directives: bool,
+}
+
+impl OutputAttributes {
+    /// The settings of file chunk `name` from the attributes of its
+    /// definitions; a later definition overrides an earlier one.
+    fn of(name: &str, definitions: &[ChunkDefView]) -> Result<Self, ChunkError> {
+        let mut settings = Self {
+            mode: None,
+            eol: None,
+            line_directives: true,
+        };
+        for def in definitions {
+            let invalid = |message: String| ChunkError::InvalidAttribute {
+                chunk: name.to_string(),
+                location: def.location.clone(),
+                message,
+            };
+            for (key, value) in def.attributes.iter() {
+                match key {
+                    "mode" => {
+                        let mode = u32::from_str_radix(value, 8)
+                            .ok()
+                            .filter(|mode| *mode <= 0o7777)
+                            .ok_or_else(|| {
+                                invalid(format!("mode={} is not an octal file mode", value))
+                            })?;
+                        settings.mode = Some(mode);
+                    }
+                    "eol" => {
+                        settings.eol = Some(match value.to_ascii_lowercase().as_str() {
+                            "lf" => "\n",
+                            "crlf" => "\r\n",
+                            _ => return Err(invalid(format!("eol={} is not lf or crlf", value))),
+                        });
+                    }
+                    "line-directives" => {
+                        settings.line_directives =
+                            def.attributes.get_bool(key).ok_or_else(|| {
+                                invalid(format!("line-directives={} is not on or off", value))
+                            })?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// Main store: chunk name -> Rc<RefCell<NamedChunk>>,
+/// plus a list of which chunk names start with @file .
+pub struct ChunkStore {
+    chunks: HashMap<String, Rc<RefCell<NamedChunk>>>,
+    file_chunks: Vec<String>,
+
+    /// The patterns of each comment marker set in use, the configured
+    /// markers first.
+    syntaxes: Vec<Syntax>,
+    /// Index into `syntaxes` of each input, by file_idx.
+    file_syntaxes: Vec<usize>,
+    /// How the comment markers of each input are chosen.
+    marker_rules: MarkerRules,
+    /// The chunk end, which further delimiter pairs are checked against.
+    chunk_end: String,
+
+    /// Whether @exec chunks may run their commands.
+    allow_exec: bool,
+
+    /// Whether chunk names are qualified with the defining file's name.
+    namespaced: bool,
+
+    /// Whether outputs are placed under a directory named after their input.
+    prefix_from_input: bool,
+
+    /// How reference lines with text after the reference are read.
+    trailing_text: TrailingText,
+
+    /// Regular chunks that received another definition without @append.
+    redefinitions: Vec<Redefinition>,
+
+    /// File chunks that received another definition without @replace.
+    file_redefinitions: Vec<FileRedefinition>,
+
+    /// Conventions the names of regular chunks must follow.
+    name_policy: NamePolicy,
+
+    /// Definitions rejected for breaking the name policy.
+    name_violations: Vec<NameViolation>,
+
+    /// Tags of the definitions to read; empty reads them all.
+    tag_selection: Vec<String>,
+
+    /// Constants declared with `@define`, substituted for `@{NAME}`.
+    defines: HashMap<String, String>,
+
+    /// `@doc` sections, in reading order.
+    docs: Vec<DocSection>,
+
+    /// The output collecting the @test chunks, if one is generated.
+    test_harness: Option<TestHarness>,
+    /// The file chunk assembled for it, once the inputs define none of that name.
+    harness_chunk: Option<String>,
+
+    /// All file names for error reporting, indexed by file_idx.
+    file_names: Vec<String>,
+}
+
+/// A deep copy: chunks read into, or referenced in, either store afterwards
+/// leave the other alone.
+impl Clone for ChunkStore {
+    fn clone(&self) -> Self {
         Self {
+            chunks: self
+                .chunks
+                .iter()
+                .map(|(name, rc)| (name.clone(), Rc::new(RefCell::new(rc.borrow().clone()))))
+                .collect(),
+            file_chunks: self.file_chunks.clone(),
+            syntaxes: self.syntaxes.clone(),
+            file_syntaxes: self.file_syntaxes.clone(),
+            marker_rules: self.marker_rules.clone(),
+            chunk_end: self.chunk_end.clone(),
+            allow_exec: self.allow_exec,
+            namespaced: self.namespaced,
+            prefix_from_input: self.prefix_from_input,
+            trailing_text: self.trailing_text,
+            redefinitions: self.redefinitions.clone(),
+            file_redefinitions: self.file_redefinitions.clone(),
+            name_policy: self.name_policy.clone(),
+            name_violations: self.name_violations.clone(),
+            tag_selection: self.tag_selection.clone(),
+            defines: self.defines.clone(),
+            docs: self.docs.clone(),
+            test_harness: self.test_harness.clone(),
+            harness_chunk: self.harness_chunk.clone(),
+            file_names: self.file_names.clone(),
+        }
+    }
+}
+
+/// Remove a comment prefix following the indentation of a line,
+/// together with one space after it.
+fn strip_comment<'a>(line: &'a str, prefix: &str) -> Cow<'a, str> {
+    let body = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - body.len()];
+    match body.strip_prefix(prefix) {
+        Some(rest) => {
+            let rest = rest.strip_prefix(' ').unwrap_or(rest);
+            if indent.is_empty() {
+                Cow::Borrowed(rest)
+            } else {
+                Cow::Owned(format!("{}{}", indent, rest))
+            }
+        }
+        None => Cow::Borrowed(line),
+    }
+}
+
+/// Run the body of an @exec chunk through the platform shell, returning its stdout.
+fn run_command(script: &str) -> Result<String, String> {
+    #[cfg(windows)]
+    let mut command = {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(script);
+        c
+    };
+    #[cfg(not(windows))]
+    let mut command = {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(script);
+        c
+    };
+    let output = command.output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// An output path with `.` components and repeated separators removed.
+fn normalize_output(path: &str) -> String {
+    Path::new(path)
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// A file chunk name with the backslashes of a Windows-style relative path,
+/// e.g. `@file src\main.rs`, turned into `/`; other names are unchanged.
+fn with_forward_slashes(name: &str) -> Cow<'_, str> {
+    if name.starts_with("@file ") && name.contains('\\') {
+        Cow::Owned(name.replace('\\', "/"))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+/// Check if the given path is safe (not absolute, no .., no colon).
+fn path_is_safe(path: &str) -> Result<(), SafeWriterError> {
+    let p = Path::new(path);
+    if p.is_absolute() {
+        return Err(SafeWriterError::SecurityViolation(
+            "Absolute paths are not allowed".to_string(),
+        ));
+    }
+    if p.to_string_lossy().contains(':') {
+        return Err(SafeWriterError::SecurityViolation(
+            "Windows-style paths are not allowed".to_string(),
+        ));
+    }
+    if p.components().any(|c| matches!(c, Component::ParentDir)) {
+        return Err(SafeWriterError::SecurityViolation(
+            "Path traversal is not allowed".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Upper bound on the compiled size of each syntax regex: ample for any
+/// sensible delimiters, while huge ones fail cleanly instead of exhausting memory.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Compile one of the patterns built from the configured syntax.
+fn compile_syntax(pattern: &str, what: &str) -> Result<Regex, ChunkError> {
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| ChunkError::InvalidDelimiters {
+            message: format!("cannot build the {} pattern: {}", what, e),
+        })
+}
+
+impl ChunkStore {
+    /// Panics if the syntax is rejected by `try_new`.
+    pub fn new(
+        open_delim: &str,
+        close_delim: &str,
+        chunk_end: &str,
+        comment_markers: &[String],
+    ) -> Self {
+        Self::try_new(open_delim, close_delim, chunk_end, comment_markers)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// A store for the given syntax, or an error naming what is wrong with it.
+    pub fn try_new(
+        open_delim: &str,           // e.g. "<<"
+        close_delim: &str,          // e.g. ">>"
+        chunk_end: &str,            // e.g. "@"
+        comment_markers: &[String], // e.g. ["#", "//"]
+    ) -> Result<Self, ChunkError> {
+        let invalid = |message: String| Err(ChunkError::InvalidDelimiters { message });
+        if chunk_end.trim().is_empty() {
+            return invalid("the chunk end is empty or blank".to_string());
+        }
+        if chunk_end.contains(['\n', '\r']) {
+            return invalid(format!(
+                "the chunk end {:?} spans more than one line",
+                chunk_end
+            ));
+        }
+        check_delimiters(open_delim, close_delim, chunk_end, &[])?;
+        let delimiters = [(open_delim.to_string(), close_delim.to_string())];
+
+        Ok(Self {
             chunks: HashMap::new(),
             file_chunks: Vec::new(),
-            open_re: Regex::new(&open_pattern).expect("Invalid open pattern"),
-            slot_re: Regex::new(&slot_pattern).expect("Invalid slot pattern"),
-            close_re: Regex::new(&close_pattern).expect("Invalid close pattern"),
+            syntaxes: vec![Syntax::new(comment_markers, chunk_end, &delimiters)?],
+            file_syntaxes: Vec::new(),
+            marker_rules: MarkerRules::default(),
+            chunk_end: chunk_end.to_string(),
+            allow_exec: false,
+            namespaced: false,
+            prefix_from_input: false,
+            trailing_text: TrailingText::default(),
+            redefinitions: Vec::new(),
+            file_redefinitions: Vec::new(),
+            name_policy: NamePolicy::default(),
+            name_violations: Vec::new(),
+            tag_selection: Vec::new(),
+            defines: HashMap::new(),
+            docs: Vec::new(),
+            test_harness: None,
+            harness_chunk: None,
             file_names: Vec::new(),
-        }
+        })
     }
 
     pub fn add_file_name(&mut self, fname: &str) -> usize {
         let idx = self.file_names.len();
         self.file_names.push(fname.to_string());
+        self.file_syntaxes.push(self.syntax_index(fname));
         idx
     }
 
-    fn validate_chunk_name(&self, chunk_name: &str, line: &str) -> bool {
+    /// Index into `syntaxes` of the markers of input `file_name`.
+    fn syntax_index(&self, file_name: &str) -> usize {
+        self.marker_rules
+            .markers_for(file_name)
+            .and_then(|markers| self.syntaxes.iter().position(|s| s.markers == markers))
+            .unwrap_or(0)
+    }
+
+    /// The patterns input `file_idx` is read with.
+    fn syntax(&self, file_idx: usize) -> &Syntax {
+        &self.syntaxes[self.file_syntaxes.get(file_idx).copied().unwrap_or(0)]
+    }
+
+    /// Choose the comment markers of the inputs read from now on by their
+    /// names, e.g. `#` for `.py` files with auto-detection.
+    pub fn set_marker_rules(&mut self, rules: MarkerRules) -> Result<(), ChunkError> {
+        let delimiters = self.delimiter_pairs();
+        let mut syntaxes = vec![self.syntaxes[0].clone()];
+        for markers in rules.marker_sets() {
+            if !syntaxes.iter().any(|s| s.markers == markers) {
+                syntaxes.push(Syntax::new(&markers, &self.chunk_end, &delimiters)?);
+            }
+        }
+        self.syntaxes = syntaxes;
+        self.marker_rules = rules;
+        self.file_syntaxes = self
+            .file_names
+            .iter()
+            .map(|name| self.syntax_index(name))
+            .collect();
+        Ok(())
+    }
+
+    /// Also read chunk openings and references written with `open` and
+    /// `close`, e.g. `⟨⟨` and `⟩⟩` next to `<<` and `>>`.
+    pub fn add_delimiters(&mut self, open: &str, close: &str) -> Result<(), ChunkError> {
+        check_delimiters(
+            open,
+            close,
+            &self.chunk_end,
+            &self.syntaxes[0].delimiter_pairs,
+        )?;
+        let pairs = self
+            .syntaxes
+            .iter()
+            .map(|syntax| DelimiterPair::new(open, close, &syntax.escaped_comments))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (syntax, pair) in self.syntaxes.iter_mut().zip(pairs) {
+            syntax.delimiter_pairs.push(pair);
+        }
+        Ok(())
+    }
+
+    /// Permit or forbid running the commands of @exec chunks.
+    pub fn set_allow_exec(&mut self, allow: bool) {
+        self.allow_exec = allow;
+    }
+
+    /// Qualify chunks with their file's name (`file.nw::chunk`) so that
+    /// unqualified references resolve within the same file first.
+    pub fn set_namespaced(&mut self, namespaced: bool) {
+        self.namespaced = namespaced;
+    }
+
+    /// Place the outputs declared in `dir/name.nw` under `dir/name/`.
+    pub fn set_prefix_from_input(&mut self, prefix: bool) {
+        self.prefix_from_input = prefix;
+    }
+
+    /// Choose how reference lines with text after the reference are read.
+    pub fn set_trailing_text(&mut self, mode: TrailingText) {
+        self.trailing_text = mode;
+    }
+
+    /// The captures of a reference line: indentation, modifier, chunk name
+    /// and trailing text. Lines with trailing text are plain text unless
+    /// trailing text is enabled.
+    fn reference<'t>(&self, file_idx: usize, line: &'t str) -> Option<regex::Captures<'t>> {
+        self.syntax(file_idx)
+            .delimiter_pairs
+            .iter()
+            .find_map(|pair| self.reference_with(pair, line))
+    }
+
+    /// `reference` for the delimiters of one pair.
+    fn reference_with<'t>(
+        &self,
+        pair: &DelimiterPair,
+        line: &'t str,
+    ) -> Option<regex::Captures<'t>> {
+        let caps = pair.slot_re.captures(line)?;
+        let trailing = caps.get(4).map_or("", |m| m.as_str());
+        if self.trailing_text == TrailingText::Text && !trailing.trim().is_empty() {
+            return None;
+        }
+        Some(caps)
+    }
+
+    /// The captures of a chunk opening line: indentation, modifiers and
+    /// chunk name.
+    fn opening<'t>(&self, file_idx: usize, line: &'t str) -> Option<regex::Captures<'t>> {
+        self.syntax(file_idx)
+            .delimiter_pairs
+            .iter()
+            .find_map(|pair| pair.open_re.captures(line))
+    }
+
+    /// A chunk opening line of document `file_name` written with single
+    /// spaces after the comment marker and between the modifiers, name,
+    /// attributes and annotation, and the chunk-end line matching it: with
+    /// the same marker, or indented like the opening when it has none.
+    /// Attributes are written in key order.
+    pub(crate) fn formatted_opening(
+        &self,
+        file_name: &str,
+        line: &str,
+    ) -> Option<(String, String)> {
+        let syntax = &self.syntaxes[self.syntax_index(file_name)];
+        let (pair, caps) = syntax
+            .delimiter_pairs
+            .iter()
+            .find_map(|pair| Some((pair, pair.open_re.captures(line)?)))?;
+        let indent = caps.get(1).map_or("", |m| m.as_str());
+        let modifiers = caps.get(2)?;
+        let marker = line[indent.len()..modifiers.start() - pair.open.len()].trim();
+        let mut opening = indent.to_string();
+        if !marker.is_empty() {
+            opening.push_str(marker);
+            opening.push(' ');
+        }
+        opening.push_str(&pair.open);
+        for word in modifiers.as_str().split_whitespace() {
+            opening.push_str(word);
+            opening.push(' ');
+        }
+        opening.push_str(&caps[3]);
+        let written = caps.get(4).map_or("", |m| m.as_str());
+        match Attributes::parse(written)?.to_text() {
+            Some(attributes) => opening.push_str(&attributes),
+            // Kept as written when the values cannot be rewritten
+            None => {
+                opening.push(' ');
+                opening.push_str(written.trim());
+            }
+        }
+        opening.push_str(&pair.close);
+        opening.push('=');
+        let annotation = caps.get(5).map_or("", |m| m.as_str());
+        if !annotation.is_empty() {
+            opening.push(' ');
+            opening.push_str(annotation);
+        }
+        let end = if marker.is_empty() {
+            format!("{}{}", indent, self.chunk_end)
+        } else {
+            format!("{} {}", marker, self.chunk_end)
+        };
+        Some((opening, end))
+    }
+
+    /// The configured (open, close) reference delimiters.
+    pub fn delimiters(&self) -> (String, String) {
+        let pair = &self.syntaxes[0].delimiter_pairs[0];
+        (pair.open.clone(), pair.close.clone())
+    }
+
+    /// Every (open, close) pair read, the configured one first.
+    pub fn delimiter_pairs(&self) -> Vec<(String, String)> {
+        self.syntaxes[0]
+            .delimiter_pairs
+            .iter()
+            .map(|pair| (pair.open.clone(), pair.close.clone()))
+            .collect()
+    }
+
+    /// Replace the `@<<` escape with a literal open delimiter. An escaped
+    /// reference never matches as a slot, so it reaches the output as text.
+    pub(crate) fn unescape<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        let mut line = Cow::Borrowed(line);
+        for pair in &self.syntaxes[0].delimiter_pairs {
+            let escaped = format!("@{}", pair.open);
+            if line.contains(&escaped) {
+                line = Cow::Owned(line.replace(&escaped, &pair.open));
+            }
+        }
+        line
+    }
+
+    /// The inverse of `unescape` for lines that would otherwise be read as a
+    /// reference or a chunk opening.
+    pub(crate) fn escape(&self, line: &str) -> String {
+        let mut line = line.to_string();
+        let pairs = &self.syntaxes[0].delimiter_pairs;
+        for pair in pairs {
+            let escaped = format!("@{}", pair.open);
+            line = line.replace(&escaped, &format!("@{}", escaped));
+        }
+        let active = pairs.iter().find(|pair| {
+            self.reference_with(pair, &line).is_some() || pair.open_re.is_match(&line)
+        });
+        match active {
+            Some(pair) => line.replacen(&pair.open, &format!("@{}", pair.open), 1),
+            None => line,
+        }
+    }
+
+    /// The name of the input registered under `file_idx`.
+    pub fn file_name(&self, file_idx: usize) -> String {
+        self.file_names.get(file_idx).cloned().unwrap_or_default()
+    }
+
+    /// A location in input `file_idx`, with the file's name filled in.
+    pub fn location(&self, file_idx: usize, line: usize) -> ChunkLocation {
+        ChunkLocation {
+            file_idx,
+            file_name: self.file_name(file_idx),
+            line,
+        }
+    }
+
+    pub(crate) fn validate_chunk_name(&self, chunk_name: &str, line: &str) -> bool {
         if line.contains("@file") {
             // Then chunk_name is a path
             path_is_safe(chunk_name).is_ok()
@@ -256,138 +1645,481 @@ impl ChunkStore {
     /// - If the line opens a chunk, we define it (or replace it).
     /// - If the line closes a chunk, we end the current one.
     /// - Otherwise, if we’re inside a chunk, we add lines to it.
+    /// - Lines of a @doc section are kept apart from the chunks.
+    ///
     /// Then we fill out file_chunks for any chunk name that starts with @file .
     pub fn read(&mut self, text: &str, file_idx: usize) {
         let mut current_chunk: Option<(String, usize)> = None;
+        // Index into `docs` of the @doc section being read
+        let mut current_doc: Option<usize> = None;
+        // A @verbatim body may show chunk openings; only the end marker closes it.
+        let mut verbatim = false;
         let mut line_no: i32 = -1;
 
         for line in text.lines() {
             line_no += 1;
 
             // Check if it's an opening line for a chunk
-            if let Some(caps) = self.open_re.captures(line) {
+            let opening = if verbatim {
+                None
+            } else {
+                self.opening(file_idx, line).and_then(|caps| {
+                    let modifiers = ChunkModifiers::of_opening(&caps)?;
+                    Some((caps, modifiers))
+                })
+            };
+            if let Some((caps, modifiers)) = opening {
                 let indentation = caps.get(1).map_or("", |m| m.as_str());
-                let base_name = caps.get(2).map_or("", |m| m.as_str()).to_string();
+                let base_name = caps.get(3).map_or("", |m| m.as_str()).to_string();
+
+                current_doc = None;
+                if modifiers.doc {
+                    current_chunk = None;
+                    current_doc = Some(self.docs.len());
+                    self.docs.push(DocSection {
+                        name: base_name,
+                        content: Vec::new(),
+                        location: self.location(file_idx, line_no as usize),
+                    });
+                    continue;
+                }
 
-                let is_replace = line.contains("@replace");
-                let is_file = line.contains("@file");
                 // If line has @file, chunk name should be "@file something"
-                let full_name = if is_file {
+                let full_name = if modifiers.file {
                     format!("@file {}", base_name)
                 } else {
                     base_name
                 };
 
-                if self.validate_chunk_name(&full_name, line) {
-                    // If this is a file chunk, check for existing definitions
-                    // unless @replace is present
-                    if full_name.starts_with("@file ") {
-                        if self.chunks.contains_key(&full_name) && !is_replace {
-                            // Return an error: multiple definitions for the same file chunk
-                            // We'll store a placeholder chunk error with the needed data
-                            // Because this is "read", we can’t return an error here easily
-                            // so let's just remove the chunk later, or store a special chunk error.
-                            // But to integrate with your code, let's define a single approach:
-                            // We'll create a chunk error by wrapping it in IoError for now:
-                            let location = ChunkLocation {
-                                file_idx,
-                                line: line_no as usize,
-                            };
-                            // We'll store an error in place of that chunk
-                            // or you might prefer to panic, or do something else
-                            // Here, let's forcibly remove it so the user sees an error at expansion time:
-                            let _err_msg = format!(
-                                "Chunk error: {}",
-                                ChunkError::FileChunkRedefinition {
-                                    file_chunk: full_name.clone(),
-                                    file_name: self
-                                        .file_names
-                                        .get(file_idx)
-                                        .cloned()
-                                        .unwrap_or_default(),
-                                    location,
-                                }
-                            );
-                            // We'll forcibly remove old chunk, so there's no conflict
-                            // and store a dummy chunk that references the error
-                            self.chunks.remove(&full_name);
-                            // or you might do eprintln!("{}", err_msg);
-                            // for now, let's just continue to skip:
-                            continue;
-                        }
-                        if is_replace {
-                            // remove old definition
-                            self.chunks.remove(&full_name);
-                        }
-                    } else if is_replace {
-                        // normal chunk with @replace
-                        self.chunks.remove(&full_name);
-                    }
-
-                    // Now define the chunk
-                    let rc = self
-                        .chunks
-                        .entry(full_name.clone())
-                        .or_insert_with(|| Rc::new(RefCell::new(NamedChunk::new())));
-                    let mut borrowed = rc.borrow_mut();
-                    let def_idx = borrowed.definitions.len();
-                    borrowed.definitions.push(ChunkDef::new(
+                if self.validate_chunk_name(&full_name, &full_name) {
+                    if !self.is_tag_selected(&modifiers.attributes) {
+                        // Skip the body of a definition left out by its tags
+                        current_chunk = None;
+                        verbatim = modifiers.verbatim;
+                    } else if let Some(target) = self.begin_definition(
+                        &full_name,
+                        &modifiers,
                         indentation.len(),
                         file_idx,
                         line_no as usize,
-                    ));
-                    drop(borrowed);
-
-                    current_chunk = Some((full_name, def_idx));
+                    ) {
+                        current_chunk = Some(target);
+                        verbatim = modifiers.verbatim;
+                    }
                 }
                 continue;
             }
 
             // If it's a closing line
-            if self.close_re.is_match(line) {
+            if self.syntax(file_idx).close_re.is_match(line) {
                 current_chunk = None;
+                current_doc = None;
+                verbatim = false;
                 continue;
             }
 
             // If we're in a chunk, add lines to it
             if let Some((ref cname, idx)) = current_chunk {
-                if let Some(rc) = self.chunks.get(cname) {
-                    let mut borrowed = rc.borrow_mut();
-                    let def = borrowed.definitions.get_mut(idx).unwrap();
-                    if line.ends_with('\n') {
-                        def.content.push(line.to_string());
-                    } else {
-                        def.content.push(format!("{}\n", line));
-                    }
-                }
+                self.push_line(cname, idx, line, line_no as usize);
+            } else if let Some(idx) = current_doc {
+                self.docs[idx].content.push(format!("{}\n", line));
+            } else if let Some(caps) = self.syntax(file_idx).define_re.captures(line) {
+                let value = caps.get(2).map_or("", |m| m.as_str());
+                self.defines.insert(caps[1].to_string(), value.to_string());
             }
         }
 
-        // Update file_chunks array
-        let mut fc = Vec::new();
-        for (name, _) in &self.chunks {
-            if name.starts_with("@file ") {
-                fc.push(name.clone());
+        self.finish_read();
+    }
+
+    /// Starts a new definition of `name`, honoring @replace semantics.
+    /// Returns the stored chunk name (qualified when namespacing is on) and the
+    /// index of the new definition, or None if it was rejected.
+    pub(crate) fn begin_definition(
+        &mut self,
+        name: &str,
+        modifiers: &ChunkModifiers,
+        base_indent: usize,
+        file_idx: usize,
+        line: usize,
+    ) -> Option<(String, usize)> {
+        if !name.starts_with("@file ") {
+            if let Err(reason) = self.name_policy.check(name) {
+                self.name_violations.push(NameViolation {
+                    chunk: name.to_string(),
+                    location: self.location(file_idx, line),
+                    reason,
+                });
+                return None;
+            }
+        }
+        if !self.is_tag_selected(&modifiers.attributes) {
+            debug!(
+                "definition of '{}' left out: none of its tags is selected",
+                name
+            );
+            return None;
+        }
+        let full_name = &self.qualify(&with_forward_slashes(name), file_idx);
+        let full_name = full_name.as_str();
+        if self.harness_chunk.as_deref() == Some(full_name) {
+            // The inputs define the harness's path themselves
+            self.chunks.remove(full_name);
+            self.harness_chunk = None;
+        }
+        let is_replace = modifiers.replace;
+        if let Some(from) = &modifiers.replace_from {
+            self.remove_definitions_from(full_name, from);
+        }
+        // If this is a file chunk, check for existing definitions
+        // unless @replace is present
+        if modifiers.replace_from.is_some() {
+            // Definitions from other files are kept and continued.
+        } else if full_name.starts_with("@file ") {
+            if self.chunks.contains_key(full_name) && !is_replace {
+                // Reported when the outputs are written
+                self.note_file_redefinition(full_name, file_idx, line);
+                return None;
             }
+            if is_replace {
+                // remove old definition
+                self.chunks.remove(full_name);
+            }
+        } else if is_replace {
+            // normal chunk with @replace
+            self.chunks.remove(full_name);
+        } else if !modifiers.append {
+            self.note_redefinition(full_name, file_idx, line);
         }
-        self.file_chunks = fc;
+
+        // Now define the chunk
+        let rc = self
+            .chunks
+            .entry(full_name.to_string())
+            .or_insert_with(|| Rc::new(RefCell::new(NamedChunk::new())));
+        let mut borrowed = rc.borrow_mut();
+        let def_idx = borrowed.definitions.len();
+        let mut def = ChunkDef::new(base_indent, file_idx, line);
+        def.exec = modifiers.exec;
+        def.private = modifiers.private;
+        def.verbatim = modifiers.verbatim;
+        def.managed = modifiers.managed;
+        def.test = modifiers.test;
+        def.order = modifiers.order.unwrap_or(0);
+        def.uncomment = modifiers.uncomment.clone();
+        def.annotation = modifiers.annotation.clone();
+        def.lang = modifiers.language().map(str::to_string);
+        def.attributes = modifiers.attributes.clone();
+        borrowed.definitions.push(def);
+        Some((full_name.to_string(), def_idx))
     }
 
-    /// Increments references on a chunk or returns an error if undefined.
-    fn inc_references(&self, chunk_name: &str, location: &ChunkLocation) -> Result<(), ChunkError> {
-        if let Some(rc) = self.chunks.get(chunk_name) {
-            let mut borrowed = rc.borrow_mut();
-            borrowed.references += 1;
-            Ok(())
-        } else {
-            let file_name = self
-                .file_names
-                .get(location.file_idx)
-                .cloned()
-                .unwrap_or_default();
-            Err(ChunkError::UndefinedChunk {
+    /// Drop the definitions of `name` read from a file whose path ends with `from`.
+    fn remove_definitions_from(&mut self, name: &str, from: &str) {
+        let Some(rc) = self.chunks.get(name) else {
+            return;
+        };
+        let file_names = &self.file_names;
+        rc.borrow_mut().definitions.retain(|d| {
+            let file_name = file_names.get(d.file_idx).map_or("", String::as_str);
+            !Path::new(file_name).ends_with(from)
+        });
+    }
+
+    /// Record that `name` is being defined again at `file_idx`/`line`.
+    fn note_redefinition(&mut self, name: &str, file_idx: usize, line: usize) {
+        let previous = self.chunks.get(name).and_then(|rc| {
+            rc.borrow()
+                .definitions
+                .last()
+                .map(|d| self.location(d.file_idx, d.line))
+        });
+        if let Some(previous_location) = previous {
+            self.redefinitions.push(Redefinition {
+                chunk: name.to_string(),
+                file_name: self.file_name(file_idx),
+                location: self.location(file_idx, line),
+                previous_file_name: self.file_name(previous_location.file_idx),
+                previous_location,
+            });
+        }
+    }
+
+    /// Record that file chunk `name` is being defined again at `file_idx`/`line`.
+    fn note_file_redefinition(&mut self, name: &str, file_idx: usize, line: usize) {
+        let previous = self.chunks.get(name).and_then(|rc| {
+            rc.borrow()
+                .definitions
+                .first()
+                .map(|d| self.location(d.file_idx, d.line))
+        });
+        if let Some(previous_location) = previous {
+            self.file_redefinitions.push(FileRedefinition {
+                chunk: name.to_string(),
+                location: self.location(file_idx, line),
+                previous_location,
+            });
+        }
+    }
+
+    /// Constants declared with `@define` so far.
+    pub fn defines(&self) -> &HashMap<String, String> {
+        &self.defines
+    }
+
+    /// Definitions that appended to an existing regular chunk, in reading order.
+    pub fn redefinitions(&self) -> &[Redefinition] {
+        &self.redefinitions
+    }
+
+    /// File chunks defined again without @replace, in reading order.
+    pub fn file_redefinitions(&self) -> &[FileRedefinition] {
+        &self.file_redefinitions
+    }
+
+    /// The `@doc` sections read so far, in reading order.
+    pub fn doc_sections(&self) -> &[DocSection] {
+        &self.docs
+    }
+
+    /// Check the names of the chunks read from now on against `policy`.
+    pub fn set_name_policy(&mut self, policy: NamePolicy) {
+        self.name_policy = policy;
+    }
+
+    /// Definitions rejected for breaking the name policy, in reading order.
+    pub fn name_violations(&self) -> &[NameViolation] {
+        &self.name_violations
+    }
+
+    /// Read only the definitions without a `tags` attribute or with one of
+    /// `tags` among theirs from now on; an empty list reads them all.
+    pub fn set_tag_selection(&mut self, tags: Vec<String>) {
+        self.tag_selection = tags;
+    }
+
+    /// Whether a definition with these attributes passes the tag selection.
+    fn is_tag_selected(&self, attributes: &Attributes) -> bool {
+        let tags = attributes.get_list("tags");
+        self.tag_selection.is_empty()
+            || tags.is_empty()
+            || tags
+                .iter()
+                .any(|tag| self.tag_selection.iter().any(|t| t == tag))
+    }
+
+    /// Namespace of the chunks defined in input `file_idx`: its base name.
+    fn namespace_of(&self, file_idx: usize) -> String {
+        let file_name = self.file_name(file_idx);
+        Path::new(&file_name)
+            .file_name()
+            .map_or(file_name.clone(), |n| n.to_string_lossy().into_owned())
+    }
+
+    /// The first two inputs whose chunks share a namespace, which would merge
+    /// their chunks silently.
+    pub fn namespace_collision(&self) -> Option<ChunkError> {
+        if !self.namespaced {
+            return None;
+        }
+        let mut seen: HashMap<String, &str> = HashMap::new();
+        for (idx, file_name) in self.file_names.iter().enumerate() {
+            let namespace = self.namespace_of(idx);
+            match seen.get(&namespace) {
+                Some(previous) if *previous != file_name => {
+                    return Some(ChunkError::NamespaceCollision {
+                        namespace,
+                        file_name: file_name.clone(),
+                        previous_file_name: previous.to_string(),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    seen.insert(namespace, file_name);
+                }
+            }
+        }
+        None
+    }
+
+    /// The stored name for a chunk defined in `file_idx`: `file.nw::name` when
+    /// namespacing is on, except for file chunks and already qualified names.
+    /// File chunks get the input's output prefix when that is enabled.
+    fn qualify(&self, name: &str, file_idx: usize) -> String {
+        if let Some(path) = name.strip_prefix("@file ") {
+            return match self.output_prefix(file_idx) {
+                Some(prefix) => format!("@file {}/{}", prefix, path.trim()),
+                None => name.to_string(),
+            };
+        }
+        if !self.namespaced || name.contains("::") {
+            return name.to_string();
+        }
+        format!("{}::{}", self.namespace_of(file_idx), name)
+    }
+
+    /// `name`, stored for a chunk defined in `file_idx`, as it was written:
+    /// without the namespace `qualify` added.
+    pub(crate) fn unqualified<'a>(&self, name: &'a str, file_idx: usize) -> &'a str {
+        if !self.namespaced {
+            return name;
+        }
+        name.strip_prefix(&format!("{}::", self.namespace_of(file_idx)))
+            .unwrap_or(name)
+    }
+
+    /// `modules/foo` for outputs declared in `modules/foo.nw`, when prefixing
+    /// is on. Inputs outside the working tree contribute only their stem.
+    fn output_prefix(&self, file_idx: usize) -> Option<String> {
+        if !self.prefix_from_input {
+            return None;
+        }
+        let file_name = self.file_name(file_idx);
+        let path = Path::new(&file_name);
+        let stem = path.file_stem()?.to_string_lossy().into_owned();
+        let parent = path.parent().unwrap_or(Path::new(""));
+        let relative = parent
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        let dirs: Vec<String> = if relative {
+            parent
+                .components()
+                .filter_map(|c| match c {
+                    Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+                    _ => None,
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Some(
+            dirs.into_iter()
+                .chain(std::iter::once(stem))
+                .collect::<Vec<_>>()
+                .join("/"),
+        )
+    }
+
+    /// Resolve a reference made from input `file_idx`: the local chunk first,
+    /// then the name as written.
+    fn resolve_reference(&self, name: &str, file_idx: usize) -> String {
+        let local = self.qualify(name, file_idx);
+        if local != name && self.chunks.contains_key(&local) {
+            debug!("reference '{}' resolved to local chunk '{}'", name, local);
+            local
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Resolve a top-level chunk name; with namespacing an unqualified name
+    /// also matches a single chunk of that name in any namespace.
+    fn resolve_top_level(&self, name: &str) -> String {
+        if !self.namespaced || self.chunks.contains_key(name) {
+            return name.to_string();
+        }
+        let suffix = format!("::{}", name);
+        let mut matches = self.chunks.keys().filter(|k| k.ends_with(&suffix));
+        match (matches.next(), matches.next()) {
+            (Some(only), None) => only.clone(),
+            _ => name.to_string(),
+        }
+    }
+
+    /// Appends one body line, read from `source_line`, to definition `def_idx` of chunk `name`.
+    pub(crate) fn push_line(&mut self, name: &str, def_idx: usize, line: &str, source_line: usize) {
+        if let Some(rc) = self.chunks.get(name) {
+            let mut borrowed = rc.borrow_mut();
+            let def = borrowed.definitions.get_mut(def_idx).unwrap();
+            def.source_lines.push(source_line);
+            if line.ends_with('\n') {
+                def.content.push(line.to_string());
+            } else {
+                def.content.push(format!("{}\n", line));
+            }
+        }
+    }
+
+    /// Rebuilds the list of file chunks after a batch of definitions, in the
+    /// order of their first definitions so outputs are written reproducibly.
+    pub(crate) fn finish_read(&mut self) {
+        self.assemble_test_harness();
+        let mut fc: Vec<((usize, usize), String)> = self
+            .chunks
+            .iter()
+            .filter(|(name, _)| name.starts_with("@file "))
+            .map(|(name, rc)| {
+                let first = rc
+                    .borrow()
+                    .definitions
+                    .first()
+                    .map_or((0, 0), |d| (d.file_idx, d.line));
+                (first, name.clone())
+            })
+            .collect();
+        fc.sort();
+        self.file_chunks = fc.into_iter().map(|(_, name)| name).collect();
+    }
+
+    /// (Re)define the test harness's file chunk with a test for each chunk
+    /// having a @test definition, in the order of their first definitions.
+    /// A harness path the inputs define themselves is left to them.
+    fn assemble_test_harness(&mut self) {
+        if let Some(previous) = self.harness_chunk.take() {
+            self.chunks.remove(&previous);
+        }
+        let Some(harness) = &self.test_harness else {
+            return;
+        };
+        let name = format!("@file {}", harness.path());
+        if self.chunks.contains_key(&name) {
+            return;
+        }
+        let mut tests: Vec<((usize, usize), String)> = self
+            .chunks
+            .iter()
+            .filter_map(|(chunk, rc)| {
+                let chunk_ref = rc.borrow();
+                let first = chunk_ref.definitions.first()?;
+                chunk_ref
+                    .definitions
+                    .iter()
+                    .any(|d| d.test)
+                    .then(|| ((first.file_idx, first.line), chunk.clone()))
+            })
+            .collect();
+        if tests.is_empty() {
+            return;
+        }
+        tests.sort();
+        let ((file_idx, line), _) = tests[0];
+        let tests: Vec<String> = tests.into_iter().map(|(_, chunk)| chunk).collect();
+        let pair = &self.syntaxes[0].delimiter_pairs[0];
+        let mut def = ChunkDef::new(0, file_idx, line);
+        def.content = harness.lines(&tests, &pair.open, &pair.close);
+        def.source_lines = vec![line; def.content.len()];
+        let mut chunk = NamedChunk::new();
+        chunk.definitions.push(def);
+        self.chunks
+            .insert(name.clone(), Rc::new(RefCell::new(chunk)));
+        self.harness_chunk = Some(name);
+    }
+
+    /// Collect the @test chunks into a generated output, or stop doing so.
+    pub fn set_test_harness(&mut self, harness: Option<TestHarness>) {
+        self.test_harness = harness;
+        self.finish_read();
+    }
+
+    /// Increments references on a chunk or returns an error if undefined.
+    fn inc_references(&self, chunk_name: &str, location: &ChunkLocation) -> Result<(), ChunkError> {
+        if let Some(rc) = self.chunks.get(chunk_name) {
+            let mut borrowed = rc.borrow_mut();
+            borrowed.references += 1;
+            Ok(())
+        } else {
+            Err(ChunkError::UndefinedChunk {
                 chunk: chunk_name.to_string(),
-                file_name,
                 location: location.clone(),
             })
         }
@@ -403,30 +2135,50 @@ impl ChunkStore {
         reference_location: ChunkLocation,
         reversed_mode: bool,
     ) -> Result<Vec<String>, ChunkError> {
-        const MAX_DEPTH: usize = 100;
-        if depth > MAX_DEPTH {
-            let file_name = self
-                .file_names
-                .get(reference_location.file_idx)
-                .cloned()
-                .unwrap_or_default();
+        let mut lines = Vec::new();
+        let mut sink = |line, _| {
+            lines.push(line);
+            Ok(())
+        };
+        let mut out = Emitter::new(&mut sink);
+        self.expand_into(
+            chunk_name,
+            target_indent,
+            depth,
+            seen,
+            reference_location,
+            reversed_mode,
+            &ExpandOptions::default(),
+            &mut out,
+        )?;
+        out.finish()?;
+        Ok(lines)
+    }
+
+    /// Expansion worker: emits each output line with its provenance to `out`.
+    #[allow(clippy::too_many_arguments)]
+    fn expand_into(
+        &self,
+        chunk_name: &str,
+        target_indent: &str,
+        depth: usize,
+        seen: &mut Vec<(String, ChunkLocation)>,
+        reference_location: ChunkLocation,
+        reversed_mode: bool,
+        options: &ExpandOptions,
+        out: &mut Emitter,
+    ) -> Result<(), ChunkError> {
+        if depth > options.max_depth {
             return Err(ChunkError::RecursionLimit {
                 chunk: chunk_name.to_string(),
-                file_name,
                 location: reference_location,
             });
         }
 
         // Check recursion
         if seen.iter().any(|(nm, _)| nm == chunk_name) {
-            let file_name = self
-                .file_names
-                .get(reference_location.file_idx)
-                .cloned()
-                .unwrap_or_default();
             return Err(ChunkError::RecursiveReference {
                 chunk: chunk_name.to_string(),
-                file_name,
                 location: reference_location,
             });
         }
@@ -437,43 +2189,59 @@ impl ChunkStore {
         let rc = match self.chunks.get(chunk_name) {
             Some(r) => r,
             None => {
-                let file_name = self
-                    .file_names
-                    .get(reference_location.file_idx)
-                    .cloned()
-                    .unwrap_or_default();
                 return Err(ChunkError::UndefinedChunk {
                     chunk: chunk_name.to_string(),
-                    file_name,
                     location: reference_location,
                 });
             }
         };
 
         let borrowed = rc.borrow();
-        let defs = &borrowed.definitions;
+        trace!(
+            "expanding '{}' ({} definition(s)) at depth {}",
+            chunk_name,
+            borrowed.definitions.len(),
+            depth
+        );
+        // Weighted order first; the sort is stable, so ties keep reading order
+        let mut defs: Vec<&ChunkDef> = borrowed.definitions.iter().collect();
+        defs.sort_by_key(|d| d.order);
 
         // Reverse definitions if @reversed
         let iter: Box<dyn Iterator<Item = &ChunkDef>> = if reversed_mode {
-            Box::new(defs.iter().rev())
+            Box::new(defs.into_iter().rev())
         } else {
-            Box::new(defs.iter())
+            Box::new(defs.into_iter())
         };
 
         seen.push((chunk_name.to_string(), reference_location));
-        let mut result = Vec::new();
 
         for def in iter {
-            let mut def_output = Vec::new();
+            out.inputs.insert(self.file_name(def.file_idx));
+            if def.exec {
+                self.expand_exec(chunk_name, def, target_indent, out)?;
+                continue;
+            }
             let mut line_count = 0;
-            for line in &def.content {
+            // A line ended by the continuation marker, waiting for the next one
+            let mut pending: Option<(String, Provenance)> = None;
+            for (line, &source_line) in def.content.iter().zip(&def.source_lines) {
                 line_count += 1;
                 // Check if line references another chunk
-                if let Some(caps) = self.slot_re.captures(line) {
+                let slot = if def.verbatim {
+                    None
+                } else {
+                    self.reference(def.file_idx, line)
+                };
+                if let Some(caps) = slot {
+                    if let Some((head, provenance)) = pending.take() {
+                        out.push((format!("{}\n", head), provenance))?;
+                    }
                     let add_indent = caps.get(1).map_or("", |m| m.as_str());
-                    let referenced_chunk = caps.get(2).map_or("", |m| m.as_str());
+                    let modifier = caps.get(2).map_or("", |m| m.as_str().trim());
+                    let referenced_chunk = caps.get(3).map_or("", |m| m.as_str());
 
-                    let line_is_reversed = line.contains("@reversed");
+                    let line_is_reversed = modifier == "@reversed";
                     let relative_indent = if add_indent.len() > def.base_indent {
                         &add_indent[def.base_indent..]
                     } else {
@@ -484,20 +2252,55 @@ impl ChunkStore {
                     } else {
                         format!("{}{}", target_indent, relative_indent)
                     };
-                    let new_loc = ChunkLocation {
-                        file_idx: def.file_idx,
-                        line: def.line + line_count - 1,
-                    };
+                    let new_loc = self.location(def.file_idx, def.line + line_count - 1);
+                    let expanded_from = out.len();
+
+                    if modifier == "@insert" {
+                        let location = self.location(def.file_idx, source_line);
+                        self.expand_insert(
+                            referenced_chunk,
+                            chunk_name,
+                            location,
+                            &new_indent,
+                            out,
+                        )?;
+                    } else {
+                        let resolved =
+                            self.resolve_reference(referenced_chunk.trim(), def.file_idx);
+                        self.check_private(&resolved, &new_loc)?;
+                        self.expand_into(
+                            &resolved,
+                            &new_indent,
+                            depth + 1,
+                            seen,
+                            new_loc,
+                            line_is_reversed,
+                            options,
+                            out,
+                        )?;
+                    }
 
-                    let expanded = self.expand_with_depth(
-                        referenced_chunk.trim(),
-                        &new_indent,
-                        depth + 1,
-                        seen,
-                        new_loc,
-                        line_is_reversed,
-                    )?;
-                    def_output.extend(expanded);
+                    let trailing = caps.get(4).map_or("", |m| m.as_str());
+                    if !trailing.trim().is_empty() {
+                        let trailing = options.substitute(self.unescape(trailing));
+                        let append =
+                            self.trailing_text == TrailingText::Append && out.len() > expanded_from;
+                        if let (true, Some((last, _))) = (append, out.last_mut()) {
+                            let end = last.trim_end_matches(['\n', '\r']).len();
+                            last.insert_str(end, &trailing);
+                        } else {
+                            let provenance = Provenance {
+                                chunk: chunk_name.to_string(),
+                                location: self.location(def.file_idx, source_line),
+                                indent: target_indent.to_string(),
+                                base_indent: def.base_indent,
+                            };
+                            out.push((
+                                format!("{}{}\n", new_indent, trailing.trim_start()),
+                                provenance,
+                            ))?;
+                        }
+                    }
                 } else {
                     // Plain line
                     let line_indent = if line.len() > def.base_indent {
@@ -505,123 +2308,1147 @@ impl ChunkStore {
                     } else {
                         line
                     };
-                    if target_indent.is_empty() {
-                        def_output.push(line_indent.to_owned());
+                    let uncomment = def
+                        .uncomment
+                        .as_deref()
+                        .or(options.uncomment.as_deref().filter(|_| !def.verbatim));
+                    let stripped = match uncomment {
+                        Some(prefix) => strip_comment(line_indent, prefix),
+                        None => Cow::Borrowed(line_indent),
+                    };
+                    let line_indent = if def.verbatim {
+                        Cow::Borrowed(stripped.as_ref())
                     } else {
-                        def_output.push(format!("{}{}", target_indent, line_indent));
+                        options.substitute(self.unescape(&stripped))
+                    };
+                    let provenance = Provenance {
+                        chunk: chunk_name.to_string(),
+                        location: self.location(def.file_idx, source_line),
+                        indent: target_indent.to_string(),
+                        base_indent: def.base_indent,
+                    };
+                    let (text, provenance) = match pending.take() {
+                        Some((mut head, provenance)) => {
+                            head.push_str(line_indent.trim_start());
+                            (head, provenance)
+                        }
+                        None if target_indent.is_empty() => (line_indent.into_owned(), provenance),
+                        None => (format!("{}{}", target_indent, line_indent), provenance),
+                    };
+                    match options.continued(&text).filter(|_| !def.verbatim) {
+                        Some(head) => pending = Some((head, provenance)),
+                        None => out.push((text, provenance))?,
                     }
                 }
             }
-            result.extend(def_output);
+            if let Some((head, provenance)) = pending {
+                out.push((format!("{}\n", head), provenance))?;
+            }
+        }
+
+        seen.pop();
+        Ok(())
+    }
+
+    /// Reject a reference to a @private chunk from outside its defining file.
+    fn check_private(&self, chunk_name: &str, location: &ChunkLocation) -> Result<(), ChunkError> {
+        let Some(rc) = self.chunks.get(chunk_name) else {
+            return Ok(());
+        };
+        let borrowed = rc.borrow();
+        let foreign = borrowed
+            .definitions
+            .iter()
+            .find(|d| d.private && d.file_idx != location.file_idx);
+        match foreign {
+            Some(d) => Err(ChunkError::PrivateChunkReference {
+                chunk: chunk_name.to_string(),
+                location: location.clone(),
+                defined_in: self.file_name(d.file_idx),
+            }),
+            None => Ok(()),
         }
+    }
+
+    /// Splice the raw contents of an external file for `<<@insert path>>`.
+    /// The path is resolved against the directory of the including input and
+    /// may not leave it: absolute paths and `..` are rejected.
+    fn expand_insert(
+        &self,
+        path: &str,
+        chunk_name: &str,
+        location: ChunkLocation,
+        target_indent: &str,
+        out: &mut Emitter,
+    ) -> Result<(), ChunkError> {
+        let file_name = self.file_name(location.file_idx);
+        let insert_failed = |message: String| ChunkError::InsertFailed {
+            path: path.to_string(),
+            location: location.clone(),
+            message,
+        };
+        path_is_safe(path).map_err(|e| insert_failed(e.to_string()))?;
+        let base_dir = Path::new(&file_name).parent().unwrap_or(Path::new(""));
+        let inserted = base_dir.join(path);
+        out.inputs.insert(inserted.to_string_lossy().into_owned());
+        let text = fs::read_to_string(&inserted).map_err(|e| insert_failed(e.to_string()))?;
+        for line in text.lines() {
+            let provenance = Provenance {
+                chunk: chunk_name.to_string(),
+                location: location.clone(),
+                indent: target_indent.to_string(),
+                base_indent: 0,
+            };
+            out.push((format!("{}{}\n", target_indent, line), provenance))?;
+        }
+        Ok(())
+    }
+
+    /// Run an @exec definition and emit its output as plain lines.
+    fn expand_exec(
+        &self,
+        chunk_name: &str,
+        def: &ChunkDef,
+        target_indent: &str,
+        out: &mut Emitter,
+    ) -> Result<(), ChunkError> {
+        let location = self.location(def.file_idx, def.line);
+        if !self.allow_exec {
+            return Err(ChunkError::ExecNotAllowed {
+                chunk: chunk_name.to_string(),
+                location,
+            });
+        }
+        let stdout =
+            run_command(&def.content.concat()).map_err(|message| ChunkError::ExecFailed {
+                chunk: chunk_name.to_string(),
+                location: location.clone(),
+                message,
+            })?;
+        for line in stdout.lines() {
+            let provenance = Provenance {
+                chunk: chunk_name.to_string(),
+                location: location.clone(),
+                indent: target_indent.to_string(),
+                base_indent: def.base_indent,
+            };
+            out.push((format!("{}{}\n", target_indent, line), provenance))?;
+        }
+        Ok(())
+    }
+
+    /// Expand from top-level, keeping the provenance of every output line.
+    pub fn expand_with_provenance(
+        &self,
+        chunk_name: &str,
+        indent: &str,
+    ) -> Result<Vec<(String, Provenance)>, ChunkError> {
+        let options = ExpandOptions {
+            indent: indent.to_string(),
+            ..ExpandOptions::default()
+        };
+        self.expand_traced(chunk_name, &options)
+    }
+
+    /// Expand from top-level (no reversed).
+    pub fn expand(&self, chunk_name: &str, indent: &str) -> Result<Vec<String>, ChunkError> {
+        let options = ExpandOptions {
+            indent: indent.to_string(),
+            ..ExpandOptions::default()
+        };
+        self.expand_with_options(chunk_name, &options)
+    }
+
+    /// Expand from top-level as configured by `options`.
+    pub fn expand_with_options(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+    ) -> Result<Vec<String>, ChunkError> {
+        let lines = self.expand_traced(chunk_name, options)?;
+        Ok(lines.into_iter().map(|(line, _)| line).collect())
+    }
+
+    /// Expand from top-level as configured by `options`, keeping provenance.
+    /// Line directives share the provenance of the line they introduce.
+    pub fn expand_traced(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+    ) -> Result<Vec<(String, Provenance)>, ChunkError> {
+        let mut out = Vec::new();
+        self.expand_each(chunk_name, options, &mut |line, provenance| {
+            out.push((line, provenance));
+            Ok(())
+        })?;
+        Ok(out)
+    }
+
+    /// Expand from top-level as configured by `options`, writing the lines
+    /// to `writer` as they are produced instead of collecting them first.
+    /// On an error the lines before it have been written already.
+    pub fn expand_to<W: io::Write>(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+        writer: &mut W,
+    ) -> Result<(), ChunkError> {
+        self.expand_each(chunk_name, options, &mut |line, _| {
+            Ok(writer.write_all(line.as_bytes())?)
+        })?;
+        Ok(())
+    }
+
+    /// The inputs an expansion from top-level reads, as configured by
+    /// `options`: the documents of every definition it visits, whether or
+    /// not they emit lines, and the files spliced in by `@insert`.
+    pub fn expand_inputs(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+    ) -> Result<BTreeSet<String>, ChunkError> {
+        self.expand_each(chunk_name, options, &mut |_, _| Ok(()))
+    }
+
+    /// Expand from top-level as configured by `options`, calling `visitor`
+    /// with each line and where it came from, in output order, as soon as
+    /// the line is complete.
+    pub fn expand_with_visitor<F: FnMut(&str, &Provenance)>(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+        mut visitor: F,
+    ) -> Result<(), ChunkError> {
+        self.expand_each(chunk_name, options, &mut |line, provenance| {
+            visitor(&line, &provenance);
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Expand from top-level as configured by `options`, passing each line
+    /// and its provenance to `sink` as soon as it is complete. Returns the
+    /// files the expansion read.
+    fn expand_each(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+        sink: &mut LineSink,
+    ) -> Result<BTreeSet<String>, ChunkError> {
+        let mut seen = Vec::new();
+        let loc = self.location(0, 0);
+        let chunk_name = self.resolve_top_level(chunk_name);
+        // Document constants, overridden by those passed in the options
+        let merged;
+        let options = if self.defines.is_empty() {
+            options
+        } else {
+            let mut defines = self.defines.clone();
+            defines.extend(options.defines.clone());
+            merged = ExpandOptions {
+                defines,
+                ..options.clone()
+            };
+            &merged
+        };
+
+        let mut previous: Option<ChunkLocation> = None;
+        let mut with_directives = |line: String, provenance: Provenance| {
+            if options.line_directive.is_some() {
+                let location = &provenance.location;
+                // Spliced lines (@insert, @exec) all carry the location of their reference
+                let follows = previous.as_ref().is_some_and(|p| {
+                    p.file_idx == location.file_idx
+                        && (p.line == location.line || p.line + 1 == location.line)
+                });
+                if !follows {
+                    let file_name = self.file_name(location.file_idx);
+                    if let Some(directive) = options.directive(&file_name, location.line) {
+                        sink(directive, provenance.clone())?;
+                    }
+                }
+                previous = Some(location.clone());
+            }
+            sink(line, provenance)
+        };
+        let mut out = Emitter::new(&mut with_directives);
+        self.expand_into(
+            &chunk_name,
+            &options.indent,
+            0,
+            &mut seen,
+            loc,
+            options.reversed,
+            options,
+            &mut out,
+        )?;
+        out.finish()
+    }
+
+    /// For tests or direct usage: get chunk content with no indentation.
+    pub fn get_chunk_content(&self, chunk_name: &str) -> Result<Vec<String>, ChunkError> {
+        self.expand(chunk_name, "")
+    }
+
+    /// Return a slice of chunk names that start with "@file ".
+    pub fn get_file_chunks(&self) -> &[String] {
+        &self.file_chunks
+    }
+
+    /// All chunk names, sorted.
+    pub fn chunk_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.chunks.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Every (chunk, referenced chunk) pair, sorted and without duplicates.
+    /// References are resolved as expansion resolves them; `@insert` lines
+    /// name files, not chunks, and are left out.
+    pub fn references(&self) -> Vec<(String, String)> {
+        let mut edges = BTreeSet::new();
+        for (name, rc) in &self.chunks {
+            for def in rc.borrow().definitions.iter().filter(|d| !d.verbatim) {
+                for line in &def.content {
+                    if let Some(caps) = self.reference(def.file_idx, line) {
+                        if caps.get(2).is_some_and(|m| m.as_str().trim() == "@insert") {
+                            continue;
+                        }
+                        let target = self.resolve_reference(&caps[3], def.file_idx);
+                        edges.insert((name.clone(), target));
+                    }
+                }
+            }
+        }
+        edges.into_iter().collect()
+    }
+
+    /// Every reference cycle, one per strongly connected component of the
+    /// reference graph that has more than one chunk or a chunk referencing
+    /// itself; sorted by their first chunk.
+    pub fn reference_cycles(&self) -> Vec<ReferenceCycle> {
+        let mut sites: Vec<ReferenceSite> = Vec::new();
+        for (name, rc) in &self.chunks {
+            for def in rc.borrow().definitions.iter().filter(|d| !d.verbatim) {
+                for (line, &source_line) in def.content.iter().zip(&def.source_lines) {
+                    let Some(caps) = self.reference(def.file_idx, line) else {
+                        continue;
+                    };
+                    if caps.get(2).is_some_and(|m| m.as_str().trim() == "@insert") {
+                        continue;
+                    }
+                    let to = self.resolve_reference(caps[3].trim(), def.file_idx);
+                    if self.chunks.contains_key(&to) {
+                        sites.push(ReferenceSite {
+                            from: name.clone(),
+                            to,
+                            file_name: self
+                                .file_names
+                                .get(def.file_idx)
+                                .cloned()
+                                .unwrap_or_default(),
+                            location: self.location(def.file_idx, source_line),
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut edges: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for site in &sites {
+            edges.entry(&site.from).or_default().insert(&site.to);
+        }
+        let mut cycles: Vec<ReferenceCycle> = Tarjan::run(&edges)
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1
+                    || edges
+                        .get(component[0])
+                        .is_some_and(|to| to.contains(component[0]))
+            })
+            .map(|component| {
+                let mut chunks: Vec<String> = component.iter().map(|c| c.to_string()).collect();
+                chunks.sort();
+                let mut references: Vec<ReferenceSite> = sites
+                    .iter()
+                    .filter(|s| chunks.contains(&s.from) && chunks.contains(&s.to))
+                    .cloned()
+                    .collect();
+                references.sort_by_key(|s| (s.location.file_idx, s.location.line));
+                ReferenceCycle { chunks, references }
+            })
+            .collect();
+        cycles.sort_by(|a, b| a.chunks.cmp(&b.chunks));
+        cycles
+    }
+
+    /// Every chunk after the chunks it references, with ties broken by name
+    /// and the file chunks last, in the order they are written. Fails with
+    /// the reference cycles if there are any.
+    pub fn topological_order(&self) -> Result<Vec<String>, ChunkError> {
+        let cycles = self.reference_cycles();
+        if !cycles.is_empty() {
+            return Err(ChunkError::ReferenceCycles { cycles });
+        }
+        let mut pending: HashMap<String, usize> = HashMap::new();
+        let mut dependents: HashMap<String, Vec<String>> = HashMap::new();
+        for (from, to) in self.references() {
+            if self.chunks.contains_key(&to) && !from.starts_with("@file ") {
+                *pending.entry(from.clone()).or_insert(0) += 1;
+                dependents.entry(to).or_default().push(from);
+            }
+        }
+        let mut ready: BTreeSet<String> = self
+            .chunks
+            .keys()
+            .filter(|name| !name.starts_with("@file ") && !pending.contains_key(*name))
+            .cloned()
+            .collect();
+        let mut order = Vec::new();
+        while let Some(name) = ready.pop_first() {
+            for dependent in dependents.get(&name).into_iter().flatten() {
+                let count = pending.get_mut(dependent).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.insert(dependent.clone());
+                }
+            }
+            order.push(name);
+        }
+        order.extend(self.file_chunks.iter().cloned());
+        Ok(order)
+    }
+
+    /// Definitions, body lines and references of every chunk, sorted by name.
+    pub fn chunk_stats(&self) -> Vec<ChunkStats> {
+        let mut stats: Vec<ChunkStats> = self
+            .chunks
+            .iter()
+            .map(|(name, rc)| {
+                let chunk = rc.borrow();
+                ChunkStats {
+                    name: name.clone(),
+                    definitions: chunk.definitions.len(),
+                    lines: chunk.definitions.iter().map(|d| d.content.len()).sum(),
+                    references: chunk
+                        .definitions
+                        .iter()
+                        .filter(|d| !d.verbatim)
+                        .flat_map(|d| d.content.iter().map(move |line| (d.file_idx, line)))
+                        .filter(|(file_idx, line)| self.reference(*file_idx, line).is_some())
+                        .count(),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
+
+    /// Every definition and `@doc` section in reading order, as classic noweb
+    /// reads them, with each chunk's bodies in expansion order and `defines`
+    /// substituted. `@insert` lines and `@exec` definitions are kept as text
+    /// and listed as unexported. The generated test harness is left out.
+    pub fn noweb_sections(&self, defines: &HashMap<String, String>) -> NowebSections {
+        let noweb_name = |name: &str| name.strip_prefix("@file ").unwrap_or(name).to_string();
+        // Document constants, overridden by those passed, as on expansion
+        let mut substitution = ExpandOptions {
+            defines: self.defines.clone(),
+            ..ExpandOptions::default()
+        };
+        substitution.defines.extend(defines.clone());
+        let mut sections: Vec<((usize, usize), NowebSection)> = Vec::new();
+        let mut unexported = Vec::new();
+        for (name, rc) in &self.chunks {
+            if self.harness_chunk.as_deref() == Some(name.as_str()) {
+                continue;
+            }
+            let borrowed = rc.borrow();
+            // noweb concatenates the definitions in reading order, so their
+            // bodies fill the definitions' places in the order of expansion
+            let mut positions: Vec<(usize, usize)> = borrowed
+                .definitions
+                .iter()
+                .map(|def| (def.file_idx, def.line))
+                .collect();
+            positions.sort();
+            let mut defs: Vec<&ChunkDef> = borrowed.definitions.iter().collect();
+            defs.sort_by_key(|def| def.order);
+            for (position, def) in positions.into_iter().zip(defs) {
+                if def.exec {
+                    unexported.push((
+                        self.location(def.file_idx, def.line),
+                        format!("@exec chunk '{}' is exported as its command", name),
+                    ));
+                }
+                let mut lines = Vec::new();
+                for (line, source_line) in def.content.iter().zip(&def.source_lines) {
+                    let line = line.trim_end_matches(['\n', '\r']);
+                    let slot = if def.verbatim {
+                        None
+                    } else {
+                        self.reference(def.file_idx, line)
+                    };
+                    let modifier = slot
+                        .as_ref()
+                        .and_then(|caps| caps.get(2))
+                        .map_or("", |m| m.as_str().trim());
+                    match slot {
+                        Some(caps) if modifier != "@insert" => {
+                            let indent = caps.get(1).map_or("", |m| m.as_str());
+                            let target = self.resolve_reference(caps[3].trim(), def.file_idx);
+                            let trailing = caps.get(4).map_or("", |m| m.as_str());
+                            lines.push(NowebLine::Reference {
+                                indent: indent.get(def.base_indent..).unwrap_or("").to_string(),
+                                name: noweb_name(&target),
+                                trailing: substitution
+                                    .substitute(self.unescape(trailing))
+                                    .into_owned(),
+                            });
+                        }
+                        _ => {
+                            if slot.is_some() {
+                                unexported.push((
+                                    self.location(def.file_idx, *source_line),
+                                    format!(
+                                        "@insert in '{}' has no noweb equivalent and is \
+                                         exported as text",
+                                        name
+                                    ),
+                                ));
+                            }
+                            let text = line.get(def.base_indent..).unwrap_or(line);
+                            let text = if def.verbatim {
+                                Cow::Borrowed(text)
+                            } else {
+                                substitution.substitute(self.unescape(text))
+                            };
+                            lines.push(NowebLine::Text(text.into_owned()));
+                        }
+                    }
+                }
+                sections.push((
+                    position,
+                    NowebSection::Chunk {
+                        name: noweb_name(name),
+                        lines,
+                    },
+                ));
+            }
+        }
+        for doc in &self.docs {
+            let lines = doc
+                .content
+                .iter()
+                .map(|line| line.trim_end_matches(['\n', '\r']).to_string())
+                .collect();
+            sections.push((
+                (doc.location.file_idx, doc.location.line),
+                NowebSection::Doc(lines),
+            ));
+        }
+        sections.sort_by_key(|(position, _)| *position);
+        unexported.sort_by_key(|(location, _): &(ChunkLocation, String)| {
+            (location.file_idx, location.line)
+        });
+        NowebSections {
+            sections: sections.into_iter().map(|(_, section)| section).collect(),
+            unexported,
+        }
+    }
+
+    /// How a line of document `file_name` reads: a chunk opening (name, and
+    /// whether it is @verbatim), a closing line, a reference, the opening of
+    /// a @doc section or text.
+    pub(crate) fn classify(&self, file_name: &str, line: &str) -> LineKind {
+        let syntax = &self.syntaxes[self.syntax_index(file_name)];
+        let opening = syntax
+            .delimiter_pairs
+            .iter()
+            .find_map(|pair| pair.open_re.captures(line));
+        if let Some(caps) = opening {
+            if let Some(modifiers) = ChunkModifiers::of_opening(&caps) {
+                if modifiers.doc {
+                    return LineKind::Doc {
+                        name: caps[3].to_string(),
+                    };
+                }
+                let name = if modifiers.file {
+                    format!("@file {}", &caps[3])
+                } else {
+                    caps[3].to_string()
+                };
+                return LineKind::Open {
+                    name,
+                    verbatim: modifiers.verbatim,
+                    lang: modifiers.language().map(str::to_string),
+                };
+            }
+        }
+        if syntax.close_re.is_match(line) {
+            return LineKind::Close;
+        }
+        let reference = syntax
+            .delimiter_pairs
+            .iter()
+            .find_map(|pair| self.reference_with(pair, line));
+        if let Some(caps) = reference {
+            return LineKind::Reference {
+                name: caps[3].to_string(),
+            };
+        }
+        LineKind::Text
+    }
+
+    /// Whether any definition of the chunk carries @managed.
+    pub fn is_managed(&self, name: &str) -> bool {
+        self.chunks
+            .get(name)
+            .is_some_and(|rc| rc.borrow().definitions.iter().any(|d| d.managed))
+    }
+
+    /// The language declared by the first definition of the chunk that
+    /// declares one.
+    pub fn language_of(&self, name: &str) -> Option<String> {
+        let rc = self.chunks.get(&self.resolve_top_level(name))?;
+        let chunk = rc.borrow();
+        chunk.definitions.iter().find_map(|d| d.lang.clone())
+    }
+
+    /// Check if the store has a chunk of the given name.
+    pub fn has_chunk(&self, name: &str) -> bool {
+        self.chunks.contains_key(&self.resolve_top_level(name))
+    }
+
+    /// The definitions of the chunk, in reading order; empty if it has none.
+    /// The views are copies, since definitions may change on later reads.
+    pub fn definitions_of(&self, name: &str) -> Vec<ChunkDefView> {
+        self.chunks
+            .get(&self.resolve_top_level(name))
+            .map_or_else(Vec::new, |rc| {
+                rc.borrow()
+                    .definitions
+                    .iter()
+                    .map(|def| ChunkDefView {
+                        content: def.content.clone(),
+                        base_indent: def.base_indent,
+                        location: self.location(def.file_idx, def.line),
+                        annotation: def.annotation.clone(),
+                        lang: def.lang.clone(),
+                        attributes: def.attributes.clone(),
+                    })
+                    .collect()
+            })
+    }
+
+    /// Where the chunk is defined, in reading order; empty if it is not.
+    pub fn definition_locations(&self, name: &str) -> Vec<ChunkLocation> {
+        self.definitions_of(name)
+            .into_iter()
+            .map(|def| def.location)
+            .collect()
+    }
+
+    /// Forget everything read; the settings are kept.
+    pub fn reset(&mut self) {
+        self.chunks.clear();
+        self.file_chunks.clear();
+        self.file_names.clear();
+        self.file_syntaxes.clear();
+        self.redefinitions.clear();
+        self.file_redefinitions.clear();
+        self.name_violations.clear();
+        self.defines.clear();
+        self.docs.clear();
+        self.harness_chunk = None;
+    }
+
+    /// Warnings for any chunk never referenced.
+    pub fn check_unused_chunks(&self) -> Vec<String> {
+        let mut warns = Vec::new();
+        for (name, rc) in &self.chunks {
+            if !name.starts_with("@file ") {
+                let borrowed = rc.borrow();
+                if borrowed.references == 0 {
+                    if let Some(first_def) = borrowed.definitions.first() {
+                        let fname = self
+                            .file_names
+                            .get(first_def.file_idx)
+                            .cloned()
+                            .unwrap_or_default();
+                        let ln = first_def.line + 1;
+                        warns.push((
+                            (first_def.file_idx, first_def.line),
+                            format!(
+                                "{} line {}: chunk '{}' is defined but never referenced",
+                                fname, ln, name
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+        // In source order, like the chunks themselves
+        warns.sort();
+        warns.into_iter().map(|(_, warn)| warn).collect()
+    }
+}
+
+/// Writes @file ... chunks to disk
+pub struct ChunkWriter<'a> {
+    safe_file_writer: &'a mut SafeFileWriter,
+}
+
+impl<'a> ChunkWriter<'a> {
+    pub fn new(sw: &'a mut SafeFileWriter) -> Self {
+        Self {
+            safe_file_writer: sw,
+        }
+    }
+
+    pub fn write_chunk(&mut self, chunk_name: &str, content: &[String]) -> Result<(), AzadiError> {
+        if !chunk_name.starts_with("@file ") {
+            return Ok(());
+        }
+        let path_str = &chunk_name[5..].trim();
+        self.safe_file_writer
+            .write_atomic(path_str, content.concat().as_bytes())?;
+        Ok(())
+    }
+}
+
+/// High-level reading, expanding, writing API. Clones are independent
+/// copies of the chunks read, sharing the filters and reporter.
+#[derive(Clone)]
+pub struct Clip {
+    store: ChunkStore,
+    writer: SafeFileWriter,
+    dialect: Dialect,
+    /// Treat appending redefinitions of regular chunks as errors.
+    strict_redefinitions: bool,
+    /// Per-line provenance of the outputs last written with tracking enabled.
+    output_provenance: HashMap<String, Vec<Provenance>>,
+    /// Applied in order to every output before it is written.
+    filters: Vec<Rc<dyn OutputFilter>>,
+    /// Glob patterns of the file chunk paths to write; empty writes all.
+    file_selection: Vec<String>,
+    /// Receives warnings and progress, shared with the writer.
+    reporter: Arc<dyn Reporter>,
+    /// Delete orphaned outputs instead of warning about them.
+    prune: bool,
+    /// Go on with the other outputs when one cannot be generated.
+    keep_going: bool,
+}
+
+/// Step-by-step construction of a `Clip`. Everything not set keeps the
+/// default of the command line: `<<`, `>>` and `@` delimiters, `#` and `//`
+/// comment markers, the noweb dialect, and a writer for `gen` with its
+/// private files in `_azadi_work`.
+///
+/// ```
+/// use azadi_noweb::{Clip, SafeFileWriter};
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let clip = Clip::builder()
+///     .delimiters("[[", "]]")
+///     .chunk_end("%")
+///     .comment_markers(&["--"])
+///     .safe_writer(SafeFileWriter::new(dir.path().join("out"), dir.path().join("work")))
+///     .build()
+///     .unwrap();
+/// assert_eq!(clip.delimiters(), ("[[".to_string(), "]]".to_string()));
+/// ```
+pub struct ClipBuilder {
+    safe_writer: Option<SafeFileWriter>,
+    open_delim: String,
+    close_delim: String,
+    extra_delimiters: Vec<(String, String)>,
+    chunk_end: String,
+    comment_markers: Vec<String>,
+    marker_rules: MarkerRules,
+    dialect: Dialect,
+    reporter: Option<Arc<dyn Reporter>>,
+    allow_exec: bool,
+    namespaced: bool,
+    prefix_from_input: bool,
+    trailing_text: TrailingText,
+    strict_redefinitions: bool,
+    name_policy: NamePolicy,
+    tags: Vec<String>,
+    test_harness: Option<TestHarness>,
+}
+
+impl Default for ClipBuilder {
+    fn default() -> Self {
+        Self {
+            safe_writer: None,
+            open_delim: "<<".to_string(),
+            close_delim: ">>".to_string(),
+            extra_delimiters: Vec::new(),
+            chunk_end: "@".to_string(),
+            comment_markers: vec!["#".to_string(), "//".to_string()],
+            marker_rules: MarkerRules::default(),
+            dialect: Dialect::default(),
+            reporter: None,
+            allow_exec: false,
+            namespaced: false,
+            prefix_from_input: false,
+            trailing_text: TrailingText::default(),
+            strict_redefinitions: false,
+            name_policy: NamePolicy::default(),
+            tags: Vec::new(),
+            test_harness: None,
+        }
+    }
+}
+
+impl ClipBuilder {
+    /// Delimiters around chunk names.
+    pub fn delimiters(mut self, open: &str, close: &str) -> Self {
+        self.open_delim = open.to_string();
+        self.close_delim = close.to_string();
+        self
+    }
+
+    /// See `Clip::add_delimiters`.
+    pub fn extra_delimiters(mut self, open: &str, close: &str) -> Self {
+        self.extra_delimiters
+            .push((open.to_string(), close.to_string()));
+        self
+    }
+
+    /// Marker of chunk-end lines.
+    pub fn chunk_end(mut self, chunk_end: &str) -> Self {
+        self.chunk_end = chunk_end.to_string();
+        self
+    }
+
+    /// Comment markers that may precede chunk lines.
+    pub fn comment_markers<S: AsRef<str>>(mut self, markers: &[S]) -> Self {
+        self.comment_markers = markers.iter().map(|m| m.as_ref().to_string()).collect();
+        self
+    }
+
+    /// See `Clip::set_marker_rules`.
+    pub fn marker_rules(mut self, rules: MarkerRules) -> Self {
+        self.marker_rules = rules;
+        self
+    }
+
+    /// The writer of file chunks.
+    pub fn safe_writer(mut self, writer: SafeFileWriter) -> Self {
+        self.safe_writer = Some(writer);
+        self
+    }
+
+    /// See `Clip::set_dialect`.
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// See `Clip::set_reporter`.
+    pub fn reporter(mut self, reporter: Arc<dyn Reporter>) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    /// See `Clip::set_allow_exec`.
+    pub fn allow_exec(mut self, allow: bool) -> Self {
+        self.allow_exec = allow;
+        self
+    }
+
+    /// See `Clip::set_namespaced`.
+    pub fn namespaced(mut self, namespaced: bool) -> Self {
+        self.namespaced = namespaced;
+        self
+    }
+
+    /// See `Clip::set_prefix_from_input`.
+    pub fn prefix_from_input(mut self, prefix: bool) -> Self {
+        self.prefix_from_input = prefix;
+        self
+    }
+
+    /// See `Clip::set_trailing_text`.
+    pub fn trailing_text(mut self, mode: TrailingText) -> Self {
+        self.trailing_text = mode;
+        self
+    }
+
+    /// See `Clip::set_strict_redefinitions`.
+    pub fn strict_redefinitions(mut self, strict: bool) -> Self {
+        self.strict_redefinitions = strict;
+        self
+    }
+
+    /// See `Clip::set_name_policy`.
+    pub fn name_policy(mut self, policy: NamePolicy) -> Self {
+        self.name_policy = policy;
+        self
+    }
+
+    /// See `Clip::set_tag_selection`.
+    pub fn tags<S: AsRef<str>>(mut self, tags: &[S]) -> Self {
+        self.tags = tags.iter().map(|t| t.as_ref().to_string()).collect();
+        self
+    }
+
+    /// See `Clip::set_test_harness`.
+    pub fn test_harness(mut self, harness: TestHarness) -> Self {
+        self.test_harness = Some(harness);
+        self
+    }
+
+    /// The clip, or an error naming what is wrong with its syntax.
+    pub fn build(self) -> Result<Clip, ChunkError> {
+        let mut store = ChunkStore::try_new(
+            &self.open_delim,
+            &self.close_delim,
+            &self.chunk_end,
+            &self.comment_markers,
+        )?;
+        for (open, close) in &self.extra_delimiters {
+            store.add_delimiters(open, close)?;
+        }
+        store.set_marker_rules(self.marker_rules)?;
+        // The default writer creates its directories, so not before the
+        // syntax is known to be valid
+        let writer = self
+            .safe_writer
+            .unwrap_or_else(|| SafeFileWriter::new("gen", "_azadi_work"));
+        let mut clip = Clip::with_store(writer, store);
+        if let Some(reporter) = self.reporter {
+            clip.set_reporter(reporter);
+        }
+        clip.set_dialect(self.dialect);
+        clip.set_allow_exec(self.allow_exec);
+        clip.set_namespaced(self.namespaced);
+        clip.set_prefix_from_input(self.prefix_from_input);
+        clip.set_trailing_text(self.trailing_text);
+        clip.set_strict_redefinitions(self.strict_redefinitions);
+        clip.set_name_policy(self.name_policy);
+        clip.set_tag_selection(self.tags);
+        clip.set_test_harness(self.test_harness);
+        Ok(clip)
+    }
+}
+
+impl Clip {
+    /// Start building a clip; see `ClipBuilder`.
+    pub fn builder() -> ClipBuilder {
+        ClipBuilder::default()
+    }
+
+    /// Panics if the syntax is rejected by `try_new`.
+    pub fn new(
+        safe_file_writer: SafeFileWriter,
+        open_delim: &str,
+        close_delim: &str,
+        chunk_end: &str,
+        comment_markers: &[String],
+    ) -> Self {
+        Self::try_new(
+            safe_file_writer,
+            open_delim,
+            close_delim,
+            chunk_end,
+            comment_markers,
+        )
+        .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// A clip for the given syntax, or an error naming what is wrong with it.
+    pub fn try_new(
+        safe_file_writer: SafeFileWriter,
+        open_delim: &str,
+        close_delim: &str,
+        chunk_end: &str,
+        comment_markers: &[String],
+    ) -> Result<Self, ChunkError> {
+        let store = ChunkStore::try_new(open_delim, close_delim, chunk_end, comment_markers)?;
+        Ok(Self::with_store(safe_file_writer, store))
+    }
+
+    fn with_store(safe_file_writer: SafeFileWriter, store: ChunkStore) -> Self {
+        Self {
+            store,
+            writer: safe_file_writer,
+            dialect: Dialect::default(),
+            strict_redefinitions: false,
+            output_provenance: HashMap::new(),
+            filters: Vec::new(),
+            file_selection: Vec::new(),
+            reporter: report::console(),
+            prune: false,
+            keep_going: false,
+        }
+    }
+
+    /// Send warnings, notices and progress of this clip and its writer to `reporter`.
+    pub fn set_reporter(&mut self, reporter: Arc<dyn Reporter>) {
+        self.writer.set_reporter(Arc::clone(&reporter));
+        self.reporter = reporter;
+    }
+
+    /// Where this clip's messages go.
+    pub fn reporter(&self) -> &Arc<dyn Reporter> {
+        &self.reporter
+    }
+
+    /// Also read chunks written with another pair of delimiters; see
+    /// `ChunkStore::add_delimiters`.
+    pub fn add_delimiters(&mut self, open: &str, close: &str) -> Result<(), ChunkError> {
+        self.store.add_delimiters(open, close)
+    }
+
+    /// Choose the comment markers of the inputs read from now on; see
+    /// `ChunkStore::set_marker_rules`.
+    pub fn set_marker_rules(&mut self, rules: MarkerRules) -> Result<(), ChunkError> {
+        self.store.set_marker_rules(rules)
+    }
+
+    /// Permit or forbid running the commands of @exec chunks.
+    pub fn set_allow_exec(&mut self, allow: bool) {
+        self.store.set_allow_exec(allow);
+    }
+
+    /// Register a filter applied only to the outputs whose chunk declares
+    /// `language`, e.g. with `<<@file a.py>>= python`.
+    pub fn add_language_filter<F: OutputFilter + 'static>(&mut self, language: &str, filter: F) {
+        self.filters.push(Rc::new(ForLanguage {
+            language: language.to_string(),
+            filter,
+        }));
+    }
+
+    /// Register a filter applied to every output before it is written.
+    pub fn add_filter<F: OutputFilter + 'static>(&mut self, filter: F) {
+        self.filters.push(Rc::new(filter));
+    }
+
+    /// Register a filter applied to each line of every output before it is written.
+    pub fn add_line_filter<F: LineFilter + 'static>(&mut self, filter: F) {
+        self.filters.push(Rc::new(PerLine(filter)));
+    }
 
-        seen.pop();
-        Ok(result)
+    /// Only write the file chunks whose path matches one of these glob patterns;
+    /// an empty list writes all of them.
+    pub fn set_file_selection(&mut self, patterns: Vec<String>) {
+        self.file_selection = patterns;
     }
 
-    /// Expand from top-level (no reversed).
-    pub fn expand(&self, chunk_name: &str, indent: &str) -> Result<Vec<String>, ChunkError> {
-        let mut seen = Vec::new();
-        let loc = ChunkLocation {
-            file_idx: 0,
-            line: 0,
-        };
-        self.expand_with_depth(chunk_name, indent, 0, &mut seen, loc, false)
+    fn is_selected(&self, file_chunk: &str) -> bool {
+        let path = file_chunk["@file ".len()..].trim();
+        self.file_selection.is_empty() || self.file_selection.iter().any(|p| glob_match(p, path))
     }
 
-    /// For tests or direct usage: get chunk content with no indentation.
-    pub fn get_chunk_content(&self, chunk_name: &str) -> Result<Vec<String>, ChunkError> {
-        self.expand(chunk_name, "")
+    /// Delete the outputs whose file chunk has disappeared from the sources
+    /// when writing, instead of warning about them.
+    pub fn set_prune(&mut self, prune: bool) {
+        self.prune = prune;
     }
 
-    /// Return a slice of chunk names that start with "@file ".
-    pub fn get_file_chunks(&self) -> &[String] {
-        &self.file_chunks
+    /// When an output cannot be expanded or written, go on with the others
+    /// and fail at the end with `AzadiError::Outputs`, listing every failure.
+    pub fn set_keep_going(&mut self, keep_going: bool) {
+        self.keep_going = keep_going;
     }
 
-    /// Check if the store has a chunk of the given name.
-    pub fn has_chunk(&self, name: &str) -> bool {
-        self.chunks.contains_key(name)
+    /// Make appending redefinitions of regular chunks an error instead of a warning.
+    pub fn set_strict_redefinitions(&mut self, strict: bool) {
+        self.strict_redefinitions = strict;
     }
 
-    /// Reset everything
-    pub fn reset(&mut self) {
-        self.chunks.clear();
-        self.file_chunks.clear();
-        self.file_names.clear();
+    /// Require the names of regular chunks read from now on to follow
+    /// `policy`; definitions breaking it are left out and reported by
+    /// `check_chunk_names`.
+    pub fn set_name_policy(&mut self, policy: NamePolicy) {
+        self.store.set_name_policy(policy);
     }
 
-    /// Warnings for any chunk never referenced.
-    pub fn check_unused_chunks(&self) -> Vec<String> {
-        let mut warns = Vec::new();
-        for (name, rc) in &self.chunks {
-            if !name.starts_with("@file ") {
-                let borrowed = rc.borrow();
-                if borrowed.references == 0 {
-                    if let Some(first_def) = borrowed.definitions.first() {
-                        let fname = self
-                            .file_names
-                            .get(first_def.file_idx)
-                            .cloned()
-                            .unwrap_or_default();
-                        let ln = first_def.line + 1;
-                        warns.push(format!(
-                            "Warning: {} line {}: chunk '{}' is defined but never referenced",
-                            fname, ln, name
-                        ));
-                    }
-                }
-            }
-        }
-        warns.sort();
-        warns
+    /// Read only the definitions whose `tags` attribute names one of `tags`,
+    /// and those without tags, from now on, so that a document yields the
+    /// subset for one platform or tutorial step; an empty list reads all.
+    pub fn set_tag_selection(&mut self, tags: Vec<String>) {
+        self.store.set_tag_selection(tags);
     }
-}
 
-/// Writes @file ... chunks to disk
-pub struct ChunkWriter<'a> {
-    safe_file_writer: &'a mut SafeFileWriter,
-}
+    /// Write the chunks opened with `@test` as the tests of one generated
+    /// output, in the template of its language; `None` stops doing so. The
+    /// harness is kept up to date as inputs are read.
+    pub fn set_test_harness(&mut self, harness: Option<TestHarness>) {
+        self.store.set_test_harness(harness);
+    }
 
-impl<'a> ChunkWriter<'a> {
-    pub fn new(sw: &'a mut SafeFileWriter) -> Self {
-        Self {
-            safe_file_writer: sw,
+    /// Fail when two inputs would share a namespace under `--namespaced`.
+    pub fn check_namespaces(&self) -> Result<(), ChunkError> {
+        match self.store.namespace_collision() {
+            Some(collision) => Err(collision),
+            None => Ok(()),
         }
     }
 
-    pub fn write_chunk(&mut self, chunk_name: &str, content: &[String]) -> Result<(), AzadiError> {
-        if !chunk_name.starts_with("@file ") {
-            return Ok(());
+    /// The first chunk definition rejected by the name policy, as an error.
+    pub fn check_chunk_names(&self) -> Result<(), AzadiError> {
+        match self.store.name_violations().first() {
+            Some(violation) => Err(violation.to_error().into()),
+            None => Ok(()),
         }
-        let path_str = &chunk_name[5..].trim();
-        let final_path = self.safe_file_writer.before_write(path_str)?;
-        let mut f = fs::File::create(&final_path)?;
-        for line in content {
-            f.write_all(line.as_bytes())?;
+    }
+
+    /// Warnings for chunks that were silently continued by a later definition,
+    /// or the first of them as an error in strict mode.
+    pub fn check_redefinitions(&self) -> Result<Vec<String>, AzadiError> {
+        let redefinitions = self.store.redefinitions();
+        if self.strict_redefinitions {
+            if let Some(first) = redefinitions.first() {
+                return Err(first.to_error().into());
+            }
         }
-        self.safe_file_writer.after_write(path_str)?;
-        Ok(())
+        Ok(redefinitions.iter().map(ToString::to_string).collect())
     }
-}
 
-/// High-level reading, expanding, writing API.
-pub struct Clip {
-    store: ChunkStore,
-    writer: SafeFileWriter,
-}
+    /// Resolve `@<<` escapes in a stored line, as expansion does.
+    pub(crate) fn unescape<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        self.store.unescape(line)
+    }
 
-impl Clip {
-    pub fn new(
-        safe_file_writer: SafeFileWriter,
-        open_delim: &str,
-        close_delim: &str,
-        chunk_end: &str,
-        comment_markers: &[String],
-    ) -> Self {
-        Self {
-            store: ChunkStore::new(open_delim, close_delim, chunk_end, comment_markers),
-            writer: safe_file_writer,
+    /// Escape a line of output text so it reads back as plain text.
+    pub(crate) fn escape(&self, line: &str) -> String {
+        self.store.escape(line)
+    }
+
+    /// Qualify chunk names of subsequently read inputs with their file name.
+    pub fn set_namespaced(&mut self, namespaced: bool) {
+        self.store.set_namespaced(namespaced);
+    }
+
+    /// Prefix the outputs of subsequently read inputs with the input's path and
+    /// stem, so `modules/foo.nw` writes under `gen/modules/foo/`.
+    pub fn set_prefix_from_input(&mut self, prefix: bool) {
+        self.store.set_prefix_from_input(prefix);
+    }
+
+    /// Choose whether `<<chunk>> text` is a reference followed by text, and
+    /// where that text goes, or a plain line as by default.
+    pub fn set_trailing_text(&mut self, mode: TrailingText) {
+        self.store.set_trailing_text(mode);
+    }
+
+    /// Select the syntax used for subsequently read inputs.
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    /// Parse `text` according to the current dialect.
+    fn read_text(&mut self, text: &str, idx: usize) {
+        match self.dialect {
+            Dialect::Noweb => self.store.read(text, idx),
+            Dialect::Entangled => read_entangled(&mut self.store, text, idx),
+            Dialect::Rst => read_rst(&mut self.store, text, idx),
+            Dialect::Nuweb => read_nuweb(&mut self.store, text, idx),
+            Dialect::Funnelweb => read_funnelweb(&mut self.store, text, idx),
         }
     }
 
@@ -633,73 +3460,664 @@ impl Clip {
         self.store.has_chunk(name)
     }
 
+    /// The definitions of the chunk, in reading order; see `ChunkStore::definitions_of`.
+    pub fn definitions_of(&self, name: &str) -> Vec<ChunkDefView> {
+        self.store.definitions_of(name)
+    }
+
+    /// Where the chunk is defined, in reading order; empty if it is not.
+    pub fn definition_locations(&self, name: &str) -> Vec<ChunkLocation> {
+        self.store.definition_locations(name)
+    }
+
+    /// The `@doc` sections read so far, in reading order.
+    pub fn doc_sections(&self) -> &[DocSection] {
+        self.store.doc_sections()
+    }
+
     pub fn get_file_chunks(&self) -> Vec<String> {
         self.store.get_file_chunks().to_vec()
     }
 
+    /// All chunk names, sorted.
+    pub fn chunk_names(&self) -> Vec<String> {
+        self.store.chunk_names()
+    }
+
     pub fn check_unused_chunks(&self) -> Vec<String> {
         self.store.check_unused_chunks()
     }
 
+    /// Every (chunk, referenced chunk) pair, sorted.
+    pub fn references(&self) -> Vec<(String, String)> {
+        self.store.references()
+    }
+
+    /// Every chunk after the chunks it references, file chunks last; fails
+    /// with the reference cycles if there are any.
+    pub fn topological_order(&self) -> Result<Vec<String>, ChunkError> {
+        self.store.topological_order()
+    }
+
+    /// Definitions, body lines and references of every chunk, sorted by name.
+    pub fn chunk_stats(&self) -> Vec<ChunkStats> {
+        self.store.chunk_stats()
+    }
+
+    /// Every definition and `@doc` section in reading order, as classic noweb
+    /// reads them, with `defines` substituted; see [`export_noweb`].
+    ///
+    /// [`export_noweb`]: crate::export::export_noweb
+    pub fn noweb_sections(&self, defines: &HashMap<String, String>) -> NowebSections {
+        self.store.noweb_sections(defines)
+    }
+
+    /// The configured (open, close) reference delimiters.
+    pub fn delimiters(&self) -> (String, String) {
+        self.store.delimiters()
+    }
+
+    /// How a line of document `file_name` reads with this clip's syntax.
+    pub(crate) fn classify(&self, file_name: &str, line: &str) -> LineKind {
+        self.store.classify(file_name, line)
+    }
+
+    /// A chunk opening line with normalized spacing, and the chunk-end line
+    /// matching it.
+    pub(crate) fn formatted_opening(
+        &self,
+        file_name: &str,
+        line: &str,
+    ) -> Option<(String, String)> {
+        self.store.formatted_opening(file_name, line)
+    }
+
+    /// `name` without the namespace it was stored under for a chunk defined
+    /// in input `file_idx`.
+    pub(crate) fn unqualified<'a>(&self, name: &'a str, file_idx: usize) -> &'a str {
+        self.store.unqualified(name, file_idx)
+    }
+
     /// Read from a file on disk, storing chunk definitions.
     pub fn read_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), AzadiError> {
         let fname = path.as_ref().to_string_lossy().to_string();
-        let idx = self.store.add_file_name(&fname);
-        let text = fs::read_to_string(&path)?;
-        self.store.read(&text, idx);
+        self.read_from(fs::File::open(&path)?, &fname)
+    }
+
+    /// Read everything `reader` yields, such as a socket or an editor buffer,
+    /// naming it `file_name` in diagnostics. A name ending in `.ipynb` is
+    /// read as a Jupyter notebook, as with `read_file`.
+    pub fn read_from<R: io::Read>(
+        &mut self,
+        mut reader: R,
+        file_name: &str,
+    ) -> Result<(), AzadiError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        if is_notebook(file_name) {
+            self.read_notebook(&text, file_name)
+        } else {
+            self.read(&text, file_name);
+            Ok(())
+        }
+    }
+
+    /// Read a Jupyter notebook from an in-memory string.
+    pub fn read_notebook(&mut self, text: &str, file_name: &str) -> Result<(), AzadiError> {
+        let idx = self.store.add_file_name(file_name);
+        read_notebook(&mut self.store, text, idx)?;
         Ok(())
     }
 
     /// Read from an in-memory string, specifying a "filename" for error messages.
     pub fn read(&mut self, text: &str, file_name: &str) {
         let idx = self.store.add_file_name(file_name);
-        self.store.read(text, idx);
+        self.read_text(text, idx);
     }
 
     /// Write all file chunks to disk.
     pub fn write_files(&mut self) -> Result<(), AzadiError> {
-        let fc = self.store.get_file_chunks().to_vec();
+        self.write_files_with_options(&ExpandOptions::default())
+    }
+
+    /// Write all file chunks to disk, expanded as configured by `options`.
+    pub fn write_files_with_options(&mut self, options: &ExpandOptions) -> Result<(), AzadiError> {
+        self.check_chunk_names()?;
+        self.check_namespaces()?;
+        for w in self.check_redefinitions()? {
+            self.reporter.warning(Some(Code::Redefinition), &w);
+        }
+        let fc: Vec<String> = self
+            .store
+            .get_file_chunks()
+            .iter()
+            .filter(|name| self.is_selected(name))
+            .cloned()
+            .collect();
+        self.check_output_collisions()?;
+        let mut failures = Vec::new();
         for name in &fc {
-            let expanded = self.store.expand(name, "")?;
-            let mut cw = ChunkWriter::new(&mut self.writer);
-            cw.write_chunk(name, &expanded)?;
+            let path = name["@file ".len()..].trim();
+            self.reporter.progress(&format!("generating '{}'", path));
+            match self.write_file_chunk(name, path, options) {
+                Err(e) if self.keep_going => failures.push((path.to_string(), e)),
+                result => result?,
+            }
         }
         let warns = self.store.check_unused_chunks();
         for w in warns {
-            eprintln!("{}", w);
+            self.reporter.warning(Some(Code::UnusedChunk), &w);
+        }
+        self.handle_orphans()?;
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(AzadiError::Outputs(failures))
+        }
+    }
+
+    /// Fail if a file chunk is defined twice, if two file chunks name the same
+    /// output spelled differently (`./a.txt` and `a.txt`), or outputs that
+    /// differ only in case.
+    fn check_output_collisions(&self) -> Result<(), ChunkError> {
+        if let Some(first) = self.store.file_redefinitions().first() {
+            return Err(first.to_error());
+        }
+        // Case-folded path -> the first path folding to it, normalized
+        let mut seen: HashMap<String, (&str, String)> = HashMap::new();
+        for name in self.store.get_file_chunks() {
+            let path = name["@file ".len()..].trim();
+            let normalized = normalize_output(path);
+            let folded = normalized.to_lowercase();
+            let Some((previous, previous_normalized)) = seen.get(&folded) else {
+                seen.insert(folded, (path, normalized));
+                continue;
+            };
+            let first = |name: &str| {
+                self.store
+                    .definition_locations(name)
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default()
+            };
+            let path = path.to_string();
+            let location = first(name);
+            let previous_path = previous.to_string();
+            let previous_location = Box::new(first(&format!("@file {}", previous)));
+            return Err(if *previous_normalized == normalized {
+                ChunkError::DuplicateOutput {
+                    path,
+                    location,
+                    previous_path,
+                    previous_location,
+                }
+            } else {
+                ChunkError::OutputCollision {
+                    path,
+                    location,
+                    previous_path,
+                    previous_location,
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Expand file chunk `name` and write it to `path` under gen/.
+    fn write_file_chunk(
+        &mut self,
+        name: &str,
+        path: &str,
+        options: &ExpandOptions,
+    ) -> Result<(), AzadiError> {
+        if self.store.is_managed(name) && self.writer.output_path(path).is_file() {
+            // The existing file is the template; hand edits are not external modifications
+            self.writer.accept_external(path)?;
+        }
+        let expanded = self.expand_output(name, options)?;
+        let report = self
+            .writer
+            .write_atomic(path, expanded.concat().as_bytes())?;
+        let mode = OutputAttributes::of(name, &self.store.definitions_of(name))?.mode;
+        if let Some(mode) = mode {
+            if report.outcome != WriteOutcome::SkippedModified {
+                self.writer.set_mode(path, mode)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Outputs recorded by an earlier run whose file chunk no longer exists,
+    /// relative to gen/ and sorted. Outputs of documents not read this run
+    /// are not orphaned by their absence.
+    pub fn orphaned_outputs(&self) -> Result<Vec<String>, AzadiError> {
+        let current = self.defined_outputs();
+        let mut orphans: Vec<String> = self
+            .writer
+            .read_manifest_entries()?
+            .into_iter()
+            .filter(|entry| self.is_orphan(entry, &current))
+            .map(|entry| entry.path)
+            .collect();
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    /// Whether the recorded output still exists but no longer has a file
+    /// chunk, although every input that produced it was read this run.
+    fn is_orphan(&self, entry: &ManifestEntry, current: &[(String, Vec<String>)]) -> bool {
+        !current.iter().any(|(path, _)| *path == entry.path)
+            && self.inputs_read(entry)
+            && self.writer.output_path(&entry.path).is_file()
+    }
+
+    /// Whether every input that produced the recorded output was read this run.
+    fn inputs_read(&self, entry: &ManifestEntry) -> bool {
+        entry
+            .inputs
+            .iter()
+            .all(|input| self.store.file_names.contains(input))
+    }
+
+    /// Every output the sources define, selected for writing or not, with the
+    /// inputs defining its file chunk.
+    fn defined_outputs(&self) -> Vec<(String, Vec<String>)> {
+        self.store
+            .get_file_chunks()
+            .iter()
+            .map(|name| {
+                let mut inputs: Vec<String> = Vec::new();
+                for def in self.store.definitions_of(name) {
+                    if !inputs.contains(&def.location.file_name) {
+                        inputs.push(def.location.file_name);
+                    }
+                }
+                (name["@file ".len()..].trim().to_string(), inputs)
+            })
+            .collect()
+    }
+
+    /// Warn about or prune the orphaned outputs, then record the current ones;
+    /// orphans kept stay recorded so later runs warn about them again, as do
+    /// the outputs of documents not read this run.
+    fn handle_orphans(&mut self) -> Result<(), AzadiError> {
+        let current = self.defined_outputs();
+        let mut recorded: Vec<ManifestEntry> = current
+            .iter()
+            .map(|(path, inputs)| self.writer.manifest_entry(path, inputs.clone()))
+            .collect();
+        let mut earlier = self.writer.read_manifest_entries()?;
+        earlier.sort_by(|a, b| a.path.cmp(&b.path));
+        for entry in earlier {
+            if current.iter().any(|(path, _)| *path == entry.path) {
+                continue;
+            }
+            if !self.inputs_read(&entry) {
+                recorded.push(entry);
+                continue;
+            }
+            if !self.is_orphan(&entry, &current) {
+                continue;
+            }
+            let shown = self.writer.output_path(&entry.path);
+            if self.prune {
+                self.writer.remove_output(&entry.path)?;
+                self.reporter
+                    .info(&format!("removed orphaned output {}", shown.display()));
+            } else {
+                self.reporter.coded_warning(
+                    Code::OrphanedOutput,
+                    &format!(
+                        "{} is no longer defined by any @file chunk (pass --prune to remove it)",
+                        shown.display()
+                    ),
+                );
+                recorded.push(entry);
+            }
         }
+        self.writer.write_manifest(&recorded)?;
         Ok(())
     }
 
+    /// The filtered content of a file chunk's output, as it would be written.
+    fn expand_output(
+        &mut self,
+        name: &str,
+        options: &ExpandOptions,
+    ) -> Result<Vec<String>, AzadiError> {
+        let path = name["@file ".len()..].trim();
+        let language = self.store.language_of(name);
+        let settings = OutputAttributes::of(name, &self.store.definitions_of(name))?;
+        let without_directives;
+        let options = if settings.line_directives {
+            options
+        } else {
+            without_directives = ExpandOptions {
+                line_directive: None,
+                ..options.clone()
+            };
+            &without_directives
+        };
+        let expanded = if self.store.is_managed(name) {
+            self.expand_managed(name, options)?
+        } else {
+            let traced = self.store.expand_traced(name, options)?;
+            if options.track_provenance {
+                let provenance = traced.iter().map(|(_, p)| p.clone()).collect();
+                self.output_provenance.insert(path.to_string(), provenance);
+            }
+            traced.into_iter().map(|(line, _)| line).collect()
+        };
+        let filtered = self.filters.iter().fold(expanded, |lines, f| {
+            f.filter_with_language(path, language.as_deref(), lines)
+        });
+        Ok(match settings.eol {
+            Some(eol) => filtered
+                .into_iter()
+                .map(|line| match line.strip_suffix('\n') {
+                    Some(body) => format!("{}{}", body.strip_suffix('\r').unwrap_or(body), eol),
+                    None => line,
+                })
+                .collect(),
+            None => filtered,
+        })
+    }
+
+    /// Paths (relative to gen/, sorted) of the outputs whose file on disk is
+    /// missing or differs from what `write_files_with_options` would write.
+    /// Nothing is written.
+    pub fn stale_outputs(&mut self, options: &ExpandOptions) -> Result<Vec<String>, AzadiError> {
+        let fc: Vec<String> = self
+            .store
+            .get_file_chunks()
+            .iter()
+            .filter(|name| self.is_selected(name))
+            .cloned()
+            .collect();
+        let mut stale = Vec::new();
+        for name in &fc {
+            let path = name["@file ".len()..].trim();
+            let expected = self.expand_output(name, options)?.concat();
+            let current = fs::read(self.writer.output_path(path)).ok();
+            if current.as_deref() != Some(expected.as_bytes()) {
+                stale.push(path.to_string());
+            }
+        }
+        stale.sort();
+        Ok(stale)
+    }
+
+    /// Regenerate the regions of a @managed file chunk. The existing output is
+    /// the template; the chunk's own expansion seeds a new file.
+    fn expand_managed(
+        &self,
+        name: &str,
+        options: &ExpandOptions,
+    ) -> Result<Vec<String>, AzadiError> {
+        let path = name["@file ".len()..].trim();
+        let output = self.writer.output_path(path);
+        let template = if output.is_file() {
+            fs::read_to_string(&output)?
+        } else {
+            self.store.expand_with_options(name, options)?.concat()
+        };
+        let store = &self.store;
+        let filled = fill_regions(&template, path, |region, indent| {
+            let region_options = ExpandOptions {
+                indent: format!("{}{}", options.indent, indent),
+                ..options.clone()
+            };
+            store.expand_with_options(region, &region_options)
+        })?;
+        Ok(filled.split_inclusive('\n').map(str::to_string).collect())
+    }
+
+    /// Paths (relative to gen/, sorted) of the file chunks that would be written.
+    pub fn output_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self
+            .store
+            .get_file_chunks()
+            .iter()
+            .filter(|name| self.is_selected(name))
+            .map(|name| name["@file ".len()..].trim().to_string())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Every line of a file chunk's output traced to its source. Managed
+    /// outputs add the chunks of the regions currently present in the file.
+    fn traced_output(&self, name: &str) -> Result<Vec<(String, Provenance)>, AzadiError> {
+        let mut traced = self.store.expand_traced(name, &ExpandOptions::default())?;
+        let path = name["@file ".len()..].trim();
+        let output = self.writer.output_path(path);
+        if self.store.is_managed(name) && output.is_file() {
+            let template = fs::read_to_string(&output)?;
+            fill_regions(&template, path, |region, _| {
+                traced.extend(
+                    self.store
+                        .expand_traced(region, &ExpandOptions::default())?,
+                );
+                Ok(Vec::new())
+            })?;
+        }
+        Ok(traced)
+    }
+
+    /// For each file chunk that would be written, its path relative to gen/ and
+    /// the files (sorted) its expansion reads: the inputs of every definition
+    /// it visits, even one contributing no lines, and the files it inserts.
+    /// Managed outputs depend on the chunks of the regions currently present
+    /// in the file.
+    pub fn output_dependencies(&self) -> Result<Vec<(String, Vec<String>)>, AzadiError> {
+        let options = ExpandOptions::default();
+        let mut deps = Vec::new();
+        for name in self.store.get_file_chunks() {
+            if !self.is_selected(name) {
+                continue;
+            }
+            let path = name["@file ".len()..].trim();
+            let mut inputs = self.store.expand_inputs(name, &options)?;
+            let output = self.writer.output_path(path);
+            if self.store.is_managed(name) && output.is_file() {
+                let template = fs::read_to_string(&output)?;
+                fill_regions(&template, path, |region, _| {
+                    inputs.extend(self.store.expand_inputs(region, &options)?);
+                    Ok(Vec::new())
+                })?;
+            }
+            deps.push((path.to_string(), inputs.into_iter().collect()));
+        }
+        deps.sort();
+        Ok(deps)
+    }
+
+    /// Which chunks each output includes and how many of its lines each
+    /// contributes; chunks that only hold references contribute none.
+    pub fn coverage(&self) -> Result<Coverage, AzadiError> {
+        let references = self.store.references();
+        let mut coverage = Coverage::default();
+        for name in self.store.chunk_names() {
+            coverage.chunks.insert(name, Vec::new());
+        }
+        for name in self.store.get_file_chunks() {
+            if !self.is_selected(name) {
+                continue;
+            }
+            let path = name["@file ".len()..].trim().to_string();
+            let mut included: BTreeMap<String, usize> = BTreeMap::new();
+            // Every defined chunk reachable through references, then the lines of each
+            let mut pending = vec![name.clone()];
+            while let Some(chunk) = pending.pop() {
+                if included.insert(chunk.clone(), 0).is_none() {
+                    pending.extend(
+                        references
+                            .iter()
+                            .filter(|(from, to)| *from == chunk && coverage.chunks.contains_key(to))
+                            .map(|(_, to)| to.clone()),
+                    );
+                }
+            }
+            for (_, provenance) in self.traced_output(name)? {
+                *included.entry(provenance.chunk).or_insert(0) += 1;
+            }
+            for chunk in included.keys() {
+                if let Some(outputs) = coverage.chunks.get_mut(chunk) {
+                    outputs.push(path.clone());
+                }
+            }
+            coverage.outputs.insert(path, included);
+        }
+        Ok(coverage)
+    }
+
+    /// Provenance of each line of an output (path relative to gen/), recorded by
+    /// `write_files_with_options` when `track_provenance` is set.
+    pub fn output_provenance(&self, path: &str) -> Option<&[Provenance]> {
+        self.output_provenance.get(path).map(Vec::as_slice)
+    }
+
+    /// Whether the chunk is a @managed file chunk.
+    pub fn is_managed(&self, name: &str) -> bool {
+        self.store.is_managed(name)
+    }
+
+    /// The language declared for the chunk; see `ChunkStore::language_of`.
+    pub fn language_of(&self, name: &str) -> Option<String> {
+        self.store.language_of(name)
+    }
+
     /// Expand a chunk and write to an arbitrary writer.
     pub fn get_chunk<W: io::Write>(
         &self,
         chunk_name: &str,
         out_stream: &mut W,
     ) -> Result<(), AzadiError> {
-        let lines = self.store.expand(chunk_name, "")?;
-        for line in lines {
-            out_stream.write_all(line.as_bytes())?;
-        }
+        self.get_chunk_with_options(chunk_name, &ExpandOptions::default(), out_stream)
+    }
+
+    /// Expand a chunk as configured by `options` and write to an arbitrary writer.
+    pub fn get_chunk_with_options<W: io::Write>(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+        out_stream: &mut W,
+    ) -> Result<(), AzadiError> {
+        self.store.expand_to(chunk_name, options, out_stream)?;
         out_stream.write_all(b"\n")?;
         Ok(())
     }
 
+    /// Expand a chunk, pairing each line with the source location it came from.
+    pub fn expand_with_provenance(
+        &self,
+        chunk_name: &str,
+        indent: &str,
+    ) -> Result<Vec<(String, Provenance)>, AzadiError> {
+        Ok(self.store.expand_with_provenance(chunk_name, indent)?)
+    }
+
+    /// Expand a chunk as configured by `options`, writing the lines to
+    /// `writer` as they are produced.
+    pub fn expand_to<W: io::Write>(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+        writer: &mut W,
+    ) -> Result<(), AzadiError> {
+        Ok(self.store.expand_to(chunk_name, options, writer)?)
+    }
+
+    /// Expand a chunk as configured by `options`, calling `visitor` with each
+    /// line and its provenance; see `ChunkStore::expand_with_visitor`.
+    pub fn expand_with_visitor<F: FnMut(&str, &Provenance)>(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+        visitor: F,
+    ) -> Result<(), AzadiError> {
+        Ok(self
+            .store
+            .expand_with_visitor(chunk_name, options, visitor)?)
+    }
+
+    /// Expand a chunk as configured by `options`, keeping provenance.
+    pub fn expand_traced(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+    ) -> Result<Vec<(String, Provenance)>, AzadiError> {
+        Ok(self.store.expand_traced(chunk_name, options)?)
+    }
+
+    /// The name of the input registered under `file_idx`.
+    pub fn file_name(&self, file_idx: usize) -> String {
+        self.store.file_name(file_idx)
+    }
+
+    /// Base directory of generated files.
+    pub fn gen_base(&self) -> &Path {
+        self.writer.get_gen_base()
+    }
+
+    /// Where the output `path`, relative to gen/, is written; see
+    /// `SafeFileWriter::output_path`.
+    pub fn output_path(&self, path: &str) -> PathBuf {
+        self.writer.output_path(path)
+    }
+
+    /// Accept the current on-disk content of the given outputs (paths relative
+    /// to gen/) as up to date, e.g. after untangling their edits.
+    pub fn accept_outputs(&mut self, paths: &[String]) -> Result<(), AzadiError> {
+        for path in paths {
+            self.writer.accept_external(path)?;
+        }
+        Ok(())
+    }
+
     /// Expand a chunk into a vector of lines.
     pub fn expand(&self, chunk_name: &str, indent: &str) -> Result<Vec<String>, AzadiError> {
         Ok(self.store.expand(chunk_name, indent)?)
     }
 
+    /// Expand a chunk into a vector of lines as configured by `options`.
+    pub fn expand_with_options(
+        &self,
+        chunk_name: &str,
+        options: &ExpandOptions,
+    ) -> Result<Vec<String>, AzadiError> {
+        Ok(self.store.expand_with_options(chunk_name, options)?)
+    }
+
     /// Retrieve the chunk content directly (commonly used in tests).
     pub fn get_chunk_content(&self, name: &str) -> Result<Vec<String>, ChunkError> {
         self.store.get_chunk_content(name)
     }
 
+    /// Read every input, then check the chunks for reference cycles.
     pub fn read_files<P: AsRef<Path>>(&mut self, input_paths: &[P]) -> Result<(), AzadiError> {
         for path in input_paths {
             self.read_file(path)?;
         }
+        self.check_namespaces()?;
+        self.check_cycles()?;
         Ok(())
     }
+
+    /// Every reference cycle among the chunks read so far.
+    pub fn reference_cycles(&self) -> Vec<ReferenceCycle> {
+        self.store.reference_cycles()
+    }
+
+    /// Fail with all reference cycles at once, rather than with the first
+    /// one an expansion runs into.
+    pub fn check_cycles(&self) -> Result<(), ChunkError> {
+        let cycles = self.reference_cycles();
+        if cycles.is_empty() {
+            Ok(())
+        } else {
+            Err(ChunkError::ReferenceCycles { cycles })
+        }
+    }
 }
 // $$
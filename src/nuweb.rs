@@ -0,0 +1,158 @@
+// src/nuweb.rs
+//! nuweb-style front-end.
+//!
+//! `@o file` declares a file chunk and `@d name` a named chunk; the body runs
+//! from `@{` to `@}`. Lines consisting of a single `@<Name@>` reference become
+//! ordinary references, and `@@` stands for a literal `@`. Names containing
+//! whitespace are normalized by joining the words with `-`.
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::markdown::normalize_name;
+use crate::noweb::{ChunkModifiers, ChunkStore};
+
+lazy_static! {
+    static ref HEADER_RE: Regex = Regex::new(r"^\s*@([od])\s+(.*?)\s*(?:@\{(.*))?$").unwrap();
+    pub(crate) static ref BODY_OPEN_RE: Regex = Regex::new(r"^\s*@\{(.*)$").unwrap();
+    pub(crate) static ref REF_RE: Regex = Regex::new(r"^(\s*)@<\s*(.+?)\s*@>\s*$").unwrap();
+}
+
+/// Reads the chunk name declared by a scrap header line, and the text after
+/// its `@{` when the body opens on the same line.
+pub(crate) type Header = fn(&str) -> Option<(String, Option<String>)>;
+
+/// Chunk name declared by a `@o`/`@d` header.
+fn header_name(kind: &str, rest: &str) -> String {
+    if kind == "o" {
+        // nuweb allows flags such as `-d` after the file name.
+        let path = rest.split_whitespace().next().unwrap_or("");
+        format!("@file {}", path)
+    } else {
+        normalize_name(rest)
+    }
+}
+
+/// A nuweb `@o`/`@d` header.
+pub(crate) fn nuweb_header(line: &str) -> Option<(String, Option<String>)> {
+    let caps = HEADER_RE.captures(line)?;
+    Some((
+        header_name(&caps[1], &caps[2]),
+        caps.get(3).map(|rest| rest.as_str().to_string()),
+    ))
+}
+
+struct Reader<'a> {
+    store: &'a mut ChunkStore,
+    file_idx: usize,
+    open_delim: String,
+    close_delim: String,
+}
+
+impl Reader<'_> {
+    fn push(&mut self, target: &Option<(String, usize)>, text: &str, line_no: usize) {
+        let Some((name, def_idx)) = target else {
+            return;
+        };
+        let line = match REF_RE.captures(text) {
+            Some(caps) => format!(
+                "{}{}{}{}",
+                &caps[1],
+                self.open_delim,
+                normalize_name(&caps[2]),
+                self.close_delim
+            ),
+            None => text.replace("@@", "@"),
+        };
+        self.store.push_line(name, *def_idx, &line, line_no);
+    }
+
+    fn begin(&mut self, name: &str, line_no: usize) -> Option<(String, usize)> {
+        if !self.store.validate_chunk_name(name, name) {
+            return None;
+        }
+        // Repeated `@d` scraps of one name are concatenated, as in nuweb.
+        let modifiers = ChunkModifiers {
+            append: true,
+            ..ChunkModifiers::default()
+        };
+        self.store
+            .begin_definition(name, &modifiers, 0, self.file_idx, line_no)
+    }
+
+    /// Feed the text following `@{`, where an empty remainder is not a body line.
+    fn start_body(&mut self, target: &Option<(String, usize)>, rest: &str, line_no: usize) -> bool {
+        rest.trim().is_empty() || self.body_text(target, rest, line_no)
+    }
+
+    /// Feed body text (including the rest of an `@{` line); returns true while
+    /// the body is still open.
+    fn body_text(&mut self, target: &Option<(String, usize)>, text: &str, line_no: usize) -> bool {
+        match text.find("@}") {
+            Some(end) => {
+                let before = &text[..end];
+                if !before.trim().is_empty() {
+                    self.push(target, before, line_no);
+                }
+                false
+            }
+            None => {
+                self.push(target, text, line_no);
+                true
+            }
+        }
+    }
+}
+
+/// Parse nuweb text and store the chunks declared by its `@o`/`@d` scraps.
+pub fn read_nuweb(store: &mut ChunkStore, text: &str, file_idx: usize) {
+    read_scraps(store, text, file_idx, nuweb_header);
+}
+
+/// Store the chunks of text whose scraps open with lines `header` reads and
+/// have `@{ ... @}` bodies.
+pub(crate) fn read_scraps(store: &mut ChunkStore, text: &str, file_idx: usize, header: Header) {
+    let (open_delim, close_delim) = store.delimiters();
+    let mut reader = Reader {
+        store,
+        file_idx,
+        open_delim,
+        close_delim,
+    };
+
+    // A header waiting for its `@{`, and the definition whose body is open.
+    let mut pending: Option<String> = None;
+    let mut open: Option<Option<(String, usize)>> = None;
+
+    for (line_no, line) in text.lines().enumerate() {
+        if let Some(target) = &open {
+            if !reader.body_text(target, line, line_no) {
+                open = None;
+            }
+            continue;
+        }
+
+        if let Some(name) = pending.take() {
+            if let Some(caps) = BODY_OPEN_RE.captures(line) {
+                let target = reader.begin(&name, line_no);
+                if reader.start_body(&target, &caps[1], line_no) {
+                    open = Some(target);
+                }
+                continue;
+            }
+        }
+
+        if let Some((name, rest)) = header(line) {
+            match rest {
+                Some(rest) => {
+                    let target = reader.begin(&name, line_no);
+                    if reader.start_body(&target, &rest, line_no) {
+                        open = Some(target);
+                    }
+                }
+                None => pending = Some(name),
+            }
+        }
+    }
+
+    reader.store.finish_read();
+}
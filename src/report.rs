@@ -0,0 +1,236 @@
+// src/report.rs
+//! Where user-facing messages go. `Clip` and `SafeFileWriter` report warnings,
+//! notices and progress through a `Reporter`; embedders install their own to
+//! capture them instead of having them printed.
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::level_filters::LevelFilter;
+use tracing::subscriber::NoSubscriber;
+use tracing::Level;
+
+use crate::diagnostic::Code;
+use crate::json;
+
+/// Receives the messages of a tangle run. Warnings and errors come with
+/// their code, if any; messages never carry a `Warning[...]:` or `Error:`
+/// prefix, which is up to the reporter.
+pub trait Reporter: Send + Sync {
+    /// Something suspicious that did not stop the run.
+    fn warning(&self, code: Option<Code>, message: &str);
+    /// A notice such as a generated file having changed.
+    fn info(&self, message: &str);
+    /// A failure that ended the run.
+    fn error(&self, code: Option<Code>, message: &str);
+    /// A step of the run, e.g. the output being generated.
+    fn progress(&self, message: &str);
+
+    /// `message` as a warning with `code`.
+    fn coded_warning(&self, code: Code, message: &str) {
+        self.warning(Some(code), message);
+    }
+
+    /// `message` as an error with `code`.
+    fn coded_error(&self, code: Code, message: &str) {
+        self.error(Some(code), message);
+    }
+}
+
+/// The default reporter: messages become `tracing` events, so `-q` and `-v`
+/// apply and progress shows only when verbose. Without a subscriber to see
+/// them, warnings, notices and errors are printed to stderr instead. Warnings
+/// and errors are tagged with their level and code, e.g. `Warning[AZ0101]: ...`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsoleReporter;
+
+/// Whether a `tracing` subscriber is installed for this thread.
+pub(crate) fn observed() -> bool {
+    tracing::dispatcher::get_default(|dispatch| !dispatch.is::<NoSubscriber>())
+}
+
+impl Reporter for ConsoleReporter {
+    fn warning(&self, code: Option<Code>, message: &str) {
+        let message = match code {
+            Some(code) => code.warning(message),
+            None => format!("Warning: {}", message),
+        };
+        if observed() {
+            tracing::warn!("{}", message);
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+
+    fn info(&self, message: &str) {
+        if observed() {
+            tracing::info!("{}", message);
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+
+    fn error(&self, code: Option<Code>, message: &str) {
+        let message = match code {
+            Some(code) => code.error(message),
+            None => format!("Error: {}", message),
+        };
+        if observed() {
+            tracing::error!("{}", message);
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+
+    fn progress(&self, message: &str) {
+        tracing::debug!("{}", message);
+    }
+}
+
+/// Writes one JSON object per message and line, e.g.
+/// `{"level": "warning", "code": "AZ0101", "message": "..."}`, for tools that
+/// parse the output; `code` is null for messages without one. Messages
+/// above the filter's level are left out, as `-q` and `-v` do for text.
+pub struct JsonReporter<W: Write + Send> {
+    out: Mutex<W>,
+    filter: LevelFilter,
+}
+
+impl<W: Write + Send> JsonReporter<W> {
+    /// A reporter writing every message to `out`.
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+            filter: LevelFilter::TRACE,
+        }
+    }
+
+    /// Write only the messages `filter` lets through: progress is at the
+    /// debug level, notices at info.
+    pub fn with_filter(mut self, filter: LevelFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// The writer, with everything reported so far.
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn emit(&self, level: &str, code: Option<Code>, message: &str) {
+        let verbosity = match level {
+            "error" => Level::ERROR,
+            "warning" => Level::WARN,
+            "info" => Level::INFO,
+            _ => Level::DEBUG,
+        };
+        if self.filter < verbosity {
+            return;
+        }
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // A reporter has nowhere to report its own failures
+        let code = code.map_or("null".to_string(), |c| json::quote(c.as_str()));
+        let _ = writeln!(
+            out,
+            "{{\"level\": {}, \"code\": {}, \"message\": {}}}",
+            json::quote(level),
+            code,
+            json::quote(message)
+        );
+    }
+}
+
+impl<W: Write + Send> Reporter for JsonReporter<W> {
+    fn warning(&self, code: Option<Code>, message: &str) {
+        self.emit("warning", code, message);
+    }
+
+    fn info(&self, message: &str) {
+        self.emit("info", None, message);
+    }
+
+    fn error(&self, code: Option<Code>, message: &str) {
+        self.emit("error", code, message);
+    }
+
+    fn progress(&self, message: &str) {
+        self.emit("progress", None, message);
+    }
+}
+
+/// Passes every message on to another reporter, counting the warnings, e.g.
+/// to fail a run that had any.
+pub struct CountingReporter {
+    inner: Arc<dyn Reporter>,
+    warnings: AtomicUsize,
+}
+
+impl CountingReporter {
+    pub fn new(inner: Arc<dyn Reporter>) -> Self {
+        Self {
+            inner,
+            warnings: AtomicUsize::new(0),
+        }
+    }
+
+    /// How many warnings were reported so far.
+    pub fn warnings(&self) -> usize {
+        self.warnings.load(Ordering::Relaxed)
+    }
+}
+
+impl Reporter for CountingReporter {
+    fn warning(&self, code: Option<Code>, message: &str) {
+        self.warnings.fetch_add(1, Ordering::Relaxed);
+        self.inner.warning(code, message);
+    }
+
+    fn info(&self, message: &str) {
+        self.inner.info(message);
+    }
+
+    fn error(&self, code: Option<Code>, message: &str) {
+        self.inner.error(code, message);
+    }
+
+    fn progress(&self, message: &str) {
+        self.inner.progress(message);
+    }
+}
+
+/// Passes every message on to another reporter except the warnings with
+/// one of the allowed codes.
+pub struct SuppressingReporter {
+    inner: Arc<dyn Reporter>,
+    allowed: Vec<Code>,
+}
+
+impl SuppressingReporter {
+    pub fn new(inner: Arc<dyn Reporter>, allowed: Vec<Code>) -> Self {
+        Self { inner, allowed }
+    }
+}
+
+impl Reporter for SuppressingReporter {
+    fn warning(&self, code: Option<Code>, message: &str) {
+        if !code.is_some_and(|code| self.allowed.contains(&code)) {
+            self.inner.warning(code, message);
+        }
+    }
+
+    fn info(&self, message: &str) {
+        self.inner.info(message);
+    }
+
+    fn error(&self, code: Option<Code>, message: &str) {
+        self.inner.error(code, message);
+    }
+
+    fn progress(&self, message: &str) {
+        self.inner.progress(message);
+    }
+}
+
+/// The reporter used until another is installed.
+pub fn console() -> Arc<dyn Reporter> {
+    Arc::new(ConsoleReporter)
+}
@@ -0,0 +1,101 @@
+// src/rst.rs
+//! reStructuredText front-end.
+//!
+//! A `.. code-block::` (or `.. code::` / `.. sourcecode::`) directive with a
+//! `:name:` option defines that chunk, and one with a `:tangle:` option defines
+//! the file chunk for the given path. The directive body is the indented block
+//! following the options.
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::markdown::normalize_name;
+use crate::noweb::{ChunkModifiers, ChunkStore};
+
+lazy_static! {
+    static ref DIRECTIVE_RE: Regex =
+        Regex::new(r"^(\s*)\.\.\s+(?:code-block|code|sourcecode)::").unwrap();
+    static ref OPTION_RE: Regex = Regex::new(r"^\s*:([\w-]+):\s*(.*?)\s*$").unwrap();
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Parse RST text and store the chunks declared by its code directives.
+pub fn read_rst(store: &mut ChunkStore, text: &str, file_idx: usize) {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let Some(caps) = DIRECTIVE_RE.captures(lines[i]) else {
+            i += 1;
+            continue;
+        };
+        let directive_line = i;
+        let directive_indent = caps[1].len();
+        i += 1;
+
+        // Options directly follow the directive line.
+        let mut name = None;
+        let mut tangle = None;
+        while i < lines.len() && !lines[i].trim().is_empty() {
+            match OPTION_RE.captures(lines[i]) {
+                Some(opt) if indent_of(lines[i]) > directive_indent => {
+                    match &opt[1] {
+                        "name" => name = Some(normalize_name(&opt[2])),
+                        "tangle" => tangle = Some(format!("@file {}", &opt[2])),
+                        _ => {}
+                    }
+                    i += 1;
+                }
+                _ => break,
+            }
+        }
+
+        // The body is every following line indented deeper than the directive,
+        // blank lines included; trailing blank lines are not part of it.
+        let body_start = i;
+        let mut body_end = i;
+        while i < lines.len() {
+            if lines[i].trim().is_empty() {
+                i += 1;
+                continue;
+            }
+            if indent_of(lines[i]) <= directive_indent {
+                break;
+            }
+            i += 1;
+            body_end = i;
+        }
+        let body: Vec<(usize, &str)> = (body_start..body_end)
+            .map(|n| (n, lines[n]))
+            .skip_while(|(_, l)| l.trim().is_empty())
+            .collect();
+        let base_indent = body
+            .iter()
+            .filter(|(_, l)| !l.trim().is_empty())
+            .map(|(_, l)| indent_of(l))
+            .min()
+            .unwrap_or(0);
+
+        for chunk in [name, tangle].into_iter().flatten() {
+            if !store.validate_chunk_name(&chunk, &chunk) {
+                continue;
+            }
+            // Blocks sharing a `:name:` are concatenated
+            let modifiers = ChunkModifiers {
+                append: true,
+                ..ChunkModifiers::default()
+            };
+            if let Some((stored, def_idx)) =
+                store.begin_definition(&chunk, &modifiers, base_indent, file_idx, directive_line)
+            {
+                for (line_no, line) in &body {
+                    store.push_line(&stored, def_idx, line, *line_no);
+                }
+            }
+        }
+    }
+
+    store.finish_read();
+}
@@ -1,9 +1,17 @@
 use chrono::{DateTime, Local};
+use std::cell::OnceCell;
+use std::collections::BTreeSet;
 use std::fs::{self, File};
 use std::io::Read;
 use std::io::{self, BufReader};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 use std::time::SystemTime;
+use tracing::debug;
+
+use crate::diagnostic::Code;
+use crate::glob::glob_match;
+use crate::report::{self, Reporter};
 
 #[derive(Debug)]
 pub enum SafeWriterError {
@@ -32,6 +40,17 @@ impl std::fmt::Display for SafeWriterError {
     }
 }
 
+impl SafeWriterError {
+    /// The diagnostic code of the error.
+    pub fn code(&self) -> Code {
+        match self {
+            SafeWriterError::ModifiedExternally(_) => Code::ModifiedExternally,
+            SafeWriterError::SecurityViolation(_) => Code::UnsafePath,
+            _ => Code::WriteFailed,
+        }
+    }
+}
+
 impl std::error::Error for SafeWriterError {}
 
 impl From<io::Error> for SafeWriterError {
@@ -40,11 +59,37 @@ impl From<io::Error> for SafeWriterError {
     }
 }
 
+/// What to do when an output under gen/ is a symbolic link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Fail with a security violation, leaving the link and its target alone.
+    #[default]
+    Refuse,
+    /// Remove the link and write a regular file in its place.
+    Replace,
+    /// Write to the file the link points to.
+    Follow,
+}
+
 #[derive(Debug, Clone)]
 pub struct SafeWriterConfig {
     pub backup_enabled: bool,
     pub allow_overwrites: bool,
     pub modification_check: bool,
+    /// Leave an output edited since the last write as it is, reporting
+    /// `WriteOutcome::SkippedModified`, instead of failing.
+    pub skip_modified: bool,
+    pub symlinks: SymlinkPolicy,
+    /// Where backups of the outputs are kept; `__old__` in the private
+    /// directory when unset.
+    pub backup_dir: Option<PathBuf>,
+    /// Glob patterns of the output paths, relative to gen/, not to back up.
+    /// Like outputs written with backups disabled, they are not checked for
+    /// external modifications.
+    pub no_backup: Vec<String>,
+    /// Keep a copy of the outputs each run writes in a snapshot directory
+    /// named after the run's start, retaining this many runs; 0 keeps none.
+    pub snapshots: usize,
     pub buffer_size: usize,
 }
 
@@ -54,59 +99,180 @@ impl Default for SafeWriterConfig {
             backup_enabled: true,
             allow_overwrites: false,
             modification_check: true,
+            skip_modified: false,
+            symlinks: SymlinkPolicy::default(),
+            backup_dir: None,
+            no_backup: Vec::new(),
+            snapshots: 0,
             buffer_size: 8192,
         }
     }
 }
 
+/// What a write did to the output under gen/.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    /// The output was created or its content replaced.
+    Written,
+    /// The output already had the content, so it was not touched.
+    Unchanged,
+    /// The output was edited since the last write and was left as it is.
+    SkippedModified,
+}
+
+/// The outcome of a write and the output it concerns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteResult {
+    /// The output under gen/.
+    pub path: PathBuf,
+    pub outcome: WriteOutcome,
+}
+
+/// Name, under the private directory, of the list of outputs last written.
+const MANIFEST: &str = "__manifest__";
+
+/// An output recorded in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// The output, relative to gen/.
+    pub path: String,
+    /// Checksum of its content when recorded; none if it was missing.
+    pub checksum: Option<String>,
+    /// The inputs whose `@file` chunks defined it; empty in the manifests
+    /// of older versions.
+    pub inputs: Vec<String>,
+}
+
+impl ManifestEntry {
+    /// Read a line of `checksum\tpath\tinput...`; manifests of older
+    /// versions list the bare paths, or the checksums and paths alone.
+    fn parse(line: &str) -> Self {
+        let mut fields = line.split('\t');
+        let first = fields.next().unwrap_or_default();
+        let Some(path) = fields.next() else {
+            return ManifestEntry {
+                path: line.to_string(),
+                checksum: None,
+                inputs: Vec::new(),
+            };
+        };
+        ManifestEntry {
+            path: path.to_string(),
+            checksum: (!first.is_empty()).then(|| first.to_string()),
+            inputs: fields.map(str::to_string).collect(),
+        }
+    }
+
+    fn line(&self) -> String {
+        let mut line = format!("{}\t{}", self.checksum.as_deref().unwrap_or(""), self.path);
+        for input in &self.inputs {
+            line.push('\t');
+            line.push_str(input);
+        }
+        line.push('\n');
+        line
+    }
+}
+
+/// How an output under gen/ differs from what the last run recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputChange {
+    /// Its content is not what was written.
+    Modified,
+    /// It was written but no longer exists.
+    Deleted,
+    /// It was not written by the last run.
+    Added,
+}
+
+impl std::fmt::Display for OutputChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OutputChange::Modified => "modified",
+            OutputChange::Deleted => "deleted",
+            OutputChange::Added => "added",
+        })
+    }
+}
+
+/// Name, under the private directory, of the directory of run snapshots.
+const SNAPSHOTS: &str = "__snapshots__";
+
+#[derive(Clone)]
 pub struct SafeFileWriter {
     gen_base: PathBuf,
     private_dir: PathBuf,
     old_dir: PathBuf,
     old_timestamp: Option<DateTime<Local>>,
+    /// This run's snapshot directory, created with the first output written.
+    snapshot_dir: OnceCell<PathBuf>,
     config: SafeWriterConfig,
+    reporter: Arc<dyn Reporter>,
 }
 
 impl SafeFileWriter {
+    /// Panics if the directories cannot be created; see `with_config`.
     pub fn new<P: AsRef<Path>>(gen_base: P, private_dir: P) -> Self {
         Self::with_config(gen_base, private_dir, SafeWriterConfig::default())
+            .expect("Failed to initialize directories")
     }
 
+    /// A writer creating gen/, the private directory and the backup directory
+    /// `config` names, or an error naming the one that could not be created.
     pub fn with_config<P: AsRef<Path>>(
         gen_base: P,
         private_dir: P,
         config: SafeWriterConfig,
-    ) -> Self {
-        let (gen_base, private_dir) = Self::canonicalize_paths(&gen_base, &private_dir)
-            .expect("Failed to initialize directories");
-        let old_dir = private_dir.join("__old__");
-
-        // Create all required directories
-        fs::create_dir_all(&gen_base).expect("Failed to create gen_base directory");
-        fs::create_dir_all(&private_dir).expect("Failed to create private directory");
-        fs::create_dir_all(&old_dir).expect("Failed to create old directory");
-
-        SafeFileWriter {
+    ) -> Result<Self, SafeWriterError> {
+        let (gen_base, private_dir) = Self::canonicalize_paths(&gen_base, &private_dir)?;
+        let old_dir = Self::backup_dir(&private_dir, &config)?;
+        Ok(SafeFileWriter {
             gen_base,
             private_dir,
             old_dir,
             old_timestamp: None,
+            snapshot_dir: OnceCell::new(),
             config,
-        }
+            reporter: report::console(),
+        })
+    }
+
+    /// Create the backup directory `config` names and return its canonical path.
+    fn backup_dir(
+        private_dir: &Path,
+        config: &SafeWriterConfig,
+    ) -> Result<PathBuf, SafeWriterError> {
+        let dir = match &config.backup_dir {
+            Some(dir) => dir.clone(),
+            None => private_dir.join("__old__"),
+        };
+        fs::create_dir_all(&dir)
+            .and_then(|_| dir.canonicalize())
+            .map_err(|_| SafeWriterError::DirectoryCreationFailed(dir))
+    }
+
+    /// Whether the output at `path`, relative to gen/, is backed up.
+    fn backs_up(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy();
+        self.config.backup_enabled
+            && !self
+                .config
+                .no_backup
+                .iter()
+                .any(|pattern| glob_match(pattern, &path))
     }
 
     fn canonicalize_paths<P: AsRef<Path>>(
         gen_base: P,
         private_dir: P,
-    ) -> io::Result<(PathBuf, PathBuf)> {
+    ) -> Result<(PathBuf, PathBuf), SafeWriterError> {
         // Ensure directories exist before canonicalizing.
-        fs::create_dir_all(gen_base.as_ref())?;
-        fs::create_dir_all(private_dir.as_ref())?;
-
-        let gen = gen_base.as_ref().canonicalize()?;
-        let private = private_dir.as_ref().canonicalize()?;
-
-        Ok((gen, private))
+        let create = |dir: &Path| {
+            fs::create_dir_all(dir)
+                .and_then(|_| dir.canonicalize())
+                .map_err(|_| SafeWriterError::DirectoryCreationFailed(dir.to_path_buf()))
+        };
+        Ok((create(gen_base.as_ref())?, create(private_dir.as_ref())?))
     }
 
     fn atomic_copy<P: AsRef<Path>>(&self, source: P, destination: P) -> io::Result<()> {
@@ -124,6 +290,12 @@ impl SafeFileWriter {
             io::copy(&mut source_file, &mut temp_file)?;
             temp_file.sync_all()?;
         } // Handles are dropped here
+
+        // The rename replaces the destination; keep its mode, e.g. an executable bit
+        if let Ok(previous) = fs::metadata(destination.as_ref()) {
+            fs::set_permissions(&temp_path, previous.permissions())?;
+            preserve_owner(&temp_path, &previous);
+        }
         
         std::thread::sleep(std::time::Duration::from_millis(10));
         fs::rename(temp_path, destination)?;
@@ -134,14 +306,14 @@ impl SafeFileWriter {
         &self,
         source: P,
         destination: P,
-    ) -> Result<(), SafeWriterError> {
+    ) -> Result<WriteOutcome, SafeWriterError> {
         let source = source.as_ref();
         let destination = destination.as_ref();
     
         if !destination.exists() {
-            return self
-                .atomic_copy(source, destination)
-                .map_err(SafeWriterError::from);
+            debug!("{} is new, writing it", destination.display());
+            self.atomic_copy(source, destination)?;
+            return Ok(WriteOutcome::Written);
         }
     
         let are_different = {
@@ -158,12 +330,15 @@ impl SafeFileWriter {
         }; // Handles are dropped here
     
         if are_different {
-            println!("file {} changed", destination.display());
+            self.reporter
+                .info(&format!("file {} changed", destination.display()));
             std::thread::sleep(std::time::Duration::from_millis(10)); // Allow Windows to release handles
             self.atomic_copy(source, destination)?;
+            Ok(WriteOutcome::Written)
+        } else {
+            debug!("{} is unchanged, not rewriting it", destination.display());
+            Ok(WriteOutcome::Unchanged)
         }
-    
-        Ok(())
     }
 
     fn prepare_write_file<P: AsRef<Path>>(&self, file_path: P) -> Result<PathBuf, SafeWriterError> {
@@ -172,12 +347,13 @@ impl SafeFileWriter {
 
         // Create all necessary directories
         let dirs = [
-            self.gen_base.join(dest_dir),
-            self.old_dir.join(dest_dir),
-            self.private_dir.join(dest_dir),
+            (&self.gen_base, under(&self.gen_base, dest_dir)),
+            (&self.old_dir, under(&self.old_dir, dest_dir)),
+            (&self.private_dir, under(&self.private_dir, dest_dir)),
         ];
 
-        for dir in &dirs {
+        for (base, dir) in &dirs {
+            check_inside(base, dir)?;
             fs::create_dir_all(dir)
                 .map_err(|_| SafeWriterError::DirectoryCreationFailed(dir.clone()))?;
         }
@@ -192,8 +368,8 @@ impl SafeFileWriter {
         validate_filename(file_name.as_ref())?;
         let path = self.prepare_write_file(&file_name)?;
 
-        if self.config.backup_enabled {
-            let old_file_name = self.old_dir.join(&path);
+        if self.backs_up(&path) {
+            let old_file_name = under(&self.old_dir, &path);
             if old_file_name.is_file() {
                 let metadata = fs::metadata(&old_file_name)?;
                 let system_time: SystemTime = metadata.modified()?;
@@ -201,38 +377,263 @@ impl SafeFileWriter {
             } else {
                 self.old_timestamp = None;
             }
+        } else {
+            self.old_timestamp = None;
         }
 
-        Ok(self.private_dir.join(path))
+        Ok(under(&self.private_dir, path))
     }
 
-    pub fn after_write<P: AsRef<Path>>(&self, file_name: P) -> Result<(), SafeWriterError> {
+    /// Commit the content staged at the path `before_write` returned,
+    /// reporting whether the output under gen/ was written.
+    pub fn after_write<P: AsRef<Path>>(
+        &self,
+        file_name: P,
+    ) -> Result<WriteResult, SafeWriterError> {
         validate_filename(file_name.as_ref())?;
         let path = self.prepare_write_file(file_name)?;
 
-        let private_file = self.private_dir.join(&path);
-        let output_file = self.gen_base.join(&path);
-        let old_file = self.old_dir.join(&path);
+        let private_file = under(&self.private_dir, &path);
+        let output_file = self.resolve_symlink(under(&self.gen_base, &path))?;
+        let old_file = under(&self.old_dir, &path);
+
+        let modified = self.modified_externally(&output_file)?;
+        if modified && self.config.skip_modified {
+            // Keep the backup as it was, so the edit is still detected next time
+            self.reporter.warning(
+                None,
+                &format!(
+                    "{} was modified externally; leaving it unchanged",
+                    output_file.display()
+                ),
+            );
+            return Ok(WriteResult {
+                path: output_file,
+                outcome: WriteOutcome::SkippedModified,
+            });
+        }
 
         // Create backup if enabled
-        if self.config.backup_enabled {
+        if self.backs_up(&path) {
             self.atomic_copy(&private_file, &old_file)
                 .map_err(|_| SafeWriterError::BackupFailed(old_file.clone()))?;
         }
 
-        if self.config.modification_check && output_file.is_file() {
-            let system_time: SystemTime = fs::metadata(&output_file)?.modified()?;
-            let out_timestamp: DateTime<Local> = DateTime::from(system_time);
+        if modified {
+            if !self.config.allow_overwrites {
+                return Err(SafeWriterError::ModifiedExternally(output_file));
+            }
+            debug!(
+                "{} was modified after the last write; overwriting it",
+                output_file.display()
+            );
+        }
 
-            if let Some(old_timestamp) = self.old_timestamp {
-                if out_timestamp > old_timestamp && !self.config.allow_overwrites {
-                    return Err(SafeWriterError::ModifiedExternally(output_file));
-                }
+        let outcome = self.copy_if_different(&private_file, &output_file)?;
+        if outcome == WriteOutcome::Written && self.config.snapshots > 0 {
+            let snapshot = under(self.snapshot_dir()?, &path);
+            if let Some(dir) = snapshot.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::copy(&private_file, &snapshot)?;
+        }
+
+        Ok(WriteResult {
+            path: output_file,
+            outcome,
+        })
+    }
+
+    /// The file to write for `output_file`, applying the symlink policy when
+    /// it is a symbolic link.
+    fn resolve_symlink(&self, output_file: PathBuf) -> Result<PathBuf, SafeWriterError> {
+        let is_link = fs::symlink_metadata(&output_file)
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or(false);
+        if !is_link {
+            return Ok(output_file);
+        }
+        match self.config.symlinks {
+            SymlinkPolicy::Refuse => Err(SafeWriterError::SecurityViolation(format!(
+                "{} is a symbolic link",
+                output_file.display()
+            ))),
+            SymlinkPolicy::Replace => {
+                debug!("replacing the symbolic link {}", output_file.display());
+                fs::remove_file(&output_file)?;
+                Ok(output_file)
             }
+            SymlinkPolicy::Follow => match fs::canonicalize(&output_file) {
+                Ok(target) => Ok(target),
+                // A dangling link: create the file it names
+                Err(_) => {
+                    let target = fs::read_link(&output_file)?;
+                    let dir = output_file.parent().unwrap_or_else(|| Path::new(""));
+                    Ok(dir.join(target))
+                }
+            },
+        }
+    }
+
+    /// This run's snapshot directory, creating it and removing the oldest
+    /// snapshots beyond the retention limit on first use.
+    fn snapshot_dir(&self) -> Result<&PathBuf, SafeWriterError> {
+        if let Some(dir) = self.snapshot_dir.get() {
+            return Ok(dir);
+        }
+        let root = self.private_dir.join(SNAPSHOTS);
+        let stamp = Local::now().format("%Y%m%d-%H%M%S%.3f").to_string();
+        let mut dir = root.join(&stamp);
+        let mut n = 1;
+        while dir.exists() {
+            n += 1;
+            dir = root.join(format!("{}-{}", stamp, n));
+        }
+        fs::create_dir_all(&dir)
+            .map_err(|_| SafeWriterError::DirectoryCreationFailed(dir.clone()))?;
+
+        // The names sort by time
+        let mut runs: Vec<PathBuf> = fs::read_dir(&root)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir())
+            .collect();
+        runs.sort();
+        let excess = runs.len().saturating_sub(self.config.snapshots);
+        for old in &runs[..excess] {
+            debug!("removing snapshot {}", old.display());
+            fs::remove_dir_all(old)?;
+        }
+        Ok(self.snapshot_dir.get_or_init(|| dir))
+    }
+
+    /// Whether `output_file` changed after the backup `before_write` found.
+    fn modified_externally(&self, output_file: &Path) -> Result<bool, SafeWriterError> {
+        if !self.config.modification_check || !output_file.is_file() {
+            return Ok(false);
+        }
+        let Some(old_timestamp) = self.old_timestamp else {
+            return Ok(false);
+        };
+        let system_time: SystemTime = fs::metadata(output_file)?.modified()?;
+        let out_timestamp: DateTime<Local> = DateTime::from(system_time);
+        Ok(out_timestamp > old_timestamp)
+    }
+
+    /// Write `content` to `file_name` under gen/ in one step: stage it in the
+    /// private directory, back it up, check the output was not edited since
+    /// the last write and copy it into place if it changed. A staged file
+    /// left incomplete by a failed write is removed.
+    pub fn write_atomic<P: AsRef<Path>>(
+        &mut self,
+        file_name: P,
+        content: &[u8],
+    ) -> Result<WriteResult, SafeWriterError> {
+        let private_file = self.before_write(&file_name)?;
+        if let Err(err) = fs::write(&private_file, content) {
+            let _ = fs::remove_file(&private_file);
+            return Err(err.into());
+        }
+        self.after_write(file_name)
+    }
+
+    /// Give output `file_name` under gen/ the Unix permission bits `mode`,
+    /// e.g. `0o755`; later writes keep them. Does nothing on other platforms.
+    pub fn set_mode<P: AsRef<Path>>(&self, file_name: P, mode: u32) -> Result<(), SafeWriterError> {
+        let output_file = under(&self.gen_base, file_name.as_ref());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&output_file, fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = (output_file, mode);
+        Ok(())
+    }
+
+    /// Accept the current content of an output under gen/ as the new baseline,
+    /// so a later write does not treat the hand edit as an external modification.
+    pub fn accept_external<P: AsRef<Path>>(&self, file_name: P) -> Result<(), SafeWriterError> {
+        validate_filename(file_name.as_ref())?;
+        let path = self.prepare_write_file(&file_name)?;
+        let output_file = under(&self.gen_base, &path);
+        let private_file = under(&self.private_dir, &path);
+        self.atomic_copy(&output_file, &private_file)?;
+        if self.backs_up(&path) {
+            let old_file = under(&self.old_dir, &path);
+            self.atomic_copy(&output_file, &old_file)
+                .map_err(|_| SafeWriterError::BackupFailed(old_file.clone()))?;
         }
+        Ok(())
+    }
+
+    /// The outputs, relative to gen/, recorded by the last `write_manifest`;
+    /// empty before the first.
+    pub fn read_manifest(&self) -> Result<Vec<String>, SafeWriterError> {
+        Ok(self
+            .read_manifest_entries()?
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect())
+    }
 
-        self.copy_if_different(&private_file, &output_file)?;
+    /// The outputs recorded by the last `write_manifest`, with the checksum
+    /// of their content then; outputs missing at the time have none.
+    pub fn read_checksums(&self) -> Result<Vec<(String, Option<String>)>, SafeWriterError> {
+        Ok(self
+            .read_manifest_entries()?
+            .into_iter()
+            .map(|entry| (entry.path, entry.checksum))
+            .collect())
+    }
 
+    /// The entries recorded by the last `write_manifest`, in their order.
+    pub fn read_manifest_entries(&self) -> Result<Vec<ManifestEntry>, SafeWriterError> {
+        read_manifest_in(&self.private_dir)
+    }
+
+    /// The manifest entry of the output `path`, relative to gen/, produced by
+    /// `inputs`, with a checksum of its current content.
+    pub fn manifest_entry(&self, path: &str, inputs: Vec<String>) -> ManifestEntry {
+        ManifestEntry {
+            path: path.to_string(),
+            checksum: fs::read(under(&self.gen_base, path))
+                .ok()
+                .map(|bytes| checksum(&bytes)),
+            inputs,
+        }
+    }
+
+    /// Record `entries` as the outputs under gen/, replacing the earlier ones.
+    pub fn write_manifest(&self, entries: &[ManifestEntry]) -> Result<(), SafeWriterError> {
+        let content: String = entries.iter().map(ManifestEntry::line).collect();
+        fs::write(self.private_dir.join(MANIFEST), content)?;
+        Ok(())
+    }
+
+    /// The files under gen/ that were modified, deleted or added since the
+    /// last `write_manifest`, relative to gen/ and sorted.
+    pub fn verify_outputs(&self) -> Result<Vec<(String, OutputChange)>, SafeWriterError> {
+        verify_outputs_in(&self.gen_base, &self.private_dir)
+    }
+
+    /// Delete an output under gen/ along with its private copy and backup.
+    pub fn remove_output<P: AsRef<Path>>(&self, file_name: P) -> Result<(), SafeWriterError> {
+        validate_filename(file_name.as_ref())?;
+        let path = file_name.as_ref();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for base in [&self.gen_base, &self.private_dir, &self.old_dir] {
+            check_inside(base, &under(base, dir))?;
+        }
+        for file in [
+            under(&self.gen_base, path),
+            under(&self.private_dir, path),
+            under(&self.old_dir, path),
+        ] {
+            if file.is_file() {
+                fs::remove_file(&file)?;
+            }
+        }
+        debug!("removed {}", under(&self.gen_base, path).display());
         Ok(())
     }
 
@@ -240,15 +641,28 @@ impl SafeFileWriter {
         &self.config
     }
 
-    pub fn set_config(&mut self, config: SafeWriterConfig) {
+    /// Use `config` from now on, creating the backup directory it names.
+    pub fn set_config(&mut self, config: SafeWriterConfig) -> Result<(), SafeWriterError> {
+        self.old_dir = Self::backup_dir(&self.private_dir, &config)?;
         self.config = config;
+        Ok(())
+    }
+
+    /// Send the notices about written files to `reporter`.
+    pub fn set_reporter(&mut self, reporter: Arc<dyn Reporter>) {
+        self.reporter = reporter;
     }
 
-    #[cfg(test)]
     pub fn get_gen_base(&self) -> &Path {
         &self.gen_base
     }
 
+    /// Where the output `path`, relative to gen/, is written; components
+    /// that would leave gen/ are dropped, as on writing.
+    pub fn output_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        under(&self.gen_base, path)
+    }
+
     #[cfg(test)]
     pub fn get_old_dir(&self) -> &Path {
         &self.old_dir
@@ -260,12 +674,181 @@ impl SafeFileWriter {
     }
 }
 
+/// `relative` joined onto `base` one component at a time, so that it uses
+/// the native separator. On Windows the result is an extended-length
+/// (`\\?\`) path, which is not limited to MAX_PATH characters, so deeply
+/// nested outputs can be written.
+fn under(base: &Path, relative: impl AsRef<Path>) -> PathBuf {
+    let mut path = extended_length(base);
+    for component in relative.as_ref().components() {
+        if let Component::Normal(part) = component {
+            path.push(part);
+        }
+    }
+    path
+}
+
+#[cfg(windows)]
+fn extended_length(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if text.starts_with(r"\\?\") || !path.is_absolute() {
+        return path.to_path_buf();
+    }
+    match text.strip_prefix(r"\\") {
+        Some(unc) => PathBuf::from(format!(r"\\?\UNC\{}", unc)),
+        None => PathBuf::from(format!(r"\\?\{}", text)),
+    }
+}
+
+#[cfg(not(windows))]
+fn extended_length(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// A stable 64-bit FNV-1a checksum of `bytes`, in hexadecimal.
+fn checksum(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// The entries recorded in the manifest under `private_dir`, in their order;
+/// empty before the first `write_manifest`. Nothing is created, so no writer
+/// is needed.
+pub fn read_manifest_in(private_dir: &Path) -> Result<Vec<ManifestEntry>, SafeWriterError> {
+    let manifest = private_dir.join(MANIFEST);
+    if !manifest.is_file() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(manifest)?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(ManifestEntry::parse)
+        .collect())
+}
+
+/// The files under `gen_base` that were modified, deleted or added since the
+/// last `write_manifest` into `private_dir`, relative to gen/ and sorted.
+/// Like `read_manifest_in`, this only reads.
+pub fn verify_outputs_in(
+    gen_base: &Path,
+    private_dir: &Path,
+) -> Result<Vec<(String, OutputChange)>, SafeWriterError> {
+    let mut changes = Vec::new();
+    let mut recorded = BTreeSet::new();
+    for entry in read_manifest_in(private_dir)? {
+        let normalized: PathBuf = Path::new(&entry.path)
+            .components()
+            .filter(|c| !matches!(c, Component::CurDir))
+            .collect();
+        recorded.insert(normalized);
+        let Some(sum) = entry.checksum else {
+            continue;
+        };
+        match fs::read(under(gen_base, &entry.path)) {
+            Ok(bytes) if checksum(&bytes) != sum => {
+                changes.push((entry.path, OutputChange::Modified))
+            }
+            Ok(_) => {}
+            Err(_) => changes.push((entry.path, OutputChange::Deleted)),
+        }
+    }
+    let mut files = Vec::new();
+    if gen_base.is_dir() {
+        list_files(gen_base, Path::new(""), &mut files)?;
+    }
+    for file in files {
+        if !recorded.contains(&file) {
+            let path = file
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            changes.push((path, OutputChange::Added));
+        }
+    }
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(changes)
+}
+
+/// Add the files under `root.join(dir)` to `files`, relative to `root`.
+fn list_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            list_files(root, &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Give `path` the owner and group of `previous`, where allowed: only a
+/// privileged user can hand a file to someone else.
+#[cfg(unix)]
+fn preserve_owner(path: &Path, previous: &fs::Metadata) {
+    use std::os::unix::fs::MetadataExt;
+    if let Err(err) = std::os::unix::fs::chown(path, Some(previous.uid()), Some(previous.gid())) {
+        debug!("cannot keep the owner of {}: {}", path.display(), err);
+    }
+}
+
+#[cfg(not(unix))]
+fn preserve_owner(_path: &Path, _previous: &fs::Metadata) {}
+
+/// Whether `stem` names a Windows device: CON, PRN, AUX, NUL, COM1-9, LPT1-9.
+fn is_reserved_name(stem: &str) -> bool {
+    let upper = stem.to_ascii_uppercase();
+    match upper.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => {
+            let bytes = upper.as_bytes();
+            bytes.len() == 4
+                && (upper.starts_with("COM") || upper.starts_with("LPT"))
+                && (b'1'..=b'9').contains(&bytes[3])
+        }
+    }
+}
+
 /// Validate that the filename does not specify an absolute path or attempt directory traversal.
 fn validate_filename(path: &Path) -> Result<(), SafeWriterError> {
     let filename = path.to_string_lossy();
 
-    // Check for Unix-style absolute path
-    if filename.starts_with('/') {
+    // Check for NUL, newlines and other control characters
+    if let Some(c) = filename.chars().find(|c| c.is_control()) {
+        return Err(SafeWriterError::SecurityViolation(format!(
+            "Control character {:?} is not allowed in paths: {:?}",
+            c, filename
+        )));
+    }
+
+    // Check for Windows device names, which refer to devices in any directory
+    // and with any extension, e.g. "nul.txt"
+    for component in filename.split(['/', '\\']) {
+        let stem = component.split('.').next().unwrap_or("").trim_end();
+        if is_reserved_name(stem) {
+            return Err(SafeWriterError::SecurityViolation(format!(
+                "Reserved device name '{}' is not allowed in paths: {}",
+                component, filename
+            )));
+        }
+    }
+
+    // Check for UNC paths, e.g. \\server\share
+    if filename.starts_with("\\\\") || filename.starts_with("//") {
+        return Err(SafeWriterError::SecurityViolation(format!(
+            "UNC paths are not allowed: {}",
+            filename
+        )));
+    }
+
+    // Check for Unix-style absolute path, or one rooted at the current drive
+    if filename.starts_with('/') || filename.starts_with('\\') {
         return Err(SafeWriterError::SecurityViolation(format!(
             "Absolute paths are not allowed: {}",
             filename
@@ -283,8 +866,11 @@ fn validate_filename(path: &Path) -> Result<(), SafeWriterError> {
         }
     }
 
-    // Check if filename contains '..'
-    if filename.split('/').any(|component| component == "..") {
+    // Check if filename contains '..', with either separator
+    if filename
+        .split(['/', '\\'])
+        .any(|component| component == "..")
+    {
         return Err(SafeWriterError::SecurityViolation(format!(
             "Path traversal detected (..): {}",
             filename
@@ -293,3 +879,23 @@ fn validate_filename(path: &Path) -> Result<(), SafeWriterError> {
 
     Ok(())
 }
+
+/// Check that `dir`, once symbolic links are resolved, lies under `base`.
+/// The lexical checks of `validate_filename` cannot see a directory under
+/// `base` that links elsewhere. Where `dir` does not exist yet, its nearest
+/// existing ancestor is checked, before anything is created through it.
+fn check_inside(base: &Path, dir: &Path) -> Result<(), SafeWriterError> {
+    let Some(existing) = dir.ancestors().find(|ancestor| ancestor.exists()) else {
+        return Ok(());
+    };
+    let resolved = existing.canonicalize()?;
+    let base = base.canonicalize()?;
+    if !resolved.starts_with(&base) {
+        return Err(SafeWriterError::SecurityViolation(format!(
+            "{} resolves outside {}",
+            dir.display(),
+            base.display()
+        )));
+    }
+    Ok(())
+}
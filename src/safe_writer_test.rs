@@ -25,7 +25,8 @@ fn write_file(
         let mut file = fs::File::create(&private_path)?;
         write!(file, "{}", content)?;
     }
-    Ok(writer.after_write(path)?)
+    writer.after_write(path)?;
+    Ok(())
 }
 
 // Basic functionality tests
@@ -0,0 +1,196 @@
+// src/serve.rs
+//! A preview server for woven pages. It serves the page over HTTP on a local
+//! address, polls the inputs for changes, renders the page again when they
+//! change, and tells open browsers to reload through server-sent events.
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+use crate::report::Reporter;
+use crate::weave::escape_html;
+use crate::AzadiError;
+
+/// How often the inputs are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// How long an event stream stays silent before a keep-alive comment, which
+/// also notices browsers that went away.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Path of the event stream the reload script listens to.
+const EVENTS_PATH: &str = "/__events";
+
+/// The page being served, and how many times it was replaced.
+pub struct Preview {
+    state: Mutex<(u64, String)>,
+    changed: Condvar,
+}
+
+impl Preview {
+    pub fn new(page: String) -> Self {
+        Self {
+            state: Mutex::new((0, page)),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Replace the page and reload the browsers showing it.
+    pub fn update(&self, page: String) {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        state.1 = page;
+        self.changed.notify_all();
+    }
+
+    /// The page with a script added before its closing body tag, which
+    /// reloads it once a newer one is available.
+    fn page(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let script = format!(
+            "<script>new EventSource(\"{}?since={}\").onmessage = () => location.reload();</script>\n",
+            EVENTS_PATH, state.0
+        );
+        match state.1.rfind("</body>") {
+            Some(end) => format!("{}{}{}", &state.1[..end], script, &state.1[end..]),
+            None => format!("{}{}", state.1, script),
+        }
+    }
+
+    /// Answer one HTTP connection.
+    pub fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Skip the headers
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+            header.clear();
+        }
+
+        let mut words = request.split_whitespace();
+        let (method, target) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut stream = stream;
+        match (method, path) {
+            ("GET", "/") | ("GET", "/index.html") => {
+                let page = self.page();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n{}",
+                    page.len(),
+                    page
+                )
+            }
+            ("GET", EVENTS_PATH) => {
+                let since = query
+                    .strip_prefix("since=")
+                    .and_then(|since| since.parse().ok());
+                self.stream_events(stream, since)
+            }
+            _ => write!(
+                stream,
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            ),
+        }
+    }
+
+    /// Send a reload event each time the page is replaced after the version
+    /// `since` (by default, the current one), until the browser disconnects.
+    fn stream_events(&self, mut stream: TcpStream, since: Option<u64>) -> io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n"
+        )?;
+        stream.flush()?;
+        let mut seen = since.unwrap_or_else(|| self.state.lock().unwrap().0);
+        loop {
+            let state = self.state.lock().unwrap();
+            let (state, _) = self
+                .changed
+                .wait_timeout_while(state, KEEP_ALIVE, |state| state.0 == seen)
+                .unwrap();
+            let generation = state.0;
+            drop(state);
+            if generation == seen {
+                stream.write_all(b": keep-alive\n\n")?;
+            } else {
+                seen = generation;
+                stream.write_all(b"data: reload\n\n")?;
+            }
+            stream.flush()?;
+        }
+    }
+}
+
+/// The modification times of the inputs; missing files have none.
+fn modification_times(inputs: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    inputs
+        .iter()
+        .map(|path| path.metadata().and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// A page showing why rendering failed, so the browser keeps following the
+/// edits that will fix it.
+fn error_page(error: &AzadiError) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Error</title>\n</head>\n<body>\n<pre>{}</pre>\n</body>\n</html>\n",
+        escape_html(&error.to_string())
+    )
+}
+
+/// Serve the page `render` produces on `addr` until the process ends,
+/// rendering it again whenever one of `inputs` changes. Rendering errors are
+/// reported and shown in the browser instead of the page.
+pub fn serve<P, F>(
+    addr: SocketAddr,
+    inputs: &[P],
+    mut render: F,
+    reporter: Arc<dyn Reporter>,
+) -> Result<(), AzadiError>
+where
+    P: AsRef<Path>,
+    F: FnMut() -> Result<String, AzadiError>,
+{
+    let inputs: Vec<PathBuf> = inputs.iter().map(|p| p.as_ref().to_path_buf()).collect();
+    let mut render_page = || {
+        render().unwrap_or_else(|e| {
+            reporter.error(Some(e.code()), &e.to_string());
+            error_page(&e)
+        })
+    };
+
+    let mut times = modification_times(&inputs);
+    let preview = Arc::new(Preview::new(render_page()));
+    let listener = TcpListener::bind(addr)?;
+    reporter.info(&format!(
+        "serving http://{}/ (press Ctrl-C to stop)",
+        listener.local_addr()?
+    ));
+
+    let server = Arc::clone(&preview);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let preview = Arc::clone(&server);
+            thread::spawn(move || {
+                if let Err(e) = preview.handle(stream) {
+                    debug!("serve: connection closed: {}", e);
+                }
+            });
+        }
+    });
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let now = modification_times(&inputs);
+        if now != times {
+            times = now;
+            reporter.progress("inputs changed, weaving again");
+            preview.update(render_page());
+        }
+    }
+}
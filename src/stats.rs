@@ -0,0 +1,171 @@
+// src/stats.rs
+//! Project metrics: how many chunks, definitions and references a set of
+//! documents has, how much of it is code and how much prose, which chunks are
+//! the largest, and which chunks end up in which outputs.
+use std::collections::BTreeMap;
+
+use crate::json;
+use crate::Clip;
+
+/// Size of one chunk across all its definitions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkStats {
+    pub name: String,
+    pub definitions: usize,
+    /// Body lines, without the opening and closing lines.
+    pub lines: usize,
+    /// References to other chunks made from the body.
+    pub references: usize,
+}
+
+/// Metrics of a set of documents.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub chunks: usize,
+    pub definitions: usize,
+    pub references: usize,
+    pub file_chunks: usize,
+    pub code_lines: usize,
+    pub prose_lines: usize,
+    /// The largest chunks by lines, largest first.
+    pub largest: Vec<ChunkStats>,
+}
+
+impl Stats {
+    /// Collect the metrics of the documents read into `clip`; `total_lines` is
+    /// the number of lines of those documents and `top` how many of the
+    /// largest chunks to keep.
+    pub fn collect(clip: &Clip, total_lines: usize, top: usize) -> Self {
+        let mut chunks = clip.chunk_stats();
+        let definitions = chunks.iter().map(|c| c.definitions).sum();
+        let code_lines = chunks.iter().map(|c| c.lines).sum();
+        let stats = Stats {
+            chunks: chunks.len(),
+            definitions,
+            references: chunks.iter().map(|c| c.references).sum(),
+            file_chunks: chunks
+                .iter()
+                .filter(|c| c.name.starts_with("@file "))
+                .count(),
+            code_lines,
+            // Every definition has an opening and a closing line
+            prose_lines: total_lines.saturating_sub(code_lines + 2 * definitions),
+            largest: Vec::new(),
+        };
+        chunks.sort_by(|a, b| b.lines.cmp(&a.lines).then_with(|| a.name.cmp(&b.name)));
+        chunks.truncate(top);
+        Stats {
+            largest: chunks,
+            ..stats
+        }
+    }
+
+    /// A human-readable report.
+    pub fn to_text(&self) -> String {
+        let mut out = format!(
+            "chunks:       {}\nfile chunks:  {}\ndefinitions:  {}\nreferences:   {}\ncode lines:   {}\nprose lines:  {}\n",
+            self.chunks,
+            self.file_chunks,
+            self.definitions,
+            self.references,
+            self.code_lines,
+            self.prose_lines
+        );
+        if !self.largest.is_empty() {
+            out.push_str("largest chunks:\n");
+            for chunk in &self.largest {
+                out.push_str(&format!("  {:>6} lines  {}\n", chunk.lines, chunk.name));
+            }
+        }
+        out
+    }
+
+    /// The report as a JSON object.
+    pub fn to_json(&self) -> String {
+        let largest: Vec<String> = self
+            .largest
+            .iter()
+            .map(|c| {
+                format!(
+                    "{{\"name\": {}, \"definitions\": {}, \"lines\": {}, \"references\": {}}}",
+                    json::quote(&c.name),
+                    c.definitions,
+                    c.lines,
+                    c.references
+                )
+            })
+            .collect();
+        format!(
+            "{{\"chunks\": {}, \"file_chunks\": {}, \"definitions\": {}, \"references\": {}, \"code_lines\": {}, \"prose_lines\": {}, \"largest\": [{}]}}\n",
+            self.chunks,
+            self.file_chunks,
+            self.definitions,
+            self.references,
+            self.code_lines,
+            self.prose_lines,
+            largest.join(", ")
+        )
+    }
+}
+
+/// Which chunks go into which generated files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    /// For each output (path relative to gen/), the chunks it includes and
+    /// how many of its lines come from each.
+    pub outputs: BTreeMap<String, BTreeMap<String, usize>>,
+    /// For each chunk, the outputs including it; empty for unused chunks.
+    pub chunks: BTreeMap<String, Vec<String>>,
+}
+
+impl Coverage {
+    /// A human-readable report: outputs with their chunks, then chunks with
+    /// their outputs.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for (path, chunks) in &self.outputs {
+            out.push_str(&format!("{}\n", path));
+            for (chunk, lines) in chunks {
+                out.push_str(&format!("  {:>6} lines  {}\n", lines, chunk));
+            }
+        }
+        out.push_str("chunks:\n");
+        for (chunk, outputs) in &self.chunks {
+            let outputs = if outputs.is_empty() {
+                "(none)".to_string()
+            } else {
+                outputs.join(", ")
+            };
+            out.push_str(&format!("  {}: {}\n", chunk, outputs));
+        }
+        out
+    }
+
+    /// The report as a JSON object with `outputs` and `chunks` members.
+    pub fn to_json(&self) -> String {
+        let outputs: Vec<String> = self
+            .outputs
+            .iter()
+            .map(|(path, chunks)| {
+                let chunks: Vec<String> = chunks
+                    .iter()
+                    .map(|(chunk, lines)| format!("{}: {}", json::quote(chunk), lines))
+                    .collect();
+                format!("{}: {{{}}}", json::quote(path), chunks.join(", "))
+            })
+            .collect();
+        let chunks: Vec<String> = self
+            .chunks
+            .iter()
+            .map(|(chunk, outputs)| {
+                let outputs: Vec<String> = outputs.iter().map(|o| json::quote(o)).collect();
+                format!("{}: [{}]", json::quote(chunk), outputs.join(", "))
+            })
+            .collect();
+        format!(
+            "{{\"outputs\": {{{}}}, \"chunks\": {{{}}}}}\n",
+            outputs.join(", "),
+            chunks.join(", ")
+        )
+    }
+}
@@ -1,8 +1,11 @@
 // src/tests/advanced.rs
 
 use super::*;
+use crate::diagnostic::Code;
 use crate::AzadiError;
 use crate::ChunkError;
+use crate::ExpandOptions;
+use crate::{Clip, SafeFileWriter};
 
 #[test]
 fn test_file_chunk_detection() {
@@ -28,14 +31,9 @@ fn test_undefined_chunk_error() {
 
     let result = setup.clip.expand("main", "");
     match result {
-        // Note how we capture `file_name` in the pattern
-        Err(AzadiError::Chunk(ChunkError::UndefinedChunk {
-            chunk,
-            file_name,
-            location,
-        })) => {
+        Err(AzadiError::Chunk(ChunkError::UndefinedChunk { chunk, location })) => {
             assert_eq!(chunk, "nonexistent");
-            assert_eq!(file_name, "undefined.nw");
+            assert_eq!(location.file_name, "undefined.nw");
             assert_eq!(location.line, 1);
         }
         _ => panic!("Expected UndefinedChunk error"),
@@ -58,14 +56,9 @@ End
 
     let result = setup.clip.expand("recursive", "");
     match result {
-        // Also capture file_name
-        Err(AzadiError::Chunk(ChunkError::RecursiveReference {
-            chunk,
-            file_name,
-            location,
-        })) => {
+        Err(AzadiError::Chunk(ChunkError::RecursiveReference { chunk, location })) => {
             assert_eq!(chunk, "recursive");
-            assert_eq!(file_name, "recursive.nw");
+            assert_eq!(location.file_name, "recursive.nw");
             assert_eq!(location.line, 2);
         }
         _ => panic!("Expected RecursiveReference error"),
@@ -94,13 +87,9 @@ End B
 
     let result = setup.clip.expand("chunk-a", "");
     match result {
-        Err(AzadiError::Chunk(ChunkError::RecursiveReference {
-            chunk,
-            file_name,
-            location,
-        })) => {
+        Err(AzadiError::Chunk(ChunkError::RecursiveReference { chunk, location })) => {
             assert_eq!(chunk, "chunk-a");
-            assert_eq!(file_name, "mutual_recursion.nw");
+            assert_eq!(location.file_name, "mutual_recursion.nw");
             assert_eq!(location.line, 8);
         }
         _ => panic!("Expected RecursiveReference error"),
@@ -158,7 +147,9 @@ fn test_error_messages_format() {
     let err = setup.clip.expand("a", "").unwrap_err();
     let error_msg = err.to_string();
 
-    assert!(error_msg.contains("Error: errors.nw line 2:"));
+    assert_eq!(err.code(), Code::UndefinedChunk);
+    assert!(error_msg.contains("errors.nw line 2:"));
+    assert!(!error_msg.contains("AZ0001"), "{}", error_msg);
     assert!(error_msg.contains("referenced chunk 'nonexistent' is undefined"));
 }
 
@@ -208,3 +199,1044 @@ Content4
         vec!["Content1\n"]
     );
 }
+
+#[test]
+fn test_exec_chunk_requires_permission() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        r#"
+# <<main>>=
+version:
+    # <<version>>
+# @
+# <<@exec version>>=
+echo 1.2.3
+# @
+"#,
+        "exec.nw",
+    );
+
+    match setup.clip.expand("main", "") {
+        Err(AzadiError::Chunk(ChunkError::ExecNotAllowed { chunk, .. })) => {
+            assert_eq!(chunk, "version");
+        }
+        _ => panic!("Expected ExecNotAllowed error"),
+    }
+
+    setup.clip.set_allow_exec(true);
+    assert_eq!(
+        setup.clip.expand("main", "").unwrap(),
+        vec!["version:\n", "    1.2.3\n"]
+    );
+}
+
+#[test]
+fn test_insert_external_file() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    let dir = setup._temp_dir.path().to_path_buf();
+    std::fs::create_dir_all(dir.join("assets"))?;
+    std::fs::write(dir.join("assets/table.txt"), "a | b\nc | d\n")?;
+    let source = dir.join("doc.nw");
+    std::fs::write(
+        &source,
+        "# <<main>>=\nbegin\n    # <<@insert assets/table.txt>>\nend\n# @\n",
+    )?;
+    setup.clip.read_file(&source)?;
+
+    assert_eq!(
+        setup.clip.expand("main", "")?,
+        vec!["begin\n", "    a | b\n", "    c | d\n", "end\n"]
+    );
+
+    setup.clip.read(
+        "# <<broken>>=\n# <<@insert missing.txt>>\n# @\n",
+        "broken.nw",
+    );
+    assert!(matches!(
+        setup.clip.expand("broken", ""),
+        Err(AzadiError::Chunk(ChunkError::InsertFailed { .. }))
+    ));
+
+    std::fs::write(dir.join("secret.txt"), "hidden\n")?;
+    let nested = dir.join("assets/doc.nw");
+    std::fs::write(&nested, "# <<escape>>=\n# <<@insert ../secret.txt>>\n# @\n")?;
+    setup.clip.read_file(&nested)?;
+    assert!(matches!(
+        setup.clip.expand("escape", ""),
+        Err(AzadiError::Chunk(ChunkError::InsertFailed { .. }))
+    ));
+    let absolute = format!(
+        "# <<absolute>>=\n# <<@insert {}>>\n# @\n",
+        dir.join("secret.txt").display()
+    );
+    setup.clip.read(&absolute, "absolute.nw");
+    assert!(matches!(
+        setup.clip.expand("absolute", ""),
+        Err(AzadiError::Chunk(ChunkError::InsertFailed { .. }))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_namespaced_chunks() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_namespaced(true);
+    setup.clip.read(
+        "# <<helpers>>=\nparser helper\n# @\n# <<parser>>=\n# <<helpers>>\n# @\n",
+        "parser.nw",
+    );
+    setup.clip.read(
+        "# <<helpers>>=\nlexer helper\n# @\n# <<lexer>>=\n# <<helpers>>\n# <<parser.nw::helpers>>\n# @\n",
+        "lexer.nw",
+    );
+
+    assert!(setup.clip.has_chunk("parser.nw::helpers"));
+    assert!(setup.clip.has_chunk("lexer.nw::helpers"));
+    assert_eq!(
+        setup.clip.expand("parser", "").unwrap(),
+        vec!["parser helper\n"]
+    );
+    assert_eq!(
+        setup.clip.expand("lexer", "").unwrap(),
+        vec!["lexer helper\n", "parser helper\n"]
+    );
+}
+
+#[test]
+fn test_namespaced_inputs_with_same_base_name() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_namespaced(true);
+    let dir = setup._temp_dir.path().to_path_buf();
+    for sub in ["a", "b"] {
+        std::fs::create_dir_all(dir.join(sub))?;
+        std::fs::write(dir.join(sub).join("lib.nw"), "# <<helpers>>=\nx\n# @\n")?;
+    }
+    let result = setup
+        .clip
+        .read_files(&[dir.join("a/lib.nw"), dir.join("b/lib.nw")]);
+    match result {
+        Err(AzadiError::Chunk(err @ ChunkError::NamespaceCollision { .. })) => {
+            assert_eq!(err.code(), crate::diagnostic::Code::NamespaceCollision);
+            assert!(err.to_string().contains("'lib.nw'"), "{}", err);
+        }
+        other => panic!("expected a namespace collision, got {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_private_chunk_is_file_local() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@private helper>>=\ninternal\n# @\n# <<api>>=\n# <<helper>>\n# @\n",
+        "lib.nw",
+    );
+    setup
+        .clip
+        .read("# <<main>>=\n# <<helper>>\n# @\n", "main.nw");
+
+    assert_eq!(setup.clip.expand("api", "").unwrap(), vec!["internal\n"]);
+    match setup.clip.expand("main", "") {
+        Err(AzadiError::Chunk(ChunkError::PrivateChunkReference {
+            chunk, defined_in, ..
+        })) => {
+            assert_eq!(chunk, "helper");
+            assert_eq!(defined_in, "lib.nw");
+        }
+        other => panic!("Expected PrivateChunkReference, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_escaped_delimiters_are_literal() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<script>>=\ncat @<<EOF\n@<<not-a-chunk>>\n# @<<also-literal>>\nEOF\n# @\n",
+        "escape.nw",
+    );
+
+    assert_eq!(
+        setup.clip.expand("script", "").unwrap(),
+        vec![
+            "cat <<EOF\n",
+            "<<not-a-chunk>>\n",
+            "# <<also-literal>>\n",
+            "EOF\n"
+        ]
+    );
+}
+
+#[test]
+fn test_verbatim_chunk_keeps_references() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<example>>=\nsome code\n# @\n# <<@verbatim docs>>=\n<<example>>=\n    <<example>>\n@<<escaped>>\n# @\n",
+        "verbatim.nw",
+    );
+
+    assert_eq!(
+        setup.clip.expand("docs", "").unwrap(),
+        vec!["<<example>>=\n", "    <<example>>\n", "@<<escaped>>\n"]
+    );
+}
+
+#[test]
+fn test_redefinition_warns_or_fails() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<part>>=\none\n# @\n# <<@append part>>=\ntwo\n# @\n# <<part>>=\nthree\n# @\n",
+        "parts.nw",
+    );
+
+    assert_eq!(
+        setup.clip.expand("part", "").unwrap(),
+        vec!["one\n", "two\n", "three\n"]
+    );
+    let warnings = setup.clip.check_redefinitions().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("parts.nw line 7"));
+    assert!(warnings[0].contains("parts.nw line 4"));
+
+    setup.clip.set_strict_redefinitions(true);
+    match setup.clip.write_files() {
+        Err(AzadiError::Chunk(ChunkError::ChunkRedefinition {
+            chunk, location, ..
+        })) => {
+            assert_eq!(chunk, "part");
+            assert_eq!(location.line, 6);
+        }
+        other => panic!("Expected ChunkRedefinition, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_scoped_replace_keeps_other_files() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup
+        .clip
+        .read("# <<setup>>=\ncommon default\n# @\n", "lib/common.nw");
+    setup
+        .clip
+        .read("# <<@append setup>>=\nplugin step\n# @\n", "plugin.nw");
+    setup.clip.read(
+        "# <<@replace(from=common.nw) setup>>=\nproject default\n# @\n",
+        "project.nw",
+    );
+
+    assert_eq!(
+        setup.clip.expand("setup", "").unwrap(),
+        vec!["plugin step\n", "project default\n"]
+    );
+}
+
+#[test]
+fn test_order_weights_definitions() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup
+        .clip
+        .read("# <<@order 20 @append init>>=\nlate\n# @\n", "b.nw");
+    setup
+        .clip
+        .read("# <<@append init>>=\nunweighted\n# @\n", "c.nw");
+    setup
+        .clip
+        .read("# <<@order -5 @append init>>=\nearly\n# @\n", "a.nw");
+
+    assert_eq!(
+        setup.clip.expand("init", "").unwrap(),
+        vec!["early\n", "unweighted\n", "late\n"]
+    );
+}
+
+#[test]
+fn test_expand_options() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<main>>=\nversion = \"@{VERSION}\"\n# <<helper>>\nkeep @{UNKNOWN}\n# @\n# <<helper>>=\nhelp()\n# @\n",
+        "opts.nw",
+    );
+
+    let mut options = ExpandOptions {
+        indent: "  ".to_string(),
+        line_directive: Some("#line {line} \"{file}\"".to_string()),
+        ..ExpandOptions::default()
+    };
+    options
+        .defines
+        .insert("VERSION".to_string(), "1.2".to_string());
+    assert_eq!(
+        setup.clip.expand_with_options("main", &options).unwrap(),
+        vec![
+            "#line 2 \"opts.nw\"\n",
+            "  version = \"1.2\"\n",
+            "#line 7 \"opts.nw\"\n",
+            "  help()\n",
+            "#line 4 \"opts.nw\"\n",
+            "  keep @{UNKNOWN}\n",
+        ]
+    );
+
+    let shallow = ExpandOptions {
+        max_depth: 0,
+        ..ExpandOptions::default()
+    };
+    assert!(matches!(
+        setup.clip.expand_with_options("main", &shallow),
+        Err(AzadiError::Chunk(ChunkError::RecursionLimit { .. }))
+    ));
+}
+
+#[test]
+fn test_define_constants() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "@define VERSION 2.0.1\n# @define PREFIX /opt/app\n# <<install>>=\ncp app-@{VERSION}.tar @{PREFIX}\n# @\n@define LATE yes\n",
+        "defs.nw",
+    );
+
+    assert_eq!(
+        setup.clip.expand("install", "").unwrap(),
+        vec!["cp app-2.0.1.tar /opt/app\n"]
+    );
+
+    let mut options = ExpandOptions::default();
+    options
+        .defines
+        .insert("VERSION".to_string(), "3.0".to_string());
+    assert_eq!(
+        setup.clip.expand_with_options("install", &options).unwrap(),
+        vec!["cp app-3.0.tar /opt/app\n"]
+    );
+}
+
+#[test]
+fn test_prefix_outputs_from_input() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_prefix_from_input(true);
+    setup
+        .clip
+        .read("# <<@file lib.rs>>=\nfoo\n# @\n", "modules/foo.nw");
+    setup
+        .clip
+        .read("# <<@file lib.rs>>=\nbar\n# @\n", "/elsewhere/bar.nw");
+
+    let mut outputs = setup.clip.get_file_chunks();
+    outputs.sort();
+    assert_eq!(
+        outputs,
+        vec!["@file bar/lib.rs", "@file modules/foo/lib.rs"]
+    );
+}
+
+#[test]
+fn test_file_chunks_and_warnings_in_source_order() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@file z.txt>>=\nz\n# @\n# <<unused_b>>=\nb\n# @\n",
+        "first.nw",
+    );
+    setup.clip.read(
+        "# <<@file a.txt>>=\na\n# @\n# <<@file m.txt>>=\nm\n# @\n# <<unused_a>>=\na\n# @\n",
+        "second.nw",
+    );
+
+    assert_eq!(
+        setup.clip.get_file_chunks(),
+        vec!["@file z.txt", "@file a.txt", "@file m.txt"]
+    );
+    assert_eq!(
+        setup.clip.check_unused_chunks(),
+        vec![
+            "first.nw line 4: chunk 'unused_b' is defined but never referenced",
+            "second.nw line 7: chunk 'unused_a' is defined but never referenced",
+        ]
+    );
+}
+
+#[test]
+fn test_reference_cycles_reported_together() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@file out.txt>>=\n# <<a>>\n# <<self>>\n# @\n# <<a>>=\n# <<b>>\n# @\n# <<b>>=\n# <<a>>\n# @\n# <<self>>=\n# <<self>>\n# @\n",
+        "cycles.nw",
+    );
+
+    let cycles = setup.clip.reference_cycles();
+    assert_eq!(cycles.len(), 2);
+    assert_eq!(cycles[0].chunks, vec!["a", "b"]);
+    let lines: Vec<usize> = cycles[0]
+        .references
+        .iter()
+        .map(|site| site.location.line)
+        .collect();
+    assert_eq!(lines, vec![5, 8]);
+    assert_eq!(cycles[1].chunks, vec!["self"]);
+
+    match setup.clip.check_cycles() {
+        Err(ChunkError::ReferenceCycles { cycles }) => assert_eq!(cycles.len(), 2),
+        _ => panic!("Expected ReferenceCycles error"),
+    }
+    let message = setup.clip.check_cycles().unwrap_err().to_string();
+    assert!(message.contains("cycles.nw line 9: 'b' references 'a'"));
+    assert!(message.contains("cycles.nw line 12: 'self' references 'self'"));
+}
+
+#[test]
+fn test_topological_order_puts_dependencies_first() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@file main.py>>=\n# <<app>>\n# @\n# <<app>>=\n# <<helpers>>\n# <<config>>\n# @\n# <<helpers>>=\n# <<config>>\n# @\n# <<config>>=\nDEBUG = True\n# @\n# <<@file util.py>>=\n# <<helpers>>\n# @\n",
+        "order.nw",
+    );
+
+    assert_eq!(
+        setup.clip.topological_order().unwrap(),
+        vec!["config", "helpers", "app", "@file main.py", "@file util.py"]
+    );
+
+    setup.clip.read("# <<loop>>=\n# <<loop>>\n# @\n", "loop.nw");
+    assert!(matches!(
+        setup.clip.topological_order(),
+        Err(ChunkError::ReferenceCycles { .. })
+    ));
+}
+
+#[test]
+fn test_orphaned_outputs_warned_then_pruned() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    let gen = setup.clip.gen_base().to_path_buf();
+    setup.clip.read(
+        "# <<@file a.txt>>=\na\n# @\n# <<@file sub/b.txt>>=\nb\n# @\n",
+        "doc.nw",
+    );
+    setup.clip.write_files()?;
+    assert!(setup.clip.orphaned_outputs()?.is_empty());
+
+    setup.clip.reset();
+    setup.clip.read("# <<@file c.txt>>=\nc\n# @\n", "doc.nw");
+    assert_eq!(setup.clip.orphaned_outputs()?, vec!["a.txt", "sub/b.txt"]);
+    setup.clip.write_files()?;
+    assert!(gen.join("a.txt").is_file());
+    assert_eq!(setup.clip.orphaned_outputs()?, vec!["a.txt", "sub/b.txt"]);
+
+    setup.clip.reset();
+    setup.clip.read("# <<@file d.txt>>=\nd\n# @\n", "doc.nw");
+    setup.clip.set_prune(true);
+    setup.clip.write_files()?;
+    assert!(!gen.join("a.txt").exists());
+    assert!(!gen.join("sub/b.txt").exists());
+    assert!(!gen.join("c.txt").exists());
+    assert!(gen.join("d.txt").is_file());
+    assert!(setup.clip.orphaned_outputs()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_outputs_of_other_documents_not_orphaned() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    let gen = setup.clip.gen_base().to_path_buf();
+    setup.clip.read("# <<@file a.txt>>=\na\n# @\n", "a.nw");
+    setup.clip.write_files()?;
+
+    setup.clip.reset();
+    setup.clip.read("# <<@file b.txt>>=\nb\n# @\n", "b.nw");
+    assert!(setup.clip.orphaned_outputs()?.is_empty());
+    setup.clip.set_prune(true);
+    setup.clip.write_files()?;
+    assert!(gen.join("a.txt").is_file());
+
+    // Once a.nw is read again without it, a.txt is its orphan
+    setup.clip.reset();
+    setup.clip.read("# <<@file c.txt>>=\nc\n# @\n", "a.nw");
+    assert_eq!(setup.clip.orphaned_outputs()?, vec!["a.txt"]);
+    setup.clip.write_files()?;
+    assert!(!gen.join("a.txt").exists());
+    assert!(gen.join("b.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn test_reset_forgets_what_was_read() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_test_harness(Some(
+        crate::harness::TestHarness::new("tests/examples.rs").unwrap(),
+    ));
+    setup.clip.read(
+        "@define VERSION 1.0\n# <<@doc intro>>=\nAbout.\n# @\n# <<a>>=\none\n# @\n# <<a>>=\ntwo\n# @\n# <<@test works>>=\nok\n# @\n",
+        "first.nw",
+    );
+    assert_eq!(setup.clip.check_redefinitions().unwrap().len(), 1);
+    assert_eq!(setup.clip.doc_sections().len(), 1);
+
+    setup.clip.reset();
+    setup.clip.read("# <<a>>=\nv@{VERSION}\n# @\n", "second.nw");
+    assert_eq!(setup.clip.expand("a", "").unwrap(), vec!["v@{VERSION}\n"]);
+    assert!(setup.clip.check_redefinitions().unwrap().is_empty());
+    assert!(setup.clip.doc_sections().is_empty());
+    assert!(setup.clip.get_file_chunks().is_empty());
+}
+
+#[test]
+fn test_locations_carry_file_name() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read("# <<first>>=\none\n# @\n", "first.nw");
+    setup
+        .clip
+        .read("# <<second>>=\n# <<missing>>\n# @\n", "second.nw");
+
+    match setup.clip.expand("second", "") {
+        Err(AzadiError::Chunk(ChunkError::UndefinedChunk { location, .. })) => {
+            assert_eq!(location.file_name, "second.nw");
+        }
+        _ => panic!("Expected UndefinedChunk error"),
+    }
+    setup.clip.read("# <<loop>>=\n# <<loop>>\n# @\n", "loop.nw");
+    let cycles = setup.clip.reference_cycles();
+    assert_eq!(cycles[0].references[0].location.file_name, "loop.nw");
+}
+
+#[test]
+fn test_unusable_syntax_is_an_error() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let writer = || SafeFileWriter::new(temp.path().join("gen"), temp.path().join("private"));
+
+    let huge: Vec<String> = (0..20_000).map(|i| format!("marker{}", i)).collect();
+    match Clip::try_new(writer(), "<<", ">>", "@", &huge) {
+        Err(ChunkError::InvalidDelimiters { message }) => {
+            assert!(
+                message.contains("cannot build the chunk opening pattern"),
+                "{}",
+                message
+            )
+        }
+        _ => panic!("Expected InvalidDelimiters error"),
+    }
+
+    let err = Clip::try_new(writer(), "<<\n", ">>", "@", &["#".to_string()])
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "invalid delimiters: the open delimiter \"<<\\n\" spans more than one line"
+    );
+}
+
+#[test]
+fn test_delimiter_configuration_is_validated() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let markers = ["#".to_string()];
+    let reject = |open: &str, close: &str, end: &str| -> String {
+        let writer = SafeFileWriter::new(temp.path().join("gen"), temp.path().join("private"));
+        match Clip::try_new(writer, open, close, end, &markers) {
+            Err(ChunkError::InvalidDelimiters { message }) => message,
+            _ => panic!(
+                "Expected InvalidDelimiters for {:?} {:?} {:?}",
+                open, close, end
+            ),
+        }
+    };
+
+    assert_eq!(
+        reject("", ">>", "@"),
+        "the open delimiter is empty or blank"
+    );
+    assert_eq!(
+        reject("<<", " ", "@"),
+        "the close delimiter is empty or blank"
+    );
+    assert_eq!(reject("<<", ">>", ""), "the chunk end is empty or blank");
+    assert!(reject("%%", "%%", "@").contains("open and close delimiters are both \"%%\""));
+    assert!(reject("<<", ">>", "<").contains("is a prefix of the open delimiter"));
+
+    let writer = SafeFileWriter::new(temp.path().join("gen"), temp.path().join("private"));
+    assert!(Clip::try_new(writer, "<[", "]>", "%", &markers).is_ok());
+}
+
+#[test]
+fn test_trailing_text_after_reference() {
+    use crate::noweb::TrailingText;
+    let text = "# <<main>>=\nint x = 0;\n    # <<value>> // the value\n# @\n# <<value>>=\nx = compute();\nx += 1;\n# @\n";
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(text, "trailing.nw");
+    assert_eq!(
+        setup.clip.get_chunk_content("main").unwrap(),
+        vec!["int x = 0;\n", "    # <<value>> // the value\n"]
+    );
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_trailing_text(TrailingText::Append);
+    setup.clip.read(text, "trailing.nw");
+    assert_eq!(
+        setup.clip.get_chunk_content("main").unwrap(),
+        vec![
+            "int x = 0;\n",
+            "    x = compute();\n",
+            "    x += 1; // the value\n"
+        ]
+    );
+    assert_eq!(
+        setup.clip.references(),
+        vec![("main".to_string(), "value".to_string())]
+    );
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_trailing_text(TrailingText::After);
+    setup.clip.read(text, "trailing.nw");
+    assert_eq!(
+        setup.clip.get_chunk_content("main").unwrap(),
+        vec![
+            "int x = 0;\n",
+            "    x = compute();\n",
+            "    x += 1;\n",
+            "    // the value\n"
+        ]
+    );
+}
+
+#[test]
+fn test_continuation_marker_joins_lines() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<main>>=\n    call(first, \\\n         second)\n    # <<tail>>\n# @\n# <<tail>>=\nlast \\\n# @\n",
+        "continuation.nw",
+    );
+    let options = ExpandOptions {
+        continuation: Some("\\".to_string()),
+        ..ExpandOptions::default()
+    };
+    assert_eq!(
+        setup.clip.expand_with_options("main", &options).unwrap(),
+        vec!["    call(first, second)\n", "    last \n"]
+    );
+    assert_eq!(
+        setup.clip.expand("main", "").unwrap(),
+        vec![
+            "    call(first, \\\n",
+            "         second)\n",
+            "    last \\\n"
+        ]
+    );
+}
+
+#[test]
+fn test_uncomment_strips_comment_prefix() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@uncomment(//) main>>=\n// int x = 1;\n//     nested();\n//\nplain();\n    # <<helper>>\n# @\n# <<helper>>=\n-- select 1;\n# @\n",
+        "uncomment.nw",
+    );
+    assert_eq!(
+        setup.clip.expand("main", "").unwrap(),
+        vec![
+            "int x = 1;\n",
+            "    nested();\n",
+            "\n",
+            "plain();\n",
+            "    -- select 1;\n"
+        ]
+    );
+
+    let options = ExpandOptions {
+        uncomment: Some("--".to_string()),
+        ..ExpandOptions::default()
+    };
+    assert_eq!(
+        setup.clip.expand_with_options("main", &options).unwrap(),
+        vec![
+            "int x = 1;\n",
+            "    nested();\n",
+            "\n",
+            "plain();\n",
+            "    select 1;\n"
+        ]
+    );
+}
+
+#[test]
+fn test_expand_to_streams_the_expansion() {
+    use crate::noweb::TrailingText;
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_trailing_text(TrailingText::Append);
+    setup.clip.read(
+        "# <<main>>=\nstart\n    # <<value>> // the value\n# <<value>>\nend\n# @\n# <<value>>=\nx = 1;\n# @\n",
+        "stream.nw",
+    );
+    let options = ExpandOptions {
+        line_directive: Some("#line {line}".to_string()),
+        ..ExpandOptions::default()
+    };
+
+    let mut streamed = Vec::new();
+    setup
+        .clip
+        .expand_to("main", &options, &mut streamed)
+        .unwrap();
+    let collected = setup.clip.expand_with_options("main", &options).unwrap();
+    assert_eq!(String::from_utf8(streamed).unwrap(), collected.concat());
+    assert_eq!(
+        collected,
+        vec![
+            "#line 2\n",
+            "start\n",
+            "#line 8\n",
+            "    x = 1; // the value\n",
+            "x = 1;\n",
+            "#line 5\n",
+            "end\n"
+        ]
+    );
+
+    let mut partial = Vec::new();
+    assert!(setup
+        .clip
+        .expand_to("missing", &options, &mut partial)
+        .is_err());
+    assert!(partial.is_empty());
+}
+
+#[test]
+fn test_clip_builder() {
+    use crate::noweb::TrailingText;
+
+    let temp = tempfile::TempDir::new().unwrap();
+    let writer = SafeFileWriter::new(temp.path().join("gen"), temp.path().join("work"));
+    let mut clip = Clip::builder()
+        .delimiters("<[", "]>")
+        .chunk_end("%")
+        .comment_markers(&["--"])
+        .safe_writer(writer)
+        .trailing_text(TrailingText::Append)
+        .build()
+        .unwrap();
+    clip.read(
+        "-- <[main]>=\n<[body]> -- note\n%\n<[body]>=\nx\n%\n",
+        "b.nw",
+    );
+    assert_eq!(clip.expand("main", "").unwrap(), vec!["x -- note\n"]);
+    assert_eq!(clip.gen_base(), temp.path().join("gen").as_path());
+
+    // Rejected before the default writer creates gen/ in the working directory
+    assert!(Clip::builder().delimiters("<<", "<<").build().is_err());
+}
+
+#[test]
+fn test_read_from_reader() {
+    let mut setup = TestSetup::new(&["#"]);
+    let source = std::io::Cursor::new("# <<main>>=\n# <<missing>>\n# @\n");
+    setup.clip.read_from(source, "buffer://scratch").unwrap();
+    let err = setup.clip.expand("main", "").unwrap_err();
+    assert!(err.to_string().contains("buffer://scratch"));
+
+    let notebook = r#"{"cells": [{"cell_type": "code", "metadata": {"tags": ["chunk:cell"]}, "source": ["x = 1\n"]}]}"#;
+    setup
+        .clip
+        .read_from(notebook.as_bytes(), "remote.ipynb")
+        .unwrap();
+    assert_eq!(setup.clip.expand("cell", "").unwrap(), vec!["x = 1\n"]);
+
+    let invalid: &[u8] = &[0xff, 0xfe];
+    assert!(setup.clip.read_from(invalid, "bad.nw").is_err());
+}
+
+#[test]
+fn test_definitions_of() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<main>>=\n# <<body>>\n# @\n  # <<body>>=\n  first\n  # <<other>>\n# @\n",
+        "a.nw",
+    );
+    setup
+        .clip
+        .read("Prose.\n# <<body>>=\nsecond\n# @\n", "b.nw");
+
+    let defs = setup.clip.definitions_of("body");
+    assert_eq!(defs.len(), 2);
+    assert_eq!(defs[0].content, vec!["  first\n", "  # <<other>>\n"]);
+    assert_eq!(defs[0].base_indent, 2);
+    assert_eq!(
+        (defs[0].location.file_name.as_str(), defs[0].location.line),
+        ("a.nw", 3)
+    );
+    assert_eq!(defs[1].content, vec!["second\n"]);
+    assert_eq!(
+        (defs[1].location.file_name.as_str(), defs[1].location.line),
+        ("b.nw", 1)
+    );
+    assert!(setup.clip.definitions_of("nothing").is_empty());
+}
+
+#[test]
+fn test_expand_with_visitor() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<main>>=\nstart\n  # <<body>>\n# @\n# <<body>>=\nx\ny\n# @\n",
+        "visit.nw",
+    );
+
+    let mut visited = Vec::new();
+    setup
+        .clip
+        .expand_with_visitor("main", &ExpandOptions::default(), |line, provenance| {
+            visited.push(format!(
+                "{}:{} {} {}",
+                provenance.location.file_name,
+                provenance.location.line + 1,
+                provenance.chunk,
+                line.trim_end()
+            ));
+        })
+        .unwrap();
+    assert_eq!(
+        visited,
+        vec![
+            "visit.nw:2 main start",
+            "visit.nw:6 body   x",
+            "visit.nw:7 body   y"
+        ]
+    );
+}
+
+#[test]
+fn test_expansion_with_provenance() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<main>>=\n# <<body>>\nend\n# @\n# <<body>>=\none\n# @\n",
+        "a.nw",
+    );
+    setup.clip.read("# <<body>>=\ntwo\n# @\n", "b.nw");
+
+    // A source map of output line to input location, built by the caller
+    let options = ExpandOptions {
+        indent: "    ".to_string(),
+        ..ExpandOptions::default()
+    };
+    let traced = setup.clip.expand_traced("main", &options).unwrap();
+    let map: Vec<(&str, &str, usize, &str)> = traced
+        .iter()
+        .map(|(line, p)| {
+            (
+                line.as_str(),
+                p.location.file_name.as_str(),
+                p.location.line + 1,
+                p.indent.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        map,
+        vec![
+            ("    one\n", "a.nw", 6, "    "),
+            ("    two\n", "b.nw", 2, "    "),
+            ("    end\n", "a.nw", 3, "    "),
+        ]
+    );
+
+    let lines: Vec<String> = setup
+        .clip
+        .expand_with_provenance("body", "")
+        .unwrap()
+        .into_iter()
+        .map(|(line, p)| format!("{}@{}", line.trim_end(), p.chunk))
+        .collect();
+    assert_eq!(lines, vec!["one@body", "two@body"]);
+}
+
+#[test]
+fn test_cloned_clip_is_independent() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<main>>=\n# <<setup>>\n# @\n# <<setup>>=\ndefault\n# @\n# <<spare>>=\nunused\n# @\n",
+        "base.nw",
+    );
+
+    let mut variant = setup.clip.clone();
+    variant.read(
+        "# <<@replace setup>>=\noverlay\n# @\n# <<main>>=\n# <<spare>>\n# @\n",
+        "overlay.nw",
+    );
+    assert_eq!(
+        variant.expand("main", "").unwrap(),
+        vec!["overlay\n", "unused\n"]
+    );
+    assert!(variant.check_unused_chunks().is_empty());
+
+    assert_eq!(setup.clip.expand("main", "").unwrap(), vec!["default\n"]);
+    assert_eq!(setup.clip.check_unused_chunks().len(), 1);
+    assert_eq!(setup.clip.definitions_of("setup").len(), 1);
+}
+
+#[test]
+fn test_keep_going_collects_output_failures() {
+    let text = "# <<@file a.txt>>=\na\n# @\n# <<@file b.txt>>=\n# <<missing>>\n# @\n# <<@file c.txt>>=\nc\n# @\n";
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(text, "k.nw");
+    let err = setup.clip.write_files().unwrap_err();
+    assert!(matches!(err, AzadiError::Chunk(_)));
+    assert!(!setup.clip.gen_base().join("c.txt").exists());
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_keep_going(true);
+    setup.clip.read(text, "k.nw");
+    let err = setup.clip.write_files().unwrap_err();
+    match &err {
+        AzadiError::Outputs(failures) => {
+            assert_eq!(failures.len(), 1);
+            assert_eq!(failures[0].0, "b.txt");
+        }
+        other => panic!("expected output failures, got {}", other),
+    }
+    assert_eq!(err.code(), crate::diagnostic::Code::UndefinedChunk);
+    assert!(err
+        .to_string()
+        .starts_with("1 output(s) could not be generated:\n  b.txt: "));
+    let gen = setup.clip.gen_base().to_path_buf();
+    assert_eq!(std::fs::read_to_string(gen.join("a.txt")).unwrap(), "a\n");
+    assert_eq!(std::fs::read_to_string(gen.join("c.txt")).unwrap(), "c\n");
+}
+
+#[test]
+fn test_case_only_output_collision() {
+    let text = "# <<@file Lib.rs>>=\nupper\n# @\n# <<@file src/x.rs>>=\nx\n# @\n# <<@file ./lib.rs>>=\nlower\n# @\n";
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(text, "case.nw");
+    let err = setup.clip.write_files().unwrap_err();
+    assert_eq!(err.code(), crate::diagnostic::Code::OutputCollision);
+    assert!(err.to_string().contains(
+        "case.nw line 7: output './lib.rs' differs only in case from 'Lib.rs' defined at case.nw line 1"
+    ), "{}", err);
+    assert!(!setup.clip.gen_base().join("Lib.rs").exists());
+}
+
+#[test]
+fn test_file_chunks_naming_the_same_output() {
+    let text = "# <<@file src/a.txt>>=\none\n# @\n# <<@file ./src//a.txt>>=\ntwo\n# @\n";
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(text, "dup.nw");
+    let err = setup.clip.write_files().unwrap_err();
+    assert_eq!(err.code(), crate::diagnostic::Code::FileChunkRedefinition);
+    assert!(err.to_string().contains(
+        "dup.nw line 4: output './src//a.txt' is the same file as 'src/a.txt' defined at dup.nw line 1"
+    ), "{}", err);
+    assert!(!setup.clip.gen_base().join("src/a.txt").exists());
+}
+
+#[test]
+fn test_file_chunk_defined_twice() {
+    let text = "# <<@file a.txt>>=\none\n# @\n# <<@file a.txt>>=\ntwo\n# @\n";
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(text, "twice.nw");
+    let err = setup.clip.write_files().unwrap_err();
+    assert_eq!(err.code(), crate::diagnostic::Code::FileChunkRedefinition);
+    assert!(
+        err.to_string().contains(
+            "twice.nw line 4: file chunk '@file a.txt' is already defined at twice.nw line 1"
+        ),
+        "{}",
+        err
+    );
+    assert!(!setup.clip.gen_base().join("a.txt").exists());
+}
+
+#[test]
+fn test_file_chunk_with_backslashes() {
+    let text = "# <<@file src\\sub\\a.txt>>=\na\n# @\n";
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(text, "win.nw");
+    assert_eq!(setup.clip.get_file_chunks(), vec!["@file src/sub/a.txt"]);
+    setup.clip.write_files().unwrap();
+    let output = setup.clip.gen_base().join("src/sub/a.txt");
+    assert_eq!(std::fs::read_to_string(output).unwrap(), "a\n");
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup
+        .clip
+        .read("# <<@file ..\\up.txt>>=\nup\n# @\n", "win.nw");
+    let err = setup.clip.write_files().unwrap_err();
+    assert!(
+        err.to_string().contains("Path traversal detected (..)"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_extra_delimiter_pairs() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.add_delimiters("⟨⟨", "⟩⟩").unwrap();
+    setup.clip.read(
+        "# <<main>>=\n⟨⟨body⟩⟩\n<<tail>>\n@\n⟨⟨body⟩⟩=\nx @⟨⟨kept⟩⟩\n@\n# <<tail>>=\ny\n@\n",
+        "mixed.nw",
+    );
+    assert_eq!(
+        setup.clip.expand("main", "").unwrap(),
+        vec!["x ⟨⟨kept⟩⟩\n", "y\n"]
+    );
+    assert_eq!(setup.clip.escape("⟨⟨body⟩⟩"), "@⟨⟨body⟩⟩");
+
+    let err = setup.clip.add_delimiters("⟨⟨", "]]").unwrap_err();
+    assert!(err.to_string().contains("already in use"), "{}", err);
+    assert!(Clip::builder().extra_delimiters("@[", "]").build().is_err());
+}
+
+#[test]
+fn test_opening_line_annotation() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<parser>>= rust  \nfn parse() {}\n# @\n# <<parser>>=\nfn more() {}\n# @\n",
+        "annotated.nw",
+    );
+    let defs = setup.clip.definitions_of("parser");
+    assert_eq!(defs[0].annotation.as_deref(), Some("rust"));
+    assert_eq!(defs[1].annotation, None);
+    assert_eq!(
+        setup.clip.expand("parser", "").unwrap(),
+        vec!["fn parse() {}\n", "fn more() {}\n"]
+    );
+}
+
+#[test]
+fn test_output_attributes_adjust_writing() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@file run.sh mode=755 eol=crlf>>=\necho one\n# <<two>>\n# @\n# <<two>>=\necho two\n# @\n# <<@file plain.txt line-directives=off>>=\n# <<two>>\n# @\n# <<@file traced.txt>>=\n# <<two>>\n# @\n",
+        "attrs.nw",
+    );
+    let options = ExpandOptions {
+        line_directive: Some("#line {line}".to_string()),
+        ..ExpandOptions::default()
+    };
+    setup.clip.write_files_with_options(&options)?;
+
+    let gen = setup.clip.gen_base().to_path_buf();
+    let read = |name: &str| std::fs::read_to_string(gen.join(name)).unwrap();
+    assert_eq!(
+        read("run.sh"),
+        "#line 2\r\necho one\r\n#line 6\r\necho two\r\n"
+    );
+    assert_eq!(read("plain.txt"), "echo two\n");
+    assert!(read("traced.txt").starts_with("#line"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(gen.join("run.sh"))?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup
+        .clip
+        .read("# <<@file bad.txt eol=cr>>=\nx\n# @\n", "bad.nw");
+    let err = setup.clip.write_files().unwrap_err();
+    assert_eq!(err.code(), crate::diagnostic::Code::InvalidAttribute);
+    assert!(err
+        .to_string()
+        .contains("bad.nw line 1: chunk '@file bad.txt': eol=cr is not lf or crlf"));
+    Ok(())
+}
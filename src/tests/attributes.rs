@@ -0,0 +1,107 @@
+// src/tests/attributes.rs
+use super::*;
+use crate::attributes::Attributes;
+use crate::Dialect;
+
+#[test]
+fn test_parse_attributes() {
+    let attributes = Attributes::parse("mode=755 eol=lf title=\"a b\" exec=yes").unwrap();
+    assert_eq!(attributes.get("eol"), Some("lf"));
+    assert_eq!(attributes.get("title"), Some("a b"));
+    assert_eq!(attributes.get_int("mode"), Some(755));
+    assert_eq!(attributes.get_int("eol"), None);
+    assert_eq!(attributes.get_bool("exec"), Some(true));
+    assert_eq!(attributes.get("missing"), None);
+    assert_eq!(
+        attributes.iter().map(|(k, _)| k).collect::<Vec<_>>(),
+        vec!["eol", "exec", "mode", "title"]
+    );
+
+    assert!(Attributes::parse("").unwrap().is_empty());
+    assert_eq!(Attributes::parse("a=1 a=2").unwrap().get("a"), Some("2"));
+    assert!(Attributes::parse("novalue").is_none());
+    assert!(Attributes::parse("1x=2").is_none());
+    assert!(Attributes::parse("t=\"open").is_none());
+}
+
+#[test]
+fn test_attributes_to_text() {
+    let attributes = Attributes::parse("mode=755 title=\"a b\" empty=\"\"").unwrap();
+    let text = attributes.to_text().unwrap();
+    assert_eq!(text, " empty=\"\" mode=755 title=\"a b\"");
+    assert_eq!(Attributes::parse(&text), Some(attributes));
+
+    let mut attributes = Attributes::default();
+    attributes.insert("t", "say \"hi\"");
+    assert_eq!(attributes.to_text(), None);
+    attributes.insert("t", "a\"b");
+    assert_eq!(attributes.to_text(), None);
+}
+
+#[test]
+fn test_attributes_on_opening_lines() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@file a.sh mode=755 eol=lf lang=bash>>=\n# <<body>>\n# @\n# <<body note=\"two words\">>= shell\necho hi\n# @\n",
+        "attrs.nw",
+    );
+
+    let defs = setup.clip.definitions_of("@file a.sh");
+    assert_eq!(defs[0].attributes.get_int("mode"), Some(755));
+    assert_eq!(defs[0].attributes.get("eol"), Some("lf"));
+    assert_eq!(defs[0].lang.as_deref(), Some("bash"));
+
+    let defs = setup.clip.definitions_of("body");
+    assert_eq!(defs[0].attributes.get("note"), Some("two words"));
+    assert_eq!(defs[0].annotation.as_deref(), Some("shell"));
+    assert_eq!(defs[0].lang.as_deref(), Some("shell"));
+    assert_eq!(
+        setup.clip.expand("@file a.sh", "").unwrap(),
+        vec!["echo hi\n"]
+    );
+}
+
+#[test]
+fn test_entangled_attributes() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_dialect(Dialect::Entangled);
+    setup.clip.read(
+        "```{.bash #run file=run.sh mode=755}\necho hi\n```\n",
+        "doc.md",
+    );
+    let defs = setup.clip.definitions_of("@file run.sh");
+    assert_eq!(defs[0].attributes.get_int("mode"), Some(755));
+    assert_eq!(defs[0].lang.as_deref(), Some("bash"));
+}
+
+#[test]
+fn test_tag_selection() {
+    let attributes = Attributes::parse("tags=wasm,,experimental").unwrap();
+    assert_eq!(attributes.get_list("tags"), vec!["wasm", "experimental"]);
+    assert!(attributes.get_list("other").is_empty());
+
+    let text = "# <<@file main.rs>>=\n# <<platform>>\n# @\n# <<platform tags=wasm>>=\nwasm_init();\n# @\n# <<platform tags=native,experimental>>=\nnative_init();\n# @\n# <<platform>>=\ncommon();\n# @\n";
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(text, "doc.nw");
+    assert_eq!(
+        setup.clip.expand("@file main.rs", "").unwrap(),
+        vec!["wasm_init();\n", "native_init();\n", "common();\n"]
+    );
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_tag_selection(vec!["wasm".to_string()]);
+    setup.clip.read(text, "doc.nw");
+    assert_eq!(
+        setup.clip.expand("@file main.rs", "").unwrap(),
+        vec!["wasm_init();\n", "common();\n"]
+    );
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup
+        .clip
+        .set_tag_selection(vec!["experimental".to_string()]);
+    setup
+        .clip
+        .read("# <<@file beta.rs tags=wasm>>=\nbeta\n# @\n", "beta.nw");
+    assert!(setup.clip.get_file_chunks().is_empty());
+}
@@ -0,0 +1,66 @@
+// src/tests/build.rs
+use crate::build::{tangle, TangleOptions};
+use crate::AzadiError;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn test_tangle_reports_inputs_and_outputs() -> Result<(), AzadiError> {
+    let temp = TempDir::new()?;
+    let input = temp.path().join("lib.nw");
+    fs::write(
+        &input,
+        "<<@file lib.rs>>=\npub fn answer() -> u32 { 42 }\n@\n",
+    )?;
+
+    let options = TangleOptions {
+        gen: temp.path().join("gen"),
+        priv_dir: temp.path().join("work"),
+        ..TangleOptions::default()
+    };
+    let report = tangle(&[&input], &options)?;
+
+    assert_eq!(report.inputs, vec![input.clone()]);
+    assert_eq!(report.outputs.len(), 1);
+    assert!(report.outputs[0].ends_with("lib.rs"));
+    assert_eq!(
+        fs::read_to_string(&report.outputs[0])?,
+        "pub fn answer() -> u32 { 42 }\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_tangle_reports_inserted_files() -> Result<(), AzadiError> {
+    let temp = TempDir::new()?;
+    let input = temp.path().join("lib.nw");
+    fs::write(temp.path().join("table.txt"), "1 2\n")?;
+    fs::write(&input, "<<@file table.rs>>=\n<<@insert table.txt>>\n@\n")?;
+
+    let options = TangleOptions {
+        gen: temp.path().join("gen"),
+        priv_dir: temp.path().join("work"),
+        ..TangleOptions::default()
+    };
+    let report = tangle(&[&input], &options)?;
+
+    assert_eq!(report.inputs, vec![input, temp.path().join("table.txt")]);
+    Ok(())
+}
+
+#[test]
+fn test_tangle_reports_a_gen_directory_it_cannot_create() -> Result<(), AzadiError> {
+    let temp = TempDir::new()?;
+    let input = temp.path().join("lib.nw");
+    fs::write(&input, "<<@file lib.rs>>=\n@\n")?;
+    let blocker = temp.path().join("blocker");
+    fs::write(&blocker, "")?;
+
+    let options = TangleOptions {
+        gen: blocker.join("gen"),
+        priv_dir: temp.path().join("work"),
+        ..TangleOptions::default()
+    };
+    assert!(tangle(&[&input], &options).is_err());
+    Ok(())
+}
@@ -0,0 +1,80 @@
+// src/tests/completions.rs
+use crate::completions::{generate, Shell};
+use clap::{Arg, ArgAction, Command};
+
+fn command() -> Command {
+    Command::new("tool")
+        .arg(
+            Arg::new("mode")
+                .long("mode")
+                .help("Mode: fast or slow")
+                .value_parser(["fast", "slow"]),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::Count),
+        )
+        .arg(Arg::new("files").num_args(1..))
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script")
+                .arg(Arg::new("shell").value_parser(["bash", "zsh"])),
+        )
+        .subcommand(
+            Command::new("tangle")
+                .about("Write the outputs")
+                .arg(
+                    Arg::new("prune")
+                        .long("prune")
+                        .help("Delete orphans")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .help("Report format")
+                        .value_parser(["text", "json"]),
+                )
+                .arg(Arg::new("files").num_args(1..)),
+        )
+}
+
+#[test]
+fn test_bash_completes_options_values_and_subcommands() {
+    let script = generate(Shell::Bash, &command(), "tool");
+    assert!(script.contains("complete -F _tool -o nosort -o bashdefault -o default tool"));
+    assert!(script.contains("opts=\"-v -h --mode --verbose --help completions tangle help\""));
+    assert!(script.contains("--mode)\n                    COMPREPLY=($(compgen -W \"fast slow\""));
+    assert!(script.contains("opts=\"-h --help bash zsh\""));
+}
+
+#[test]
+fn test_subcommand_options_complete_after_the_subcommand() {
+    let bash = generate(Shell::Bash, &command(), "tool");
+    let tangle = &bash[bash.find("        tool__subcmd__tangle)").unwrap()..];
+    assert!(tangle.contains("opts=\"-h --prune --format --help\""));
+    assert!(tangle.contains("--format)\n                    COMPREPLY=($(compgen -W \"text json\""));
+
+    let fish = generate(Shell::Fish, &command(), "tool");
+    assert!(fish.contains(
+        "complete -c tool -n \"__fish_tool_using_subcommand tangle\" -l prune -d 'Delete orphans'"
+    ));
+    assert!(!fish.contains("__fish_tool_needs_command\" -l prune"));
+}
+
+#[test]
+fn test_other_shells_describe_the_same_options() {
+    let zsh = generate(Shell::Zsh, &command(), "tool");
+    assert!(zsh.starts_with("#compdef tool\n"));
+    assert!(zsh.contains("'--mode=[Mode\\: fast or slow]: :(fast slow)'"));
+    assert!(zsh.contains("'--format=[Report format]: :(text json)'"));
+    assert!(zsh.contains("'--prune[Delete orphans]'"));
+
+    let powershell = generate(Shell::PowerShell, &command(), "tool");
+    assert!(powershell.contains("-CommandName 'tool'"));
+    assert!(powershell.contains(
+        "[CompletionResult]::new('--prune', '--prune', [CompletionResultType]::ParameterName, 'Delete orphans')"
+    ));
+}
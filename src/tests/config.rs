@@ -0,0 +1,156 @@
+// src/tests/config.rs
+use crate::config::{Config, Profile};
+use std::path::PathBuf;
+
+#[test]
+fn test_parse_config_profiles() {
+    let text = "\
+# project settings
+strict_redefinitions = true
+
+[profiles.release]
+gen = \"dist\"
+files = [\"src/*\", 'Cargo.toml']
+
+[profiles.release.defines]
+MODE = \"release\"
+LEVEL = 3
+
+[profiles.docs]
+";
+    let config = Config::parse(text, "azadi.toml").unwrap();
+    assert_eq!(config.strict_redefinitions, Some(true));
+
+    let release = config.profile("release").unwrap();
+    assert_eq!(release.gen, Some(PathBuf::from("dist")));
+    assert_eq!(release.files, vec!["src/*", "Cargo.toml"]);
+    assert_eq!(release.defines["MODE"], "release");
+    assert_eq!(release.defines["LEVEL"], "3");
+    assert_eq!(config.profile("docs").unwrap(), &Profile::default());
+
+    let err = config.profile("dev").unwrap_err();
+    assert!(err.contains("defined: docs, release"), "{}", err);
+}
+
+#[test]
+fn test_parse_multiline_arrays_and_inline_tables() {
+    let text = "\
+[profiles.release]
+files = [
+    \"a.txt\",
+    \"src/*\",  # sources
+]
+defines = { MODE = \"release\", LEVEL = 3 }
+";
+    let config = Config::parse(text, "azadi.toml").unwrap();
+    let release = config.profile("release").unwrap();
+    assert_eq!(release.files, vec!["a.txt", "src/*"]);
+    assert_eq!(release.defines["MODE"], "release");
+    assert_eq!(release.defines["LEVEL"], "3");
+}
+
+#[test]
+fn test_config_errors_name_the_line() {
+    let err = Config::parse("[profiles.dev]\ngen = dist\n", "azadi.toml").unwrap_err();
+    assert_eq!(err.line, Some(2));
+    assert!(err.to_string().starts_with("azadi.toml line 2:"));
+
+    let err = Config::parse("[profiles.dev]\ngne = \"x\"\n", "azadi.toml").unwrap_err();
+    assert!(err.message.contains("profiles.dev.gne"), "{}", err.message);
+}
+
+#[test]
+fn test_parse_backup_settings() {
+    let text = "backup_dir = \"../backups\"\nno_backup = [\"*.log\", \"tmp/*\"]\n";
+    let config = Config::parse(text, "azadi.toml").unwrap();
+    assert_eq!(config.backup_dir, Some(PathBuf::from("../backups")));
+    assert_eq!(config.no_backup, vec!["*.log", "tmp/*"]);
+
+    let err = Config::parse("no_backup = \"*.log\"\n", "azadi.toml").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("'no_backup' must be an array of strings"));
+}
+
+#[test]
+fn test_parse_snapshots() {
+    let config = Config::parse("snapshots = 5\n", "azadi.toml").unwrap();
+    assert_eq!(config.snapshots, Some(5));
+    let err = Config::parse("snapshots = -1\n", "azadi.toml").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("'snapshots' must be a non-negative integer"));
+}
+
+#[test]
+fn test_parse_name_rules() {
+    let text = "[names]\nchars = \"a-z0-9_-\"\nmax_length = 40\nreserved_prefixes = [\"gen_\"]\n";
+    let config = Config::parse(text, "azadi.toml").unwrap();
+    assert_eq!(config.names.chars.as_deref(), Some("a-z0-9_-"));
+    assert_eq!(config.names.max_length, Some(40));
+    assert_eq!(config.names.reserved_prefixes, vec!["gen_"]);
+
+    let err = Config::parse("[names]\nmin_length = 2\n", "azadi.toml").unwrap_err();
+    assert!(err.to_string().contains("unknown key 'names.min_length'"));
+}
+
+#[test]
+fn test_parse_lint_settings() {
+    let text = "[lint]\nname_pattern = \"^[a-z-]+$\"\nmax_lines = 60\nduplicates = false\nextensionless_files = [\"Makefile\"]\n";
+    let config = Config::parse(text, "azadi.toml").unwrap();
+    assert_eq!(config.lint.name_pattern.as_deref(), Some("^[a-z-]+$"));
+    assert_eq!(config.lint.max_lines, Some(60));
+    assert_eq!(config.lint.duplicates, Some(false));
+    assert_eq!(config.lint.missing_end, None);
+    assert_eq!(config.lint.extensionless_files, vec!["Makefile"]);
+
+    let err = Config::parse("[lint]\nmax_lines = \"many\"\n", "azadi.toml").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("'lint.max_lines' must be a non-negative integer"));
+}
+
+#[test]
+fn test_parse_extra_delimiters() {
+    let config = Config::parse("extra_delimiters = [\"⟨⟨ ⟩⟩\"]\n", "azadi.toml").unwrap();
+    assert_eq!(
+        config.extra_delimiters,
+        vec![("⟨⟨".to_string(), "⟩⟩".to_string())]
+    );
+    let err = Config::parse("extra_delimiters = [\"⟨⟨\"]\n", "azadi.toml").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("is not an open and a close delimiter separated by a space"));
+}
+
+#[test]
+fn test_parse_marker_rules() {
+    let text = "auto_markers = true\nmarkers_for = [\"docs/*.tex=%\", \"src/*.nw=#,//\"]\n";
+    let config = Config::parse(text, "azadi.toml").unwrap();
+    assert_eq!(config.auto_markers, Some(true));
+    assert_eq!(
+        config.markers_for,
+        vec![
+            ("docs/*.tex".to_string(), vec!["%".to_string()]),
+            (
+                "src/*.nw".to_string(),
+                vec!["#".to_string(), "//".to_string()]
+            ),
+        ]
+    );
+    let err = Config::parse("markers_for = [\"docs\"]\n", "azadi.toml").unwrap_err();
+    assert!(err.to_string().contains("'docs' is not GLOB=MARKERS"));
+}
+
+#[test]
+fn test_parse_test_harness() {
+    let config = Config::parse("test_harness = \"tests/doc.rs\"\n", "azadi.toml").unwrap();
+    assert_eq!(
+        config.test_harness.as_ref().map(|h| h.path()),
+        Some("tests/doc.rs")
+    );
+    let err = Config::parse("test_harness = \"tests/doc.txt\"\n", "azadi.toml").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("no test harness template for 'tests/doc.txt'"));
+}
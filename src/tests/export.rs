@@ -0,0 +1,116 @@
+// src/tests/export.rs
+use super::*;
+use crate::export::export_noweb;
+use crate::migrate::migrate_noweb;
+use std::collections::HashMap;
+
+const DOC: &str = r#"
+# <<@doc intro>>=
+Greets the world.
+# @
+# <<@file hello.py>>=
+def main():
+    # <<greet>>
+    x = 1 @<<2>> 3
+# @
+  # <<greet>>=
+  print("hi")
+  @decorated
+# @
+"#;
+
+#[test]
+fn test_export_noweb() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(DOC, "doc.nw");
+    assert_eq!(
+        export_noweb(&setup.clip, &HashMap::new()).text,
+        "\
+@
+Greets the world.
+<<hello.py>>=
+def main():
+    <<greet>>
+    x = 1 @<<2>> 3
+@
+<<greet>>=
+print(\"hi\")
+@@decorated
+@
+"
+    );
+}
+
+#[test]
+fn test_exported_document_migrates_back() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(DOC, "doc.nw");
+    let expected = setup.clip.expand("@file hello.py", "").unwrap();
+
+    let exported = export_noweb(&setup.clip, &HashMap::new());
+    assert!(exported.notes.is_empty());
+    let migrated = migrate_noweb(&exported.text, "#", &["hello.py".to_string()]);
+    assert!(migrated.notes.is_empty());
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(&migrated.text, "doc.nw");
+    assert_eq!(setup.clip.expand("@file hello.py", "").unwrap(), expected);
+}
+
+#[test]
+fn test_export_follows_expansion_order() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@order 20 @append init>>=\nlate\n# @\n# <<@order 10 @append init>>=\nearly\n# @\n",
+        "doc.nw",
+    );
+    let expected = setup.clip.expand("init", "").unwrap();
+    let exported = export_noweb(&setup.clip, &HashMap::new()).text;
+    assert_eq!(exported, "<<init>>=\nearly\n@\n<<init>>=\nlate\n@\n");
+
+    let migrated = migrate_noweb(&exported, "#", &[]);
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(&migrated.text, "doc.nw");
+    assert_eq!(setup.clip.expand("init", "").unwrap(), expected);
+}
+
+#[test]
+fn test_export_substitutes_defines() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "@define VERSION 2.0\n# <<main>>=\nv = @{VERSION} @{USER} @{OTHER}\n# <<tail>>\n# @\n\
+         # <<tail>>=\nend\n# @\n",
+        "doc.nw",
+    );
+    let defines = HashMap::from([("USER".to_string(), "ann".to_string())]);
+    assert_eq!(
+        export_noweb(&setup.clip, &defines).text,
+        "<<main>>=\nv = 2.0 ann @{OTHER}\n<<tail>>\n@\n<<tail>>=\nend\n@\n"
+    );
+}
+
+#[test]
+fn test_export_reports_insert_and_exec() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<main>>=\nbegin\n# <<@insert table.txt>>\n# @\n# <<@exec date>>=\ndate\n# @\n",
+        "doc.nw",
+    );
+    let exported = export_noweb(&setup.clip, &HashMap::new());
+    assert!(exported.text.contains("\n# @<<@insert table.txt>>\n"));
+    let notes: Vec<_> = exported
+        .notes
+        .iter()
+        .map(|(location, note)| (location.file_name.as_str(), location.line, note.as_str()))
+        .collect();
+    assert_eq!(
+        notes,
+        [
+            (
+                "doc.nw",
+                2,
+                "@insert in 'main' has no noweb equivalent and is exported as text"
+            ),
+            ("doc.nw", 4, "@exec chunk 'date' is exported as its command"),
+        ]
+    );
+}
@@ -0,0 +1,58 @@
+// src/tests/filter.rs
+use super::*;
+use crate::AzadiError;
+use std::fs;
+
+#[test]
+fn test_filters_transform_outputs_in_order() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@file app.py>>=\nrun()\ndebug_dump()\ndone()\n# @\n",
+        "app.nw",
+    );
+    setup.clip.add_line_filter(|_path: &str, line: &str| {
+        (!line.starts_with("debug_")).then(|| line.to_string())
+    });
+    setup.clip.add_filter(|path: &str, mut lines: Vec<String>| {
+        lines.insert(0, format!("# generated: {}\n", path));
+        lines
+    });
+    setup.clip.write_files()?;
+
+    assert_eq!(
+        fs::read_to_string(setup.clip.gen_base().join("app.py"))?,
+        "# generated: app.py\nrun()\ndone()\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_language_filters_dispatch_on_declared_language() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@file a.py>>= python\nx = 1\n# @\n# <<@file b.sh>>=\n# <<body>>\n# @\n# <<@lang(sh) body>>=\necho hi\n# @\n",
+        "mixed.nw",
+    );
+    assert_eq!(
+        setup.clip.language_of("@file a.py").as_deref(),
+        Some("python")
+    );
+    assert_eq!(setup.clip.language_of("@file b.sh"), None);
+    assert_eq!(
+        setup.clip.definitions_of("body")[0].lang.as_deref(),
+        Some("sh")
+    );
+
+    setup
+        .clip
+        .add_language_filter("Python", |_path: &str, mut lines: Vec<String>| {
+            lines.insert(0, "# python\n".to_string());
+            lines
+        });
+    setup.clip.write_files()?;
+
+    let gen = setup.clip.gen_base();
+    assert_eq!(fs::read_to_string(gen.join("a.py"))?, "# python\nx = 1\n");
+    assert_eq!(fs::read_to_string(gen.join("b.sh"))?, "echo hi\n");
+    Ok(())
+}
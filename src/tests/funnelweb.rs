@@ -0,0 +1,38 @@
+// src/tests/funnelweb.rs
+use super::*;
+use crate::{AzadiError, Dialect};
+
+const FUNNELWEB_DOC: &str = r#"
+@A@<Program@>
+@O@<hello.c@>==@{@-
+#include <stdio.h>
+int main(void) {
+    @<Print greeting@>
+}
+@}
+
+@$@<Print greeting@>@M==@{printf("hi@@home\n");@}
+@$@<Print greeting@>+=@{
+printf("bye\n");
+@}
+"#;
+
+#[test]
+fn test_funnelweb_macros() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["//"]);
+    setup.clip.set_dialect(Dialect::Funnelweb);
+    setup.clip.read(FUNNELWEB_DOC, "prog.fw");
+
+    assert!(setup.clip.has_chunk("Print-greeting"));
+    assert_eq!(
+        setup.clip.expand("@file hello.c", "")?,
+        vec![
+            "#include <stdio.h>\n",
+            "int main(void) {\n",
+            "    printf(\"hi@home\\n\");\n",
+            "    printf(\"bye\\n\");\n",
+            "}\n"
+        ]
+    );
+    Ok(())
+}
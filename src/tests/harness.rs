@@ -0,0 +1,69 @@
+// src/tests/harness.rs
+use super::*;
+use crate::harness::{test_name, TestHarness};
+
+#[test]
+fn test_harness_templates() {
+    assert_eq!(test_name("parse-empty"), "parse_empty");
+    assert_eq!(test_name("2d::rotate"), "_2d__rotate");
+    assert!(TestHarness::new("tests/examples.txt")
+        .unwrap_err()
+        .contains("no test harness template"));
+
+    let harness = TestHarness::new("test_examples.py").unwrap();
+    assert_eq!(
+        harness.lines(&["adds".to_string(), "splits".to_string()], "<<", ">>"),
+        vec![
+            "def test_adds():\n",
+            "    <<adds>>\n",
+            "\n",
+            "def test_splits():\n",
+            "    <<splits>>\n",
+        ]
+    );
+}
+
+#[test]
+fn test_test_chunks_are_collected_into_harness() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup
+        .clip
+        .set_test_harness(Some(TestHarness::new("tests/examples.rs").unwrap()));
+    setup.clip.read(
+        "# <<@test sums-match>>=\nassert_eq!(1 + 1, 2);\n# @\n# <<helper>>=\nlet x = 1;\n# @\n",
+        "a.nw",
+    );
+    setup.clip.read(
+        "# <<@test uses-helper>>=\n# <<helper>>\nassert_eq!(x, 1);\n# @\n",
+        "b.nw",
+    );
+
+    assert_eq!(
+        setup.clip.expand("@file tests/examples.rs", "").unwrap(),
+        vec![
+            "#[test]\n",
+            "fn sums_match() {\n",
+            "    assert_eq!(1 + 1, 2);\n",
+            "}\n",
+            "\n",
+            "#[test]\n",
+            "fn uses_helper() {\n",
+            "    let x = 1;\n",
+            "    assert_eq!(x, 1);\n",
+            "}\n",
+        ]
+    );
+    assert!(setup
+        .clip
+        .get_file_chunks()
+        .contains(&"@file tests/examples.rs".to_string()));
+
+    // A harness path the inputs define wins over the generated one
+    setup
+        .clip
+        .read("# <<@file tests/examples.rs>>=\nmanual\n# @\n", "c.nw");
+    assert_eq!(
+        setup.clip.expand("@file tests/examples.rs", "").unwrap(),
+        vec!["manual\n"]
+    );
+}
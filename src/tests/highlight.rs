@@ -0,0 +1,70 @@
+// src/tests/highlight.rs
+use crate::highlight::{Highlighter, Language, Theme};
+
+#[test]
+fn test_highlight_rust_tokens() {
+    let rust = Language::by_name("RS").unwrap();
+    assert_eq!(rust.name, "rust");
+    let mut highlighter = Highlighter::new(rust);
+    let line = highlighter.line("let x2 = 42; /* open");
+    assert!(line.starts_with("<span class=\"hl-source hl-rust\"><span class=\"hl-storage hl-type hl-rust\">let</span> x2 "), "{}", line);
+    assert!(
+        line.contains(
+            "<span class=\"hl-constant hl-numeric hl-integer hl-decimal hl-rust\">42</span>"
+        ),
+        "{}",
+        line
+    );
+    assert!(line.ends_with("open</span></span>"), "{}", line);
+
+    // The block comment carries over, its span reopened on the next line
+    let line = highlighter.line("still */ if");
+    assert!(
+        line.starts_with(
+            "<span class=\"hl-source hl-rust\"><span class=\"hl-comment hl-block hl-rust\">still "
+        ),
+        "{}",
+        line
+    );
+    assert!(
+        line.ends_with("<span class=\"hl-keyword hl-control hl-rust\">if</span></span>"),
+        "{}",
+        line
+    );
+}
+
+#[test]
+fn test_highlight_escapes_html() {
+    let mut highlighter = Highlighter::new(Language::by_name("rust").unwrap());
+    let line = highlighter.line("a < b & c // \"done\"");
+    assert!(line.contains("&lt;"), "{}", line);
+    assert!(line.contains("&amp;"), "{}", line);
+    assert!(line.contains("&quot;done&quot;"), "{}", line);
+}
+
+#[test]
+fn test_language_by_name_and_path() {
+    assert_eq!(Language::by_path("src/app.py").unwrap().name, "python");
+    assert_eq!(Language::by_path("include/x.HPP").unwrap().name, "c++");
+    assert!(Language::by_path("Makefile").is_none());
+    assert_eq!(Language::by_name("shell").unwrap().name, "sh");
+    assert_eq!(Language::by_name("golang").unwrap().name, "go");
+    assert!(Language::by_name("no-such-language").is_none());
+
+    let mut sql = Highlighter::new(Language::by_name("sql").unwrap());
+    let line = sql.line("SELECT 'a' -- all");
+    assert!(line.contains(">SELECT</span>"), "{}", line);
+    assert!(
+        line.contains("<span class=\"hl-comment hl-line hl-double-dash hl-sql\">"),
+        "{}",
+        line
+    );
+}
+
+#[test]
+fn test_theme_css() {
+    let dark = Theme::Dark.css();
+    assert!(dark.starts_with("pre { background: #2b303b;"), "{}", dark);
+    assert!(dark.contains(".hl-comment"));
+    assert!(Theme::Light.css().starts_with("pre { background: #ffffff;"));
+}
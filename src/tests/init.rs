@@ -0,0 +1,45 @@
+// src/tests/init.rs
+use super::*;
+use crate::init::{scaffold, Scaffolded, STARTER_DOCUMENT};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+#[test]
+fn test_scaffold_creates_and_keeps() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path().join("project");
+    let done = scaffold(&dir, Path::new("gen"), Path::new("_azadi_work")).unwrap();
+    assert_eq!(
+        done,
+        vec![
+            Scaffolded::Created(dir.join("azadi.toml")),
+            Scaffolded::Created(dir.join(STARTER_DOCUMENT)),
+            Scaffolded::Created(dir.join("gen")),
+            Scaffolded::Created(dir.join("_azadi_work")),
+        ]
+    );
+    assert!(crate::config::Config::load(&dir.join("azadi.toml")).is_ok());
+
+    fs::write(dir.join(STARTER_DOCUMENT), "mine\n").unwrap();
+    let done = scaffold(&dir, Path::new("gen"), Path::new("_azadi_work")).unwrap();
+    assert!(done.iter().all(|step| matches!(step, Scaffolded::Kept(_))));
+    assert_eq!(
+        fs::read_to_string(dir.join(STARTER_DOCUMENT)).unwrap(),
+        "mine\n"
+    );
+}
+
+#[test]
+fn test_starter_document_tangles() {
+    let temp = TempDir::new().unwrap();
+    scaffold(temp.path(), Path::new("gen"), Path::new("_azadi_work")).unwrap();
+    let text = fs::read_to_string(temp.path().join(STARTER_DOCUMENT)).unwrap();
+
+    let mut setup = TestSetup::new(&["#", "//"]);
+    setup.clip.read(&text, STARTER_DOCUMENT);
+    let lines = setup.clip.expand("@file hello.py", "").unwrap();
+    assert_eq!(lines[0], "import sys\n");
+    assert!(lines.contains(&"    print(f\"Hello, {name}!\")\n".to_string()));
+    assert!(setup.clip.check_unused_chunks().is_empty());
+}
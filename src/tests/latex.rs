@@ -0,0 +1,70 @@
+// src/tests/latex.rs
+use super::*;
+use crate::latex::{typeset_pdf, weave_latex};
+use std::fs;
+
+#[test]
+fn test_weave_latex_links_chunks() {
+    let mut setup = TestSetup::new(&["#"]);
+    let text = "Costs 5% & more.\n\n# <<main>>=\n# <<helper>>\n# @\n# <<helper>>=\nif {x} { y }\n# @\n# <<helper>>=\nmore\n# @\n";
+    setup.clip.read(text, "doc.nw");
+
+    let tex = weave_latex(
+        &setup.clip,
+        "My_Doc",
+        &[("doc.nw".to_string(), text.to_string())],
+    );
+    assert!(tex.starts_with("\\documentclass{article}\n"));
+    assert!(tex.contains("\\title{My\\_Doc}"));
+    assert!(tex.contains("\\section*{doc.nw}"));
+    assert!(tex.contains("Costs 5\\% \\& more.\n\n"));
+    assert!(tex.contains(
+        "\\hypertarget{section-2}{}\\hypertarget{chunk-helper}{}\\noindent\\texttt{\\textless{}\\textless{}helper\\textgreater{}\\textgreater{}=}\n\\begin{alltt}\nif \\{x\\} \\{ y \\}\n\\end{alltt}\n"
+    ));
+    assert!(tex.contains("# \\hyperlink{chunk-helper}{<<helper>>}\n"));
+    assert!(tex.contains("\\hyperlink{chunk-helper}{\\texttt{\\textless{}\\textless{}helper\\textgreater{}\\textgreater{}}}\\texttt{+=}"));
+    assert!(tex.contains(
+        "Section 3: defined in sections \\hyperlink{section-2}{2}, \\hyperlink{section-3}{3}; used in section \\hyperlink{section-1}{1}."
+    ));
+    assert!(tex.contains("\\section*{Chunk index}"));
+    assert!(tex.ends_with("\\end{document}\n"));
+}
+
+#[cfg(unix)]
+fn fake_engine(dir: &std::path::Path, script: &str) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let path = dir.join("engine.sh");
+    fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_typeset_pdf_runs_engine() {
+    let dir = tempfile::tempdir().unwrap();
+    let engine = fake_engine(dir.path(), "cp weave.tex weave.pdf\n");
+    let pdf = typeset_pdf("\\relax\n", &engine, &dir.path().join("work")).unwrap();
+    assert_eq!(pdf, b"\\relax\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_typeset_pdf_reports_engine_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let engine = fake_engine(
+        dir.path(),
+        "printf '! Undefined control sequence.\\nl.12 \\\\foo\\n' > weave.log\nexit 1\n",
+    );
+    let err = typeset_pdf("\\foo\n", &engine, &dir.path().join("work")).unwrap_err();
+    assert_eq!(err.code(), crate::diagnostic::Code::TypesetFailed);
+    let message = err.to_string();
+    assert!(
+        message.contains("! Undefined control sequence.\nl.12 \\foo"),
+        "{}",
+        message
+    );
+
+    let missing = typeset_pdf("", "no-such-latex-engine", &dir.path().join("work")).unwrap_err();
+    assert!(missing.to_string().contains("cannot run it"));
+}
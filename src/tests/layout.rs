@@ -0,0 +1,115 @@
+// src/tests/layout.rs
+use super::*;
+use crate::layout::format_document;
+
+const MESSY: &str = "
+
+Intro.
+
+
+
+#   <<@file   out.py  mode=755   eol=lf>>=   python
+#  <<b>>
+    x = 1
+
+
+# @
+Between.
+#<<b>>=
+print(\"b\")
+#<<a>>=
+print(\"a\")
+@
+  <<c>>=
+  c = 3
+";
+
+const TIDY: &str = "\
+Intro.
+
+# <<@file out.py eol=lf mode=755>>= python
+#  <<b>>
+    x = 1
+
+
+# @
+
+Between.
+
+# <<b>>=
+print(\"b\")
+# @
+
+# <<a>>=
+print(\"a\")
+# @
+
+  <<c>>=
+  c = 3
+  @
+";
+
+#[test]
+fn test_format_document() {
+    let setup = TestSetup::new(&["#"]);
+    let formatted = format_document(&setup.clip, "doc.nw", MESSY, false);
+    assert_eq!(formatted, TIDY);
+    assert_eq!(
+        format_document(&setup.clip, "doc.nw", &formatted, false),
+        formatted
+    );
+}
+
+#[test]
+fn test_format_document_sorted() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(MESSY, "doc.nw");
+    let expected = setup.clip.expand("@file out.py", "").unwrap();
+
+    let sorted = format_document(&setup.clip, "doc.nw", MESSY, true);
+    assert!(sorted.starts_with("Intro.\n\n# <<@file out.py"));
+    assert!(sorted.find("<<a>>=").unwrap() < sorted.find("<<b>>=").unwrap());
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(&sorted, "doc.nw");
+    assert_eq!(setup.clip.expand("@file out.py", "").unwrap(), expected);
+}
+
+#[test]
+fn test_format_document_keeps_line_endings() {
+    let setup = TestSetup::new(&["#"]);
+    let crlf = TIDY.replace('\n', "\r\n");
+    assert_eq!(format_document(&setup.clip, "doc.nw", &crlf, false), crlf);
+    assert_eq!(
+        format_document(&setup.clip, "doc.nw", &MESSY.replace('\n', "\r\n"), false),
+        crlf
+    );
+
+    let unterminated = TIDY.trim_end_matches('\n');
+    assert_eq!(
+        format_document(&setup.clip, "doc.nw", unterminated, false),
+        unterminated
+    );
+    let unterminated = crlf.trim_end_matches("\r\n");
+    assert_eq!(
+        format_document(&setup.clip, "doc.nw", unterminated, false),
+        unterminated
+    );
+}
+
+#[test]
+fn test_format_document_keeps_quotes_readable() {
+    let setup = TestSetup::new(&["#"]);
+    let formatted = format_document(
+        &setup.clip,
+        "doc.nw",
+        "# <<@file a.py  empty=\"\"   title=\"a b\">>=\nx\n# @\n",
+        false,
+    );
+    assert_eq!(
+        formatted,
+        "# <<@file a.py empty=\"\" title=\"a b\">>=\nx\n# @\n"
+    );
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(&formatted, "doc.nw");
+    assert_eq!(setup.clip.expand("@file a.py", "").unwrap(), vec!["x\n"]);
+}
@@ -0,0 +1,86 @@
+// src/tests/lint.rs
+use super::*;
+use crate::lint::{lint, LintRules};
+
+const DOC: &str = "\
+# <<@file Makefile>>=
+all:
+# @
+# <<@file bin/run>>=
+# <<Setup>>
+# @
+# <<Setup>>=
+a = 1
+b = 2
+c = 3
+# @
+# <<Setup>>=
+a = 1
+b = 2
+c = 3
+# <<tail>>=
+done
+";
+
+fn lint_doc(rules: &LintRules) -> Vec<String> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(DOC, "doc.nw");
+    lint(
+        &setup.clip,
+        &[("doc.nw".to_string(), DOC.to_string())],
+        rules,
+    )
+    .iter()
+    .map(|finding| finding.code.warning(finding))
+    .collect()
+}
+
+#[test]
+fn test_lint_rules() {
+    let rules = LintRules::default()
+        .name_pattern("^[a-z]+$")
+        .unwrap()
+        .max_lines(2)
+        .allow_extensionless("Makefile");
+    assert_eq!(
+        lint_doc(&rules),
+        vec![
+            "Warning[AZ0113]: doc.nw line 4: file chunk 'bin/run' has no extension",
+            "Warning[AZ0109]: doc.nw line 7: chunk name 'Setup' does not match '^[a-z]+$'",
+            "Warning[AZ0112]: doc.nw line 7: definition of 'Setup' has 3 lines, more than 2",
+            "Warning[AZ0112]: doc.nw line 12: definition of 'Setup' has 3 lines, more than 2",
+            "Warning[AZ0111]: doc.nw line 12: definition of 'Setup' repeats the one at doc.nw line 7",
+            "Warning[AZ0110]: doc.nw line 12: chunk 'Setup' has no end line before the next chunk",
+            "Warning[AZ0110]: doc.nw line 16: chunk 'tail' has no end line before the end of the file",
+        ]
+    );
+}
+
+#[test]
+fn test_lint_rules_switched_off() {
+    let rules = LintRules::default()
+        .max_lines(0)
+        .missing_end(false)
+        .duplicates(false)
+        .file_extensions(false);
+    assert!(lint_doc(&rules).is_empty());
+}
+
+#[test]
+fn test_name_pattern_ignores_namespaces() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_namespaced(true);
+    setup.clip.read(
+        "# <<setup>>=\na = 1\n# @\n# <<Tail>>=\nb\n# @\n",
+        "dir/doc.nw",
+    );
+    let rules = LintRules::default().name_pattern("^[a-z]+$").unwrap();
+    let findings: Vec<String> = lint(&setup.clip, &[], &rules)
+        .iter()
+        .map(|finding| finding.to_string())
+        .collect();
+    assert_eq!(
+        findings,
+        vec!["dir/doc.nw line 4: chunk name 'doc.nw::Tail' does not match '^[a-z]+$'"]
+    );
+}
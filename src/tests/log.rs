@@ -0,0 +1,78 @@
+// src/tests/log.rs
+use crate::log;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Collects what the `log::layer` prints.
+#[derive(Clone, Default)]
+pub(crate) struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Captured {
+    pub(crate) fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `f` with events up to `filter` printed into the returned capture.
+pub(crate) fn capture<T>(filter: LevelFilter, f: impl FnOnce() -> T) -> (T, Captured) {
+    let captured = Captured::default();
+    let sink = captured.clone();
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(log::layer(move || sink.clone()));
+    let result = tracing::subscriber::with_default(subscriber, f);
+    (result, captured)
+}
+
+#[test]
+fn test_filter_for_flags() {
+    assert_eq!(log::filter_for(true, 2), LevelFilter::WARN);
+    assert_eq!(log::filter_for(false, 0), LevelFilter::INFO);
+    assert_eq!(log::filter_for(false, 1), LevelFilter::DEBUG);
+    assert_eq!(log::filter_for(false, 2), LevelFilter::TRACE);
+}
+
+#[test]
+fn test_layer_prefixes_debug_and_trace() {
+    let ((), captured) = capture(LevelFilter::DEBUG, || {
+        tracing::warn!("a warning");
+        tracing::info!(file = "a.txt", "a notice");
+        tracing::debug!("a decision");
+        tracing::trace!("an expansion");
+    });
+    assert_eq!(
+        captured.text(),
+        "a warning\na notice file=\"a.txt\"\ndebug: a decision\n"
+    );
+}
+
+/// Logs while being formatted.
+struct Noisy;
+
+impl fmt::Display for Noisy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        tracing::error!("formatting a logged value");
+        write!(f, "noisy")
+    }
+}
+
+#[test]
+fn test_logging_while_formatting_does_not_deadlock() {
+    let ((), captured) = capture(LevelFilter::INFO, || {
+        tracing::error!("value: {}", Noisy);
+    });
+    assert!(captured.text().contains("value: noisy\n"));
+}
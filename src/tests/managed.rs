@@ -0,0 +1,53 @@
+// src/tests/managed.rs
+use super::*;
+use crate::{AzadiError, ChunkError};
+use std::fs;
+
+const SOURCE: &str = "\
+# <<@managed @file app.rs>>=
+fn main() {
+    // AZADI BEGIN body
+    // AZADI END
+}
+# @
+# <<body>>=
+println!(\"generated\");
+# @
+";
+
+#[test]
+fn test_managed_regions_keep_hand_written_code() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(SOURCE, "app.nw");
+    setup.clip.write_files()?;
+
+    let output = setup.clip.gen_base().join("app.rs");
+    assert_eq!(
+        fs::read_to_string(&output)?,
+        "fn main() {\n    // AZADI BEGIN body\n    println!(\"generated\");\n    // AZADI END\n}\n"
+    );
+
+    fs::write(
+        &output,
+        "// hand-written\nfn main() {\n    // AZADI BEGIN body\n    stale();\n    // AZADI END\n    helper();\n}\n",
+    )?;
+    setup.clip.write_files()?;
+    assert_eq!(
+        fs::read_to_string(&output)?,
+        "// hand-written\nfn main() {\n    // AZADI BEGIN body\n    println!(\"generated\");\n    // AZADI END\n    helper();\n}\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_managed_region_without_end() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@managed @file broken.rs>>=\n// AZADI BEGIN body\n# @\n# <<body>>=\nx\n# @\n",
+        "broken.nw",
+    );
+    assert!(matches!(
+        setup.clip.write_files(),
+        Err(AzadiError::Chunk(ChunkError::ParseError { .. }))
+    ));
+}
@@ -0,0 +1,98 @@
+// src/tests/markdown.rs
+use super::*;
+use crate::{AzadiError, Dialect};
+
+const ENTANGLED_DOC: &str = r#"
+# Example
+
+```{.python #greet}
+print("hello")
+```
+
+```{.python file=app.py}
+def main():
+    <<greet>>
+```
+
+```python
+# not a chunk
+```
+"#;
+
+const LMT_DOC: &str = r#"
+```go "Main body"
+fmt.Println("hi")
+```
+
+```go main.go
+func main() {
+	<<<Main body>>>
+}
+```
+"#;
+
+#[test]
+fn test_entangled_blocks() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_dialect(Dialect::Entangled);
+    setup.clip.read(ENTANGLED_DOC, "doc.md");
+
+    assert!(setup.clip.has_chunk("greet"));
+    assert_eq!(
+        setup.clip.expand("@file app.py", "")?,
+        vec!["def main():\n", "    print(\"hello\")\n"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_entangled_name_and_file() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_dialect(Dialect::Entangled);
+    setup.clip.read(
+        "```{.rust #lib file=src/lib.rs}\npub fn f() {}\n```\n",
+        "doc.md",
+    );
+
+    assert_eq!(setup.clip.expand("lib", "")?, vec!["pub fn f() {}\n"]);
+    assert_eq!(
+        setup.clip.expand("@file src/lib.rs", "")?,
+        vec!["pub fn f() {}\n"]
+    );
+    assert_eq!(setup.clip.language_of("lib").as_deref(), Some("rust"));
+    assert_eq!(
+        setup.clip.language_of("@file src/lib.rs").as_deref(),
+        Some("rust")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_lmt_blocks() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["//"]);
+    setup.clip.set_dialect(Dialect::Entangled);
+    setup.clip.read(LMT_DOC, "doc.md");
+
+    assert!(setup.clip.has_chunk("Main-body"));
+    assert_eq!(setup.clip.language_of("Main-body").as_deref(), Some("go"));
+    assert_eq!(
+        setup.clip.expand("@file main.go", "")?,
+        vec!["func main() {\n", "\tfmt.Println(\"hi\")\n", "}\n"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_entangled_repeated_blocks_continue_the_chunk() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_dialect(Dialect::Entangled);
+    setup.clip.set_strict_redefinitions(true);
+    setup.clip.read(
+        "```{.python #body}\na = 1\n```\n\n```{.python #body}\nb = 2\n```\n",
+        "doc.md",
+    );
+
+    assert!(setup.clip.check_redefinitions()?.is_empty());
+    assert_eq!(setup.clip.expand("body", "")?, vec!["a = 1\n", "b = 2\n"]);
+    Ok(())
+}
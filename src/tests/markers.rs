@@ -0,0 +1,79 @@
+// src/tests/markers.rs
+use super::*;
+use crate::markers::{extension_markers, marker_override, MarkerRules};
+
+#[test]
+fn test_extension_markers() {
+    assert_eq!(extension_markers("src/lib.py"), Some(vec!["#".to_string()]));
+    assert_eq!(extension_markers("main.RS"), Some(vec!["//".to_string()]));
+    assert_eq!(extension_markers("book.tex"), Some(vec!["%".to_string()]));
+    assert_eq!(extension_markers("notes.nw"), None);
+    assert_eq!(extension_markers("Makefile"), None);
+}
+
+#[test]
+fn test_auto_detected_markers_per_input() {
+    let mut setup = TestSetup::new(&["%"]);
+    setup
+        .clip
+        .set_marker_rules(MarkerRules::default().auto_detect(true))
+        .unwrap();
+    setup.clip.read(
+        "# <<main>>=\n# <<helper>>\n# @\n// <<ignored>>=\nx\n// @\n",
+        "tool.py",
+    );
+    setup
+        .clip
+        .read("// <<helper>>=\nfn helper() {}\n// @\n", "helper.rs");
+    setup.clip.read("% <<doc>>=\ntext\n% @\n", "notes.nw");
+
+    assert_eq!(
+        setup.clip.expand("main", "").unwrap(),
+        vec!["fn helper() {}\n"]
+    );
+    assert!(!setup.clip.has_chunk("ignored"));
+    assert!(setup.clip.has_chunk("doc"));
+    assert_eq!(
+        setup.clip.references(),
+        vec![("main".to_string(), "helper".to_string())]
+    );
+}
+
+#[test]
+fn test_marker_overrides() {
+    assert_eq!(
+        marker_override("docs/*.tex=%").unwrap(),
+        ("docs/*.tex".to_string(), vec!["%".to_string()])
+    );
+    assert_eq!(
+        marker_override("src/*.nw=#, //").unwrap(),
+        (
+            "src/*.nw".to_string(),
+            vec!["#".to_string(), "//".to_string()]
+        )
+    );
+    assert_eq!(marker_override("plain/*=").unwrap().1, Vec::<String>::new());
+    assert!(marker_override("docs/*.tex").is_err());
+    assert!(marker_override("=%").is_err());
+
+    let rules = MarkerRules::default()
+        .auto_detect(true)
+        .override_for("docs/*", &["%"])
+        .override_for("docs/*.py", &[";"]);
+    assert_eq!(
+        rules.markers_for("./docs/a.py"),
+        Some(vec![";".to_string()])
+    );
+    assert_eq!(rules.markers_for("docs/b.nw"), Some(vec!["%".to_string()]));
+    assert_eq!(rules.markers_for("src/c.py"), Some(vec!["#".to_string()]));
+    assert_eq!(rules.markers_for("src/d.nw"), None);
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup
+        .clip
+        .set_marker_rules(MarkerRules::default().override_for("docs/*", &["%"]))
+        .unwrap();
+    setup.clip.read("% <<doc>>=\nfrom docs\n% @\n", "docs/a.nw");
+    setup.clip.read("# <<src>>=\n# <<doc>>\n# @\n", "src/b.nw");
+    assert_eq!(setup.clip.expand("src", "").unwrap(), vec!["from docs\n"]);
+}
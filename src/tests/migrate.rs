@@ -0,0 +1,147 @@
+// src/tests/migrate.rs
+use super::*;
+use crate::migrate::{migrate_funnelweb, migrate_noweb, migrate_nuweb};
+
+const CLASSIC: &str = "\
+A classic noweb document.
+<<hello.py>>=
+def main():
+    <<print the greeting>>
+main()
+@ %def main
+<<print the greeting>>=
+print(\"Hello\" + <<suffix>>)
+@ The suffix comes later.
+
+@
+More prose.
+";
+
+#[test]
+fn test_migrate_noweb_rewrites_chunks() {
+    let migrated = migrate_noweb(CLASSIC, "#", &["hello.py".to_string()]);
+    assert_eq!(
+        migrated.text,
+        "\
+A classic noweb document.
+# <<@file hello.py>>=
+def main():
+    # <<print-the-greeting>>
+main()
+# @
+# <<print-the-greeting>>=
+print(\"Hello\" + <<suffix>>)
+# @
+The suffix comes later.
+
+
+More prose.
+"
+    );
+    assert_eq!(migrated.notes.len(), 1);
+    assert_eq!(migrated.notes[0].0, 8);
+    assert!(migrated.notes[0].1.contains("<<suffix>>"));
+}
+
+#[test]
+fn test_migrate_noweb_notes_roots_without_files() {
+    let migrated = migrate_noweb(CLASSIC, "#", &[]);
+    assert!(migrated.text.contains("# <<hello.py>>=\n"));
+    assert_eq!(
+        migrated.notes[0],
+        (
+            2,
+            "root chunk 'hello.py' is written to no file; pass --file hello.py to make it \
+             a @file chunk"
+                .to_string()
+        )
+    );
+    assert!(!migrated
+        .notes
+        .iter()
+        .any(|(_, note)| note.contains("'print the greeting'")));
+    assert!(migrate_noweb(CLASSIC, "#", &["hello.py".to_string()])
+        .notes
+        .iter()
+        .all(|(_, note)| !note.starts_with("root chunk")));
+}
+
+#[test]
+fn test_migrated_document_tangles() {
+    let source = "<<out.py>>=\nx = 1\n<<rest>>\n<<rest>>=\ny = 2\n";
+    let migrated = migrate_noweb(source, "#", &["out.py".to_string()]);
+    assert!(migrated.notes.is_empty());
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(&migrated.text, "doc.nw");
+    assert_eq!(
+        setup.clip.expand("@file out.py", "").unwrap(),
+        vec!["x = 1\n", "y = 2\n"]
+    );
+}
+
+#[test]
+fn test_migrate_noweb_undoubles_at() {
+    let migrated = migrate_noweb(
+        "<<a.py>>=\n@@decorated\n@@\n@\n",
+        "#",
+        &["a.py".to_string()],
+    );
+    assert_eq!(migrated.text, "# <<@file a.py>>=\n@decorated\n@\n# @\n");
+    assert_eq!(
+        migrated.notes,
+        vec![(3, "this body line reads as a chunk end".to_string())]
+    );
+}
+
+#[test]
+fn test_migrate_nuweb_scraps() {
+    let source = "\\section{Program}
+@o hello.c -d
+@{int main(void) {
+    @<Print greeting@>
+    return f(@<Code@>);
+}
+@}
+@d Print greeting @{printf(\"hi@@home\\n\");@}
+";
+    let migrated = migrate_nuweb(source, "//");
+    assert_eq!(
+        migrated.text,
+        "\\section{Program}
+// <<@file hello.c>>=
+int main(void) {
+    // <<Print-greeting>>
+    return f(@<Code@>);
+}
+// @
+// <<Print-greeting>>=
+printf(\"hi@home\\n\");
+// @
+"
+    );
+    assert_eq!(migrated.notes.len(), 1);
+    assert_eq!(migrated.notes[0].0, 5);
+}
+
+#[test]
+fn test_migrated_funnelweb_tangles() {
+    let source = "\
+@O@<out.sh@>==@{@-
+@<Body@>
+@}
+@$@<Body@>+=@{echo a@}
+@$@<Body@>+=@{
+<<not a reference>>
+@}
+";
+    let migrated = migrate_funnelweb(source, "#");
+    assert!(migrated.notes.is_empty());
+
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(&migrated.text, "doc.nw");
+    assert_eq!(
+        setup.clip.expand("@file out.sh", "").unwrap(),
+        vec!["echo a\n", "<<not a reference>>\n"]
+    );
+}
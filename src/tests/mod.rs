@@ -3,6 +3,33 @@ mod common;
 mod basic;
 mod advanced;
 mod safe_writer;
+mod notebook;
+mod markdown;
+mod rst;
+mod untangle;
+mod nuweb;
+mod managed;
+mod filter;
+mod config;
+mod build;
+mod report;
+mod completions;
+mod weave;
+mod highlight;
+mod serve;
+mod stats;
+mod latex;
+mod names;
+mod markers;
+mod attributes;
+mod log;
+mod harness;
+mod init;
+mod migrate;
+mod funnelweb;
+mod export;
+mod layout;
+mod lint;
 
 pub(crate) use common::*;
 pub(crate) use utils::*;
@@ -0,0 +1,52 @@
+// src/tests/names.rs
+use super::*;
+use crate::diagnostic::Code;
+use crate::names::NamePolicy;
+use crate::AzadiError;
+
+#[test]
+fn test_name_policy_checks() {
+    let policy = NamePolicy::default()
+        .allowed_chars("a-z0-9_")
+        .unwrap()
+        .max_length(8)
+        .reserved_prefix("gen_");
+
+    assert!(policy.check("parse_io").is_ok());
+    assert_eq!(
+        policy.check("Parse").unwrap_err(),
+        "contains 'P', which is not in [a-z0-9_]"
+    );
+    assert_eq!(
+        policy.check("much_too_long").unwrap_err(),
+        "is longer than 8 characters"
+    );
+    assert_eq!(
+        policy.check("gen_main").unwrap_err(),
+        "starts with the reserved prefix 'gen_'"
+    );
+    assert!(NamePolicy::default().check("Anything-Goes").is_ok());
+    assert!(NamePolicy::default().allowed_chars("a-").is_ok());
+    assert!(NamePolicy::default().allowed_chars("z-a").is_err());
+}
+
+#[test]
+fn test_clip_rejects_names_breaking_the_policy() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup
+        .clip
+        .set_name_policy(NamePolicy::default().allowed_chars("a-z_").unwrap());
+    setup.clip.read(
+        "# <<@file Out.txt>>=\n# <<body>>\n# @\n# <<body>>=\nok\n# @\n# <<Body2>>=\nno\n# @\n",
+        "names.nw",
+    );
+
+    assert!(setup.clip.has_chunk("body"));
+    assert!(!setup.clip.has_chunk("Body2"));
+    let err = setup.clip.write_files().unwrap_err();
+    assert!(matches!(err, AzadiError::Chunk(_)));
+    assert_eq!(err.code(), Code::InvalidChunkName);
+    assert!(err
+        .to_string()
+        .contains("names.nw line 7: chunk name 'Body2' contains 'B', which is not in [a-z_]"));
+}
@@ -0,0 +1,96 @@
+// src/tests/notebook.rs
+use super::*;
+use crate::AzadiError;
+use crate::ChunkError;
+
+const NOTEBOOK: &str = r##"{
+ "cells": [
+  {"cell_type": "markdown", "metadata": {}, "source": ["# Title\n"]},
+  {"cell_type": "code", "metadata": {"tags": ["chunk:helper"]},
+   "source": ["def helper():\n", "    return 42\n"]},
+  {"cell_type": "code", "metadata": {"tags": ["file:out.py"]},
+   "source": "# <<helper>>\nprint(helper())"},
+  {"cell_type": "code", "metadata": {}, "source": ["scratch = 1\n"]}
+ ],
+ "metadata": {}, "nbformat": 4, "nbformat_minor": 5
+}"##;
+
+#[test]
+fn test_notebook_tagged_cells() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read_notebook(NOTEBOOK, "explore.ipynb")?;
+
+    assert!(setup.clip.has_chunk("helper"));
+    assert_eq!(
+        setup.clip.get_file_chunks(),
+        vec!["@file out.py".to_string()]
+    );
+    assert_eq!(
+        setup.clip.expand("@file out.py", "")?,
+        vec!["def helper():\n", "    return 42\n", "print(helper())\n"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_notebook_cells_sharing_a_tag_continue_the_chunk() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_strict_redefinitions(true);
+    setup.clip.read_notebook(
+        r#"{"cells": [
+  {"cell_type": "code", "metadata": {"tags": ["chunk:body"]}, "source": "a = 1\n"},
+  {"cell_type": "code", "metadata": {"tags": ["chunk:body"]}, "source": "b = 2\n"}
+ ]}"#,
+        "explore.ipynb",
+    )?;
+
+    assert!(setup.clip.check_redefinitions()?.is_empty());
+    assert_eq!(setup.clip.expand("body", "")?, vec!["a = 1\n", "b = 2\n"]);
+    Ok(())
+}
+
+#[test]
+fn test_notebook_parse_error() {
+    let mut setup = TestSetup::new(&["#"]);
+    let result = setup.clip.read_notebook("{\"cells\": [", "broken.ipynb");
+    match result {
+        Err(AzadiError::Chunk(ChunkError::ParseError { file_name, .. })) => {
+            assert_eq!(file_name, "broken.ipynb");
+        }
+        _ => panic!("Expected ParseError"),
+    }
+}
+
+#[test]
+fn test_notebook_unicode_escapes() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read_notebook(
+        r#"{"cells": [{"cell_type": "code", "metadata": {"tags": ["chunk:s"]},
+            "source": "print(\"\u00e9\ud83d\ude00\")"}]}"#,
+        "explore.ipynb",
+    )?;
+    assert_eq!(
+        setup.clip.expand("s", "")?,
+        vec!["print(\"\u{e9}\u{1f600}\")\n"]
+    );
+
+    let result = setup
+        .clip
+        .read_notebook(r#"{"cells": ["\ude00"]}"#, "broken.ipynb");
+    assert!(matches!(
+        result,
+        Err(AzadiError::Chunk(ChunkError::ParseError { .. }))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_notebook_nesting_too_deep_is_a_parse_error() {
+    let mut setup = TestSetup::new(&["#"]);
+    let text = format!("{{\"cells\": {}", "[".repeat(200_000));
+    let result = setup.clip.read_notebook(&text, "deep.ipynb");
+    assert!(matches!(
+        result,
+        Err(AzadiError::Chunk(ChunkError::ParseError { .. }))
+    ));
+}
@@ -0,0 +1,34 @@
+// src/tests/nuweb.rs
+use super::*;
+use crate::{AzadiError, Dialect};
+
+const NUWEB_DOC: &str = r#"
+\section{Program}
+@o hello.c -d
+@{#include <stdio.h>
+int main(void) {
+    @<Print greeting@>
+}
+@}
+
+@d Print greeting @{printf("hi@@home\n");@}
+"#;
+
+#[test]
+fn test_nuweb_scraps() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["//"]);
+    setup.clip.set_dialect(Dialect::Nuweb);
+    setup.clip.read(NUWEB_DOC, "prog.w");
+
+    assert!(setup.clip.has_chunk("Print-greeting"));
+    assert_eq!(
+        setup.clip.expand("@file hello.c", "")?,
+        vec![
+            "#include <stdio.h>\n",
+            "int main(void) {\n",
+            "    printf(\"hi@home\\n\");\n",
+            "}\n"
+        ]
+    );
+    Ok(())
+}
@@ -0,0 +1,182 @@
+// src/tests/report.rs
+use super::*;
+use crate::diagnostic::Code;
+use crate::report::{
+    ConsoleReporter, CountingReporter, JsonReporter, Reporter, SuppressingReporter,
+};
+use crate::AzadiError;
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Collecting {
+    messages: Mutex<Vec<(&'static str, String)>>,
+}
+
+impl Reporter for Collecting {
+    fn warning(&self, _code: Option<Code>, message: &str) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(("warning", message.to_string()));
+    }
+
+    fn info(&self, message: &str) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(("info", message.to_string()));
+    }
+
+    fn error(&self, _code: Option<Code>, message: &str) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(("error", message.to_string()));
+    }
+
+    fn progress(&self, message: &str) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(("progress", message.to_string()));
+    }
+}
+
+#[test]
+fn test_clip_reports_through_installed_reporter() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    let reporter = Arc::new(Collecting::default());
+    setup.clip.set_reporter(reporter.clone());
+    setup.clip.read(
+        "# <<@file out.txt>>=\nx\n# @\n# <<spare>>=\ny\n# @\n",
+        "doc.nw",
+    );
+    setup.clip.write_files()?;
+
+    assert_eq!(
+        *reporter.messages.lock().unwrap(),
+        vec![
+            ("progress", "generating 'out.txt'".to_string()),
+            (
+                "warning",
+                "doc.nw line 4: chunk 'spare' is defined but never referenced".to_string()
+            ),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_console_reporter_tags_level_and_code() {
+    use super::log::capture;
+    use tracing_subscriber::filter::LevelFilter;
+
+    let ((), captured) = capture(LevelFilter::INFO, || {
+        ConsoleReporter.coded_warning(Code::UnusedChunk, "chunk 'a' unused");
+        ConsoleReporter.warning(None, "untagged");
+        ConsoleReporter.info("a.txt changed");
+        ConsoleReporter.coded_error(Code::WriteFailed, "cannot write");
+        ConsoleReporter.error(None, "no input files");
+    });
+    assert_eq!(
+        captured.text(),
+        "Warning[AZ0101]: chunk 'a' unused\n\
+         Warning: untagged\n\
+         a.txt changed\n\
+         Error[AZ0012]: cannot write\n\
+         Error: no input files\n"
+    );
+}
+
+#[test]
+fn test_console_reporter_prints_without_subscriber() {
+    use super::log::capture;
+    use tracing_subscriber::filter::LevelFilter;
+
+    // No subscriber is installed for the tests, so messages go to stderr
+    assert!(!crate::report::observed());
+    let (observed, _) = capture(LevelFilter::INFO, crate::report::observed);
+    assert!(observed);
+}
+
+#[test]
+fn test_json_reporter_writes_one_object_per_line() {
+    let reporter = JsonReporter::new(Vec::new());
+    reporter.coded_warning(Code::UnusedChunk, "chunk \"a\" unused");
+    reporter.progress("generating 'b'");
+    // The code given wins over any quoted in the message
+    reporter.error(Some(Code::WriteFailed), "Error: see Warning[AZ0101]");
+    let out = reporter.into_inner();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"level\": \"warning\", \"code\": \"AZ0101\", \"message\": \"chunk \\\"a\\\" unused\"}\n\
+         {\"level\": \"progress\", \"code\": null, \"message\": \"generating 'b'\"}\n\
+         {\"level\": \"error\", \"code\": \"AZ0012\", \"message\": \"Error: see Warning[AZ0101]\"}\n"
+    );
+}
+
+#[test]
+fn test_json_reporter_applies_its_filter() {
+    use tracing_subscriber::filter::LevelFilter;
+
+    let reporter = JsonReporter::new(Vec::new()).with_filter(LevelFilter::WARN);
+    reporter.progress("generating 'b'");
+    reporter.info("b changed");
+    reporter.coded_warning(Code::UnusedChunk, "spare");
+    let out = String::from_utf8(reporter.into_inner()).unwrap();
+    assert_eq!(
+        out,
+        "{\"level\": \"warning\", \"code\": \"AZ0101\", \"message\": \"spare\"}\n"
+    );
+}
+
+#[test]
+fn test_counting_reporter_counts_warnings() {
+    let inner = Arc::new(Collecting::default());
+    let counter = CountingReporter::new(Arc::clone(&inner) as Arc<dyn Reporter>);
+    counter.info("written");
+    counter.warning(None, "first");
+    counter.warning(None, "second");
+    assert_eq!(counter.warnings(), 2);
+    assert_eq!(
+        *inner.messages.lock().unwrap(),
+        vec![
+            ("info", "written".to_string()),
+            ("warning", "first".to_string()),
+            ("warning", "second".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_suppressing_reporter_drops_allowed_warnings() {
+    let inner = Arc::new(Collecting::default());
+    let reporter = SuppressingReporter::new(
+        Arc::clone(&inner) as Arc<dyn Reporter>,
+        vec![Code::UnusedChunk],
+    );
+    reporter.coded_warning(Code::UnusedChunk, "spare");
+    reporter.coded_warning(Code::OrphanedOutput, "gen/old.txt");
+    reporter.warning(None, "untagged");
+    // Only the code given counts, not one quoted in the text
+    reporter.warning(None, "Warning[AZ0101]: quoted");
+    reporter.coded_error(Code::UnusedChunk, "kept");
+    assert_eq!(
+        *inner.messages.lock().unwrap(),
+        vec![
+            ("warning", "gen/old.txt".to_string()),
+            ("warning", "untagged".to_string()),
+            ("warning", "Warning[AZ0101]: quoted".to_string()),
+            ("error", "kept".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_codes_parse() {
+    for code in Code::ALL {
+        assert_eq!(Code::parse(code.as_str()), Some(*code));
+    }
+    assert_eq!(Code::parse("az0002"), Some(Code::Recursion));
+    assert_eq!(Code::parse("AZ9999"), None);
+}
@@ -0,0 +1,66 @@
+// src/tests/rst.rs
+use super::*;
+use crate::{AzadiError, Dialect};
+
+const RST_DOC: &str = r#"
+Setup
+=====
+
+.. code-block:: python
+   :name: imports
+
+   import os
+
+.. code-block:: python
+   :tangle: tool.py
+
+   # <<imports>>
+
+   def run():
+       return os.getcwd()
+
+Prose continues here.
+
+.. code-block:: python
+
+   ignored = True
+"#;
+
+#[test]
+fn test_rst_directives() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_dialect(Dialect::Rst);
+    setup.clip.read(RST_DOC, "guide.rst");
+
+    assert!(setup.clip.has_chunk("imports"));
+    assert_eq!(
+        setup.clip.get_file_chunks(),
+        vec!["@file tool.py".to_string()]
+    );
+    assert_eq!(
+        setup.clip.expand("@file tool.py", "")?,
+        vec![
+            "import os\n",
+            "\n",
+            "def run():\n",
+            "    return os.getcwd()\n"
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_rst_blocks_sharing_a_name_continue_the_chunk() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.set_dialect(Dialect::Rst);
+    setup.clip.set_strict_redefinitions(true);
+    setup.clip.read(
+        ".. code-block:: python\n   :name: body\n\n   a = 1\n\n\
+         .. code-block:: python\n   :name: body\n\n   b = 2\n",
+        "guide.rst",
+    );
+
+    assert!(setup.clip.check_redefinitions()?.is_empty());
+    assert_eq!(setup.clip.expand("body", "")?, vec!["a = 1\n", "b = 2\n"]);
+    Ok(())
+}
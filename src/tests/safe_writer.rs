@@ -1,5 +1,6 @@
 // src/tests/safe_writer.rs
 use super::*;
+use super::log::capture;
 use crate::SafeWriterError;
 use crate::AzadiError;
 use std::{fs, io::Write, path::PathBuf, thread, time::Duration};
@@ -41,7 +42,7 @@ fn test_unmodified_file_update() -> Result<(), AzadiError> {
 
     let mut config = writer.get_config().clone();
     config.modification_check = false;
-    writer.set_config(config);
+    writer.set_config(config)?;
 
     let test_file = PathBuf::from("test.txt");
 
@@ -152,7 +153,7 @@ fn test_backup_disabled() -> Result<(), AzadiError> {
 
     let mut config = writer.get_config().clone();
     config.backup_enabled = false;
-    writer.set_config(config);
+    writer.set_config(config)?;
 
     write_file(&mut writer, &test_file, "Test content")?;
 
@@ -194,3 +195,372 @@ fn test_path_safety() {
         }
     }
 }
+
+#[test]
+fn test_changed_file_reported_through_tracing() -> Result<(), AzadiError> {
+    use tracing_subscriber::filter::LevelFilter;
+
+    let (_temp, mut writer) = create_test_writer();
+    let mut config = writer.get_config().clone();
+    config.modification_check = false;
+    writer.set_config(config)?;
+    let test_file = PathBuf::from("reported.txt");
+
+    let (result, captured) = capture(LevelFilter::INFO, || {
+        write_file(&mut writer, &test_file, "one")
+            .and_then(|_| write_file(&mut writer, &test_file, "two"))
+    });
+    result?;
+    let reported: Vec<String> = captured
+        .text()
+        .lines()
+        .filter(|line| line.contains("reported.txt"))
+        .map(String::from)
+        .collect();
+    assert_eq!(reported.len(), 1);
+    assert!(reported[0].ends_with("reported.txt changed"));
+
+    // A notice, so quiet runs leave it out
+    let (result, captured) = capture(LevelFilter::WARN, || {
+        write_file(&mut writer, &test_file, "three")
+    });
+    result?;
+    assert!(!captured.text().contains("reported.txt"));
+    Ok(())
+}
+
+#[test]
+fn test_write_atomic() -> Result<(), AzadiError> {
+    let (_temp, mut writer) = create_test_writer();
+    let test_file = PathBuf::from("nested/atomic.txt");
+
+    writer.write_atomic(&test_file, b"first\n")?;
+    let output = writer.get_gen_base().join(&test_file);
+    assert_eq!(fs::read_to_string(&output)?, "first\n");
+    assert_eq!(
+        fs::read_to_string(writer.get_old_dir().join(&test_file))?,
+        "first\n"
+    );
+
+    // A hand edit of the output is still detected
+    thread::sleep(Duration::from_millis(50));
+    fs::write(&output, "edited\n")?;
+    match writer.write_atomic(&test_file, b"second\n") {
+        Err(SafeWriterError::ModifiedExternally(_)) => {}
+        other => panic!("Expected ModifiedExternally, got {:?}", other),
+    }
+    assert_eq!(fs::read_to_string(&output)?, "edited\n");
+
+    assert!(matches!(
+        writer.write_atomic("../escape.txt", b"x"),
+        Err(SafeWriterError::SecurityViolation(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_after_write_reports_outcome() -> Result<(), AzadiError> {
+    use crate::{WriteOutcome, WriteResult};
+
+    let (_temp, mut writer) = create_test_writer();
+    let mut config = writer.get_config().clone();
+    config.modification_check = false;
+    writer.set_config(config)?;
+    let test_file = PathBuf::from("outcome.txt");
+    let output = writer.get_gen_base().join(&test_file);
+    let written = |outcome| WriteResult {
+        path: output.clone(),
+        outcome,
+    };
+
+    assert_eq!(
+        writer.write_atomic(&test_file, b"one\n")?,
+        written(WriteOutcome::Written)
+    );
+    assert_eq!(
+        writer.write_atomic(&test_file, b"one\n")?,
+        written(WriteOutcome::Unchanged)
+    );
+    assert_eq!(
+        writer.write_atomic(&test_file, b"two\n")?,
+        written(WriteOutcome::Written)
+    );
+
+    let mut config = writer.get_config().clone();
+    config.modification_check = true;
+    config.skip_modified = true;
+    writer.set_config(config)?;
+    thread::sleep(Duration::from_millis(50));
+    fs::write(&output, "edited\n")?;
+    for _ in 0..2 {
+        assert_eq!(
+            writer.write_atomic(&test_file, b"three\n")?,
+            written(WriteOutcome::SkippedModified)
+        );
+    }
+    assert_eq!(fs::read_to_string(&output)?, "edited\n");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_overwrite_keeps_permissions() -> Result<(), AzadiError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (_temp, mut writer) = create_test_writer();
+    let mut config = writer.get_config().clone();
+    config.modification_check = false;
+    writer.set_config(config)?;
+    let test_file = PathBuf::from("run.sh");
+    let output = writer.get_gen_base().join(&test_file);
+
+    writer.write_atomic(&test_file, b"echo one\n")?;
+    fs::set_permissions(&output, fs::Permissions::from_mode(0o750))?;
+    writer.write_atomic(&test_file, b"echo two\n")?;
+
+    assert_eq!(fs::read_to_string(&output)?, "echo two\n");
+    assert_eq!(fs::metadata(&output)?.permissions().mode() & 0o777, 0o750);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_policy() -> Result<(), AzadiError> {
+    use crate::SymlinkPolicy;
+
+    let (temp, mut writer) = create_test_writer();
+    let test_file = PathBuf::from("linked.txt");
+    let output = writer.get_gen_base().join(&test_file);
+    let target = temp.path().join("elsewhere.txt");
+    fs::write(&target, "outside\n")?;
+    std::os::unix::fs::symlink(&target, &output)?;
+
+    match writer.write_atomic(&test_file, b"generated\n") {
+        Err(SafeWriterError::SecurityViolation(msg)) => {
+            assert!(msg.contains("is a symbolic link"))
+        }
+        other => panic!("Expected SecurityViolation, got {:?}", other),
+    }
+    assert_eq!(fs::read_to_string(&target)?, "outside\n");
+
+    let mut config = writer.get_config().clone();
+    config.symlinks = SymlinkPolicy::Follow;
+    writer.set_config(config.clone())?;
+    let report = writer.write_atomic(&test_file, b"generated\n")?;
+    assert_eq!(report.path, target.canonicalize()?);
+    assert_eq!(fs::read_to_string(&target)?, "generated\n");
+    assert!(fs::symlink_metadata(&output)?.file_type().is_symlink());
+
+    config.symlinks = SymlinkPolicy::Replace;
+    config.modification_check = false;
+    writer.set_config(config)?;
+    writer.write_atomic(&test_file, b"replaced\n")?;
+    assert!(fs::symlink_metadata(&output)?.file_type().is_file());
+    assert_eq!(fs::read_to_string(&output)?, "replaced\n");
+    assert_eq!(fs::read_to_string(&target)?, "generated\n");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_directory_linking_outside_gen_is_refused() -> Result<(), AzadiError> {
+    let (temp, mut writer) = create_test_writer();
+    let outside = temp.path().join("outside");
+    fs::create_dir(&outside)?;
+    std::os::unix::fs::symlink(&outside, writer.get_gen_base().join("escape"))?;
+
+    for path in ["escape/out.txt", "escape/deeper/out.txt"] {
+        match writer.write_atomic(path, b"generated\n") {
+            Err(SafeWriterError::SecurityViolation(msg)) => {
+                assert!(msg.contains("resolves outside"), "{}", msg)
+            }
+            other => panic!("Expected SecurityViolation for {}, got {:?}", path, other),
+        }
+    }
+    assert!(fs::read_dir(&outside)?.next().is_none());
+    Ok(())
+}
+
+#[test]
+fn test_backup_dir_and_no_backup() -> Result<(), AzadiError> {
+    let (temp, mut writer) = create_test_writer();
+    let backups = temp.path().join("backups");
+    let mut config = writer.get_config().clone();
+    config.backup_dir = Some(backups.clone());
+    config.no_backup = vec!["*.log".to_string()];
+    writer.set_config(config)?;
+
+    write_file(&mut writer, &PathBuf::from("src/kept.txt"), "kept")?;
+    write_file(&mut writer, &PathBuf::from("run.log"), "log")?;
+
+    assert_eq!(fs::read_to_string(backups.join("src/kept.txt"))?, "kept");
+    assert!(!backups.join("run.log").exists());
+    assert!(!writer
+        .get_private_dir()
+        .join("__old__/src/kept.txt")
+        .exists());
+    assert_eq!(
+        fs::read_to_string(writer.get_gen_base().join("run.log"))?,
+        "log"
+    );
+
+    let mut config = writer.get_config().clone();
+    config.backup_dir = Some(writer.get_gen_base().join("run.log"));
+    assert!(matches!(
+        writer.set_config(config),
+        Err(SafeWriterError::DirectoryCreationFailed(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_snapshots_keep_recent_runs() -> Result<(), AzadiError> {
+    let temp = tempfile::TempDir::new()?;
+    let config = crate::safe_writer::SafeWriterConfig {
+        modification_check: false,
+        snapshots: 2,
+        ..Default::default()
+    };
+    let snapshots = temp.path().join("private/__snapshots__");
+    let runs = || -> Vec<PathBuf> {
+        let mut runs: Vec<PathBuf> = fs::read_dir(&snapshots)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        runs.sort();
+        runs
+    };
+
+    for content in ["one", "two", "three"] {
+        // A new writer for each run
+        let mut writer = crate::SafeFileWriter::with_config(
+            temp.path().join("gen"),
+            temp.path().join("private"),
+            config.clone(),
+        )?;
+        write_file(&mut writer, &PathBuf::from("src/a.txt"), content)?;
+        write_file(&mut writer, &PathBuf::from("b.txt"), "same")?;
+    }
+
+    let runs = runs();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(fs::read_to_string(runs[0].join("src/a.txt"))?, "two");
+    assert_eq!(fs::read_to_string(runs[1].join("src/a.txt"))?, "three");
+    // Unchanged outputs are not copied again
+    assert!(!runs[1].join("b.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_verify_outputs() -> Result<(), AzadiError> {
+    use crate::safe_writer::OutputChange;
+
+    let (_temp, mut writer) = create_test_writer();
+    for (path, content) in [("a.txt", "a"), ("src/b.txt", "b"), ("c.txt", "c")] {
+        write_file(&mut writer, &PathBuf::from(path), content)?;
+    }
+    let entries =
+        ["a.txt", "./src/b.txt", "c.txt"].map(|path| writer.manifest_entry(path, Vec::new()));
+    writer.write_manifest(&entries)?;
+    assert!(writer.verify_outputs()?.is_empty());
+
+    let gen = writer.get_gen_base().to_path_buf();
+    fs::write(gen.join("a.txt"), "edited")?;
+    fs::remove_file(gen.join("c.txt"))?;
+    fs::write(gen.join("src/extra.txt"), "extra")?;
+    assert_eq!(
+        writer.verify_outputs()?,
+        vec![
+            ("a.txt".to_string(), OutputChange::Modified),
+            ("c.txt".to_string(), OutputChange::Deleted),
+            ("src/extra.txt".to_string(), OutputChange::Added),
+        ]
+    );
+    assert_eq!(
+        writer.read_manifest()?,
+        vec!["a.txt", "./src/b.txt", "c.txt"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_windows_style_path_safety() {
+    let (_temp, mut writer) = create_test_writer();
+
+    let test_cases = [
+        ("..\\outside.txt", "Path traversal detected (..)"),
+        ("src\\..\\..\\outside.txt", "Path traversal detected (..)"),
+        ("\\\\server\\share\\a.txt", "UNC paths are not allowed"),
+        ("//server/share/a.txt", "UNC paths are not allowed"),
+        ("\\rooted.txt", "Absolute paths are not allowed"),
+        (
+            "D:\\data\\a.txt",
+            "Windows-style absolute paths are not allowed",
+        ),
+    ];
+
+    for (path, expected_msg) in test_cases {
+        match writer.write_atomic(path, b"Should fail") {
+            Err(SafeWriterError::SecurityViolation(msg)) => {
+                assert!(msg.contains(expected_msg), "{}: {}", path, msg)
+            }
+            other => panic!("Expected SecurityViolation for {}, got {:?}", path, other),
+        }
+    }
+}
+
+#[test]
+fn test_output_path_stays_under_gen() {
+    let (_temp, writer) = create_test_writer();
+    let gen = writer.get_gen_base();
+    for path in ["a.txt", "../a.txt", "/a.txt", "sub/../../a.txt"] {
+        let output = writer.output_path(path);
+        assert!(output.starts_with(gen), "{}: {}", path, output.display());
+    }
+}
+
+#[test]
+fn test_deeply_nested_output() -> Result<(), AzadiError> {
+    let (_temp, mut writer) = create_test_writer();
+    // Longer than the 260 characters of MAX_PATH on Windows
+    let dirs: Vec<String> = (0..30).map(|i| format!("directory{:02}", i)).collect();
+    let test_file = PathBuf::from(format!("{}/deep.txt", dirs.join("/")));
+    assert!(test_file.to_string_lossy().len() > 260);
+
+    writer.write_atomic(&test_file, b"deep\n")?;
+    let content = fs::read_to_string(writer.get_gen_base().join(&test_file))?;
+    assert_eq!(content, "deep\n");
+    Ok(())
+}
+
+#[test]
+fn test_control_characters_and_device_names_rejected() {
+    let (_temp, mut writer) = create_test_writer();
+
+    let test_cases = [
+        ("bad\nname.txt", "Control character '\\n' is not allowed"),
+        ("nul\0byte.txt", "Control character '\\0' is not allowed"),
+        ("tab\there.txt", "Control character '\\t' is not allowed"),
+        ("CON", "Reserved device name 'CON'"),
+        ("src/nul.txt", "Reserved device name 'nul.txt'"),
+        ("Com1.log", "Reserved device name 'Com1.log'"),
+        ("lpt9/x.txt", "Reserved device name 'lpt9'"),
+    ];
+    for (path, expected_msg) in test_cases {
+        match writer.write_atomic(path, b"Should fail") {
+            Err(SafeWriterError::SecurityViolation(msg)) => {
+                assert!(msg.contains(expected_msg), "{:?}: {}", path, msg)
+            }
+            other => panic!("Expected SecurityViolation for {:?}, got {:?}", path, other),
+        }
+    }
+
+    for allowed in [
+        "console.txt",
+        "com10.txt",
+        "nullable/aux_data.txt",
+        "lpt0.txt",
+    ] {
+        assert!(writer.write_atomic(allowed, b"ok").is_ok(), "{}", allowed);
+    }
+}
@@ -0,0 +1,70 @@
+// src/tests/serve.rs
+use crate::serve::Preview;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+/// Serve `preview` on a free local port.
+fn start(preview: &Arc<Preview>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let preview = Arc::clone(preview);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let preview = Arc::clone(&preview);
+            thread::spawn(move || preview.handle(stream));
+        }
+    });
+    addr
+}
+
+fn get(addr: &str, target: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+    stream
+}
+
+/// Read an event stream up to its first event.
+fn next_event(reader: &mut BufReader<TcpStream>) -> String {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        if line.starts_with("data:") {
+            return line;
+        }
+    }
+}
+
+#[test]
+fn test_preview_serves_page_and_reloads() {
+    let preview = Arc::new(Preview::new(
+        "<html><body>\n<p>v1</p>\n</body></html>\n".to_string(),
+    ));
+    let addr = start(&preview);
+
+    let mut page = String::new();
+    get(&addr, "/").read_to_string(&mut page).unwrap();
+    assert!(page.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(page.contains(
+        "<p>v1</p>\n<script>new EventSource(\"/__events?since=0\").onmessage = () => location.reload();</script>\n</body></html>\n"
+    ));
+
+    let mut missing = String::new();
+    get(&addr, "/style.css")
+        .read_to_string(&mut missing)
+        .unwrap();
+    assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let mut events = BufReader::new(get(&addr, "/__events?since=0"));
+    let mut status = String::new();
+    events.read_line(&mut status).unwrap();
+    assert_eq!(status, "HTTP/1.1 200 OK\r\n");
+    preview.update("<html><body>v2</body></html>".to_string());
+    assert_eq!(next_event(&mut events), "data: reload\n");
+
+    // A browser that subscribes after missing an update reloads right away
+    let mut late = BufReader::new(get(&addr, "/__events?since=0"));
+    assert_eq!(next_event(&mut late), "data: reload\n");
+}
@@ -0,0 +1,77 @@
+// src/tests/stats.rs
+use super::*;
+use crate::stats::{ChunkStats, Stats};
+
+#[test]
+fn test_stats_count_chunks_lines_and_largest() {
+    let mut setup = TestSetup::new(&["#"]);
+    let text = "Intro.\n\n# <<@file app.py>>=\n# <<imports>>\nmain()\n# @\nMore prose.\n# <<imports>>=\nimport os\n# @\n# <<imports>>=\nimport sys\nimport re\n# @\n";
+    setup.clip.read(text, "app.nw");
+
+    let stats = Stats::collect(&setup.clip, text.lines().count(), 1);
+    assert_eq!(stats.chunks, 2);
+    assert_eq!(stats.file_chunks, 1);
+    assert_eq!(stats.definitions, 3);
+    assert_eq!(stats.references, 1);
+    assert_eq!(stats.code_lines, 5);
+    assert_eq!(stats.prose_lines, 3);
+    assert_eq!(
+        stats.largest,
+        vec![ChunkStats {
+            name: "imports".to_string(),
+            definitions: 2,
+            lines: 3,
+            references: 0,
+        }]
+    );
+    assert!(stats.to_text().contains("     3 lines  imports\n"));
+    assert!(stats.to_json().starts_with(
+        "{\"chunks\": 2, \"file_chunks\": 1, \"definitions\": 3, \"references\": 1, \"code_lines\": 5, \"prose_lines\": 3, \"largest\": [{\"name\": \"imports\""
+    ));
+}
+
+#[test]
+fn test_coverage_counts_lines_per_output_and_chunk() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@file a.py>>=\n# <<shared>>\nprint('a')\n# @\n# <<@file b.py>>=\n# <<shared>>\n# @\n# <<shared>>=\nimport os\nimport sys\n# @\n# <<unused>>=\npass\n# @\n",
+        "app.nw",
+    );
+
+    let coverage = setup.clip.coverage().unwrap();
+    let a: Vec<(&str, usize)> = coverage.outputs["a.py"]
+        .iter()
+        .map(|(chunk, lines)| (chunk.as_str(), *lines))
+        .collect();
+    assert_eq!(a, vec![("@file a.py", 1), ("shared", 2)]);
+    let b: Vec<(&str, usize)> = coverage.outputs["b.py"]
+        .iter()
+        .map(|(chunk, lines)| (chunk.as_str(), *lines))
+        .collect();
+    assert_eq!(b, vec![("@file b.py", 0), ("shared", 2)]);
+    assert_eq!(coverage.chunks["shared"], vec!["a.py", "b.py"]);
+    assert!(coverage.chunks["unused"].is_empty());
+    assert!(coverage.to_text().contains("  unused: (none)\n"));
+}
+
+#[test]
+fn test_coverage_leaves_out_inserted_files() -> Result<(), crate::AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    let dir = setup._temp_dir.path().to_path_buf();
+    std::fs::write(dir.join("asset.txt"), "x\ny\n")?;
+    let source = dir.join("app.nw");
+    std::fs::write(
+        &source,
+        "# <<@file a.txt>>=\n# <<body>>\n# <<@insert asset.txt>>\n# @\n# <<body>>=\nbody\n# @\n",
+    )?;
+    setup.clip.read_file(&source)?;
+
+    let coverage = setup.clip.coverage()?;
+    let a: Vec<(&str, usize)> = coverage.outputs["a.txt"]
+        .iter()
+        .map(|(chunk, lines)| (chunk.as_str(), *lines))
+        .collect();
+    assert_eq!(a, vec![("@file a.txt", 2), ("body", 1)]);
+    assert!(!coverage.to_text().contains("asset.txt"));
+    Ok(())
+}
@@ -0,0 +1,153 @@
+// src/tests/untangle.rs
+use super::*;
+use crate::untangle::{apply_edits, format_patch, untangle};
+use crate::AzadiError;
+use std::fs;
+
+const SOURCE: &str = "\
+# <<@file app.py>>=
+def main():
+    # <<body>>
+# @
+# <<body>>=
+print('one')
+print('two')
+# @
+";
+
+#[test]
+fn test_untangle_maps_edits_to_source() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    let source = setup._temp_dir.path().join("app.nw");
+    fs::write(&source, SOURCE)?;
+    setup.clip.read_file(&source)?;
+    setup.clip.write_files()?;
+
+    let output = setup.clip.gen_base().join("app.py");
+    fs::write(
+        &output,
+        "def main():\n    print('uno')\n    print('two')\n    print('three')\n",
+    )?;
+
+    let report = untangle(&setup.clip)?;
+    assert!(report.skipped.is_empty(), "{:?}", report.skipped);
+    assert_eq!(report.changed_outputs, vec!["app.py".to_string()]);
+    assert!(format_patch(&report.edits)?.contains("+print('uno')"));
+
+    apply_edits(&report.edits)?;
+    let updated = fs::read_to_string(&source)?;
+    assert!(updated.contains("print('uno')\nprint('two')\nprint('three')\n# @"));
+    Ok(())
+}
+
+#[test]
+fn test_untangle_unchanged_output() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    let source = setup._temp_dir.path().join("app.nw");
+    fs::write(&source, SOURCE)?;
+    setup.clip.read_file(&source)?;
+    setup.clip.write_files()?;
+
+    let report = untangle(&setup.clip)?;
+    assert!(report.edits.is_empty());
+    assert!(report.changed_outputs.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_untangle_patch_applies() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    let dir = setup._temp_dir.path().to_path_buf();
+    let source = dir.join("app.nw");
+    fs::write(
+        &source,
+        "\
+# <<@file app.py>>=
+import os
+# <<body>>
+# <<tail>>
+# @
+# <<body>>=
+a = 1
+b = 2
+c = 3
+d = 4
+e = 5
+f = 6
+g = 7
+h = 8
+i = 9
+# @
+# <<tail>>=
+print(a)
+# @
+",
+    )?;
+    setup.clip.read_file(&source)?;
+    setup.clip.write_files()?;
+
+    // A pure insertion, then a replacement far enough away for its own hunk
+    let output = setup.clip.gen_base().join("app.py");
+    fs::write(
+        &output,
+        "import os\na = 1\nb = 2\nzero = 0\nc = 3\nd = 4\ne = 5\nf = 6\ng = 7\nh = 8\ni = 9\nprint(a, b)\n",
+    )?;
+
+    let report = untangle(&setup.clip)?;
+    assert!(report.skipped.is_empty(), "{:?}", report.skipped);
+    let patch = format_patch(&report.edits)?;
+    assert_eq!(patch.matches("@@ -").count(), 2, "{}", patch);
+    assert!(patch.contains("@@ -6,6 +6,7 @@"), "{}", patch);
+    assert!(patch.contains("@@ -15,5 +16,5 @@"), "{}", patch);
+
+    let patch_file = dir.join("untangle.diff");
+    fs::write(&patch_file, &patch)?;
+    let Ok(status) = std::process::Command::new("git")
+        .args(["apply", "--check", "--unsafe-paths"])
+        .arg(&patch_file)
+        .current_dir(&dir)
+        .status()
+    else {
+        // Without git there is nothing to check against
+        return Ok(());
+    };
+    assert!(status.success(), "git apply rejected:\n{}", patch);
+    Ok(())
+}
+
+#[test]
+fn test_untangle_keeps_crlf_line_endings() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    let source = setup._temp_dir.path().join("app.nw");
+    fs::write(&source, SOURCE.replace('\n', "\r\n"))?;
+    setup.clip.read_file(&source)?;
+    setup.clip.write_files()?;
+
+    let output = setup.clip.gen_base().join("app.py");
+    fs::write(
+        &output,
+        "def main():\r\n    print('uno')\r\n    print('two')\r\n",
+    )?;
+
+    apply_edits(&untangle(&setup.clip)?.edits)?;
+    let updated = fs::read_to_string(&source)?;
+    assert_eq!(updated, SOURCE.replace("one", "uno").replace('\n', "\r\n"));
+    Ok(())
+}
+
+#[test]
+fn test_untangle_keeps_a_missing_final_newline() -> Result<(), AzadiError> {
+    let mut setup = TestSetup::new(&["#"]);
+    let source = setup._temp_dir.path().join("app.nw");
+    let text = format!("{}Closing prose.", SOURCE);
+    fs::write(&source, &text)?;
+    setup.clip.read_file(&source)?;
+    setup.clip.write_files()?;
+
+    let output = setup.clip.gen_base().join("app.py");
+    fs::write(&output, "def main():\n    print('uno')\n    print('two')\n")?;
+
+    apply_edits(&untangle(&setup.clip)?.edits)?;
+    assert_eq!(fs::read_to_string(&source)?, text.replace("one", "uno"));
+    Ok(())
+}
@@ -0,0 +1,358 @@
+// src/tests/weave.rs
+use super::*;
+use crate::weave::weave_html;
+
+#[test]
+fn test_weave_links_references_and_continuations() {
+    let mut setup = TestSetup::new(&["#"]);
+    let text = "Some <prose>.\n\n# <<main>>=\n# <<helper>>\n# @\n# <<helper>>=\nx < y\n# @\n# <<helper>>=\nmore\n# @\n";
+    setup.clip.read(text, "doc.nw");
+
+    let html = weave_html(
+        &setup.clip,
+        "Doc",
+        &[("doc.nw".to_string(), text.to_string())],
+    );
+    assert!(html.contains("<title>Doc</title>"));
+    assert!(html.contains("<p>Some &lt;prose&gt;.</p>"));
+    assert!(html.contains(
+        "<div class=\"chunk\" id=\"chunk-helper\"><div class=\"chunk-name\">&lt;&lt;helper&gt;&gt;=</div><pre><code>x &lt; y\n</code></pre></div>"
+    ));
+    assert!(html.contains("# <a class=\"ref\" href=\"#chunk-helper\">&lt;&lt;helper&gt;&gt;</a>\n"));
+    assert!(html.contains(
+        "<a class=\"ref\" href=\"#chunk-helper\">&lt;&lt;helper&gt;&gt;</a>+=</div><pre><code>more\n"
+    ));
+}
+
+#[test]
+fn test_weave_anchors_tell_similar_names_apart() {
+    let mut setup = TestSetup::new(&["#"]);
+    let text = "# <<main>>=\n# <<a-b>>\n# @\n# <<a_b>>=\nunderscore\n# @\n# <<a-b>>=\ndash\n# @\n";
+    setup.clip.read(text, "doc.nw");
+
+    let html = weave_html(
+        &setup.clip,
+        "Doc",
+        &[("doc.nw".to_string(), text.to_string())],
+    );
+    assert!(html.contains("id=\"chunk-a-5fb\""));
+    assert!(html.contains("id=\"chunk-a-2db\""));
+    assert!(html.contains("# <a class=\"ref\" href=\"#chunk-a-2db\">&lt;&lt;a-b&gt;&gt;</a>\n"));
+}
+
+#[test]
+fn test_references_graph() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@file out.txt>>=\n# <<a>>\n# <<b>>\n# @\n# <<a>>=\n# <<b>>\n# @\n# <<b>>=\nb\n# @\n",
+        "doc.nw",
+    );
+    assert_eq!(
+        setup.clip.references(),
+        vec![
+            ("@file out.txt".to_string(), "a".to_string()),
+            ("@file out.txt".to_string(), "b".to_string()),
+            ("a".to_string(), "b".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_references_graph_leaves_out_inserts() {
+    let mut setup = TestSetup::new(&["#"]);
+    setup.clip.read(
+        "# <<@file out.txt>>=\n# <<a>>\n# <<@insert asset.txt>>\n# @\n# <<a>>=\na\n# @\n",
+        "doc.nw",
+    );
+    assert_eq!(
+        setup.clip.references(),
+        vec![("@file out.txt".to_string(), "a".to_string())]
+    );
+}
+
+#[test]
+fn test_weave_highlights_by_language() {
+    use crate::highlight::Theme;
+    use crate::weave::{weave_html_with_options, WeaveOptions};
+
+    let mut setup = TestSetup::new(&["#"]);
+    let text = "# <<@file app.py>>=\n# <<body>>\n# @\n# <<body>>=\nreturn 1\n# @\n# <<@lang(sh) setup>>=\nexport X=1\n# @\n# <<notes>>=\nreturn\n# @\n";
+    setup.clip.read(text, "doc.nw");
+    let documents = [("doc.nw".to_string(), text.to_string())];
+
+    let html = weave_html_with_options(
+        &setup.clip,
+        "Doc",
+        &documents,
+        &WeaveOptions {
+            theme: Some(Theme::Dark),
+            ..WeaveOptions::default()
+        },
+    );
+    assert!(html.contains("pre { background: #2b303b;"));
+    assert!(html.contains(
+        "<pre><code class=\"language-python\"><span class=\"hl-source hl-python\"><span class=\"hl-keyword hl-control hl-flow hl-return hl-python\">return</span> "
+    ));
+    assert!(html.contains(
+        "<pre><code class=\"language-sh\"><span class=\"hl-source hl-shell hl-bash\"><span class=\"hl-meta hl-function-call hl-shell\"><span class=\"hl-storage hl-modifier hl-shell\">export</span>"
+    ));
+    assert!(html.contains("<pre><code>return\n"));
+
+    let plain = weave_html_with_options(
+        &setup.clip,
+        "Doc",
+        &documents,
+        &WeaveOptions {
+            theme: None,
+            ..WeaveOptions::default()
+        },
+    );
+    assert!(plain.contains("<pre><code>return 1\n"));
+    assert!(!plain.contains("hl-keyword"));
+}
+
+#[test]
+fn test_weave_cross_references_and_index() {
+    let mut setup = TestSetup::new(&["#"]);
+    let text = "# <<@file out.txt>>=\n# <<part>>\n# @\n# <<part>>=\none\n# @\n# <<@file other.txt>>=\n# <<part>>\n# <<part>>\n# @\n# <<part>>=\ntwo\n# @\n# <<spare>>=\n# @\n";
+    setup.clip.read(text, "doc.nw");
+
+    let html = weave_html(
+        &setup.clip,
+        "Doc",
+        &[("doc.nw".to_string(), text.to_string())],
+    );
+    assert!(html.contains("<a id=\"section-2\"></a><div class=\"chunk\" id=\"chunk-part\">"));
+    assert!(html.contains(
+        "</code></pre></div>\n<div class=\"chunk-xref\">Section 1: defined in section <a href=\"#section-1\">1</a>.</div>"
+    ));
+    assert!(html.contains(
+        "<div class=\"chunk-xref\">Section 4: defined in sections <a href=\"#section-2\">2</a>, <a href=\"#section-4\">4</a>; used in sections <a href=\"#section-1\">1</a>, <a href=\"#section-3\">3</a>.</div>"
+    ));
+    assert!(html.contains("<h2>Chunk index</h2>"));
+    assert!(html.contains(
+        "<li><a class=\"ref\" href=\"#chunk-spare\">&lt;&lt;spare&gt;&gt;</a>: defined in section <a href=\"#section-5\">5</a>; never used</li>"
+    ));
+    let index = html.find("<ul class=\"chunk-index\">").unwrap();
+    let positions: Vec<usize> = ["@file other.txt", "@file out.txt", "part", "spare"]
+        .iter()
+        .map(|name| {
+            html[index..]
+                .find(&format!("&lt;&lt;{}&gt;&gt;", name))
+                .unwrap()
+        })
+        .collect();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+fn test_weave_reuses_cached_documents() {
+    use crate::weave::{weave_html_with_options, WeaveOptions};
+
+    let cache = tempfile::tempdir().unwrap();
+    let options = WeaveOptions {
+        cache_dir: Some(cache.path().to_path_buf()),
+        ..WeaveOptions::default()
+    };
+    let weave = |a: &str, b: &str| {
+        let mut setup = TestSetup::new(&["#"]);
+        setup.clip.read(a, "a.nw");
+        setup.clip.read(b, "b.nw");
+        let documents = [
+            ("a.nw".to_string(), a.to_string()),
+            ("b.nw".to_string(), b.to_string()),
+        ];
+        weave_html_with_options(&setup.clip, "Book", &documents, &options)
+    };
+    let b = "# <<b>>=\nbee\n# @\n";
+    let first = weave("Intro.\n", b);
+    assert!(first.contains("bee\n"));
+
+    // Mark the cached rendering of b.nw to see whether it is reused
+    let mut entries: Vec<_> = std::fs::read_dir(cache.path())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(entries.len(), 2);
+    entries.retain(|path| std::fs::read_to_string(path).unwrap().contains("bee"));
+    let cached = std::fs::read_to_string(&entries[0]).unwrap();
+    std::fs::write(&entries[0], cached.replace("bee", "cached bee")).unwrap();
+
+    let second = weave("Changed intro.\n", b);
+    assert!(second.contains("<p>Changed intro.</p>"));
+    assert!(second.contains("cached bee\n"));
+
+    // A new use of chunk b changes the cross-references shown in b.nw
+    let third = weave("# <<a>>=\n# <<b>>\n# @\n", b);
+    assert!(!third.contains("cached bee"));
+    assert!(third.contains("used in section <a href=\"#section-1\">1</a>"));
+}
+
+#[test]
+fn test_weave_templates() {
+    use crate::weave::{weave_html_with_options, WeaveOptions, WeaveTemplate};
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("chunk.html"),
+        "<section id=\"{section_id}\" data-lang=\"{lang}\"><h3>{section}. {name}</h3><pre>{code}</pre><footer>{xref} {unknown}</footer></section>\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("footer.html"),
+        "<hr>{title}\n</body></html>\n",
+    )
+    .unwrap();
+    let template = WeaveTemplate::load(dir.path()).unwrap();
+    assert_eq!(template.header, WeaveTemplate::default().header);
+
+    let mut setup = TestSetup::new(&["#"]);
+    let text = "# <<@file run.sh>>=\necho {x}\n# @\n";
+    setup.clip.read(text, "doc.nw");
+    let html = weave_html_with_options(
+        &setup.clip,
+        "A & B",
+        &[("doc.nw".to_string(), text.to_string())],
+        &WeaveOptions {
+            template,
+            theme: None,
+            ..WeaveOptions::default()
+        },
+    );
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<title>A &amp; B</title>"));
+    assert!(html.contains(
+        "<section id=\"section-1\" data-lang=\"\"><h3>1. &lt;&lt;@file run.sh&gt;&gt;</h3><pre>echo {x}\n</pre><footer>defined in section <a href=\"#section-1\">1</a> {unknown}</footer></section>\n"
+    ));
+    assert!(html.ends_with("<hr>A &amp; B\n</body></html>\n"));
+}
+
+#[test]
+fn test_weave_site_links_across_pages() {
+    use crate::weave::{page_names, weave_site, WeaveOptions};
+
+    let mut setup = TestSetup::new(&["#"]);
+    let a = "# <<main>>=\n# <<helper>>\n# @\n";
+    let b = "# <<helper>>=\nx\n# @\n";
+    setup.clip.read(a, "docs/a.nw");
+    setup.clip.read(b, "b.nw");
+    let documents = [
+        ("docs/a.nw".to_string(), a.to_string()),
+        ("b.nw".to_string(), b.to_string()),
+    ];
+
+    let pages = weave_site(&setup.clip, "Site", &documents, &WeaveOptions::default());
+    let names: Vec<&str> = pages.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["index.html", "docs-a.html", "b.html"]);
+
+    let index = &pages[0].1;
+    assert!(index.contains("<li><a href=\"docs-a.html\">docs/a.nw</a> (1 section)</li>"));
+    assert!(index.contains(
+        "<li><a class=\"ref\" href=\"b.html#chunk-helper\">&lt;&lt;helper&gt;&gt;</a>: defined in section <a href=\"b.html#section-2\">2</a>; used in section <a href=\"docs-a.html#section-1\">1</a></li>"
+    ));
+
+    let page_a = &pages[1].1;
+    assert!(page_a.contains("<nav class=\"site-nav\"><a href=\"index.html\">Contents</a> | <a href=\"b.html\">b.nw &rarr;</a></nav>"));
+    assert!(page_a.contains("# <a class=\"ref\" href=\"b.html#chunk-helper\">"));
+    assert!(!page_a.contains("Chunk index"));
+    assert!(pages[2]
+        .1
+        .contains("<a href=\"docs-a.html\">&larr; docs/a.nw</a>"));
+
+    let same = [
+        ("index.nw".to_string(), String::new()),
+        ("x/a.nw".to_string(), String::new()),
+        ("x-a.md".to_string(), String::new()),
+    ];
+    assert_eq!(
+        page_names(&same),
+        ["index-2.html", "x-a.html", "x-a-2.html"]
+    );
+}
+
+#[test]
+fn test_weave_links_definitions_outside_the_woven_documents() {
+    use crate::weave::{weave_site, WeaveOptions};
+
+    let mut setup = TestSetup::new(&["#"]);
+    let a = "# <<main>>=\n# <<helper>>\n# @\n";
+    setup.clip.read(a, "a.nw");
+    setup.clip.read("Intro.\n# <<helper>>=\nx\n# @\n", "b.nw");
+    let locations = setup.clip.definition_locations("helper");
+    assert_eq!(locations.len(), 1);
+    assert_eq!(
+        (locations[0].file_name.as_str(), locations[0].line),
+        ("b.nw", 1)
+    );
+
+    // Only a.nw is woven; the chunk store still knows where helper is
+    let documents = [("a.nw".to_string(), a.to_string())];
+    let html = weave_html(&setup.clip, "Doc", &documents);
+    assert!(html.contains(
+        "# <span class=\"ref\" title=\"defined in b.nw, line 2\">&lt;&lt;helper&gt;&gt;</span>\n"
+    ));
+
+    // On a site holding b.nw's page, the reference links to it
+    let documents = [
+        ("a.nw".to_string(), a.to_string()),
+        ("b.nw".to_string(), "Intro.\n".to_string()),
+    ];
+    let pages = weave_site(&setup.clip, "Site", &documents, &WeaveOptions::default());
+    assert!(pages[1].1.contains(
+        "# <a class=\"ref\" href=\"b.html\" title=\"defined in b.nw, line 2\">&lt;&lt;helper&gt;&gt;</a>\n"
+    ));
+}
+
+#[test]
+fn test_weave_highlights_annotated_language() {
+    use crate::highlight::Theme;
+    use crate::weave::{weave_html_with_options, WeaveOptions};
+
+    let mut setup = TestSetup::new(&["#"]);
+    let text = "# <<setup>>= sh\nexport X=1\n# @\n";
+    setup.clip.read(text, "doc.nw");
+    let documents = [("doc.nw".to_string(), text.to_string())];
+
+    let html = weave_html_with_options(
+        &setup.clip,
+        "Doc",
+        &documents,
+        &WeaveOptions {
+            theme: Some(Theme::Light),
+            ..WeaveOptions::default()
+        },
+    );
+    assert!(html
+        .contains("<pre><code class=\"language-sh\"><span class=\"hl-source hl-shell hl-bash\">"));
+}
+
+#[test]
+fn test_doc_sections_are_kept_for_weaving() {
+    let mut setup = TestSetup::new(&["#"]);
+    let text =
+        "# <<@doc intro>>=\nWhy <main> exists.\n\nSee <<main>>.\n# @\n# <<main>>=\nrun\n# @\n";
+    setup.clip.read(text, "doc.nw");
+
+    let docs = setup.clip.doc_sections();
+    assert_eq!(docs.len(), 1);
+    assert_eq!(docs[0].name, "intro");
+    assert_eq!(docs[0].location.line, 0);
+    assert_eq!(docs[0].location.file_name, "doc.nw");
+    assert_eq!(
+        docs[0].content,
+        vec!["Why <main> exists.\n", "\n", "See <<main>>.\n"]
+    );
+    assert!(!setup.clip.has_chunk("intro"));
+    assert_eq!(setup.clip.get_chunk_content("main").unwrap(), vec!["run\n"]);
+
+    let html = weave_html(
+        &setup.clip,
+        "Doc",
+        &[("doc.nw".to_string(), text.to_string())],
+    );
+    assert!(html.contains(
+        "<div class=\"doc\" id=\"doc-intro\">\n<p>Why &lt;main&gt; exists.</p>\n<p>See &lt;&lt;main&gt;&gt;.</p>\n</div>\n"
+    ));
+    assert!(html.contains("Section 1: "));
+    assert!(!html.contains("Section 2: "));
+}
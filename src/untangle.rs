@@ -0,0 +1,459 @@
+// src/untangle.rs
+//! Untangling: carry edits made to generated files back into the literate sources.
+//!
+//! Every expanded line knows which source line produced it, so a line diff
+//! between the expected expansion of a file chunk and the file currently under
+//! gen/ can be mapped back onto the definitions that produced it.
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+
+use crate::notebook::is_notebook;
+use crate::noweb::{Clip, Provenance};
+use crate::AzadiError;
+
+/// Beyond this many diff cells the changed region is treated as one hunk.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// A replacement of consecutive source lines (or an insertion when `old` is empty).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceEdit {
+    pub file_name: String,
+    /// Zero-based index of the first replaced line, or the insertion point.
+    pub line: usize,
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+}
+
+/// The outcome of comparing generated files with their sources.
+#[derive(Debug, Default)]
+pub struct UntangleReport {
+    pub edits: Vec<SourceEdit>,
+    /// Output changes that could not be mapped back, described for the user.
+    pub skipped: Vec<String>,
+    /// Output paths whose on-disk content differs from the expansion.
+    pub changed_outputs: Vec<String>,
+}
+
+/// A contiguous region where `expected[a_start..a_end]` became `actual[b_start..b_end]`.
+#[derive(Debug, PartialEq)]
+struct Hunk {
+    a_start: usize,
+    a_end: usize,
+    b_start: usize,
+    b_end: usize,
+}
+
+fn strip_eol(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])
+}
+
+/// The line ending of `line`, if it has one.
+fn eol_of(line: &str) -> Option<&'static str> {
+    if line.ends_with("\r\n") {
+        Some("\r\n")
+    } else if line.ends_with('\n') {
+        Some("\n")
+    } else {
+        None
+    }
+}
+
+/// The ending of the first terminated line of `lines`, or `\n`.
+fn file_eol<S: AsRef<str>>(lines: &[S]) -> &'static str {
+    lines
+        .iter()
+        .find_map(|l| eol_of(l.as_ref()))
+        .unwrap_or("\n")
+}
+
+/// Line diff between `a` and `b`, as a list of changed hunks.
+fn diff_hunks(a: &[&str], b: &[&str]) -> Vec<Hunk> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+
+    if a_mid.is_empty() && b_mid.is_empty() {
+        return Vec::new();
+    }
+    let whole = Hunk {
+        a_start: prefix,
+        a_end: prefix + a_mid.len(),
+        b_start: prefix,
+        b_end: prefix + b_mid.len(),
+    };
+    if a_mid.is_empty() || b_mid.is_empty() || a_mid.len() * b_mid.len() > MAX_DIFF_CELLS {
+        return vec![whole];
+    }
+
+    // Longest common subsequence table over the changed middle.
+    let (n, m) = (a_mid.len(), b_mid.len());
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if a_mid[i] == b_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut open: Option<(usize, usize)> = None;
+    while i < n || j < m {
+        if i < n && j < m && a_mid[i] == b_mid[j] {
+            if let Some((si, sj)) = open.take() {
+                hunks.push(Hunk {
+                    a_start: prefix + si,
+                    a_end: prefix + i,
+                    b_start: prefix + sj,
+                    b_end: prefix + j,
+                });
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+        open.get_or_insert((i, j));
+        if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            j += 1;
+        } else {
+            i += 1;
+        }
+    }
+    if let Some((si, sj)) = open {
+        hunks.push(Hunk {
+            a_start: prefix + si,
+            a_end: prefix + n,
+            b_start: prefix + sj,
+            b_end: prefix + m,
+        });
+    }
+    hunks
+}
+
+/// Leading `base_indent` characters of a source line, reused for replacement lines.
+fn source_prefix(source_line: &str, base_indent: usize) -> String {
+    source_line.chars().take(base_indent).collect()
+}
+
+/// Turn an output line back into the source line that would produce it,
+/// ended with `eol`.
+fn to_source_line(
+    clip: &Clip,
+    output_line: &str,
+    provenance: &Provenance,
+    prefix: &str,
+    eol: &str,
+) -> String {
+    let body = strip_eol(output_line);
+    let body = body
+        .strip_prefix(provenance.indent.as_str())
+        .unwrap_or(body);
+    format!("{}{}{}", prefix, clip.escape(body), eol)
+}
+
+struct SourceCache {
+    files: HashMap<String, Option<Vec<String>>>,
+}
+
+impl SourceCache {
+    fn lines(&mut self, file_name: &str) -> Option<&Vec<String>> {
+        self.files
+            .entry(file_name.to_string())
+            .or_insert_with(|| {
+                if is_notebook(file_name) {
+                    return None;
+                }
+                fs::read_to_string(file_name)
+                    .ok()
+                    .map(|text| text.split_inclusive('\n').map(str::to_string).collect())
+            })
+            .as_ref()
+    }
+}
+
+/// Map one hunk onto a source edit, or explain why it cannot be mapped.
+fn map_hunk(
+    clip: &Clip,
+    sources: &mut SourceCache,
+    expected: &[(String, Provenance)],
+    actual: &[&str],
+    hunk: &Hunk,
+) -> Result<SourceEdit, String> {
+    let replaced = &expected[hunk.a_start..hunk.a_end];
+    let inserted = &actual[hunk.b_start..hunk.b_end];
+
+    // The provenance that anchors the edit, and where in the source it applies.
+    let (anchor, line) = if let Some((_, first)) = replaced.first() {
+        let contiguous = replaced.iter().enumerate().all(|(k, (_, p))| {
+            p.location.file_idx == first.location.file_idx
+                && p.location.line == first.location.line + k
+                && p.chunk == first.chunk
+        });
+        if !contiguous {
+            return Err(format!(
+                "changed lines span several definitions (starting in chunk '{}')",
+                first.chunk
+            ));
+        }
+        (first, first.location.line)
+    } else if hunk.a_start > 0 {
+        let prev = &expected[hunk.a_start - 1].1;
+        (prev, prev.location.line + 1)
+    } else if let Some((_, next)) = expected.first() {
+        (next, next.location.line)
+    } else {
+        return Err("nothing to anchor inserted lines to".to_string());
+    };
+
+    let file_name = anchor.location.file_name.clone();
+    let source = sources
+        .lines(&file_name)
+        .ok_or_else(|| format!("source '{}' cannot be edited", file_name))?;
+
+    if line + replaced.len() > source.len() {
+        return Err(format!("{} is shorter than expected", file_name));
+    }
+
+    // The source must still produce what we expect before we touch it.
+    for (k, (output, provenance)) in replaced.iter().enumerate() {
+        let idx = line + k;
+        let stripped: String = source[idx].chars().skip(provenance.base_indent).collect();
+        let regenerated = format!(
+            "{}{}",
+            provenance.indent,
+            clip.unescape(strip_eol(&stripped))
+        );
+        if regenerated != strip_eol(output) {
+            return Err(format!(
+                "{} line {} no longer matches the generated output",
+                file_name,
+                idx + 1
+            ));
+        }
+    }
+
+    let prefix_line = source
+        .get(anchor.location.line)
+        .map(String::as_str)
+        .unwrap_or("");
+    let prefix = source_prefix(prefix_line, anchor.base_indent);
+    // New lines end like the line they replace or follow
+    let eol = source
+        .get(line)
+        .or_else(|| source.get(anchor.location.line))
+        .and_then(|l| eol_of(l))
+        .unwrap_or_else(|| file_eol(source));
+    Ok(SourceEdit {
+        file_name,
+        line,
+        old: source[line..line + replaced.len()].to_vec(),
+        new: inserted
+            .iter()
+            .map(|l| to_source_line(clip, l, anchor, &prefix, eol))
+            .collect(),
+    })
+}
+
+/// Compare every file chunk with its file under gen/ and map the differences
+/// back onto source edits. Outputs that do not exist yet are ignored.
+pub fn untangle(clip: &Clip) -> Result<UntangleReport, AzadiError> {
+    let mut report = UntangleReport::default();
+    let mut sources = SourceCache {
+        files: HashMap::new(),
+    };
+
+    for chunk in clip.get_file_chunks() {
+        let path = chunk["@file ".len()..].trim();
+        // Managed files are mostly hand-written; only their regions are generated.
+        if clip.is_managed(&chunk) {
+            continue;
+        }
+        let output = clip.output_path(path);
+        let Ok(text) = fs::read_to_string(&output) else {
+            continue;
+        };
+        let expected = clip.expand_with_provenance(&chunk, "")?;
+        let expected_lines: Vec<&str> = expected.iter().map(|(l, _)| strip_eol(l)).collect();
+        let actual: Vec<&str> = text.lines().collect();
+
+        let hunks = diff_hunks(&expected_lines, &actual);
+        if hunks.is_empty() {
+            continue;
+        }
+        report.changed_outputs.push(path.to_string());
+
+        for hunk in &hunks {
+            match map_hunk(clip, &mut sources, &expected, &actual, hunk) {
+                Ok(edit) => {
+                    let overlaps = report.edits.iter().any(|e| {
+                        e.file_name == edit.file_name
+                            && e.line < edit.line + edit.old.len().max(1)
+                            && edit.line < e.line + e.old.len().max(1)
+                    });
+                    if report.edits.contains(&edit) {
+                        continue;
+                    }
+                    if overlaps {
+                        report.skipped.push(format!(
+                            "{}: conflicting edits to {} line {}",
+                            path,
+                            edit.file_name,
+                            edit.line + 1
+                        ));
+                    } else {
+                        report.edits.push(edit);
+                    }
+                }
+                Err(reason) => report.skipped.push(format!(
+                    "{}: lines {}-{} skipped: {}",
+                    path,
+                    hunk.b_start + 1,
+                    hunk.b_end.max(hunk.b_start + 1),
+                    reason
+                )),
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Unchanged source lines shown around each change in a patch.
+const PATCH_CONTEXT: usize = 3;
+
+/// Render edits as a unified diff against the sources, with three lines of
+/// context, for `git apply` or `patch -p1`.
+pub fn format_patch(edits: &[SourceEdit]) -> io::Result<String> {
+    let mut by_file: Vec<(&str, Vec<&SourceEdit>)> = Vec::new();
+    for edit in edits {
+        match by_file.iter_mut().find(|(f, _)| *f == edit.file_name) {
+            Some((_, file_edits)) => file_edits.push(edit),
+            None => by_file.push((&edit.file_name, vec![edit])),
+        }
+    }
+
+    let mut out = String::new();
+    for (file_name, mut file_edits) in by_file {
+        let text = fs::read_to_string(file_name)?;
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        // The last line of a file without a final newline needs a marker after it
+        let unterminated = |idx: usize| idx + 1 == lines.len() && !text.ends_with('\n');
+        file_edits.sort_by_key(|e| e.line);
+
+        let _ = writeln!(out, "--- a/{}", file_name);
+        let _ = writeln!(out, "+++ b/{}", file_name);
+        // Lines added minus lines removed by the hunks written so far
+        let mut offset = 0isize;
+        let mut rest = &file_edits[..];
+        while let Some(first) = rest.first() {
+            // Edits whose context would overlap share a hunk
+            let mut count = 1;
+            while count < rest.len() {
+                let prev = rest[count - 1];
+                if rest[count].line > prev.line + prev.old.len() + 2 * PATCH_CONTEXT {
+                    break;
+                }
+                count += 1;
+            }
+            let (group, tail) = rest.split_at(count);
+            rest = tail;
+
+            let start = first.line.saturating_sub(PATCH_CONTEXT).min(lines.len());
+            let mut body = String::new();
+            let (mut old_len, mut new_len) = (0, 0);
+            let mut pos = start;
+            let context = |body: &mut String, from: usize, to: usize| {
+                for (idx, line) in lines.iter().enumerate().take(to).skip(from) {
+                    let _ = writeln!(body, " {}", strip_eol(line));
+                    if unterminated(idx) {
+                        body.push_str("\\ No newline at end of file\n");
+                    }
+                }
+                to - from
+            };
+            for edit in group {
+                let line = edit.line.clamp(pos, lines.len());
+                let shown = context(&mut body, pos, line);
+                old_len += shown;
+                new_len += shown;
+                for (k, old) in edit.old.iter().enumerate() {
+                    let _ = writeln!(body, "-{}", strip_eol(old));
+                    if unterminated(line + k) {
+                        body.push_str("\\ No newline at end of file\n");
+                    }
+                }
+                for new in &edit.new {
+                    let _ = writeln!(body, "+{}", strip_eol(new));
+                }
+                old_len += edit.old.len();
+                new_len += edit.new.len();
+                pos = (line + edit.old.len()).min(lines.len());
+            }
+            let end = (pos + PATCH_CONTEXT).min(lines.len());
+            let shown = context(&mut body, pos, end);
+            old_len += shown;
+            new_len += shown;
+
+            // An empty side is numbered by the line before it
+            let old_start = if old_len == 0 { start } else { start + 1 };
+            let new_first = (start as isize + offset) as usize;
+            let new_start = if new_len == 0 {
+                new_first
+            } else {
+                new_first + 1
+            };
+            let _ = writeln!(
+                out,
+                "@@ -{},{} +{},{} @@",
+                old_start, old_len, new_start, new_len
+            );
+            out.push_str(&body);
+            offset += new_len as isize - old_len as isize;
+        }
+    }
+    Ok(out)
+}
+
+/// Apply edits to the source files on disk. A file without a final newline
+/// is left without one.
+pub fn apply_edits(edits: &[SourceEdit]) -> io::Result<()> {
+    let mut by_file: HashMap<&str, Vec<&SourceEdit>> = HashMap::new();
+    for edit in edits {
+        by_file.entry(&edit.file_name).or_default().push(edit);
+    }
+    for (file_name, mut file_edits) in by_file {
+        let text = fs::read_to_string(file_name)?;
+        let mut lines: Vec<String> = text.split_inclusive('\n').map(str::to_string).collect();
+        let eol = file_eol(&lines);
+        let unterminated = !text.is_empty() && !text.ends_with('\n');
+        if unterminated {
+            // Terminated while edits may follow it, restored below
+            if let Some(last) = lines.last_mut() {
+                last.push_str(eol);
+            }
+        }
+        // Apply bottom-up so earlier line numbers stay valid.
+        file_edits.sort_by_key(|e| std::cmp::Reverse(e.line));
+        for edit in file_edits {
+            let end = (edit.line + edit.old.len()).min(lines.len());
+            let start = edit.line.min(end);
+            lines.splice(start..end, edit.new.iter().cloned());
+        }
+        let mut result = lines.concat();
+        if let Some(eol) = eol_of(&result).filter(|_| unterminated) {
+            result.truncate(result.len() - eol.len());
+        }
+        fs::write(file_name, result)?;
+    }
+    Ok(())
+}
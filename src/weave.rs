@@ -0,0 +1,799 @@
+// src/weave.rs
+//! Weaving: rendering noweb documents as a single HTML page. Prose becomes
+//! paragraphs, and a `@doc` section a block of paragraphs; each chunk
+//! definition becomes a code block headed by its name, with every reference
+//! linking to the first definition of the chunk it names and every continued
+//! definition linking back to the first one. Each definition is a numbered
+//! section listing where its chunk is defined and used, and a chunk index
+//! closes the page. Bodies in a known language are syntax highlighted.
+//! Alternatively, `weave_site` renders each document as a page of its own,
+//! with an index page and links across the pages.
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::highlight::{Highlighter, Language, Theme};
+use crate::noweb::LineKind;
+use crate::Clip;
+
+const STYLE: &str = "body { max-width: 50em; margin: 2em auto; font-family: sans-serif; }
+.chunk { margin: 1em 0; }
+.chunk-name { font-family: monospace; font-weight: bold; }
+pre { background: #f6f6f6; padding: 0.5em; overflow-x: auto; }
+a.ref { text-decoration: none; }
+.chunk-xref { font-size: smaller; margin-top: -0.5em; }
+.site-nav { margin-bottom: 1em; }";
+
+/// The markup around the woven content. Each part is text with `{key}`
+/// placeholders; braces around anything else are kept as written.
+///
+/// - `header`: `{title}`, `{style}` (the built-in style sheet)
+/// - `footer`: `{title}`
+/// - `chunk`, one chunk definition: `{section}` (its number), `{section_id}`,
+///   `{chunk_id}` (anchor of the chunk's first definition), `{anchor_attr}`
+///   (that anchor as an `id` attribute, on the first definition only),
+///   `{name}`, `{heading}` (the name followed by `=` or `+=`), `{lang}`,
+///   `{code_attr}`, `{code}` and `{xref}` (where the chunk is defined and used)
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeaveTemplate {
+    pub header: String,
+    pub footer: String,
+    pub chunk: String,
+}
+
+impl Default for WeaveTemplate {
+    fn default() -> Self {
+        Self {
+            header: "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n{style}\n</style>\n</head>\n<body>\n<h1>{title}</h1>\n".to_string(),
+            footer: "</body>\n</html>\n".to_string(),
+            chunk: "<a id=\"{section_id}\"></a><div class=\"chunk\"{anchor_attr}><div class=\"chunk-name\">{heading}</div><pre><code{code_attr}>{code}</code></pre></div>\n<div class=\"chunk-xref\">Section {section}: {xref}.</div>\n".to_string(),
+        }
+    }
+}
+
+impl WeaveTemplate {
+    /// Read `header.html`, `footer.html` and `chunk.html` from `dir`; the
+    /// parts without a file keep their default.
+    pub fn load<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let mut template = Self::default();
+        for (file, part) in [
+            ("header.html", &mut template.header),
+            ("footer.html", &mut template.footer),
+            ("chunk.html", &mut template.chunk),
+        ] {
+            let path = dir.as_ref().join(file);
+            if path.exists() {
+                *part = fs::read_to_string(path)?;
+            }
+        }
+        Ok(template)
+    }
+}
+
+/// Options for weaving.
+#[derive(Debug, Clone)]
+pub struct WeaveOptions {
+    /// Colour scheme of highlighted chunk bodies; `None` leaves them plain.
+    pub theme: Option<Theme>,
+    /// Directory where the rendering of each document is cached, so that
+    /// unchanged documents are not rendered again.
+    pub cache_dir: Option<PathBuf>,
+    /// Markup around the content.
+    pub template: WeaveTemplate,
+}
+
+impl Default for WeaveOptions {
+    fn default() -> Self {
+        Self {
+            theme: Some(Theme::Light),
+            cache_dir: None,
+            template: WeaveTemplate::default(),
+        }
+    }
+}
+
+/// Escape text for HTML element content and attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// `name` with every byte but ASCII letters and digits written as `-` and
+/// two hex digits, e.g. `a_b` as `a-5fb`, so that different names never
+/// share a slug.
+fn slug(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("-{:02x}", b));
+        }
+    }
+    out
+}
+
+/// The anchor of a chunk's first definition.
+pub(crate) fn anchor(name: &str) -> String {
+    format!("chunk-{}", slug(name))
+}
+
+/// The anchor of a @doc section.
+fn doc_anchor(name: &str) -> String {
+    format!("doc-{}", slug(name))
+}
+
+/// Emit the pending prose lines as one paragraph.
+fn flush(paragraph: &mut Vec<&str>, body: &mut String) {
+    if !paragraph.is_empty() {
+        body.push_str(&format!("<p>{}</p>\n", escape_html(&paragraph.join("\n"))));
+        paragraph.clear();
+    }
+}
+
+/// Where every chunk is defined and used. Sections are the chunk
+/// definitions, numbered from 1 in the order they appear on the page.
+pub(crate) struct CrossReferences {
+    /// Chunk name and declared language of every section.
+    sections: Vec<(String, Option<Language>)>,
+    /// Sections defining each chunk.
+    pub(crate) defined: HashMap<String, Vec<usize>>,
+    /// Sections whose bodies reference each chunk.
+    used: HashMap<String, Vec<usize>>,
+    /// Number of sections before each document.
+    starts: Vec<usize>,
+    /// Digest of each document's lines as the reader sees them.
+    fingerprints: Vec<u64>,
+}
+
+impl CrossReferences {
+    /// Scan the documents the way `weave_html_with_options` renders them.
+    pub(crate) fn collect(clip: &Clip, documents: &[(String, String)]) -> Self {
+        let mut xref = CrossReferences {
+            sections: Vec::new(),
+            defined: HashMap::new(),
+            used: HashMap::new(),
+            starts: Vec::new(),
+            fingerprints: Vec::new(),
+        };
+        for (file_name, text) in documents {
+            xref.starts.push(xref.sections.len());
+            let mut hasher = DefaultHasher::new();
+            // The verbatim flag of the chunk being read, if any
+            let mut in_chunk: Option<bool> = None;
+            for line in text.lines() {
+                let kind = clip.classify(file_name, line);
+                (line, &kind).hash(&mut hasher);
+                match (kind, in_chunk) {
+                    (LineKind::Close, Some(_)) => in_chunk = None,
+                    (LineKind::Reference { name }, Some(false)) => {
+                        let section = xref.sections.len();
+                        let users = xref.used.entry(name).or_default();
+                        if users.last() != Some(&section) {
+                            users.push(section);
+                        }
+                    }
+                    (
+                        LineKind::Open {
+                            name,
+                            verbatim,
+                            lang,
+                        },
+                        None,
+                    ) => {
+                        let language = lang.as_deref().and_then(Language::by_name);
+                        xref.sections.push((name.clone(), language));
+                        xref.defined
+                            .entry(name)
+                            .or_default()
+                            .push(xref.sections.len());
+                        in_chunk = Some(verbatim);
+                    }
+                    // A @doc section is prose: its references are not uses
+                    (LineKind::Doc { .. }, None) => in_chunk = Some(true),
+                    _ => {}
+                }
+            }
+            xref.fingerprints.push(hasher.finish());
+        }
+        xref
+    }
+
+    /// The language of every chunk: the one given with `@lang(NAME)`, else the
+    /// one of a `@file` chunk's extension, else one of the chunks referencing it.
+    fn languages(&self) -> HashMap<String, Language> {
+        fn resolve(
+            xref: &CrossReferences,
+            name: &str,
+            visited: &mut HashSet<String>,
+        ) -> Option<Language> {
+            if !visited.insert(name.to_string()) {
+                return None;
+            }
+            let declared = xref.defined.get(name).and_then(|sections| {
+                sections
+                    .iter()
+                    .find_map(|&section| xref.sections[section - 1].1)
+            });
+            if declared.is_some() {
+                return declared;
+            }
+            if let Some(path) = name.strip_prefix("@file ") {
+                return Language::by_path(path);
+            }
+            xref.used.get(name)?.iter().find_map(|&section| {
+                let user = &xref.sections[section - 1].0;
+                resolve(xref, user, visited)
+            })
+        }
+
+        self.defined
+            .keys()
+            .filter_map(|name| {
+                let language = resolve(self, name, &mut HashSet::new())?;
+                Some((name.clone(), language))
+            })
+            .collect()
+    }
+
+    /// The summary of `name`, with each section number written by `link`.
+    pub(crate) fn summary_with(&self, name: &str, link: impl Fn(usize) -> String) -> String {
+        let defined = self.defined.get(name).map_or(&[][..], Vec::as_slice);
+        let used = self.used.get(name).map_or(&[][..], Vec::as_slice);
+        let mut text = format!("defined in {}", section_links(defined, &link));
+        if !used.is_empty() {
+            text.push_str(&format!("; used in {}", section_links(used, &link)));
+        } else if !name.starts_with("@file ") {
+            text.push_str("; never used");
+        }
+        text
+    }
+}
+
+/// The anchor of a section.
+pub(crate) fn section_anchor(section: usize) -> String {
+    format!("section-{}", section)
+}
+
+/// "section 1" or "sections 1, 3", each number written by `link`.
+fn section_links(sections: &[usize], link: impl Fn(usize) -> String) -> String {
+    let links: Vec<String> = sections.iter().map(|&section| link(section)).collect();
+    let noun = if sections.len() == 1 {
+        "section"
+    } else {
+        "sections"
+    };
+    format!("{} {}", noun, links.join(", "))
+}
+
+/// Render `documents` (file name, text) read with `clip`'s delimiters as one
+/// HTML page titled `title`.
+pub fn weave_html(clip: &Clip, title: &str, documents: &[(String, String)]) -> String {
+    weave_html_with_options(clip, title, documents, &WeaveOptions::default())
+}
+
+/// Like `weave_html`, with explicit options.
+pub fn weave_html_with_options(
+    clip: &Clip,
+    title: &str,
+    documents: &[(String, String)],
+    options: &WeaveOptions,
+) -> String {
+    let page = Page::new(clip, documents, options, Vec::new());
+    let mut body = page.documents_html(documents, options).concat();
+    body.push_str(&page.chunk_index());
+    page.wrap(title, &body, options)
+}
+
+/// The file name of each document's page in a site: its path without the
+/// extension and with unusual characters replaced, made unique.
+pub fn page_names(documents: &[(String, String)]) -> Vec<String> {
+    let mut taken: HashSet<String> = HashSet::from(["index".to_string()]);
+    documents
+        .iter()
+        .map(|(file_name, _)| {
+            let stem = Path::new(file_name).with_extension("");
+            let stem: String = stem
+                .to_string_lossy()
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                        c
+                    } else {
+                        '-'
+                    }
+                })
+                .collect();
+            let stem = match stem.trim_matches('-') {
+                "" => "document",
+                stem => stem,
+            };
+            let mut name = stem.to_string();
+            let mut count = 1;
+            while !taken.insert(name.clone()) {
+                count += 1;
+                name = format!("{}-{}", stem, count);
+            }
+            format!("{}.html", name)
+        })
+        .collect()
+}
+
+/// Render `documents` as a site: `index.html`, listing the documents and
+/// every chunk, and a page for each document named by `page_names`, with
+/// links between them. Returns each page's file name and content, the index
+/// first.
+pub fn weave_site(
+    clip: &Clip,
+    title: &str,
+    documents: &[(String, String)],
+    options: &WeaveOptions,
+) -> Vec<(String, String)> {
+    let names = page_names(documents);
+    let page = Page::new(clip, documents, options, names.clone());
+    let fragments = page.documents_html(documents, options);
+
+    let mut index = String::from("<h2>Documents</h2>\n<ul class=\"site-documents\">\n");
+    for (i, (file_name, _)) in documents.iter().enumerate() {
+        let sections = page.sections_of(i).len();
+        index.push_str(&format!(
+            "<li><a href=\"{}\">{}</a> ({} {})</li>\n",
+            names[i],
+            escape_html(file_name),
+            sections,
+            if sections == 1 { "section" } else { "sections" }
+        ));
+    }
+    index.push_str("</ul>\n");
+    index.push_str(&page.chunk_index());
+
+    let mut pages = vec![("index.html".to_string(), page.wrap(title, &index, options))];
+    for (i, fragment) in fragments.iter().enumerate() {
+        let mut nav = String::from("<nav class=\"site-nav\"><a href=\"index.html\">Contents</a>");
+        if i > 0 {
+            nav.push_str(&format!(
+                " | <a href=\"{}\">&larr; {}</a>",
+                names[i - 1],
+                escape_html(&documents[i - 1].0)
+            ));
+        }
+        if i + 1 < documents.len() {
+            nav.push_str(&format!(
+                " | <a href=\"{}\">{} &rarr;</a>",
+                names[i + 1],
+                escape_html(&documents[i + 1].0)
+            ));
+        }
+        nav.push_str("</nav>\n");
+        let body = format!("{}{}", nav, fragment);
+        pages.push((names[i].clone(), page.wrap(title, &body, options)));
+    }
+    pages
+}
+
+/// What rendering one document needs to know about the whole page.
+struct Page<'a> {
+    clip: &'a Clip,
+    open: String,
+    close: String,
+    xref: CrossReferences,
+    languages: HashMap<String, Language>,
+    /// File name of each document's page in a site; empty for a single page.
+    pages: Vec<String>,
+    /// File name of each document.
+    files: Vec<String>,
+}
+
+impl<'a> Page<'a> {
+    fn new(
+        clip: &'a Clip,
+        documents: &[(String, String)],
+        options: &WeaveOptions,
+        pages: Vec<String>,
+    ) -> Self {
+        let (open, close) = clip.delimiters();
+        let xref = CrossReferences::collect(clip, documents);
+        let languages = match options.theme {
+            Some(_) => xref.languages(),
+            None => HashMap::new(),
+        };
+        Page {
+            clip,
+            open,
+            close,
+            xref,
+            languages,
+            pages,
+            files: documents.iter().map(|(name, _)| name.clone()).collect(),
+        }
+    }
+}
+
+impl Page<'_> {
+    /// The rendering of each document, from the cache where it is current.
+    fn documents_html(
+        &self,
+        documents: &[(String, String)],
+        options: &WeaveOptions,
+    ) -> Vec<String> {
+        let template = &options.template.chunk;
+        let mut fragments = Vec::new();
+        for (index, (file_name, text)) in documents.iter().enumerate() {
+            let Some(dir) = &options.cache_dir else {
+                fragments.push(self.render(index, file_name, text, template));
+                continue;
+            };
+            let key = self.cache_key(index, file_name, options);
+            let path = cache_path(dir, file_name);
+            if let Some(fragment) = cached(&path, &key) {
+                debug!("weave: {} is unchanged, reusing its rendering", file_name);
+                fragments.push(fragment);
+                continue;
+            }
+            let fragment = self.render(index, file_name, text, template);
+            let stored = fs::create_dir_all(dir)
+                .and_then(|_| fs::write(&path, format!("{}\n{}", key, fragment)));
+            if let Err(e) = stored {
+                debug!("weave: cannot cache the rendering of {}: {}", file_name, e);
+            }
+            fragments.push(fragment);
+        }
+        fragments
+    }
+
+    /// Every chunk, with where it is defined and used.
+    fn chunk_index(&self) -> String {
+        let mut names: Vec<&String> = self.xref.defined.keys().collect();
+        if names.is_empty() {
+            return String::new();
+        }
+        names.sort();
+        let mut index = String::from("<h2>Chunk index</h2>\n<ul class=\"chunk-index\">\n");
+        for name in names {
+            index.push_str(&format!(
+                "<li><a class=\"ref\" href=\"{}\">{}</a>: {}</li>\n",
+                self.chunk_href(name),
+                self.show(name),
+                self.summary(name)
+            ));
+        }
+        index.push_str("</ul>\n");
+        index
+    }
+
+    /// `body` between the header and footer of the template.
+    fn wrap(&self, title: &str, body: &str, options: &WeaveOptions) -> String {
+        let style = match options.theme {
+            Some(theme) => format!("{}\n{}", STYLE, theme.css()),
+            None => STYLE.to_string(),
+        };
+        let title = escape_html(title);
+        format!(
+            "{}{}{}",
+            fill(
+                &options.template.header,
+                &[("title", &title), ("style", &style)]
+            ),
+            body,
+            fill(&options.template.footer, &[("title", &title)])
+        )
+    }
+
+    /// The page holding a section: empty on a single page.
+    fn page_of(&self, section: usize) -> &str {
+        let document = self.xref.starts.partition_point(|&start| start < section) - 1;
+        self.pages.get(document).map_or("", String::as_str)
+    }
+
+    /// The link target of a section.
+    fn section_href(&self, section: usize) -> String {
+        format!("{}#{}", self.page_of(section), section_anchor(section))
+    }
+
+    /// The link target of a chunk's first definition.
+    fn chunk_href(&self, name: &str) -> String {
+        match self
+            .xref
+            .defined
+            .get(name)
+            .and_then(|sections| sections.first())
+        {
+            Some(&section) => format!("{}#{}", self.page_of(section), anchor(name)),
+            None => format!("#{}", anchor(name)),
+        }
+    }
+
+    /// A reference to chunk `name`, linking to its first definition. A chunk
+    /// no section defines is looked up in the chunk store, which knows every
+    /// document read, woven or not: on a site, the reference links to the
+    /// page of the document defining it; elsewhere, it names the document in
+    /// a tooltip.
+    fn reference_html(&self, name: &str) -> String {
+        let location = match self.clip.definition_locations(name).into_iter().next() {
+            Some(location) if !self.xref.defined.contains_key(name) => location,
+            _ => {
+                return format!(
+                    "<a class=\"ref\" href=\"{}\">{}</a>",
+                    self.chunk_href(name),
+                    self.show(name)
+                )
+            }
+        };
+        let title = escape_html(&format!(
+            "defined in {}, line {}",
+            location.file_name,
+            location.line + 1
+        ));
+        let page = self
+            .files
+            .iter()
+            .position(|file| *file == location.file_name)
+            .and_then(|document| self.pages.get(document));
+        match page {
+            Some(page) => format!(
+                "<a class=\"ref\" href=\"{}\" title=\"{}\">{}</a>",
+                page,
+                title,
+                self.show(name)
+            ),
+            None => format!(
+                "<span class=\"ref\" title=\"{}\">{}</span>",
+                title,
+                self.show(name)
+            ),
+        }
+    }
+
+    /// "defined in sections 1, 3; used in section 2", with the numbers linked.
+    fn summary(&self, name: &str) -> String {
+        self.xref.summary_with(name, |section| {
+            format!("<a href=\"{}\">{}</a>", self.section_href(section), section)
+        })
+    }
+
+    /// A chunk name between the delimiters, escaped.
+    fn show(&self, name: &str) -> String {
+        escape_html(&format!("{}{}{}", self.open, name, self.close))
+    }
+
+    /// The sections of document `index`, by number.
+    fn sections_of(&self, index: usize) -> std::ops::Range<usize> {
+        let first = self.xref.starts[index] + 1;
+        let end = self
+            .xref
+            .starts
+            .get(index + 1)
+            .map_or(self.xref.sections.len(), |&s| s);
+        first..end + 1
+    }
+
+    /// A digest of everything the rendering of document `index` depends on.
+    fn cache_key(&self, index: usize, file_name: &str, options: &WeaveOptions) -> String {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        (&self.open, &self.close, file_name).hash(&mut hasher);
+        options.theme.map(|t| t.to_string()).hash(&mut hasher);
+        options.template.chunk.hash(&mut hasher);
+        self.xref.fingerprints[index].hash(&mut hasher);
+        for section in self.sections_of(index) {
+            let name = &self.xref.sections[section - 1].0;
+            section.hash(&mut hasher);
+            self.summary(name).hash(&mut hasher);
+            self.languages.get(name).map(|l| l.name).hash(&mut hasher);
+        }
+        // References to chunks defined outside the sections depend on the store
+        let mut undefined: Vec<&String> = self
+            .xref
+            .used
+            .keys()
+            .filter(|name| !self.xref.defined.contains_key(*name))
+            .collect();
+        undefined.sort();
+        for name in undefined {
+            self.reference_html(name).hash(&mut hasher);
+        }
+        // On a site, references may link to any page
+        if !self.pages.is_empty() {
+            let mut names: Vec<&String> = self.xref.defined.keys().collect();
+            names.sort();
+            for name in names {
+                self.chunk_href(name).hash(&mut hasher);
+            }
+        }
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Render document `index` as a heading followed by its prose and sections.
+    fn render(&self, index: usize, file_name: &str, text: &str, template: &str) -> String {
+        let clip = self.clip;
+        let mut section = self.xref.starts[index];
+        let mut body = format!("<h2>{}</h2>\n", escape_html(file_name));
+        let mut paragraph: Vec<&str> = Vec::new();
+        let mut verbatim = false;
+        // The name of the chunk being read, and its rendered body so far
+        let mut current: Option<String> = None;
+        let mut code = String::new();
+        let mut highlighter: Option<Highlighter> = None;
+        let mut in_doc = false;
+
+        for line in text.lines() {
+            let kind = clip.classify(file_name, line);
+            if in_doc {
+                match kind {
+                    LineKind::Close => {
+                        flush(&mut paragraph, &mut body);
+                        body.push_str("</div>\n");
+                        in_doc = false;
+                    }
+                    _ if line.trim().is_empty() => flush(&mut paragraph, &mut body),
+                    _ => paragraph.push(line),
+                }
+                continue;
+            }
+            if let Some(name) = &current {
+                match kind {
+                    LineKind::Close => {
+                        body.push_str(&self.section_html(template, section, name, &code));
+                        current = None;
+                    }
+                    LineKind::Reference { name } if !verbatim => {
+                        // Keep what surrounds the reference, e.g. a comment leader
+                        let reference = format!("{}{}{}", self.open, name, self.close);
+                        let (before, after) = line.split_once(&reference).unwrap_or((line, ""));
+                        code.push_str(&format!(
+                            "{}{}{}\n",
+                            escape_html(before),
+                            self.reference_html(&name),
+                            escape_html(after)
+                        ));
+                    }
+                    _ => {
+                        let line = if verbatim {
+                            Cow::Borrowed(line)
+                        } else {
+                            clip.unescape(line)
+                        };
+                        match highlighter.as_mut() {
+                            Some(highlighter) => code.push_str(&highlighter.line(&line)),
+                            None => code.push_str(&escape_html(&line)),
+                        }
+                        code.push('\n');
+                    }
+                }
+                continue;
+            }
+
+            match kind {
+                LineKind::Open {
+                    name,
+                    verbatim: is_verbatim,
+                    ..
+                } => {
+                    flush(&mut paragraph, &mut body);
+                    section += 1;
+                    highlighter = self.languages.get(&name).copied().map(Highlighter::new);
+                    code.clear();
+                    current = Some(name);
+                    verbatim = is_verbatim;
+                }
+                LineKind::Doc { name } => {
+                    flush(&mut paragraph, &mut body);
+                    body.push_str(&format!(
+                        "<div class=\"doc\" id=\"{}\">\n",
+                        doc_anchor(&name)
+                    ));
+                    in_doc = true;
+                }
+                _ if line.trim().is_empty() => flush(&mut paragraph, &mut body),
+                _ => paragraph.push(line),
+            }
+        }
+        flush(&mut paragraph, &mut body);
+        if in_doc {
+            body.push_str("</div>\n");
+        }
+        if let Some(name) = &current {
+            body.push_str(&self.section_html(template, section, name, &code));
+        }
+        body
+    }
+
+    /// Fill the chunk template for a section of chunk `name` whose body
+    /// rendered as `code`.
+    fn section_html(&self, template: &str, section: usize, name: &str, code: &str) -> String {
+        let first = self.xref.defined[name].first() == Some(&section);
+        let heading = if first {
+            format!("{}=", self.show(name))
+        } else {
+            format!(
+                "<a class=\"ref\" href=\"{}\">{}</a>+=",
+                self.chunk_href(name),
+                self.show(name)
+            )
+        };
+        let anchor_attr = if first {
+            format!(" id=\"{}\"", anchor(name))
+        } else {
+            String::new()
+        };
+        let lang = self.languages.get(name).map_or("", |l| l.name);
+        let code_attr = if lang.is_empty() {
+            String::new()
+        } else {
+            format!(" class=\"language-{}\"", lang)
+        };
+        fill(
+            template,
+            &[
+                ("section", &section.to_string()),
+                ("section_id", &section_anchor(section)),
+                ("chunk_id", &anchor(name)),
+                ("anchor_attr", &anchor_attr),
+                ("name", &self.show(name)),
+                ("heading", &heading),
+                ("lang", lang),
+                ("code_attr", &code_attr),
+                ("code", code),
+                ("xref", &self.summary(name)),
+            ],
+        )
+    }
+}
+
+/// Replace each `{key}` in `template` with its value, in one pass, so that
+/// braces in the values or elsewhere in the template are left alone.
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            let key = &after[..end];
+            values
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, value)| (end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                out.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The cache file of a document's rendering.
+fn cache_path(dir: &Path, file_name: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    file_name.hash(&mut hasher);
+    dir.join(format!("{:016x}.html", hasher.finish()))
+}
+
+/// The cached rendering at `path`, if it was stored under `key`.
+fn cached(path: &Path, key: &str) -> Option<String> {
+    let text = fs::read_to_string(path).ok()?;
+    let (stored, fragment) = text.split_once('\n')?;
+    (stored == key).then(|| fragment.to_string())
+}
@@ -121,3 +121,1693 @@ fn test_extract_chunk_to_file() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_failed_extract_leaves_output_untouched() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<main>>=\nfirst\nsecond\n<<missing>>\n@\n",
+    )?;
+    fs::write(dir.path().join("out.txt"), "previous\n")?;
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "extract", "--chunks", "main", "--output", "out.txt", "input.nw",
+    ]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("missing"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out.txt"))?,
+        "previous\n"
+    );
+    assert!(!dir.path().join(".out.txt.tmp").exists());
+    Ok(())
+}
+
+#[test]
+fn test_extract_chunks_to_output_dir() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let input_file = dir.path().join("input.nw");
+    fs::write(
+        &input_file,
+        "<<alpha>>=\nfirst\n@\n<<beta/gamma>>=\nsecond\n@\n",
+    )?;
+
+    let out_dir = dir.path().join("snippets");
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.arg("--priv-dir")
+        .arg(dir.path().join("_azadi_work"))
+        .arg("--gen")
+        .arg(dir.path().join("gen"))
+        .arg("--chunks")
+        .arg("alpha,beta/gamma")
+        .arg("--output-dir")
+        .arg(&out_dir)
+        .arg("--output-template")
+        .arg("{name}.txt")
+        .arg(&input_file);
+
+    cmd.assert().success();
+
+    assert_eq!(fs::read_to_string(out_dir.join("alpha.txt"))?, "first\n");
+    assert_eq!(
+        fs::read_to_string(out_dir.join("beta_gamma.txt"))?,
+        "second\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_extract_output_dir_collision() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("doc.nw"),
+        "<<a/b>>=\nfirst\n@\n<<a_b>>=\nsecond\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "extract",
+        "--chunks",
+        "a/b,a_b",
+        "--output-dir",
+        "out",
+        "doc.nw",
+    ]);
+    cmd.assert().success().stderr(predicate::str::contains(
+        "chunks 'a/b' and 'a_b' both write to",
+    ));
+    assert_eq!(fs::read_to_string(dir.path().join("out/a_b"))?, "first\n");
+    Ok(())
+}
+
+#[test]
+fn test_extract_chunks_by_pattern() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let input_file = dir.path().join("input.nw");
+    fs::write(
+        &input_file,
+        "<<test_one>>=\nT1\n@\n<<test_two>>=\nT2\n@\n<<ch7>>=\nC7\n@\n<<other>>=\nOTHER\n@\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.arg("--priv-dir")
+        .arg(dir.path().join("_azadi_work"))
+        .arg("--gen")
+        .arg(dir.path().join("gen"))
+        .arg("--chunks")
+        .arg("test_*")
+        .arg("--chunks-re")
+        .arg("^ch[0-9]+$")
+        .arg(&input_file);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("T1"))
+        .stdout(predicate::str::contains("T2"))
+        .stdout(predicate::str::contains("C7"))
+        .stdout(predicate::str::contains("OTHER").not());
+    Ok(())
+}
+
+#[test]
+fn test_extract_chunk_reversed() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let input_file = dir.path().join("input.nw");
+    fs::write(
+        &input_file,
+        "<<steps>>=\nfirst\n@\n<<@append steps>>=\nsecond\n@\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.arg("--priv-dir")
+        .arg(dir.path().join("_azadi_work"))
+        .arg("--gen")
+        .arg(dir.path().join("gen"))
+        .arg("--chunks")
+        .arg("steps")
+        .arg("--reversed")
+        .arg(&input_file);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("second\nfirst\n"));
+    Ok(())
+}
+
+#[test]
+fn test_define_overrides_document_constant() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let input_file = dir.path().join("input.nw");
+    fs::write(
+        &input_file,
+        "@define VERSION 1.0\n<<@file version.txt>>=\nversion @{VERSION}\n@\n",
+    )?;
+    let gen_dir = dir.path().join("gen");
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.arg("--priv-dir")
+        .arg(dir.path().join("_azadi_work"))
+        .arg("--gen")
+        .arg(&gen_dir)
+        .arg("-D")
+        .arg("VERSION=2.5")
+        .arg(&input_file);
+
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(gen_dir.join("version.txt"))?,
+        "version 2.5\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_profile_selects_gen_dir_and_files() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let input_file = dir.path().join("input.nw");
+    fs::write(
+        &input_file,
+        "<<@file app.txt>>=\nmode @{MODE}\n@\n<<@file notes.md>>=\nnotes\n@\n",
+    )?;
+    let config = dir.path().join("azadi.toml");
+    fs::write(
+        &config,
+        "[profiles.release]\ngen = \"dist\"\nfiles = [\"*.txt\"]\n\n[profiles.release.defines]\nMODE = \"release\"\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .arg("--priv-dir")
+        .arg("_azadi_work")
+        .arg("--config")
+        .arg(&config)
+        .arg("--profile")
+        .arg("release")
+        .arg(&input_file);
+
+    cmd.assert().success();
+    let dist = dir.path().join("dist");
+    assert_eq!(fs::read_to_string(dist.join("app.txt"))?, "mode release\n");
+    assert!(!dist.join("notes.md").exists());
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .arg("--profile")
+        .arg("missing")
+        .arg(&input_file);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown profile 'missing'"));
+    Ok(())
+}
+
+#[test]
+fn test_emit_depfile() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("main.nw"),
+        "<<@file app.py>>=\n<<helpers>>\nmain()\n@\n<<@file other.txt>>=\nother\n@\n",
+    )?;
+    fs::write(
+        dir.path().join("helpers.nw"),
+        "<<helpers>>=\ndef main(): pass\n@\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .arg("--emit-depfile")
+        .arg("azadi.d")
+        .arg("main.nw")
+        .arg("helpers.nw");
+
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("azadi.d"))?,
+        "gen/app.py: helpers.nw main.nw\ngen/other.txt: main.nw\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_depfile_lists_every_input_read() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    // The file chunk itself emits no line of a.nw, only b.nw's body and the table
+    fs::write(
+        dir.path().join("a.nw"),
+        "<<@file out.txt>>=\n<<body>>\n<<@insert table.txt>>\n@\n",
+    )?;
+    fs::write(dir.path().join("b.nw"), "<<body>>=\nx\n@\n")?;
+    fs::write(dir.path().join("table.txt"), "t\n")?;
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--emit-depfile", "azadi.d", "a.nw", "b.nw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("azadi.d"))?,
+        "gen/out.txt: a.nw b.nw table.txt\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_list_outputs_without_writing() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file src/b.rs>>=\nb\n@\n<<@file a.txt>>=\na\n@\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .arg("--list-outputs")
+        .arg("input.nw");
+    cmd.assert().success().stdout("gen/a.txt\ngen/src/b.rs\n");
+    assert!(!dir.path().join("gen/a.txt").exists());
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .arg("--list-outputs=json")
+        .arg("input.nw");
+    cmd.assert()
+        .success()
+        .stdout("[\"gen/a.txt\", \"gen/src/b.rs\"]\n");
+    Ok(())
+}
+
+#[test]
+fn test_check_reports_stale_outputs() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\na\n@\n<<@file b.txt>>=\nb\n@\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("--check").arg("input.nw");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("stale: gen/a.txt"))
+        .stderr(predicate::str::contains("stale: gen/b.txt"));
+    assert!(!dir.path().join("gen/a.txt").exists());
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("input.nw");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("--check").arg("input.nw");
+    cmd.assert().success();
+
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\nchanged\n@\n<<@file b.txt>>=\nb\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("--check").arg("input.nw");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("stale: gen/a.txt"))
+        .stderr(predicate::str::contains("b.txt").not());
+    assert_eq!(fs::read_to_string(dir.path().join("gen/a.txt"))?, "a\n");
+    Ok(())
+}
+
+#[test]
+fn test_verbosity_flags() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(dir.path().join("input.nw"), "<<@file a.txt>>=\na\n@\n")?;
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("-v").arg("input.nw");
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("debug: generating 'a.txt'"))
+        .stderr(predicate::str::contains("a.txt is new, writing it"))
+        .stderr(predicate::str::contains("trace:").not());
+
+    // Make the output older than its backup, so rewrites are not taken for hand edits
+    let output = fs::File::options()
+        .write(true)
+        .open(dir.path().join("gen/a.txt"))?;
+    output.set_modified(std::time::SystemTime::UNIX_EPOCH)?;
+    drop(output);
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("-vv").arg("input.nw");
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("trace: expanding '@file a.txt'"))
+        .stderr(predicate::str::contains(
+            "a.txt is unchanged, not rewriting it",
+        ));
+
+    fs::write(dir.path().join("input.nw"), "<<@file a.txt>>=\nb\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .arg("tangle")
+        .arg("-q")
+        .arg("input.nw");
+    cmd.assert().success().stdout("").stderr("");
+    assert_eq!(fs::read_to_string(dir.path().join("gen/a.txt"))?, "b\n");
+    Ok(())
+}
+
+#[test]
+fn test_change_notices_stay_off_stdout() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(dir.path().join("input.nw"), "<<@file a.txt>>=\na\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("input.nw");
+    cmd.assert().success();
+
+    // Make the output older than its backup, so the rewrite is not taken for a hand edit
+    let output = fs::File::options()
+        .write(true)
+        .open(dir.path().join("gen/a.txt"))?;
+    output.set_modified(std::time::SystemTime::UNIX_EPOCH)?;
+    drop(output);
+
+    fs::write(dir.path().join("input.nw"), "<<@file a.txt>>=\nb\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .arg("--chunks")
+        .arg("@file a.txt")
+        .arg("input.nw");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("b\n").and(predicate::str::contains("changed").not()))
+        .stderr(predicate::str::contains("a.txt changed"));
+    Ok(())
+}
+
+#[test]
+fn test_json_message_format() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\na\n@\n<<spare>>=\nb\n@\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .arg("tangle")
+        .arg("--message-format")
+        .arg("json")
+        .arg("input.nw");
+    cmd.assert().success().stderr(
+        "{\"level\": \"warning\", \"code\": \"AZ0101\", \
+         \"message\": \"input.nw line 4: \
+         chunk 'spare' is defined but never referenced\"}\n",
+    );
+
+    // Like text, JSON shows progress only when verbose, and -q leaves out notices
+    let verbose = tempdir()?;
+    fs::copy(dir.path().join("input.nw"), verbose.path().join("input.nw"))?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(verbose.path())
+        .args(["tangle", "-v", "--message-format", "json", "input.nw"]);
+    cmd.assert().success().stderr(predicate::str::contains(
+        "{\"level\": \"progress\", \"code\": null, \"message\": \"generating 'a.txt'\"}\n",
+    ));
+
+    let quiet = tempdir()?;
+    fs::write(quiet.path().join("input.nw"), "<<@file a.txt>>=\nb\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(quiet.path())
+        .args(["tangle", "-q", "--message-format", "json", "input.nw"]);
+    cmd.assert().success().stderr("");
+    Ok(())
+}
+
+#[test]
+fn test_completions_subcommand() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.arg("completions").arg("bash");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "complete -F _azadi__noweb -o nosort -o bashdefault -o default azadi-noweb",
+        ))
+        .stdout(predicate::str::contains("--message-format)"));
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.arg("completions").arg("tcsh");
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_subcommands() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "Intro & notes.\n<<@file a.txt>>=\n<<body>>\n@\n<<body>>=\nhello\n@\n",
+    )?;
+    let azadi = |args: &[&str]| -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("azadi-noweb")?;
+        cmd.current_dir(dir.path()).args(args).arg("input.nw");
+        Ok(cmd)
+    };
+
+    azadi(&["extract", "--chunks", "body"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("hello\n"));
+    assert!(!dir.path().join("gen/a.txt").exists());
+    azadi(&["extract"])?.assert().failure();
+
+    azadi(&["list"])?.assert().success().stdout("gen/a.txt\n");
+    azadi(&["check"])?.assert().failure();
+    azadi(&["graph"])?.assert().success().stdout(
+        "digraph chunks {\n    \"@file a.txt\" [shape=box];\n    \"@file a.txt\" -> \"body\";\n}\n",
+    );
+    azadi(&["weave"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<p>Intro &amp; notes.</p>"))
+        .stdout(predicate::str::contains(
+            "<a class=\"ref\" href=\"#chunk-body\">&lt;&lt;body&gt;&gt;</a>",
+        ));
+
+    azadi(&["tangle"])?.assert().success().stderr("");
+    assert_eq!(fs::read_to_string(dir.path().join("gen/a.txt"))?, "hello\n");
+    azadi(&["check"])?.assert().success();
+    Ok(())
+}
+
+#[test]
+fn test_invocation_without_subcommand_is_deprecated() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(dir.path().join("input.nw"), "<<@file a.txt>>=\na\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("input.nw");
+    cmd.assert().success().stderr(predicate::str::contains(
+        "without a subcommand is deprecated",
+    ));
+    assert_eq!(fs::read_to_string(dir.path().join("gen/a.txt"))?, "a\n");
+    Ok(())
+}
+
+#[test]
+fn test_stats_subcommand() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "Prose.\n<<@file a.txt>>=\n<<body>>\n@\n<<body>>=\nhello\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["stats", "--format", "json", "--top", "1"])
+        .arg("input.nw");
+    cmd.assert().success().stdout(
+        "{\"chunks\": 2, \"file_chunks\": 1, \"definitions\": 2, \"references\": 1, \
+         \"code_lines\": 2, \"prose_lines\": 1, \"largest\": [{\"name\": \"@file a.txt\", \
+         \"definitions\": 1, \"lines\": 1, \"references\": 1}]}\n",
+    );
+    assert!(!dir.path().join("gen/a.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_coverage_subcommand() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\n<<body>>\n@\n<<body>>=\nhello\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["coverage", "--format", "json"])
+        .arg("input.nw");
+    cmd.assert().success().stdout(
+        "{\"outputs\": {\"a.txt\": {\"@file a.txt\": 0, \"body\": 1}}, \
+         \"chunks\": {\"@file a.txt\": [\"a.txt\"], \"body\": [\"a.txt\"]}}\n",
+    );
+    Ok(())
+}
+
+#[test]
+fn test_reference_cycles_fail_before_writing() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\nok\n@\n<<@file b.txt>>=\n<<x>>\n<<y>>\n@\n<<x>>=\n<<x>>\n@\n<<y>>=\n<<y>>\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("tangle").arg("input.nw");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("2 reference cycle(s)"))
+        .stderr(predicate::str::contains(
+            "input.nw line 9: 'x' references 'x'",
+        ))
+        .stderr(predicate::str::contains(
+            "input.nw line 12: 'y' references 'y'",
+        ));
+    assert!(!dir.path().join("gen/a.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_orphaned_outputs_pruned() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\na\n@\n<<@file old.txt>>=\nold\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["tangle", "input.nw"]);
+    cmd.assert().success();
+
+    fs::write(dir.path().join("input.nw"), "<<@file b.txt>>=\nb\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["tangle", "input.nw"]);
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("old.txt is no longer defined"));
+    assert!(dir.path().join("gen/old.txt").exists());
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--prune", "input.nw"]);
+    fs::File::options()
+        .write(true)
+        .open(dir.path().join("gen/b.txt"))?
+        .set_modified(std::time::SystemTime::UNIX_EPOCH)?;
+    cmd.assert().success();
+    assert!(!dir.path().join("gen/a.txt").exists());
+    assert!(!dir.path().join("gen/old.txt").exists());
+    assert!(dir.path().join("gen/b.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_deny_warnings_exit_status() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\na\n@\n<<unused>>=\nx\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--deny-warnings", "input.nw"]);
+    cmd.assert()
+        .code(3)
+        .stderr(predicate::str::contains(
+            "'unused' is defined but never referenced",
+        ))
+        .stderr(predicate::str::contains("--deny-warnings"));
+    assert!(dir.path().join("gen/a.txt").exists());
+
+    let clean = tempdir()?;
+    fs::write(clean.path().join("clean.nw"), "<<@file b.txt>>=\nb\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(clean.path())
+        .args(["tangle", "--deny-warnings", "clean.nw"]);
+    cmd.assert().success();
+    Ok(())
+}
+
+#[test]
+fn test_deny_warnings_without_subcommand() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(dir.path().join("input.nw"), "<<@file a.txt>>=\na\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["--deny-warnings", "input.nw"]);
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("AZ0107"));
+    assert!(dir.path().join("gen/a.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_allow_suppresses_warnings_by_code() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\na\n@\n<<unused>>=\nx\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "tangle",
+        "--deny-warnings",
+        "--allow",
+        "AZ0101",
+        "input.nw",
+    ]);
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("never referenced").not());
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["check", "--allow", "AZ9999", "input.nw"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown diagnostic code 'AZ9999'"));
+    Ok(())
+}
+
+#[test]
+fn test_invalid_delimiters_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(dir.path().join("input.nw"), "<<@file a.txt>>=\na\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "tangle",
+        "--open-delim",
+        "@@",
+        "--chunk-end",
+        "@",
+        "input.nw",
+    ]);
+    cmd.assert().code(1).stderr(predicate::str::contains(
+        "Error[AZ0017]: Chunk error: invalid delimiters: the chunk end \"@\" is a prefix of the open delimiter \"@@\"",
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_trailing_text_option() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.c>>=\n<<body>> /* generated */\n@\n<<body>>=\nreturn 0;\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--trailing-text", "append", "input.nw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("gen/a.c"))?,
+        "return 0; /* generated */\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_continuation_option() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file cmd.sh>>=\nexec tool --verbose \\\n    --output out.txt\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--continuation", "\\", "input.nw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("gen/cmd.sh"))?,
+        "exec tool --verbose --output out.txt\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_uncomment_option() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "# <<@file app.py>>=\n# def main():\n#     return 0\n# @\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--uncomment", "#", "input.nw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("gen/app.py"))?,
+        "def main():\n    return 0\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_weave_theme_options() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file main.rs>>=\nfn main() {}\n@\n",
+    )?;
+    let weave = |args: &[&str]| -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("azadi-noweb")?;
+        cmd.current_dir(dir.path())
+            .arg("weave")
+            .args(args)
+            .arg("input.nw");
+        Ok(cmd)
+    };
+    weave(&["--theme", "dark"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("pre { background: #2b303b;"))
+        .stdout(predicate::str::contains(
+            "<code class=\"language-rust\"><span class=\"hl-source hl-rust\">",
+        ));
+    weave(&["--no-highlight"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<pre><code>fn main() {}\n"));
+    weave(&["--theme", "neon"])?.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_weave_leaves_unchanged_page_alone() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(dir.path().join("input.nw"), "<<a>>=\nx\n@\n")?;
+    let weave = || -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("azadi-noweb")?;
+        cmd.current_dir(dir.path())
+            .args(["weave", "-o", "page.html", "input.nw"]);
+        Ok(cmd)
+    };
+    weave()?.assert().success();
+    assert!(dir.path().join("_azadi_work/__weave__").is_dir());
+
+    let page = dir.path().join("page.html");
+    fs::File::options()
+        .write(true)
+        .open(&page)?
+        .set_modified(std::time::UNIX_EPOCH)?;
+    weave()?.assert().success();
+    assert_eq!(fs::metadata(&page)?.modified()?, std::time::UNIX_EPOCH);
+
+    fs::write(dir.path().join("input.nw"), "<<a>>=\ny\n@\n")?;
+    weave()?.assert().success();
+    assert!(fs::read_to_string(&page)?.contains("y\n"));
+    Ok(())
+}
+
+#[test]
+fn test_weave_template_option() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(dir.path().join("input.nw"), "<<a>>=\nx\n@\n")?;
+    fs::create_dir(dir.path().join("templates"))?;
+    fs::write(
+        dir.path().join("templates/header.html"),
+        "<html><head><title>{title}</title></head><body class=\"docs\">\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "weave",
+        "--title",
+        "Manual",
+        "--template",
+        "templates",
+        "input.nw",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "<html><head><title>Manual</title></head><body class=\"docs\">\n<h2>input.nw</h2>",
+        ))
+        .stdout(predicate::str::ends_with("</body>\n</html>\n"));
+    Ok(())
+}
+
+#[test]
+fn test_weave_latex_format() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(dir.path().join("input.nw"), "<<a>>=\nx\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["weave", "--format", "latex", "input.nw"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("\\documentclass{article}\n"))
+        .stdout(predicate::str::contains("\\begin{alltt}\nx\n\\end{alltt}"));
+
+    // PDF output needs a file to go to
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["weave", "--format", "pdf", "input.nw"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_weave_pdf_format() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir()?;
+    fs::write(dir.path().join("input.nw"), "<<a>>=\nx\n@\n")?;
+    let engine = dir.path().join("engine.sh");
+    fs::write(&engine, "#!/bin/sh\necho 'PDF' > weave.pdf\n")?;
+    fs::set_permissions(&engine, fs::Permissions::from_mode(0o755))?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "weave",
+        "--format",
+        "pdf",
+        "--latex-engine",
+        engine.to_str().unwrap(),
+        "-o",
+        "doc.pdf",
+        "input.nw",
+    ]);
+    cmd.assert().success();
+    assert_eq!(fs::read_to_string(dir.path().join("doc.pdf"))?, "PDF\n");
+
+    fs::write(
+        &engine,
+        "#!/bin/sh\necho '! Emergency stop.' > weave.log\nexit 1\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "weave",
+        "--format",
+        "pdf",
+        "--latex-engine",
+        engine.to_str().unwrap(),
+        "-o",
+        "doc.pdf",
+        "input.nw",
+    ]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Error[AZ0018]"))
+        .stderr(predicate::str::contains("! Emergency stop."));
+    Ok(())
+}
+
+#[test]
+fn test_weave_site_option() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(dir.path().join("a.nw"), "<<main>>=\n<<helper>>\n@\n")?;
+    fs::write(dir.path().join("b.nw"), "<<helper>>=\nx\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["weave", "--site", "site", "a.nw", "b.nw"]);
+    cmd.assert().success().stdout("");
+
+    let index = fs::read_to_string(dir.path().join("site/index.html"))?;
+    assert!(index.contains("<a href=\"a.html\">a.nw</a>"));
+    let page = fs::read_to_string(dir.path().join("site/a.html"))?;
+    assert!(page.contains("href=\"b.html#chunk-helper\""));
+    assert!(dir.path().join("site/b.html").exists());
+    Ok(())
+}
+
+#[test]
+fn test_extract_json_output_format() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<main>>=\nsay \"hi\"\n<<body>>\n@\n<<body>>=\nhello\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "extract",
+        "--chunks",
+        "main,body",
+        "--output-format",
+        "json",
+        "input.nw",
+    ]);
+    cmd.assert().success().stdout(concat!(
+        "[{\"name\": \"main\", \"lines\": [\"say \\\"hi\\\"\", \"hello\"], ",
+        "\"sources\": [{\"file\": \"input.nw\", \"line\": 2, \"chunk\": \"main\"}, ",
+        "{\"file\": \"input.nw\", \"line\": 6, \"chunk\": \"body\"}]}, ",
+        "{\"name\": \"body\", \"lines\": [\"hello\"], ",
+        "\"sources\": [{\"file\": \"input.nw\", \"line\": 6, \"chunk\": \"body\"}]}]\n"
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_extract_print0() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<a>>=\none\n\ntwo\n@\n<<b>>=\nthree\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["extract", "--chunks", "a,b", "--print0", "input.nw"]);
+    cmd.assert().success().stdout("one\n\ntwo\n\0three\n\0");
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "extract",
+        "--chunks",
+        "a",
+        "--print0",
+        "--output-format",
+        "json",
+        "input.nw",
+    ]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_tangle_keep_going() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\n<<missing>>\n@\n<<@file b.txt>>=\nb\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--keep-going", "input.nw"]);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "1 output(s) could not be generated:\n  a.txt: ",
+    ));
+    assert_eq!(fs::read_to_string(dir.path().join("gen/b.txt"))?, "b\n");
+    Ok(())
+}
+
+#[test]
+fn test_backup_dir_option() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\na\n@\n<<@file b.log>>=\nb\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "tangle",
+        "--backup-dir",
+        "backups",
+        "--no-backup",
+        "*.log",
+        "input.nw",
+    ]);
+    cmd.assert().success();
+    assert_eq!(fs::read_to_string(dir.path().join("backups/a.txt"))?, "a\n");
+    assert!(!dir.path().join("backups/b.log").exists());
+    assert!(!dir.path().join("_azadi_work/__old__/a.txt").exists());
+
+    // A backup directory that cannot be created is an error, not a panic
+    fs::write(dir.path().join("taken"), "")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--backup-dir", "taken", "input.nw"]);
+    cmd.assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Failed to create directory: taken",
+        ))
+        .stderr(predicate::str::contains("panicked").not());
+    Ok(())
+}
+
+#[test]
+fn test_verify_command() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\na\n@\n<<@file b.txt>>=\nb\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["tangle", "input.nw"]);
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["verify", "input.nw"]);
+    cmd.assert().success().stdout("");
+
+    fs::write(dir.path().join("gen/a.txt"), "edited\n")?;
+    fs::write(dir.path().join("gen/new.txt"), "new\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["verify", "input.nw"]);
+    cmd.assert()
+        .failure()
+        .stdout("modified: gen/a.txt\nadded: gen/new.txt\n")
+        .stderr(predicate::str::contains(
+            "2 generated file(s) changed since the last tangle",
+        ));
+    Ok(())
+}
+
+#[test]
+fn test_verify_checks_the_given_documents() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("azadi.toml"),
+        "[profiles.release]\ngen = \"out\"\n",
+    )?;
+    fs::write(dir.path().join("a.nw"), "<<@file a.txt>>=\na\n@\n")?;
+    fs::write(dir.path().join("b.nw"), "<<@file b.txt>>=\nb\n@\n")?;
+    for doc in ["a.nw", "b.nw"] {
+        let mut cmd = Command::cargo_bin("azadi-noweb")?;
+        cmd.current_dir(dir.path())
+            .args(["tangle", "--profile", "release", doc]);
+        cmd.assert().success();
+    }
+
+    // Neither document's outputs count as added for the other
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["verify", "--profile", "release", "a.nw"]);
+    cmd.assert().success().stdout("");
+
+    fs::write(dir.path().join("out/b.txt"), "edited\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["verify", "--profile", "release", "a.nw"]);
+    cmd.assert().success().stdout("");
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["verify", "--profile", "release", "b.nw"]);
+    cmd.assert().code(1).stdout("modified: out/b.txt\n");
+
+    // Without documents, every output in the manifest is checked
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["verify", "--profile", "release"]);
+    cmd.assert().code(1).stdout("modified: out/b.txt\n");
+
+    // A document the manifest does not know is an error, not a clean result
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["verify", "--profile", "release", "nonexistent.nw"]);
+    cmd.assert().code(1).stderr(predicate::str::contains(
+        "nonexistent.nw generated no files in the manifest",
+    ));
+
+    // Nothing is created where no tangle ran
+    let empty = tempdir()?;
+    fs::write(empty.path().join("a.nw"), "<<@file a.txt>>=\na\n@\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(empty.path()).args(["verify", "a.nw"]);
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("a.nw generated no files"));
+    assert!(!empty.path().join("gen").exists());
+    assert!(!empty.path().join("_azadi_work").exists());
+    Ok(())
+}
+
+#[test]
+fn test_config_name_rules() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(dir.path().join("azadi.toml"), "[names]\nchars = \"a-z_\"\n")?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\n<<Main>>\n@\n<<Main>>=\nm\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["tangle", "input.nw"]);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Error[AZ0020]: Chunk error: input.nw line 4: chunk name 'Main' contains 'M', which is not in [a-z_]",
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_tangle_extra_delims() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("input.nw"),
+        "<<@file a.txt>>=\n[[body]]\n@\n[[body]]=\nfrom brackets\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--extra-delims", "[[ ]]", "input.nw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("gen/a.txt"))?,
+        "from brackets\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_tangle_auto_markers() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("gen.py"),
+        "# <<@file out.txt>>=\n# <<part>>\n# @\n",
+    )?;
+    fs::write(dir.path().join("part.tex"), "% <<part>>=\nfrom tex\n% @\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--auto-markers", "gen.py", "part.tex"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("gen/out.txt"))?,
+        "from tex\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_tangle_markers_for() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::create_dir(dir.path().join("docs"))?;
+    fs::write(
+        dir.path().join("main.nw"),
+        "# <<@file out.txt>>=\n# <<part>>\n# @\n",
+    )?;
+    fs::write(
+        dir.path().join("docs/part.nw"),
+        "% <<part>>=\nfrom docs\n% @\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "tangle",
+        "--markers-for",
+        "docs/*=%",
+        "main.nw",
+        "docs/part.nw",
+    ]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("gen/out.txt"))?,
+        "from docs\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_tangle_test_harness() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("doc.nw"),
+        "# <<@test doubles>>=\nassert double(2) == 4\n# @\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--test-harness", "test_doc.py", "doc.nw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("gen/test_doc.py"))?,
+        "def test_doubles():\n    assert double(2) == 4\n"
+    );
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--test-harness", "doc.tests", "doc.nw"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("no test harness template"));
+    Ok(())
+}
+
+#[test]
+fn test_tangle_tags() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("doc.nw"),
+        "# <<@file init.sh>>=\n# <<steps>>\n# @\n# <<steps tags=step1>>=\necho one\n# @\n# <<@append steps tags=step2>>=\necho two\n# @\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["extract", "--chunks", "steps", "--tags", "step2", "doc.nw"]);
+    cmd.assert().success().stdout(
+        predicate::str::contains("echo two").and(predicate::str::contains("echo one").not()),
+    );
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--tags", "step1", "doc.nw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("gen/init.sh"))?,
+        "echo one\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_extract_explode() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("doc.nw"),
+        "# <<@file out.txt>>=\n# <<greet/en>>\n# @\n# <<greet/en>>=\nhello\n# <<name>>\n# @\n# <<name>>=\nworld\n# @\n# <<greet_en>>=\nclash\n# @\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["extract", "--explode", "snippets", "doc.nw"]);
+    cmd.assert().success().stderr(predicate::str::contains(
+        "chunks 'greet/en' and 'greet_en' both write to",
+    ));
+    let snippets = dir.path().join("snippets");
+    assert_eq!(
+        fs::read_to_string(snippets.join("greet_en"))?,
+        "hello\nworld\n"
+    );
+    assert_eq!(fs::read_to_string(snippets.join("name"))?, "world\n");
+    assert_eq!(fs::read_dir(&snippets)?.count(), 2);
+    assert!(!dir.path().join("gen/out.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_extract_indent() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("doc.nw"),
+        "# <<body>>=\nif ok {\n    <<inner>>\n}\n# @\n# <<inner>>=\nrun();\n# @\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "extract", "--chunks", "body", "--indent", "4", "--print0", "doc.nw",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout("    if ok {\n        run();\n    }\n\0");
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "extract", "--chunks", "inner", "--indent", "\t", "--print0", "doc.nw",
+    ]);
+    cmd.assert().success().stdout("\trun();\n\0");
+    Ok(())
+}
+
+#[test]
+fn test_extract_with_locations() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("doc.nw"),
+        "Prose.\n# <<body>>=\nstart\n<<inner>>\n# @\n# <<inner>>=\nnested\n# @\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["extract", "--chunks", "body", "--with-locations", "doc.nw"]);
+    cmd.assert()
+        .success()
+        .stdout("doc.nw:3:start\ndoc.nw:7:nested\n\n");
+    Ok(())
+}
+
+#[test]
+fn test_tangle_filelist() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::create_dir(dir.path().join("parts"))?;
+    fs::write(
+        dir.path().join("main.nw"),
+        "# <<@file out.txt>>=\n# <<a>>\n# <<b>>\n# @\n",
+    )?;
+    fs::write(dir.path().join("parts/a.nw"), "# <<a>>=\nfrom a\n# @\n")?;
+    fs::write(dir.path().join("parts/b.nw"), "# <<b>>=\nfrom b\n# @\n")?;
+    fs::write(
+        dir.path().join("inputs.txt"),
+        "# generated by the build\nparts/a.nw\n\n  parts/b.nw  \n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--filelist", "inputs.txt", "main.nw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("gen/out.txt"))?,
+        "from a\nfrom b\n"
+    );
+
+    fs::write(dir.path().join("empty.txt"), "# nothing yet\n")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--filelist", "empty.txt"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("empty.txt lists no input files"));
+    Ok(())
+}
+
+#[test]
+fn test_response_files() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("my doc.nw"),
+        "# [[@file out.txt]]=\n# [[body]]\n# @\n# [[body]]=\nhello\n# @\n",
+    )?;
+    fs::write(
+        dir.path().join("args.rsp"),
+        "tangle --open-delim [[ --close-delim ]]\n@gen.rsp \"my doc.nw\"\n",
+    )?;
+    fs::write(dir.path().join("gen.rsp"), "--gen 'out dir'")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("@args.rsp");
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out dir/out.txt"))?,
+        "hello\n"
+    );
+
+    fs::write(dir.path().join("bad.rsp"), "tangle \"doc.nw")?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("@bad.rsp");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("bad.rsp: unterminated \" quote"));
+    Ok(())
+}
+
+#[test]
+fn test_environment_defaults() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("doc.nw"),
+        "# [[@file out.txt]]=\nfrom env\n# @\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .env("AZADI_GEN", "ci-gen")
+        .env("AZADI_OPEN_DELIM", "[[")
+        .env("AZADI_CLOSE_DELIM", "]]")
+        .args(["tangle", "doc.nw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("ci-gen/out.txt"))?,
+        "from env\n"
+    );
+
+    // The command line wins over the environment
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .env("AZADI_GEN", "ci-gen")
+        .env("AZADI_OPEN_DELIM", "[[")
+        .env("AZADI_CLOSE_DELIM", "]]")
+        .args(["tangle", "--gen", "cli-gen", "doc.nw"]);
+    cmd.assert().success();
+    assert!(dir.path().join("cli-gen/out.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_init_then_tangle() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("init");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("created: ./hello.nw"))
+        .stdout(predicate::str::contains(
+            "next: azadi-noweb tangle hello.nw",
+        ));
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["tangle", "hello.nw"]);
+    cmd.assert().success();
+    assert!(fs::read_to_string(dir.path().join("gen/hello.py"))?.starts_with("import sys\n"));
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).arg("init");
+    cmd.assert().success().stdout(predicate::str::contains(
+        "kept: ./azadi.toml (already exists)",
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_migrate_from_noweb() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("old.nw"),
+        "Prose.\n<<main.rs>>=\nfn main() {\n    <<say hello>>\n}\n@\n<<say hello>>=\nprintln!(\"{}\", <<greeting>>);\n@\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "migrate", "--from", "noweb", "--file", "main.rs", "-o", "new.nw", "old.nw",
+    ]);
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("AZ0108"))
+        .stderr(predicate::str::contains("old.nw line 8"));
+    let text = fs::read_to_string(dir.path().join("new.nw"))?;
+    assert!(text.starts_with("Prose.\n// <<@file main.rs>>=\nfn main() {\n    // <<say-hello>>\n"));
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["migrate", "--from", "noweb", "--language", "py", "old.nw"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("# <<say-hello>>=\n"));
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["migrate", "--from", "noweb", "--language", "xyz", "old.nw"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown language 'xyz'"));
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["migrate", "--from", "noweb", "old.nw"]);
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("pass --language or --marker"));
+    Ok(())
+}
+
+#[test]
+fn test_funnelweb_tangle_and_migrate() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("prog.fw"),
+        "@O@<out.py@>==@{@-\n@<Greeting@>\n@}\n@$@<Greeting@>==@{print(\"hi\")@}\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["tangle", "--dialect", "funnelweb", "prog.fw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("gen/out.py"))?,
+        "print(\"hi\")\n"
+    );
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "migrate",
+        "--from",
+        "funnelweb",
+        "--language",
+        "py",
+        "prog.fw",
+    ]);
+    cmd.assert().success().stdout(predicate::str::starts_with(
+        "# <<@file out.py>>=\n# <<Greeting>>\n# @\n",
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_export_noweb() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("doc.nw"),
+        "# <<@file out.py>>=\n# <<body>>\n# @\n# <<body>>=\nprint(1)\n# @\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["export", "doc.nw"]);
+    cmd.assert()
+        .success()
+        .stdout("<<out.py>>=\n<<body>>\n@\n<<body>>=\nprint(1)\n@\n");
+    assert!(!dir.path().join("gen/out.py").exists());
+    Ok(())
+}
+
+#[test]
+fn test_fmt_check_and_write() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("doc.nw"),
+        "#<<@file  out.py>>=\nprint(1)\n@\n\n\n\nEnd.\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["fmt", "--check", "doc.nw"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("would reformat: doc.nw"))
+        .stderr(predicate::str::contains("AZ0022"));
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["fmt", "doc.nw"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("doc.nw"))?,
+        "# <<@file out.py>>=\nprint(1)\n# @\n\nEnd.\n"
+    );
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["fmt", "--check", "doc.nw"]);
+    cmd.assert().success();
+    Ok(())
+}
+
+#[test]
+fn test_fmt_keeps_crlf() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let tidy = "# <<@file out.py>>=\r\nprint(1)\r\n# @\r\n\r\nEnd.\r\n";
+    fs::write(dir.path().join("doc.nw"), tidy)?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["fmt", "--check", "doc.nw"]);
+    cmd.assert().success();
+
+    fs::write(
+        dir.path().join("doc.nw"),
+        "#<<@file  out.py>>=\r\nprint(1)\r\n@\r\n\r\n\r\nEnd.\r\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["fmt", "doc.nw"]);
+    cmd.assert().success();
+    assert_eq!(fs::read_to_string(dir.path().join("doc.nw"))?, tidy);
+    Ok(())
+}
+
+#[test]
+fn test_lint() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::write(
+        dir.path().join("azadi.toml"),
+        "[lint]\nname_pattern = \"^[a-z-]+$\"\n",
+    )?;
+    fs::write(
+        dir.path().join("doc.nw"),
+        "# <<@file run>>=\n# <<Body>>\n# @\n# <<Body>>=\necho hi\n",
+    )?;
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args(["lint", "doc.nw"]);
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("AZ0113"))
+        .stderr(predicate::str::contains("AZ0109"))
+        .stderr(predicate::str::contains(
+            "doc.nw line 4: chunk 'Body' has no end line",
+        ));
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path()).args([
+        "lint",
+        "--deny-warnings",
+        "--allow",
+        "AZ0113",
+        "--allow",
+        "AZ0109",
+        "doc.nw",
+    ]);
+    cmd.assert()
+        .code(3)
+        .stderr(predicate::str::contains("AZ0110"))
+        .stderr(predicate::str::contains("AZ0113").not());
+    assert!(!dir.path().join("gen/run").exists());
+    Ok(())
+}
+
+/// A child process killed when dropped, even if the test fails first.
+struct KillOnDrop(std::process::Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn test_serve_reloads_on_change() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    let dir = tempdir()?;
+    let input = dir.path().join("input.nw");
+    fs::write(&input, "First draft.\n")?;
+    let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let _server = KillOnDrop(
+        Command::cargo_bin("azadi-noweb")?
+            .current_dir(dir.path())
+            .args(["serve", "--port", &port.to_string(), "input.nw"])
+            .spawn()?,
+    );
+
+    let addr = format!("127.0.0.1:{}", port);
+    let get = |target: &str| -> std::io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(&addr)?;
+        write!(stream, "GET {} HTTP/1.1\r\n\r\n", target)?;
+        Ok(stream)
+    };
+    let started = Instant::now();
+    let mut stream = loop {
+        match get("/") {
+            Ok(stream) => break stream,
+            Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                std::thread::sleep(Duration::from_millis(50))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    let mut page = String::new();
+    stream.read_to_string(&mut page)?;
+    assert!(page.contains("<p>First draft.</p>"));
+    assert!(page.contains("new EventSource(\"/__events?since=0\")"));
+
+    let mut events = BufReader::new(get("/__events?since=0")?);
+    fs::write(&input, "Second draft.\n")?;
+    let mut line = String::new();
+    while !line.starts_with("data:") {
+        line.clear();
+        events.read_line(&mut line)?;
+    }
+    assert_eq!(line, "data: reload\n");
+
+    let mut page = String::new();
+    get("/")?.read_to_string(&mut page)?;
+    assert!(page.contains("<p>Second draft.</p>"));
+    Ok(())
+}
+
+#[test]
+fn test_serve_binds_ipv6_addresses() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    // Hosts without IPv6 loopback have nothing to check
+    let Ok(listener) = TcpListener::bind("[::1]:0") else {
+        return Ok(());
+    };
+    let port = listener.local_addr()?.port();
+    drop(listener);
+    let dir = tempdir()?;
+    fs::write(dir.path().join("input.nw"), "Draft.\n")?;
+    let _server = KillOnDrop(
+        Command::cargo_bin("azadi-noweb")?
+            .current_dir(dir.path())
+            .args([
+                "serve",
+                "--bind",
+                "::1",
+                "--port",
+                &port.to_string(),
+                "input.nw",
+            ])
+            .spawn()?,
+    );
+
+    let started = Instant::now();
+    let mut stream = loop {
+        match TcpStream::connect(("::1", port)) {
+            Ok(stream) => break stream,
+            Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                std::thread::sleep(Duration::from_millis(50))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    write!(stream, "GET / HTTP/1.1\r\n\r\n")?;
+    let mut page = String::new();
+    stream.read_to_string(&mut page)?;
+    assert!(page.contains("<p>Draft.</p>"));
+
+    let mut cmd = Command::cargo_bin("azadi-noweb")?;
+    cmd.current_dir(dir.path())
+        .args(["serve", "--bind", "localhost:80", "input.nw"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value 'localhost:80'"));
+    Ok(())
+}